@@ -165,4 +165,8 @@ impl ConnectionProvider for AsyncStdConnectionProvider {
     ) -> Result<Self::FutureConn, io::Error> {
         self.connection_provider.new_connection(config, options)
     }
+
+    fn spawn_bg(&self, task: Pin<Box<dyn Future<Output = ()> + Send>>) {
+        self.connection_provider.spawn_bg(task)
+    }
 }
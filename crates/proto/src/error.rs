@@ -65,6 +65,24 @@ pub enum ProtoErrorKind {
     #[error("there should only be one query per request, got: {0}")]
     BadQueryCount(usize),
 
+    /// The query of a response does not match the query of the request
+    #[error("response query {received} does not match request query {expected}")]
+    QueryMismatch {
+        /// Query sent in the request
+        expected: Box<Query>,
+        /// Query received in the response
+        received: Box<Query>,
+    },
+
+    /// The response contains an answer record of another type than the query
+    #[error("answer of type {record_type} to query {query}")]
+    AnswerTypeMismatch {
+        /// Query sent in the request
+        query: Box<Query>,
+        /// Type of the mismatched answer record
+        record_type: RecordType,
+    },
+
     /// The underlying resource is too busy
     ///
     /// This is a signal that an internal resource is too busy. The intended action should be tried
@@ -73,6 +91,13 @@ pub enum ProtoErrorKind {
     #[error("resource too busy")]
     Busy,
 
+    /// The records of the response failed DNSSEC validation, equivalent to a SERVFAIL
+    #[error("DNSSEC validation failed for {query}")]
+    Bogus {
+        /// Query whose response is bogus
+        query: Box<Query>,
+    },
+
     /// An error caused by a canceled future
     #[error("future was canceled: {0:?}")]
     Canceled(futures_channel::oneshot::Canceled),
@@ -152,6 +177,10 @@ pub enum ProtoErrorKind {
         ptr: u16,
     },
 
+    /// A name was reached through more compression pointers than allowed
+    #[error("compression pointer chain exceeds the maximum depth: {0}")]
+    PointerChainTooLong(usize),
+
     /// The maximum buffer size was exceeded
     #[error("maximum buffer size exceeded: {0}")]
     MaxBufferSizeExceeded(usize),
@@ -242,6 +271,14 @@ pub enum ProtoErrorKind {
     #[error("lock poisoned error")]
     Poisoned,
 
+    /// A response to a recursive query didn't have the Recursion Available flag set
+    #[error("recursion not available from the name server")]
+    RecursionUnavailable,
+
+    /// A response to a query with EDNS didn't have an OPT record, the name server doesn't support EDNS
+    #[error("EDNS not supported by the name server")]
+    EdnsUnsupported,
+
     /// A request was Refused due to some access check
     #[error("request refused")]
     RequestRefused,
@@ -262,6 +299,28 @@ pub enum ProtoErrorKind {
     #[error("request timed out")]
     Timeout,
 
+    /// A response received over a stream connection was still truncated
+    #[error("truncated response received over a stream connection")]
+    TruncatedStreamResponse,
+
+    /// A response received over a stream connection was larger than the maximum response size
+    #[error("response of {received} bytes received over a stream connection exceeds the maximum size of {max} bytes")]
+    OversizedStreamResponse {
+        /// The maximum response size
+        max: usize,
+        /// The size of the received response
+        received: usize,
+    },
+
+    /// A UDP response was larger than the payload size advertised in the request
+    #[error("UDP response of at least {received} bytes exceeds the advertised payload size of {advertised} bytes")]
+    OversizedUdpResponse {
+        /// The payload size advertised in the request
+        advertised: usize,
+        /// The bytes received of the response, which is cut once they exceed the advertised size
+        received: usize,
+    },
+
     /// Tsig key verification failed
     #[error("Tsig key wrong key error")]
     TsigWrongKey,
@@ -338,6 +397,10 @@ pub enum ProtoErrorKind {
     #[error("rustls construction error: {0}")]
     RustlsError(#[from] rustls::Error),
 
+    /// The public key of the certificate of a TLS name server matches none of the pinned keys
+    #[error("the public key of the certificate of {0} does not match any pinned SPKI")]
+    SpkiPinMismatch(String),
+
     /// No valid certificates found in the native root store.
     #[cfg(all(feature = "native-certs", not(feature = "webpki-roots")))]
     #[error("no valid certificates found in the native root store")]
@@ -472,6 +535,18 @@ impl ProtoError {
         matches!(*self.kind, ProtoErrorKind::NoRecordsFound { .. })
     }
 
+    /// Returns true if the server responded with SERVFAIL
+    #[inline]
+    pub fn is_serv_fail(&self) -> bool {
+        matches!(
+            *self.kind,
+            ProtoErrorKind::NoRecordsFound {
+                response_code: ResponseCode::ServFail,
+                ..
+            }
+        )
+    }
+
     /// Returns the SOA record, if the error contains one
     #[inline]
     pub fn into_soa(self) -> Option<Box<Record<SOA>>> {
@@ -481,6 +556,23 @@ impl ProtoError {
         }
     }
 
+    /// Returns the NS records, with their glue, of the authority section of a negative response
+    ///
+    /// These are the name servers of the closest existing zone, if the upstream included them.
+    #[inline]
+    pub fn name_servers(&self) -> Option<&[ForwardNSData]> {
+        match self.kind.as_ref() {
+            ProtoErrorKind::NoRecordsFound { ns, .. } => ns.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Returns true if a UDP response exceeded the advertised payload size
+    #[inline]
+    pub fn is_oversized_udp_response(&self) -> bool {
+        matches!(*self.kind, ProtoErrorKind::OversizedUdpResponse { .. })
+    }
+
     /// Returns true if this is a std::io::Error
     #[inline]
     pub fn is_io(&self) -> bool {
@@ -671,6 +763,7 @@ impl From<DecodeError> for ProtoError {
             DecodeError::PointerNotPriorToLabel { idx, ptr } => {
                 ProtoErrorKind::PointerNotPriorToLabel { idx, ptr }
             }
+            DecodeError::PointerChainTooLong(max) => ProtoErrorKind::PointerChainTooLong(max),
             DecodeError::LabelBytesTooLong(len) => ProtoErrorKind::LabelBytesTooLong(len),
             DecodeError::UnrecognizedLabelCode(code) => ProtoErrorKind::UnrecognizedLabelCode(code),
             DecodeError::DomainNameTooLong(len) => ProtoErrorKind::DomainNameTooLong(len),
@@ -737,7 +830,24 @@ impl Clone for ProtoErrorKind {
         use self::ProtoErrorKind::*;
         match *self {
             BadQueryCount(count) => BadQueryCount(count),
+            QueryMismatch {
+                ref expected,
+                ref received,
+            } => QueryMismatch {
+                expected: expected.clone(),
+                received: received.clone(),
+            },
+            AnswerTypeMismatch {
+                ref query,
+                record_type,
+            } => AnswerTypeMismatch {
+                query: query.clone(),
+                record_type,
+            },
             Busy => Busy,
+            Bogus { ref query } => Bogus {
+                query: query.clone(),
+            },
             Canceled(ref c) => Canceled(*c),
             CharacterDataTooLong { max, len } => CharacterDataTooLong { max, len },
             LabelOverlapsWithOther { label, other } => LabelOverlapsWithOther { label, other },
@@ -752,6 +862,7 @@ impl Clone for ProtoErrorKind {
             IncorrectRDataLengthRead { read, len } => IncorrectRDataLengthRead { read, len },
             LabelBytesTooLong(len) => LabelBytesTooLong(len),
             PointerNotPriorToLabel { idx, ptr } => PointerNotPriorToLabel { idx, ptr },
+            PointerChainTooLong(max) => PointerChainTooLong(max),
             MaxBufferSizeExceeded(max) => MaxBufferSizeExceeded(max),
             Message(msg) => Message(msg),
             Msg(ref msg) => Msg(msg.clone()),
@@ -775,6 +886,8 @@ impl Clone for ProtoErrorKind {
                 trusted,
                 authorities: authorities.clone(),
             },
+            RecursionUnavailable => RecursionUnavailable,
+            EdnsUnsupported => EdnsUnsupported,
             RequestRefused => RequestRefused,
             #[cfg(feature = "dnssec")]
             Nsec { ref query, proof } => Nsec {
@@ -795,6 +908,15 @@ impl Clone for ProtoErrorKind {
             SSL(ref e) => Msg(format!("there was an SSL error: {e}")),
             Timeout => Timeout,
             Timer => Timer,
+            TruncatedStreamResponse => TruncatedStreamResponse,
+            OversizedStreamResponse { max, received } => OversizedStreamResponse { max, received },
+            OversizedUdpResponse {
+                advertised,
+                received,
+            } => OversizedUdpResponse {
+                advertised,
+                received,
+            },
             #[cfg(feature = "dnssec")]
             TsigUnsupportedMacAlgorithm(ref alg) => TsigUnsupportedMacAlgorithm(alg.clone()),
             TsigWrongKey => TsigWrongKey,
@@ -822,6 +944,7 @@ impl Clone for ProtoErrorKind {
             QuinnUnknownStreamError => QuinnUnknownStreamError,
             #[cfg(feature = "rustls")]
             RustlsError(ref e) => RustlsError(e.clone()),
+            SpkiPinMismatch(ref name) => SpkiPinMismatch(name.clone()),
             #[cfg(all(feature = "native-certs", not(feature = "webpki-roots")))]
             NativeCerts => NativeCerts,
         }
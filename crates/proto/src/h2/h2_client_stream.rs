@@ -790,7 +790,7 @@ mod tests {
         root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
 
         let mut client_config =
-            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            ClientConfig::builder_with_provider(crate::rustls::crypto_provider())
                 .with_safe_default_protocol_versions()
                 .unwrap()
                 .with_root_certificates(root_store)
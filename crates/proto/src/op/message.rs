@@ -1123,6 +1123,7 @@ impl fmt::Display for Message {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::serialize::binary::DEFAULT_MAX_POINTER_DEPTH;
 
     #[test]
     fn test_emit_and_read_header() {
@@ -1305,4 +1306,46 @@ mod tests {
 
         Message::from_vec(CRASHING_MESSAGE).expect("failed to parse message");
     }
+
+    #[test]
+    fn test_compression_pointer_limits() {
+        let header = |query_count: u16| {
+            let mut bytes = vec![0, 0, 0x01, 0x00];
+            bytes.extend(query_count.to_be_bytes());
+            bytes.extend([0; 6]);
+            bytes
+        };
+
+        // a query name pointing to itself
+        let mut bytes = header(1);
+        bytes.extend([0xC0, 0x0C, 0x00, 0x01, 0x00, 0x01]);
+        let error = Message::from_vec(&bytes).expect_err("self-referential pointer");
+        assert!(matches!(
+            error.kind(),
+            ProtoErrorKind::PointerNotPriorToLabel { .. }
+        ));
+
+        // each query name points to the previous one, the chain grows with each query
+        let query_count = 200_u16;
+        let mut bytes = header(query_count);
+        bytes.extend([0x00, 0x00, 0x01, 0x00, 0x01]);
+        let mut previous = 12_u16;
+        for _ in 1..query_count {
+            let start = bytes.len() as u16;
+            bytes.extend((0xC000 | previous).to_be_bytes());
+            bytes.extend([0x00, 0x01, 0x00, 0x01]);
+            previous = start;
+        }
+
+        let error = Message::from_vec(&bytes).expect_err("pointer chain too long");
+        assert!(matches!(
+            error.kind(),
+            ProtoErrorKind::PointerChainTooLong(DEFAULT_MAX_POINTER_DEPTH)
+        ));
+
+        let mut decoder = BinDecoder::new(&bytes);
+        decoder.set_max_pointer_depth(usize::from(query_count));
+        let message = Message::read(&mut decoder).expect("failed to parse message");
+        assert_eq!(message.queries().len(), usize::from(query_count));
+    }
 }
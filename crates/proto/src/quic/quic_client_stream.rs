@@ -295,9 +295,8 @@ pub fn client_config_tls13() -> Result<TlsClientConfig, ProtoError> {
     root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
 
     Ok(
-        TlsClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
-            .with_protocol_versions(&[&TLS13])
-            .unwrap() // The ring default provider is guaranteed to support TLS 1.3
+        TlsClientConfig::builder_with_provider(crate::rustls::crypto_provider())
+            .with_protocol_versions(&[&TLS13])?
             .with_root_certificates(root_store)
             .with_no_client_auth(),
     )
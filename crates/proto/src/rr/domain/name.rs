@@ -1153,7 +1153,7 @@ impl<'r> BinDecodable<'r> for Name {
     fn read(decoder: &mut BinDecoder<'r>) -> ProtoResult<Self> {
         let mut name = Self::root(); // this is FQDN
 
-        read_inner(decoder, &mut name, None)?;
+        read_inner(decoder, &mut name, None, 0)?;
         Ok(name)
     }
 }
@@ -1162,6 +1162,7 @@ fn read_inner(
     decoder: &mut BinDecoder<'_>,
     name: &mut Name,
     max_idx: Option<usize>,
+    pointer_depth: usize,
 ) -> Result<(), DecodeError> {
     let mut state: LabelParseState = LabelParseState::LabelLengthOrPointer;
     let name_start = decoder.index();
@@ -1230,6 +1231,13 @@ fn read_inner(
             // domain header).  A zero offset specifies the first byte of the ID field,
             // etc.
             LabelParseState::Pointer => {
+                // each pointer must be prior to the last, but long chains still cost deep recursion
+                if pointer_depth >= decoder.max_pointer_depth() {
+                    return Err(DecodeError::PointerChainTooLong(
+                        decoder.max_pointer_depth(),
+                    ));
+                }
+
                 let pointer_location = decoder.index();
                 let location = decoder
                     .read_u16()?
@@ -1247,7 +1255,7 @@ fn read_inner(
                     })?;
 
                 let mut pointer = decoder.clone(location);
-                read_inner(&mut pointer, name, Some(name_start))?;
+                read_inner(&mut pointer, name, Some(name_start), pointer_depth + 1)?;
 
                 // Pointers always finish the name, break like Root.
                 break;
@@ -7,6 +7,11 @@
 
 //! TLS protocol related components for DNS over TLS
 
+use std::sync::Arc;
+
+use once_cell::sync::OnceCell;
+use rustls::crypto::CryptoProvider;
+
 pub mod tls_client_stream;
 pub mod tls_server;
 pub mod tls_stream;
@@ -16,5 +21,29 @@ pub use self::tls_client_stream::{
 };
 pub use self::tls_stream::{tls_connect, tls_connect_with_bind_addr, tls_from_stream, TlsStream};
 
+static CRYPTO_PROVIDER: OnceCell<Arc<CryptoProvider>> = OnceCell::new();
+
+/// Sets the crypto provider of the default TLS client configs, used by DNS over TLS, HTTPS, QUIC
+///  and H3 without a custom client config
+///
+/// This must be called before the first default client config is built. Otherwise the provider
+///  installed process-wide with [`CryptoProvider::install_default`] is used, or the ring provider
+///  if none is installed. Returns the provider back if one was already chosen.
+pub fn set_crypto_provider(provider: Arc<CryptoProvider>) -> Result<(), Arc<CryptoProvider>> {
+    CRYPTO_PROVIDER.set(provider)
+}
+
+/// Returns the crypto provider of the default client configs, choosing it on the first call
+///
+/// See [`set_crypto_provider`].
+pub fn crypto_provider() -> Arc<CryptoProvider> {
+    CRYPTO_PROVIDER
+        .get_or_init(|| match CryptoProvider::get_default() {
+            Some(provider) => provider.clone(),
+            None => Arc::new(rustls::crypto::ring::default_provider()),
+        })
+        .clone()
+}
+
 #[cfg(test)]
 pub(crate) mod tests;
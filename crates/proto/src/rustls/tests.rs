@@ -27,6 +27,7 @@ use tokio::runtime::Runtime;
 
 use crate::runtime::TokioRuntimeProvider;
 use crate::rustls::tls_connect;
+use crate::tcp::TcpClientStream;
 use crate::xfer::SerialMessage;
 use crate::DnsStreamHandle;
 
@@ -193,7 +194,12 @@ fn tls_client_stream_test(server_addr: IpAddr) {
     );
 
     // TODO: there is a race failure here... a race with the server thread most likely...
-    let mut stream = io_loop.block_on(stream).expect("run failed to get stream");
+    let stream = io_loop.block_on(stream).expect("run failed to get stream");
+
+    // the negotiated parameters are known once connected
+    let mut stream = TcpClientStream::from_stream(stream);
+    assert!(stream.protocol_version().is_some());
+    assert!(stream.negotiated_cipher_suite().is_some());
 
     for _ in 0..send_recv_times {
         // test once
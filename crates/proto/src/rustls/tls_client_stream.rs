@@ -14,7 +14,7 @@ use std::pin::Pin;
 use std::sync::Arc;
 
 use futures_util::TryFutureExt;
-use rustls::ClientConfig;
+use rustls::{ClientConfig, HandshakeKind, ProtocolVersion, SupportedCipherSuite};
 
 use crate::error::ProtoError;
 use crate::runtime::iocompat::{AsyncIoStdAsTokio, AsyncIoTokioAsStd};
@@ -27,6 +27,33 @@ use crate::xfer::BufDnsStreamHandle;
 pub type TlsClientStream<S> =
     TcpClientStream<AsyncIoTokioAsStd<tokio_rustls::client::TlsStream<AsyncIoStdAsTokio<S>>>>;
 
+impl<S: DnsTcpStream> TlsClientStream<S> {
+    /// Returns the TLS protocol version negotiated with the name server
+    ///
+    /// This is `None` until the handshake completes, which is the case of the streams returned by
+    ///  the connect functions.
+    pub fn protocol_version(&self) -> Option<ProtocolVersion> {
+        let (_, connection) = self.tcp_stream().socket().0.get_ref();
+        connection.protocol_version()
+    }
+
+    /// Returns the cipher suite negotiated with the name server
+    ///
+    /// This is `None` until the handshake completes, like [`TlsClientStream::protocol_version`].
+    pub fn negotiated_cipher_suite(&self) -> Option<SupportedCipherSuite> {
+        let (_, connection) = self.tcp_stream().socket().0.get_ref();
+        connection.negotiated_cipher_suite()
+    }
+
+    /// Returns whether the handshake with the name server was a full one or resumed a session
+    ///
+    /// This is `None` until the handshake completes, like [`TlsClientStream::protocol_version`].
+    pub fn handshake_kind(&self) -> Option<HandshakeKind> {
+        let (_, connection) = self.tcp_stream().socket().0.get_ref();
+        connection.handshake_kind()
+    }
+}
+
 /// Creates a new TlsStream to the specified name_server
 ///
 /// # Arguments
@@ -86,6 +86,7 @@ pub fn new_acceptor(
             .with_no_client_auth()
             .with_single_cert(cert, key)?;
 
-    config.alpn_protocols = vec![b"h2".to_vec()];
+    // the acceptor is used for both DNS over HTTPS and DNS over TLS
+    config.alpn_protocols = vec![b"h2".to_vec(), b"dot".to_vec()];
     Ok(config)
 }
@@ -17,6 +17,11 @@
 use crate::serialize::binary::Restrict;
 use thiserror::Error;
 
+/// Default maximum number of compression pointers followed to read a single name
+///
+/// Legitimate messages use at most one pointer per label, a name has no more than 127 labels.
+pub const DEFAULT_MAX_POINTER_DEPTH: usize = 128;
+
 /// This is non-destructive to the inner buffer, b/c for pointer types we need to perform a reverse
 ///  seek to lookup names
 ///
@@ -25,8 +30,9 @@ use thiserror::Error;
 ///  this is a simpler implementation without the cruft, at least for serializing to/from the
 ///  binary DNS protocols.
 pub struct BinDecoder<'a> {
-    buffer: &'a [u8],    // The entire original buffer
+    buffer: &'a [u8],         // The entire original buffer
     remaining: &'a [u8], // The unread section of the original buffer, so that reads do not cause a bounds check at the current seek offset
+    max_pointer_depth: usize, // The maximum number of compression pointers followed to read a single name
 }
 
 pub(crate) type DecodeResult<T> = Result<T, DecodeError>;
@@ -55,6 +61,10 @@ pub enum DecodeError {
         ptr: u16,
     },
 
+    /// A name was reached through more compression pointers than allowed
+    #[error("compression pointer chain exceeds the maximum depth: {0}")]
+    PointerChainTooLong(usize),
+
     /// Label bytes exceeded the limit of 63
     #[error("label bytes exceed 63: {0}")]
     LabelBytesTooLong(usize),
@@ -87,9 +97,22 @@ impl<'a> BinDecoder<'a> {
         BinDecoder {
             buffer,
             remaining: buffer,
+            max_pointer_depth: DEFAULT_MAX_POINTER_DEPTH,
         }
     }
 
+    /// Sets the maximum number of compression pointers followed to read a single name
+    ///
+    /// Defaults to [`DEFAULT_MAX_POINTER_DEPTH`], names which exceed it fail to decode.
+    pub fn set_max_pointer_depth(&mut self, max_pointer_depth: usize) {
+        self.max_pointer_depth = max_pointer_depth;
+    }
+
+    /// Returns the maximum number of compression pointers followed to read a single name
+    pub fn max_pointer_depth(&self) -> usize {
+        self.max_pointer_depth
+    }
+
     /// Pop one byte from the buffer
     pub fn pop(&mut self) -> DecodeResult<Restrict<u8>> {
         if let Some((first, remaining)) = self.remaining.split_first() {
@@ -135,6 +158,7 @@ impl<'a> BinDecoder<'a> {
         BinDecoder {
             buffer: self.buffer,
             remaining: &self.buffer[index_at as usize..],
+            max_pointer_depth: self.max_pointer_depth,
         }
     }
 
@@ -11,7 +11,7 @@ mod decoder;
 mod encoder;
 mod restrict;
 
-pub use self::decoder::{BinDecoder, DecodeError};
+pub use self::decoder::{BinDecoder, DecodeError, DEFAULT_MAX_POINTER_DEPTH};
 pub use self::encoder::BinEncoder;
 pub use self::encoder::EncodeMode;
 pub use self::restrict::{Restrict, RestrictedMath, Verified};
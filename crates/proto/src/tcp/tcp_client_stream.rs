@@ -66,6 +66,11 @@ impl<S: DnsTcpStream> TcpClientStream<S> {
     pub fn from_stream(tcp_stream: TcpStream<S>) -> Self {
         Self { tcp_stream }
     }
+
+    /// Returns the wrapped TcpStream
+    pub fn tcp_stream(&self) -> &TcpStream<S> {
+        &self.tcp_stream
+    }
 }
 
 impl<S: DnsTcpStream> Display for TcpClientStream<S> {
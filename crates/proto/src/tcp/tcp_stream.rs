@@ -85,6 +85,11 @@ impl<S: DnsTcpStream> TcpStream<S> {
         self.peer_addr
     }
 
+    /// Returns the underlying IO stream, e.g. to inspect a TLS connection
+    pub fn socket(&self) -> &S {
+        &self.socket
+    }
+
     fn pollable_split(
         &mut self,
     ) -> (
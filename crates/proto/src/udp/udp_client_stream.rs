@@ -16,9 +16,11 @@ use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use futures_util::{future::Future, stream::Stream};
 use tracing::{debug, trace, warn};
 
-use crate::error::ProtoError;
-use crate::op::{Message, MessageFinalizer, MessageVerifier};
+use crate::error::{ProtoError, ProtoErrorKind};
+use crate::op::{Header, Message, MessageFinalizer, MessageVerifier, Query};
+use crate::rr::rdata::opt::{EdnsCode, EdnsOption};
 use crate::runtime::{RuntimeProvider, Time};
+use crate::serialize::binary::{BinDecodable, BinDecoder};
 use crate::udp::udp_stream::NextRandomUdpSocket;
 use crate::udp::{DnsUdpSocket, MAX_RECEIVE_BUFFER_SIZE};
 use crate::xfer::{DnsRequest, DnsRequestSender, DnsResponse, DnsResponseStream, SerialMessage};
@@ -32,6 +34,7 @@ pub struct UdpClientStreamBuilder<P> {
     signer: Option<Arc<dyn MessageFinalizer>>,
     bind_addr: Option<SocketAddr>,
     avoid_local_ports: Arc<HashSet<u16>>,
+    reject_oversized_responses: bool,
     provider: P,
 }
 
@@ -50,6 +53,7 @@ impl<P> UdpClientStreamBuilder<P> {
             signer,
             bind_addr: self.bind_addr,
             avoid_local_ports: self.avoid_local_ports,
+            reject_oversized_responses: self.reject_oversized_responses,
             provider: self.provider,
         }
     }
@@ -70,6 +74,17 @@ impl<P> UdpClientStreamBuilder<P> {
         self
     }
 
+    /// Fail with [`ProtoErrorKind::OversizedUdpResponse`] when a response is larger than the
+    /// payload size advertised in the query.
+    ///
+    /// Such responses are accepted by default, as long as they fit in the receive buffer of
+    /// [`MAX_RECEIVE_BUFFER_SIZE`] bytes. When they are rejected, the buffer only exceeds the
+    /// advertised payload size by one byte.
+    pub fn reject_oversized_responses(mut self, reject: bool) -> Self {
+        self.reject_oversized_responses = reject;
+        self
+    }
+
     /// Construct a new UDP client stream.
     ///
     /// Returns a future that outputs the client stream.
@@ -80,6 +95,7 @@ impl<P> UdpClientStreamBuilder<P> {
             signer: self.signer,
             bind_addr: self.bind_addr,
             avoid_local_ports: self.avoid_local_ports.clone(),
+            reject_oversized_responses: self.reject_oversized_responses,
             provider: self.provider,
         }
     }
@@ -98,6 +114,7 @@ pub struct UdpClientStream<P> {
     signer: Option<Arc<dyn MessageFinalizer>>,
     bind_addr: Option<SocketAddr>,
     avoid_local_ports: Arc<HashSet<u16>>,
+    reject_oversized_responses: bool,
     provider: P,
 }
 
@@ -110,6 +127,7 @@ impl<P: RuntimeProvider> UdpClientStream<P> {
             signer: None,
             bind_addr: None,
             avoid_local_ports: Arc::default(),
+            reject_oversized_responses: false,
             provider,
         }
     }
@@ -160,8 +178,8 @@ impl<P: RuntimeProvider> DnsRequestSender for UdpClientStream<P> {
             }
         }
 
-        // Get an appropriate read buffer size.
-        let recv_buf_size = MAX_RECEIVE_BUFFER_SIZE.min(message.max_payload() as usize);
+        // The payload size advertised to the name server, non-conformant servers may exceed it
+        let max_payload = message.max_payload() as usize;
 
         let bytes = match message.to_vec() {
             Ok(bytes) => bytes,
@@ -183,14 +201,22 @@ impl<P: RuntimeProvider> DnsRequestSender for UdpClientStream<P> {
         let addr = message.addr();
         let bind_addr = self.bind_addr;
         let avoid_local_ports = self.avoid_local_ports.clone();
+        let reject_oversized = self.reject_oversized_responses;
 
         P::Timer::timeout::<Pin<Box<dyn Future<Output = Result<DnsResponse, ProtoError>> + Send>>>(
             self.timeout,
             Box::pin(async move {
                 let socket =
                     NextRandomUdpSocket::new(addr, bind_addr, avoid_local_ports, provider).await?;
-                send_serial_message_inner(message, message_id, verifier, socket, recv_buf_size)
-                    .await
+                send_serial_message_inner(
+                    message,
+                    message_id,
+                    verifier,
+                    socket,
+                    max_payload,
+                    reject_oversized,
+                )
+                .await
             }),
         )
         .into()
@@ -226,6 +252,7 @@ pub struct UdpClientConnect<P> {
     signer: Option<Arc<dyn MessageFinalizer>>,
     bind_addr: Option<SocketAddr>,
     avoid_local_ports: Arc<HashSet<u16>>,
+    reject_oversized_responses: bool,
     provider: P,
 }
 
@@ -241,6 +268,7 @@ impl<P: RuntimeProvider> Future for UdpClientConnect<P> {
             signer: self.signer.take(),
             bind_addr: self.bind_addr,
             avoid_local_ports: self.avoid_local_ports.clone(),
+            reject_oversized_responses: self.reject_oversized_responses,
             provider: self.provider.clone(),
         }))
     }
@@ -251,7 +279,8 @@ async fn send_serial_message_inner<S: DnsUdpSocket + Send>(
     msg_id: u16,
     verifier: Option<MessageVerifier>,
     socket: S,
-    recv_buf_size: usize,
+    max_payload: usize,
+    reject_oversized: bool,
 ) -> Result<DnsResponse, ProtoError> {
     let bytes = msg.bytes();
     let addr = msg.addr();
@@ -265,7 +294,13 @@ async fn send_serial_message_inner<S: DnsUdpSocket + Send>(
         )));
     }
 
-    // Create the receive buffer.
+    // Create the receive buffer. One byte above the advertised payload size is enough to detect
+    // the responses exceeding it, while accepting them requires a buffer fitting the datagram.
+    let recv_buf_size = if reject_oversized {
+        MAX_RECEIVE_BUFFER_SIZE.min(max_payload + 1)
+    } else {
+        MAX_RECEIVE_BUFFER_SIZE
+    };
     trace!("creating UDP receive buffer with size {recv_buf_size}");
     let mut recv_buf = vec![0; recv_buf_size];
 
@@ -290,6 +325,22 @@ async fn send_serial_message_inner<S: DnsUdpSocket + Send>(
             continue;
         }
 
+        // The receive buffer cuts the responses exceeding the advertised payload size, so only
+        // their header and question section are checked before they are rejected
+        if reject_oversized && len > max_payload {
+            if !is_response_to(&buffer, msg_id, msg.bytes()) {
+                warn!("oversized response from {src} doesn't match the query, dropped");
+                continue;
+            }
+
+            warn!("response from {src} exceeds the advertised payload size of {max_payload} bytes");
+            return Err(ProtoErrorKind::OversizedUdpResponse {
+                advertised: max_payload,
+                received: len,
+            }
+            .into());
+        }
+
         match Message::from_vec(&buffer) {
             Ok(message) => {
                 // Validate the message id in the response matches the value chosen for the query.
@@ -341,6 +392,16 @@ async fn send_serial_message_inner<S: DnsUdpSocket + Send>(
                     continue;
                 }
 
+                // A response with another client cookie is spoofed, see RFC 7873 section 5.3
+                if !same_client_cookie(&request_message, &message) {
+                    warn!("client cookie mismatch in response from {src}, dropped");
+                    continue;
+                }
+
+                if len > max_payload {
+                    warn!("response of {len} bytes from {src} exceeds the advertised payload size of {max_payload} bytes");
+                }
+
                 debug!("received message id: {}", message.id());
                 if let Some(mut verifier) = verifier {
                     return verifier(&buffer);
@@ -361,12 +422,61 @@ async fn send_serial_message_inner<S: DnsUdpSocket + Send>(
     }
 }
 
+/// Length of the client cookie, see RFC 7873 section 4
+const CLIENT_COOKIE_LEN: usize = 8;
+
+/// True if the header and the question section of the possibly cut `response` match the request
+fn is_response_to(response: &[u8], msg_id: u16, request: &[u8]) -> bool {
+    let mut decoder = BinDecoder::new(response);
+    let Ok(header) = Header::read(&mut decoder) else {
+        return false;
+    };
+    let Ok(request) = Message::from_vec(request) else {
+        return false;
+    };
+
+    header.id() == msg_id
+        && (0..header.query_count()).all(|_| {
+            Query::read(&mut decoder).is_ok_and(|query| request.queries().contains(&query))
+        })
+}
+
+/// False if the response doesn't echo the client cookie sent with the request
+///
+/// Responses without a COOKIE option, e.g. from name servers which don't support cookies, are
+///  accepted, unless the request carries a server cookie: the name server then supports cookies,
+///  and a response without one is spoofed.
+fn same_client_cookie(request: &Message, response: &Message) -> bool {
+    fn cookie(message: &Message) -> Option<&[u8]> {
+        match message.extensions().as_ref()?.option(EdnsCode::Cookie)? {
+            EdnsOption::Unknown(_, data) => Some(data),
+            _ => None,
+        }
+    }
+
+    match (cookie(request), cookie(response)) {
+        (Some(sent), Some(received)) => {
+            sent.len() >= CLIENT_COOKIE_LEN
+                && received.get(..CLIENT_COOKIE_LEN) == Some(&sent[..CLIENT_COOKIE_LEN])
+        }
+        (Some(sent), None) => sent.len() <= CLIENT_COOKIE_LEN,
+        _ => true,
+    }
+}
+
 #[cfg(test)]
 #[cfg(feature = "tokio-runtime")]
 mod tests {
     #![allow(clippy::dbg_macro, clippy::print_stdout)]
+    use super::UdpClientStream;
+    use crate::op::{Edns, Message, Query};
+    use crate::rr::rdata::opt::{EdnsCode, EdnsOption};
+    use crate::rr::{rdata::A, Name, RData, Record, RecordType};
+    use crate::xfer::{DnsRequest, DnsRequestOptions, DnsRequestSender, FirstAnswer};
     use crate::{runtime::TokioRuntimeProvider, tests::udp_client_stream_test};
-    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+    use std::str::FromStr;
+    use std::time::Duration;
     use test_support::subscribe;
     use tokio::runtime::Runtime;
 
@@ -389,4 +499,180 @@ mod tests {
             provider,
         )
     }
+
+    #[test]
+    fn test_udp_client_stream_oversized_response() {
+        subscribe();
+        let io_loop = Runtime::new().expect("failed to create tokio runtime");
+
+        let server = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)).unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let server_addr = server.local_addr().unwrap();
+
+        // answers each query with 100 A records, about 1600 bytes
+        let server_handle = std::thread::spawn(move || {
+            let mut buffer = [0_u8; 512];
+            for _ in 0..2 {
+                let (len, addr) = server.recv_from(&mut buffer).expect("receive failed");
+                let request = Message::from_vec(&buffer[0..len]).expect("failed parse of request");
+
+                let mut message = Message::new();
+                message.set_id(request.id());
+                message.add_queries(request.queries().to_vec());
+                for i in 0..100 {
+                    message.add_answer(Record::from_rdata(
+                        request.queries()[0].name().clone(),
+                        300,
+                        RData::A(A::new(127, 0, 0, i)),
+                    ));
+                }
+
+                let bytes = message.to_vec().unwrap();
+                assert!(bytes.len() > 1232);
+                server.send_to(&bytes, addr).expect("send failed");
+            }
+        });
+
+        let mut query = Message::new();
+        query.add_query(Query::query(
+            Name::from_str("large.example.com.").unwrap(),
+            RecordType::A,
+        ));
+        let mut edns = Edns::new();
+        edns.set_max_payload(1232);
+        query.set_edns(edns);
+
+        let provider = TokioRuntimeProvider::new();
+
+        // accepted by default
+        let stream = UdpClientStream::builder(server_addr, provider.clone())
+            .with_timeout(Some(Duration::from_secs(5)))
+            .build();
+        let mut stream = io_loop.block_on(stream).unwrap();
+        let response = io_loop
+            .block_on(
+                stream
+                    .send_message(DnsRequest::new(query.clone(), DnsRequestOptions::default()))
+                    .first_answer(),
+            )
+            .expect("oversized response should be accepted");
+        assert_eq!(response.answers().len(), 100);
+
+        // rejected when configured
+        let stream = UdpClientStream::builder(server_addr, provider)
+            .with_timeout(Some(Duration::from_secs(5)))
+            .reject_oversized_responses(true)
+            .build();
+        let mut stream = io_loop.block_on(stream).unwrap();
+        let error = io_loop
+            .block_on(
+                stream
+                    .send_message(DnsRequest::new(query, DnsRequestOptions::default()))
+                    .first_answer(),
+            )
+            .expect_err("oversized response should be rejected");
+        assert!(error.is_oversized_udp_response(), "{error:?}");
+
+        server_handle.join().expect("server thread failed");
+    }
+
+    #[test]
+    fn test_udp_client_stream_cookie_mismatch() {
+        cookie_spoofing_test(vec![1, 2, 3, 4, 5, 6, 7, 8], Some(vec![0; 16]));
+    }
+
+    #[test]
+    fn test_udp_client_stream_cookie_omitted_with_server_cookie() {
+        // the server cookie is known, a response without a COOKIE option is spoofed
+        let mut cookie = vec![1, 2, 3, 4, 5, 6, 7, 8];
+        cookie.extend_from_slice(&[0xab; 8]);
+        cookie_spoofing_test(cookie, None);
+    }
+
+    /// Answers the query with the `spoofed` COOKIE option first, as an off-path attacker would,
+    ///  then with the client cookie of the query, which must be the response received
+    fn cookie_spoofing_test(query_cookie: Vec<u8>, spoofed: Option<Vec<u8>>) {
+        subscribe();
+        let io_loop = Runtime::new().expect("failed to create tokio runtime");
+
+        let server = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)).unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let server_addr = server.local_addr().unwrap();
+
+        let server_handle = std::thread::spawn(move || {
+            let mut buffer = [0_u8; 512];
+            let (len, addr) = server.recv_from(&mut buffer).expect("receive failed");
+            let request = Message::from_vec(&buffer[0..len]).expect("failed parse of request");
+
+            let response = |cookie: Option<Vec<u8>>, ip: u8| {
+                let mut message = Message::new();
+                message.set_id(request.id());
+                message.add_queries(request.queries().to_vec());
+                message.add_answer(Record::from_rdata(
+                    request.queries()[0].name().clone(),
+                    300,
+                    RData::A(A::new(127, 0, 0, ip)),
+                ));
+                let mut edns = Edns::new();
+                if let Some(cookie) = cookie {
+                    edns.options_mut()
+                        .insert(EdnsOption::Unknown(EdnsCode::Cookie.into(), cookie));
+                }
+                message.set_edns(edns);
+                message.to_vec().unwrap()
+            };
+
+            let mut client_cookie = match request
+                .extensions()
+                .as_ref()
+                .unwrap()
+                .option(EdnsCode::Cookie)
+            {
+                Some(EdnsOption::Unknown(_, data)) => data.clone(),
+                option => panic!("unexpected cookie option: {option:?}"),
+            };
+            server
+                .send_to(&response(spoofed, 1), addr)
+                .expect("send failed");
+
+            client_cookie.truncate(8);
+            client_cookie.extend_from_slice(&[0xab; 8]);
+            server
+                .send_to(&response(Some(client_cookie), 2), addr)
+                .expect("send failed");
+        });
+
+        let mut query = Message::new();
+        query.add_query(Query::query(
+            Name::from_str("www.example.com.").unwrap(),
+            RecordType::A,
+        ));
+        let mut edns = Edns::new();
+        edns.options_mut()
+            .insert(EdnsOption::Unknown(EdnsCode::Cookie.into(), query_cookie));
+        query.set_edns(edns);
+
+        let provider = TokioRuntimeProvider::new();
+        let stream = UdpClientStream::builder(server_addr, provider)
+            .with_timeout(Some(Duration::from_secs(5)))
+            .build();
+        let mut stream = io_loop.block_on(stream).unwrap();
+        let response = io_loop
+            .block_on(
+                stream
+                    .send_message(DnsRequest::new(query, DnsRequestOptions::default()))
+                    .first_answer(),
+            )
+            .expect("the response with the client cookie should be received");
+        assert_eq!(
+            response.answers()[0].data(),
+            &RData::A(A::new(127, 0, 0, 2))
+        );
+
+        server_handle.join().expect("server thread failed");
+    }
 }
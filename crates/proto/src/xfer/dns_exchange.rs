@@ -291,7 +291,8 @@ where
     },
     Connected {
         exchange: DnsExchange,
-        background: Option<DnsExchangeBackground<S, TE>>,
+        // boxed, the background holds the next request to send along with its options
+        background: Option<Box<DnsExchangeBackground<S, TE>>>,
     },
     FailAll {
         error: ProtoError,
@@ -333,7 +334,7 @@ where
 
                             next = Self::Connected {
                                 exchange,
-                                background: Some(background),
+                                background: Some(Box::new(background)),
                             };
                         }
                         Poll::Pending => return Poll::Pending,
@@ -355,7 +356,7 @@ where
                     let exchange = exchange.clone();
                     let background = background.take().expect("cannot poll after complete");
 
-                    return Poll::Ready(Ok((exchange, background)));
+                    return Poll::Ready(Ok((exchange, *background)));
                 }
                 Self::FailAll {
                     error,
@@ -7,12 +7,15 @@
 
 //! `DnsHandle` types perform conversions of the raw DNS messages before sending the messages on the specified streams.
 
+use std::sync::Arc;
+
 use futures_util::stream::Stream;
 use rand;
 use tracing::debug;
 
 use crate::op::{Message, MessageType, OpCode, Query};
-use crate::xfer::{DnsRequest, DnsRequestOptions, DnsResponse, SerialMessage};
+use crate::rr::rdata::opt::EdnsOption;
+use crate::xfer::{DnsRequest, DnsRequestOptions, DnsResponse, RequestTimer, SerialMessage};
 use crate::{error::*, op::Edns};
 
 // TODO: this should be configurable
@@ -64,6 +67,21 @@ pub trait DnsHandle: 'static + Clone + Send + Sync + Unpin {
         debug!("querying: {} {:?}", query.name(), query.query_type());
         self.send(DnsRequest::new(build_message(query, options), options))
     }
+
+    /// A *classic* DNS query, recording the time spent transferring it with the `timer`
+    ///
+    /// See [`Self::lookup`] and [`RequestTimer`].
+    fn lookup_with_timer(
+        &self,
+        query: Query,
+        options: DnsRequestOptions,
+        timer: Arc<dyn RequestTimer>,
+    ) -> Self::Response {
+        debug!("querying: {} {:?}", query.name(), query.query_type());
+        let mut request = DnsRequest::new(build_message(query, options), options);
+        request.set_timer(Some(timer));
+        self.send(request)
+    }
 }
 
 fn build_message(query: Query, options: DnsRequestOptions) -> Message {
@@ -77,16 +95,19 @@ fn build_message(query: Query, options: DnsRequestOptions) -> Message {
         .set_id(id)
         .set_message_type(MessageType::Query)
         .set_op_code(OpCode::Query)
-        .set_recursion_desired(options.recursion_desired);
+        .set_recursion_desired(options.recursion_desired)
+        .set_checking_disabled(options.checking_disabled);
 
-    // Extended dns
-    if options.use_edns {
-        message
-            .extensions_mut()
-            .get_or_insert_with(Edns::new)
-            .set_max_payload(MAX_PAYLOAD_LEN)
+    // Extended dns, the client subnet is sent in an EDNS option
+    if options.use_edns || options.client_subnet.is_some() {
+        let edns = message.extensions_mut().get_or_insert_with(Edns::new);
+        edns.set_max_payload(MAX_PAYLOAD_LEN)
             .set_version(0)
             .set_dnssec_ok(options.edns_set_dnssec_ok);
+
+        if let Some(subnet) = options.client_subnet {
+            edns.options_mut().insert(EdnsOption::Subnet(subnet));
+        }
     }
     message
 }
@@ -33,7 +33,7 @@ use tracing::debug;
 
 use crate::{
     error::{ProtoError, ProtoErrorKind},
-    op::{MessageFinalizer, MessageVerifier},
+    op::{Message, MessageFinalizer, MessageVerifier, Query},
     runtime::Time,
     xfer::{
         ignore_send, BufDnsStreamHandle, DnsClientStream, DnsRequest, DnsRequestSender,
@@ -48,6 +48,8 @@ struct ActiveRequest {
     // the completion is the channel for a response to the original request
     completion: mpsc::Sender<Result<DnsResponse, ProtoError>>,
     request_id: u16,
+    // the queries of the request, responses must echo them back
+    queries: Vec<Query>,
     timeout: Box<dyn Future<Output = ()> + Send + Unpin>,
     verifier: Option<MessageVerifier>,
 }
@@ -56,12 +58,14 @@ impl ActiveRequest {
     fn new(
         completion: mpsc::Sender<Result<DnsResponse, ProtoError>>,
         request_id: u16,
+        queries: Vec<Query>,
         timeout: Box<dyn Future<Output = ()> + Send + Unpin>,
         verifier: Option<MessageVerifier>,
     ) -> Self {
         Self {
             completion,
             request_id,
+            queries,
             // request,
             timeout,
             verifier,
@@ -83,6 +87,14 @@ impl ActiveRequest {
         self.request_id
     }
 
+    /// Returns true if the question section of the response matches the one of the request
+    ///
+    /// Responses without a question section are accepted, as is allowed for the subsequent
+    /// messages of a zone transfer.
+    fn matches(&self, response: &Message) -> bool {
+        response.queries().is_empty() || response.queries() == self.queries.as_slice()
+    }
+
     /// Sends an error
     fn complete_with_error(mut self, error: ProtoError) {
         ignore_send(self.completion.try_send(Err(error)));
@@ -308,8 +320,13 @@ where
         let (complete, receiver) = mpsc::channel(CHANNEL_BUFFER_SIZE);
 
         // send the message
-        let active_request =
-            ActiveRequest::new(complete, request.id(), Box::new(timeout), verifier);
+        let active_request = ActiveRequest::new(
+            complete,
+            request.id(),
+            request.queries().to_vec(),
+            Box::new(timeout),
+            verifier,
+        );
 
         match request.to_vec() {
             Ok(buffer) => {
@@ -382,6 +399,15 @@ where
                     //   deserialize or log decode_error
                     match buffer.to_message() {
                         Ok(message) => match self.active_requests.entry(message.id()) {
+                            Entry::Occupied(request_entry)
+                                if !request_entry.get().matches(&message) =>
+                            {
+                                // same id but a different question, this is not our response
+                                debug!(
+                                    id = %message.id(),
+                                    "response question does not match the request, ignoring"
+                                );
+                            }
                             Entry::Occupied(mut request_entry) => {
                                 // send the response, complete the request...
                                 let active_request = request_entry.get_mut();
@@ -510,6 +536,101 @@ mod test {
         }
     }
 
+    /// Waits for two requests, then answers each of them first with the question and answer of
+    /// the other request, and only then with the correct response
+    struct CrossedClientStream {
+        addr: SocketAddr,
+        requests: Vec<Message>,
+        responses: Option<Vec<Message>>,
+        receiver: Option<StreamReceiver>,
+    }
+
+    impl CrossedClientStream {
+        fn new(addr: SocketAddr) -> Pin<Box<dyn Future<Output = Result<Self, ProtoError>> + Send>> {
+            Box::pin(future::ok(Self {
+                addr,
+                requests: Vec::new(),
+                responses: None,
+                receiver: None,
+            }))
+        }
+
+        fn response(id: u16, request: &Message) -> Message {
+            let query = request.queries()[0].clone();
+            let rdata = match query.query_type() {
+                RecordType::A => RData::A(Ipv4Addr::new(93, 184, 215, 14).into()),
+                _ => RData::AAAA(Ipv6Addr::new(0x2606, 0x2800, 0x21f, 0, 0, 0, 0, 1).into()),
+            };
+            let mut msg = request.clone();
+            msg.set_id(id)
+                .set_message_type(MessageType::Response)
+                .add_answer(Record::from_rdata(query.name().clone(), 86400, rdata));
+            msg
+        }
+    }
+
+    impl fmt::Display for CrossedClientStream {
+        fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+            write!(formatter, "CrossedClientStream")
+        }
+    }
+
+    impl Stream for CrossedClientStream {
+        type Item = Result<SerialMessage, ProtoError>;
+
+        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            while self.requests.len() < 2 {
+                let serial = ready!(self
+                    .receiver
+                    .as_mut()
+                    .expect("should only be polled after receiver has been set")
+                    .poll_next_unpin(cx));
+                let message = serial.unwrap().to_message().unwrap();
+                self.requests.push(message);
+            }
+
+            if self.responses.is_none() {
+                let (first, second) = (&self.requests[0], &self.requests[1]);
+                let mut responses = vec![
+                    Self::response(first.id(), second),
+                    Self::response(second.id(), first),
+                    Self::response(first.id(), first),
+                    Self::response(second.id(), second),
+                ];
+                responses.reverse();
+                self.responses = Some(responses);
+            }
+
+            match self.responses.as_mut().and_then(Vec::pop) {
+                Some(message) => Poll::Ready(Some(Ok(SerialMessage::new(
+                    message.to_bytes().unwrap(),
+                    self.addr,
+                )))),
+                None => Poll::Pending,
+            }
+        }
+    }
+
+    impl DnsClientStream for CrossedClientStream {
+        type Time = crate::runtime::TokioTime;
+
+        fn name_server_addr(&self) -> SocketAddr {
+            self.addr
+        }
+    }
+
+    fn query(record_type: RecordType) -> DnsRequest {
+        let mut msg = Message::new();
+        msg.add_query(Query::query(
+            Name::from_ascii("www.example.com.").unwrap(),
+            record_type,
+        ))
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Query)
+        .set_recursion_desired(true);
+        DnsRequest::new(msg, DnsRequestOptions::default())
+    }
+
     async fn get_mocked_multiplexer(
         mock_response: Vec<Message>,
     ) -> DnsMultiplexer<MockClientStream> {
@@ -707,4 +828,36 @@ mod test {
             axfr_response().len()
         );
     }
+
+    #[tokio::test]
+    async fn test_multiplexer_no_cross_delivery() {
+        let addr = SocketAddr::from(([127, 0, 0, 1], 1234));
+        let (handler, receiver) = BufDnsStreamHandle::new(addr);
+        let mut multiplexer = DnsMultiplexer::with_timeout(
+            CrossedClientStream::new(addr),
+            handler,
+            Duration::from_millis(100),
+            None,
+        )
+        .await
+        .unwrap();
+        multiplexer.stream.receiver = Some(receiver);
+
+        let a = multiplexer.send_message(query(RecordType::A));
+        let aaaa = multiplexer.send_message(query(RecordType::AAAA));
+        let (a, aaaa) = tokio::select! {
+            _ = multiplexer.next() => {
+                // polling multiplexer to make it run
+                panic!("should never end")
+            },
+            r = future::try_join(a.try_collect::<Vec<_>>(), aaaa.try_collect::<Vec<_>>()) => r.unwrap(),
+        };
+
+        assert_eq!(a.len(), 1);
+        assert_eq!(a[0].queries()[0].query_type(), RecordType::A);
+        assert_eq!(a[0].answers()[0].record_type(), RecordType::A);
+        assert_eq!(aaaa.len(), 1);
+        assert_eq!(aaaa[0].queries()[0].query_type(), RecordType::AAAA);
+        assert_eq!(aaaa[0].answers()[0].record_type(), RecordType::AAAA);
+    }
 }
@@ -8,8 +8,11 @@
 //! `DnsRequest` wraps a `Message` and associates a set of `DnsRequestOptions` for specifying different transfer options.
 
 use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use crate::op::Message;
+use crate::rr::rdata::opt::ClientSubnet;
 
 /// A set of options for expressing options to how requests should be treated
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -31,6 +34,24 @@ pub struct DnsRequestOptions {
     pub max_request_depth: usize,
     /// set recursion desired (or not) for any requests
     pub recursion_desired: bool,
+    /// When true, sets the CD (checking disabled) bit and skips local DNSSEC validation failures
+    ///
+    /// Records are still returned annotated with their DNSSEC `Proof`, so a bogus
+    ///  answer will be returned with `Proof::Bogus` rather than failing the request.
+    pub checking_disabled: bool,
+    /// The instant by which the request must complete, or fail with a timeout
+    ///
+    /// This also bounds the queries issued to validate the response with DNSSEC, e.g. the DS and
+    ///  DNSKEY records of the chain of trust, which are sent with the same options.
+    pub deadline: Option<Instant>,
+    /// A minimum TTL for which the records of the response are cached, by resolvers which cache
+    pub cache_min_ttl: Option<Duration>,
+    /// When true, resolvers which cache neither read the response from nor store it in their cache
+    pub bypass_cache: bool,
+    /// An edns-client-subnet option attached to the query, see RFC 7871
+    ///
+    /// Resolvers which cache store the response in a cache of this subnet only.
+    pub client_subnet: Option<ClientSubnet>,
 }
 
 impl Default for DnsRequestOptions {
@@ -42,23 +63,45 @@ impl Default for DnsRequestOptions {
             use_edns: false,
             edns_set_dnssec_ok: false,
             recursion_desired: true,
+            checking_disabled: false,
+            deadline: None,
+            cache_min_ttl: None,
+            bypass_cache: false,
+            client_subnet: None,
         }
     }
 }
 
+/// Records the time a request spends being transferred, e.g. to break down the duration of a lookup
+///
+/// The timer is carried by the request through the handles sending it, and by the requests these
+///  handles send on its behalf, e.g. the queries for the keys validating the response with DNSSEC.
+pub trait RequestTimer: Send + Sync {
+    /// Records the time spent establishing a connection to send the request
+    fn record_connect(&self, elapsed: Duration);
+
+    /// Records the time spent awaiting the response to the request
+    fn record_response(&self, elapsed: Duration);
+}
+
 /// A DNS request object
 ///
 /// This wraps a DNS Message for requests. It also has request options associated for controlling certain features of the DNS protocol handlers.
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone)]
 pub struct DnsRequest {
     message: Message,
     options: DnsRequestOptions,
+    timer: Option<Arc<dyn RequestTimer>>,
 }
 
 impl DnsRequest {
     /// Returns a new DnsRequest object
     pub fn new(message: Message, options: DnsRequestOptions) -> Self {
-        Self { message, options }
+        Self {
+            message,
+            options,
+            timer: None,
+        }
     }
 
     /// Get the set of request options associated with this request
@@ -66,12 +109,31 @@ impl DnsRequest {
         &self.options
     }
 
+    /// The timer recording the time spent transferring this request, if it is timed
+    pub fn timer(&self) -> Option<&Arc<dyn RequestTimer>> {
+        self.timer.as_ref()
+    }
+
+    /// Sets the timer recording the time spent transferring this request, see [`RequestTimer`]
+    pub fn set_timer(&mut self, timer: Option<Arc<dyn RequestTimer>>) {
+        self.timer = timer;
+    }
+
     /// Unwraps the raw message
     pub fn into_parts(self) -> (Message, DnsRequestOptions) {
         (self.message, self.options)
     }
 }
 
+// the timer only observes the request, it isn't part of it
+impl PartialEq for DnsRequest {
+    fn eq(&self, other: &Self) -> bool {
+        self.message == other.message && self.options == other.options
+    }
+}
+
+impl Eq for DnsRequest {}
+
 impl Deref for DnsRequest {
     type Target = Message;
     fn deref(&self) -> &Self::Target {
@@ -22,7 +22,14 @@ use futures_util::{ready, stream::Stream};
 use crate::{
     error::{ProtoError, ProtoErrorKind, ProtoResult},
     op::{Message, ResponseCode},
-    rr::{rdata::SOA, resource::RecordRef, RecordType},
+    rr::{
+        rdata::{
+            opt::{EdnsCode, EdnsOption},
+            SOA,
+        },
+        resource::RecordRef,
+        RecordType,
+    },
 };
 
 /// A stream returning DNS responses
@@ -292,6 +299,17 @@ impl DnsResponse {
         }
     }
 
+    /// All EDNS options received in the OPT record of the response, in the order they were received
+    ///
+    /// This includes options which have no typed accessor, e.g. cookies, NSID or padding, which
+    ///  are returned as [`EdnsOption::Unknown`]. Empty if the response did not contain an OPT record.
+    pub fn edns_options(&self) -> &[(EdnsCode, EdnsOption)] {
+        self.extensions()
+            .as_ref()
+            .map(|edns| edns.options().as_ref())
+            .unwrap_or_default()
+    }
+
     /// Borrow the inner buffer from the response
     pub fn as_buffer(&self) -> &[u8] {
         &self.buffer
@@ -644,7 +662,10 @@ impl NegativeType {
 
 #[cfg(test)]
 mod tests {
-    use crate::op::{Message, Query, ResponseCode};
+    use std::net::Ipv4Addr;
+
+    use crate::op::{Edns, Message, Query, ResponseCode};
+    use crate::rr::rdata::opt::ClientSubnet;
     use crate::rr::rdata::{A, CNAME, NS, SOA};
     use crate::rr::RData;
     use crate::rr::{Name, Record, RecordType};
@@ -719,6 +740,40 @@ mod tests {
         Query::query(another_example(), RecordType::A)
     }
 
+    #[test]
+    fn test_edns_options() {
+        let mut message = Message::default();
+        message.add_query(an_query());
+
+        let mut edns = Edns::new();
+        let options = vec![
+            EdnsOption::Unknown(10, vec![0x0b, 0x64, 0xb4, 0xdc, 0xd7, 0xb0, 0xcc, 0x8f]),
+            EdnsOption::Unknown(3, b"ns1".to_vec()),
+            EdnsOption::Subnet(ClientSubnet::new(Ipv4Addr::new(192, 0, 2, 0).into(), 24, 0)),
+            EdnsOption::Unknown(12, vec![0; 8]),
+        ];
+        for option in options.iter().cloned() {
+            edns.options_mut().insert(option);
+        }
+        message.set_edns(edns);
+
+        // round trip through the wire format
+        let buffer = message.to_vec().unwrap();
+        let response = DnsResponse::new(Message::from_vec(&buffer).unwrap(), buffer);
+
+        let received = response
+            .edns_options()
+            .iter()
+            .map(|(_, option)| option.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(received, options);
+        assert_eq!(response.edns_options()[0].0, EdnsCode::Cookie);
+        assert_eq!(response.edns_options()[1].0, EdnsCode::NSID);
+
+        let response = DnsResponse::from_message(Message::default()).unwrap();
+        assert!(response.edns_options().is_empty());
+    }
+
     #[test]
     fn test_contains_answer() {
         let mut message = Message::default();
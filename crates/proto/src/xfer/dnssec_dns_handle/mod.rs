@@ -12,14 +12,16 @@ use std::{
     collections::{HashMap, HashSet},
     pin::Pin,
     sync::Arc,
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use async_recursion::async_recursion;
 use futures_util::{
-    future::{self, TryFutureExt},
+    future::{self, FutureExt, TryFutureExt},
     stream::{self, Stream, TryStreamExt},
 };
+use lru_cache::LruCache;
+use parking_lot::Mutex;
 use tracing::{debug, trace, warn};
 
 use crate::{
@@ -32,7 +34,10 @@ use crate::{
         },
         Name, RData, Record, RecordData, RecordType, SerialNumber,
     },
-    xfer::{dns_handle::DnsHandle, DnsRequest, DnsRequestOptions, DnsResponse, FirstAnswer},
+    xfer::{
+        dns_handle::DnsHandle, DnsRequest, DnsRequestOptions, DnsResponse, FirstAnswer,
+        RequestTimer,
+    },
 };
 
 #[cfg(feature = "dnssec")]
@@ -58,9 +63,13 @@ where
 {
     handle: H,
     trust_anchor: Arc<TrustAnchor>,
+    ds_cache: DsCache,
     request_depth: usize,
     minimum_key_len: usize,
     minimum_algorithm: Algorithm, // used to prevent down grade attacks...
+    verification_offload: Option<usize>,
+    /// The timer of the request validated by this handle, carried by the queries validating it
+    timer: Option<Arc<dyn RequestTimer>>,
 }
 
 impl<H> DnssecDnsHandle<H>
@@ -88,12 +97,35 @@ where
         Self {
             handle,
             trust_anchor,
+            ds_cache: DsCache::new(DEFAULT_DS_CACHE_SIZE, DEFAULT_DS_CACHE_MAX_TTL),
             request_depth: 0,
             minimum_key_len: 0,
             minimum_algorithm: Algorithm::RSASHA256,
+            verification_offload: None,
+            timer: None,
         }
     }
 
+    /// Offloads the signature verification of RRsets with at least `min_rrsigs` RRSIGs to the
+    ///  blocking thread pool, keeping the executor responsive under validation load.
+    ///
+    /// Signatures are always verified inline without the `tokio-runtime` feature, or when not
+    ///  running in a tokio runtime.
+    pub fn with_verification_offload(mut self, min_rrsigs: Option<usize>) -> Self {
+        self.verification_offload = min_rrsigs;
+        self
+    }
+
+    /// Bounds the cache of the DS records validated at the zone cuts to `size` zones, each cached
+    ///  for no longer than `max_ttl` whatever the TTL of its records
+    ///
+    /// Defaults to 1024 zones cached for at most a day. The cache is shared by all the clones of
+    ///  this handle, and replaced by a new one.
+    pub fn with_ds_cache(mut self, size: usize, max_ttl: Duration) -> Self {
+        self.ds_cache = DsCache::new(size, max_ttl);
+        self
+    }
+
     /// An internal function used to clone the handle, but maintain some information back to the
     ///  original handle, such as the request_depth such that infinite recursion does
     ///  not occur.
@@ -101,10 +133,79 @@ where
         Self {
             handle: self.handle.clone(),
             trust_anchor: Arc::clone(&self.trust_anchor),
+            ds_cache: self.ds_cache.clone(),
             request_depth: self.request_depth + 1,
             minimum_key_len: self.minimum_key_len,
             minimum_algorithm: self.minimum_algorithm,
+            verification_offload: self.verification_offload,
+            timer: self.timer.clone(),
+        }
+    }
+
+    /// Returns the validated DS records of the zone cuts above and including `name`
+    ///
+    /// DS records are fetched and cached at each zone cut while building the chain of trust from
+    ///  the trust anchor. The records are ordered from the zone closest to the trust anchor down to
+    ///  `name`, zones without cached DS records, e.g. those of the trust anchor, are skipped.
+    pub fn ds_chain(&self, name: &Name) -> Vec<Record<DS>> {
+        let now = Instant::now();
+        let mut zone = name.clone();
+        let mut zones = vec![zone.clone()];
+        while zone.num_labels() > 0 {
+            zone = zone.base_name();
+            zones.push(zone.clone());
+        }
+
+        zones
+            .iter()
+            .rev()
+            .filter_map(|zone| self.ds_cache.get(zone, now))
+            .flatten()
+            .collect()
+    }
+}
+
+/// Default number of zones of the DS cache, see [`DnssecDnsHandle::with_ds_cache`]
+const DEFAULT_DS_CACHE_SIZE: usize = 1024;
+
+/// Default maximum duration for which DS records are cached, see [`DnssecDnsHandle::with_ds_cache`]
+const DEFAULT_DS_CACHE_MAX_TTL: Duration = Duration::from_secs(86400);
+
+/// Secure DS records found at zone cuts, shared by all clones of a `DnssecDnsHandle`
+#[derive(Clone)]
+struct DsCache {
+    records: Arc<Mutex<DsRecords>>,
+    /// Maximum duration for which the records of a zone are cached, whatever their TTL
+    max_ttl: Duration,
+}
+
+/// The DS records of each zone, with the instant until which they are valid
+type DsRecords = LruCache<Name, (Vec<Record<DS>>, Instant)>;
+
+impl DsCache {
+    fn new(size: usize, max_ttl: Duration) -> Self {
+        Self {
+            records: Arc::new(Mutex::new(LruCache::new(size))),
+            max_ttl,
+        }
+    }
+
+    fn get(&self, zone: &Name, now: Instant) -> Option<Vec<Record<DS>>> {
+        let mut cache = self.records.lock();
+        let (records, valid_until) = cache.get_mut(zone)?;
+        if now <= *valid_until {
+            return Some(records.clone());
         }
+
+        cache.remove(zone);
+        None
+    }
+
+    fn insert(&self, zone: Name, records: Vec<Record<DS>>, now: Instant) {
+        let ttl = records.iter().map(Record::ttl).min().unwrap_or_default();
+        let ttl = Duration::from_secs(u64::from(ttl)).min(self.max_ttl);
+
+        self.records.lock().insert(zone, (records, now + ttl));
     }
 }
 
@@ -121,6 +222,9 @@ where
 
     fn send<R: Into<DnsRequest>>(&self, request: R) -> Self::Response {
         let mut request = request.into();
+        if request.timer().is_none() {
+            request.set_timer(self.timer.clone());
+        }
 
         // backstop
         if self.request_depth > request.options().max_request_depth {
@@ -129,6 +233,13 @@ where
             ))));
         }
 
+        if is_past(request.options().deadline) {
+            debug!("deadline exceeded, not sending request");
+            return Box::pin(stream::once(future::err(ProtoError::from(
+                ProtoErrorKind::Timeout,
+            ))));
+        }
+
         // dnssec only matters on queries.
         match request.op_code() {
             OpCode::Query => {}
@@ -145,7 +256,8 @@ where
             ))));
         };
 
-        let handle: Self = self.clone_with_context();
+        let mut handle: Self = self.clone_with_context();
+        handle.timer = request.timer().cloned();
 
         // TODO: cache response of the server about understood algorithms
         #[cfg(feature = "dnssec")]
@@ -156,9 +268,11 @@ where
                 .enable_dnssec();
         }
 
-        request.set_authentic_data(true);
-        request.set_checking_disabled(false);
+        // when checking is disabled for this request, ask upstream for the raw records, they are
+        //  still annotated with their proofs below but a failed validation is not an error.
         let options = *request.options();
+        request.set_authentic_data(true);
+        request.set_checking_disabled(options.checking_disabled);
 
         Box::pin(
             self.handle
@@ -201,12 +315,30 @@ where
                     verify_response(handle.clone(), message_response, options)
                 })
                 .and_then(move |verified_message| {
+                    // the chain of trust is incomplete if its queries were cut by the deadline
+                    if is_past(options.deadline) {
+                        debug!("deadline exceeded while validating response");
+                        return future::err(ProtoError::from(ProtoErrorKind::Timeout));
+                    }
+
+                    future::ok(verified_message)
+                })
+                .and_then(move |verified_message| {
+                    if options.checking_disabled {
+                        return future::ok(verified_message);
+                    }
+
                     future::ready(check_nsec(verified_message, &query))
                 }),
         )
     }
 }
 
+/// True if the deadline of a request has passed
+fn is_past(deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|deadline| Instant::now() >= deadline)
+}
+
 /// TODO: I've noticed upstream resolvers don't always return NSEC responses
 ///   this causes bottom up evaluation to fail
 ///
@@ -603,6 +735,10 @@ async fn find_ds_records<H>(
 where
     H: DnsHandle + Sync + Unpin,
 {
+    if let Some(ds_records) = handle.ds_cache.get(&zone, Instant::now()) {
+        return Ok(ds_records);
+    }
+
     // need to get DS records for each DNSKEY
     //   there will be a DS record for everything under the root keys
     let ds_message = handle
@@ -643,6 +779,9 @@ where
                     ProofErrorKind::UnknownKeyAlgorithm,
                 ));
             } else if !supported_records.is_empty() {
+                handle
+                    .ds_cache
+                    .insert(zone, supported_records.clone(), Instant::now());
                 return Ok(supported_records);
             } else {
                 ProtoError::from(ProtoErrorKind::NoError)
@@ -743,6 +882,9 @@ where
 
     // use the same current time value for all rrsig + rrset pairs.
     let current_time = current_time();
+    let offload = handle
+        .verification_offload
+        .is_some_and(|min_rrsigs| rrsigs.len() >= min_rrsigs);
 
     // Special case for self-signed DNSKEYS, validate with itself...
     if rrsigs.iter().any(|rrsig| {
@@ -752,28 +894,33 @@ where
         //  then return rrset. Like the standard case below, the DNSKEY is validated
         //  after this function. This function is only responsible for validating the signature
         //  the DNSKey validation should come after, see verify_rrset().
-        let (proof, adjusted_ttl) = rrsigs
+        let dnskeys = rrset
+            .records()
             .iter()
-            .find_map(|rrsig| {
-                rrset
-                    .records()
-                    .iter()
-                    .filter_map(|r| r.try_borrow::<DNSKEY>())
-                    // DNSKEY must be signed using a KSK
-                    .filter(|r| r.data().is_key_signing_key())
-                    .find_map(|dnskey| {
-                        // If we had rrsigs to verify, then we want them to be secure, or the result is a Bogus proof
-                        verify_rrset_with_dnskey(dnskey, *rrsig, &rrset, current_time).ok()
-                    })
-            })
-            .ok_or_else(|| {
-                ProofError::new(
-                    Proof::Bogus,
-                    ProofErrorKind::SelfSignedKeyInvalid {
-                        name: rrset.name().clone(),
-                    },
-                )
-            })?;
+            .filter_map(|r| r.try_borrow::<DNSKEY>())
+            // DNSKEY must be signed using a KSK
+            .filter(|r| r.data().is_key_signing_key())
+            .collect::<Vec<_>>();
+
+        // If we had rrsigs to verify, then we want them to be secure, or the result is a Bogus proof
+        let verifications = rrsigs.iter().map(|rrsig| {
+            Box::pin(
+                verify_rrsig_with_dnskeys(offload, dnskeys.clone(), *rrsig, &rrset, current_time)
+                    .map(|proof| {
+                        proof.ok_or_else(|| {
+                            ProofError::new(
+                                Proof::Bogus,
+                                ProofErrorKind::SelfSignedKeyInvalid {
+                                    name: rrset.name().clone(),
+                                },
+                            )
+                        })
+                    }),
+            )
+        });
+
+        let ((proof, adjusted_ttl), rest) = future::select_ok(verifications).await?;
+        drop(rest);
 
         // Getting here means the rrset (and records), have been verified
         return Ok((proof, adjusted_ttl));
@@ -788,51 +935,67 @@ where
     //         susceptible until that algorithm is removed as an option.
     //        dns over TLS will mitigate this.
     //  TODO: strip RRSIGS to accepted algorithms and make algorithms configurable.
+    let rrset = &rrset;
     let verifications = rrsigs
         .iter()
         .map(|rrsig| {
             let handle = handle.clone_with_context();
             let query = Query::query(rrsig.data().signer_name().clone(), RecordType::DNSKEY);
+            let rrsig = *rrsig;
 
             // TODO: Should this sig.signer_name should be confirmed to be in the same zone as the rrsigs and rrset?
-            handle
-                .lookup(query.clone(), options)
-                .first_answer()
-                .map_err(|proto| {
-                    ProofError::new(Proof::Indeterminate, ProofErrorKind::Proto { query, proto })
-                })
-                .map_ok(|message| {
-                    // DNSKEYs were already validated by the inner query in the above lookup
-                    let dnskeys = message
-                        .answers()
-                        .iter()
-                        .filter_map(|r| r.try_borrow::<DNSKEY>());
-
-                    let mut all_insecure = None;
-                    for dnskey in dnskeys {
-                        match dnskey.proof() {
-                            Proof::Secure => {
-                                all_insecure = Some(false);
-                                if let Ok(proof) =
-                                    verify_rrset_with_dnskey(dnskey, *rrsig, &rrset, current_time)
-                                {
-                                    return Some(proof);
+            Box::pin(
+                handle
+                    .lookup(query.clone(), options)
+                    .first_answer()
+                    .map_err(|proto| {
+                        ProofError::new(
+                            Proof::Indeterminate,
+                            ProofErrorKind::Proto { query, proto },
+                        )
+                    })
+                    .and_then(move |message| async move {
+                        // DNSKEYs were already validated by the inner query in the above lookup
+                        let dnskeys = message
+                            .answers()
+                            .iter()
+                            .filter_map(|r| r.try_borrow::<DNSKEY>());
+
+                        let mut all_insecure = None;
+                        let mut secure_dnskeys = Vec::new();
+                        for dnskey in dnskeys {
+                            match dnskey.proof() {
+                                Proof::Secure => {
+                                    all_insecure = Some(false);
+                                    secure_dnskeys.push(dnskey);
                                 }
+                                Proof::Insecure => {
+                                    all_insecure.get_or_insert(true);
+                                }
+                                _ => all_insecure = Some(false),
                             }
-                            Proof::Insecure => {
-                                all_insecure.get_or_insert(true);
-                            }
-                            _ => all_insecure = Some(false),
                         }
-                    }
 
-                    if all_insecure.unwrap_or(false) {
-                        // inherit Insecure state
-                        Some((Proof::Insecure, None))
-                    } else {
-                        None
-                    }
-                })
+                        let proof = verify_rrsig_with_dnskeys(
+                            offload,
+                            secure_dnskeys,
+                            rrsig,
+                            rrset,
+                            current_time,
+                        )
+                        .await;
+                        if proof.is_some() {
+                            return Ok(proof);
+                        }
+
+                        if all_insecure.unwrap_or(false) {
+                            // inherit Insecure state
+                            Ok(Some((Proof::Insecure, None)))
+                        } else {
+                            Ok(None)
+                        }
+                    }),
+            )
         })
         .collect::<Vec<_>>();
 
@@ -860,6 +1023,59 @@ where
     )
 }
 
+/// Verifies the RRSIG of the RRSET with the first DNSKEY which validates it
+///
+/// If `offload` is set and a tokio runtime is available, the signatures are verified on its
+///  blocking thread pool.
+async fn verify_rrsig_with_dnskeys(
+    offload: bool,
+    dnskeys: Vec<RecordRef<'_, DNSKEY>>,
+    rrsig: RecordRef<'_, RRSIG>,
+    rrset: &Rrset<'_>,
+    current_time: u32,
+) -> Option<(Proof, Option<u32>)> {
+    #[cfg(feature = "tokio-runtime")]
+    if offload {
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            // the blocking task requires owned records
+            let dnskeys = dnskeys
+                .iter()
+                .map(|dnskey| dnskey.to_owned().into_record_of_rdata())
+                .collect::<Vec<_>>();
+            let rrsig = rrsig.to_owned().into_record_of_rdata();
+            let records = rrset
+                .records()
+                .iter()
+                .map(|r| (*r).clone())
+                .collect::<Vec<_>>();
+
+            let verification = runtime.spawn_blocking(move || {
+                let rrsig = rrsig.try_borrow::<RRSIG>()?;
+                let mut rrset = Rrset::new(records.first()?);
+                for record in records.iter().skip(1) {
+                    rrset.add(record);
+                }
+
+                dnskeys
+                    .iter()
+                    .filter_map(|r| r.try_borrow::<DNSKEY>())
+                    .find_map(|dnskey| {
+                        verify_rrset_with_dnskey(dnskey, rrsig, &rrset, current_time).ok()
+                    })
+            });
+
+            return verification.await.ok().flatten();
+        }
+    }
+
+    #[cfg(not(feature = "tokio-runtime"))]
+    let _ = offload;
+
+    dnskeys
+        .into_iter()
+        .find_map(|dnskey| verify_rrset_with_dnskey(dnskey, rrsig, rrset, current_time).ok())
+}
+
 /// Verifies the given SIG of the RRSET with the DNSKEY.
 #[cfg(feature = "dnssec")]
 fn verify_rrset_with_dnskey(
@@ -1214,3 +1430,135 @@ mod rrset {
         }
     }
 }
+
+#[cfg(test)]
+#[cfg(all(feature = "dnssec-ring", feature = "tokio-runtime"))]
+mod tests {
+    use tokio::runtime::Runtime;
+
+    use super::*;
+    use crate::rr::dnssec::{DigestType, KeyFormat, TBS};
+    use crate::rr::rdata::A;
+
+    #[test]
+    fn test_offloaded_rrsig_verification() {
+        let runtime = Runtime::new().unwrap();
+        let name = Name::from_ascii("example.com.").unwrap();
+        let current_time = current_time();
+
+        let records = (1..=4)
+            .map(|i| Record::from_rdata(name.clone(), 3600, RData::A(A::new(192, 0, 2, i))))
+            .collect::<Vec<_>>();
+        let mut rrset = Rrset::new(&records[0]);
+        for record in &records[1..] {
+            rrset.add(record);
+        }
+
+        // a large RRSIG set, each signed by its own key
+        let (dnskeys, rrsigs): (Vec<Record>, Vec<Record>) = (0..16)
+            .map(|_| {
+                let key = KeyFormat::Pkcs8
+                    .decode_key(
+                        &KeyFormat::Pkcs8
+                            .generate_and_encode(Algorithm::ED25519, None)
+                            .unwrap(),
+                        None,
+                        Algorithm::ED25519,
+                    )
+                    .unwrap();
+                let dnskey = key.to_dnskey(Algorithm::ED25519).unwrap();
+                let rrsig = |sig| {
+                    Record::from_rdata(
+                        name.clone(),
+                        3600,
+                        RRSIG::new(
+                            RecordType::A,
+                            Algorithm::ED25519,
+                            name.num_labels(),
+                            3600,
+                            current_time + 3600,
+                            current_time - 3600,
+                            dnskey.calculate_key_tag().unwrap(),
+                            name.clone(),
+                            sig,
+                        ),
+                    )
+                };
+
+                let tbs = TBS::from_rrsig(&rrsig(vec![]), records.iter()).unwrap();
+                let sig = key.sign(Algorithm::ED25519, &tbs).unwrap();
+                let rrsig = rrsig(sig).into_record_of_rdata();
+                let dnskey = Record::from_rdata(
+                    name.clone(),
+                    3600,
+                    RData::DNSSEC(DNSSECRData::DNSKEY(dnskey)),
+                );
+
+                (dnskey, rrsig)
+            })
+            .unzip();
+
+        let dnskeys = dnskeys
+            .iter()
+            .filter_map(|r| r.try_borrow::<DNSKEY>())
+            .collect::<Vec<_>>();
+        let rrsigs = rrsigs
+            .iter()
+            .filter_map(|r| r.try_borrow::<RRSIG>())
+            .collect::<Vec<_>>();
+
+        for offload in [false, true] {
+            let verifications = rrsigs.iter().map(|rrsig| {
+                verify_rrsig_with_dnskeys(offload, dnskeys.clone(), *rrsig, &rrset, current_time)
+            });
+            let proofs = runtime.block_on(future::join_all(verifications));
+
+            assert_eq!(proofs.len(), 16);
+            for proof in proofs {
+                assert!(
+                    matches!(proof, Some((Proof::Secure, Some(3600)))),
+                    "offload: {offload}, proof: {proof:?}"
+                );
+            }
+        }
+
+        // a tampered RRset fails verification
+        let tampered = Record::from_rdata(name.clone(), 3600, RData::A(A::new(192, 0, 2, 5)));
+        rrset.add(&tampered);
+        let proof = runtime.block_on(verify_rrsig_with_dnskeys(
+            true,
+            dnskeys.clone(),
+            rrsigs[0],
+            &rrset,
+            current_time,
+        ));
+        assert!(proof.is_none());
+    }
+
+    #[test]
+    fn test_ds_cache() {
+        let ds = |zone: &Name, ttl| {
+            let ds = DS::new(1, Algorithm::ED25519, DigestType::SHA256, vec![0; 32]);
+            Record::from_rdata(zone.clone(), ttl, ds)
+        };
+        let com = Name::from_ascii("com.").unwrap();
+        let org = Name::from_ascii("org.").unwrap();
+        let net = Name::from_ascii("net.").unwrap();
+        let cache = DsCache::new(2, Duration::from_secs(60));
+        let now = Instant::now();
+
+        // the TTL of the records is clamped to the maximum TTL of the cache
+        cache.insert(com.clone(), vec![ds(&com, 86400)], now);
+        assert!(cache.get(&com, now + Duration::from_secs(60)).is_some());
+        assert!(cache.get(&com, now + Duration::from_secs(61)).is_none());
+
+        // the least recently used zone is evicted
+        cache.insert(com.clone(), vec![ds(&com, 30)], now);
+        cache.insert(org.clone(), vec![ds(&org, 30)], now);
+        assert!(cache.get(&com, now).is_some());
+        cache.insert(net.clone(), vec![ds(&net, 30)], now);
+        assert!(cache.get(&org, now).is_none());
+        assert!(cache.get(&com, now).is_some());
+        assert!(cache.get(&net, now).is_some());
+    }
+}
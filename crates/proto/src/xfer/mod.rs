@@ -36,7 +36,7 @@ pub use self::dns_exchange::{
 };
 pub use self::dns_handle::{DnsHandle, DnsStreamHandle};
 pub use self::dns_multiplexer::{DnsMultiplexer, DnsMultiplexerConnect};
-pub use self::dns_request::{DnsRequest, DnsRequestOptions};
+pub use self::dns_request::{DnsRequest, DnsRequestOptions, RequestTimer};
 pub use self::dns_response::{DnsResponse, DnsResponseStream};
 #[cfg(feature = "dnssec")]
 pub use self::dnssec_dns_handle::DnssecDnsHandle;
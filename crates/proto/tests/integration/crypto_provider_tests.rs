@@ -0,0 +1,30 @@
+#![cfg(feature = "dns-over-rustls")]
+
+use std::sync::Arc;
+
+use hickory_proto::rustls::{crypto_provider, set_crypto_provider};
+use rustls::crypto::{ring, CryptoProvider};
+
+// the provider is chosen once per process, no other test of this binary uses it
+#[test]
+fn test_set_crypto_provider() {
+    let provider = Arc::new(CryptoProvider {
+        cipher_suites: vec![ring::cipher_suite::TLS13_AES_128_GCM_SHA256],
+        ..ring::default_provider()
+    });
+    set_crypto_provider(provider.clone()).expect("the provider was already chosen");
+    assert!(Arc::ptr_eq(&crypto_provider(), &provider));
+
+    // the provider can't be replaced once chosen
+    let other = Arc::new(ring::default_provider());
+    let rejected = set_crypto_provider(other.clone()).expect_err("the provider was replaced");
+    assert!(Arc::ptr_eq(&rejected, &other));
+    assert!(Arc::ptr_eq(&crypto_provider(), &provider));
+
+    // the default client config of QUIC uses it
+    #[cfg(feature = "dns-over-quic")]
+    {
+        let client_config = hickory_proto::quic::client_config_tls13().unwrap();
+        assert!(Arc::ptr_eq(client_config.crypto_provider(), &provider));
+    }
+}
@@ -1,2 +1,3 @@
+mod crypto_provider_tests;
 mod dnssec_presentation_format_tests;
 mod openssl_tests;
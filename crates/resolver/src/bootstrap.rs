@@ -0,0 +1,191 @@
+//! Bootstrap resolution of encrypted name servers identified by hostname
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use crate::config::BootstrapConfig;
+#[cfg(any(feature = "dns-over-tls", feature = "dns-over-https-rustls"))]
+use crate::config::NameServerConfigGroup;
+use crate::error::ResolveError;
+use crate::name_server::ConnectionProvider;
+use crate::proto::rr::Name;
+use crate::Resolver;
+
+/// Resolves the addresses of encrypted name servers which are identified by hostname
+///
+/// Pinned addresses are returned without issuing any query. Otherwise the addresses are looked up
+///  with the resolver passed by the caller, e.g. one using plain DNS, and cached.
+#[derive(Clone, Debug, Default)]
+pub struct Bootstrap {
+    pinned_addrs: HashMap<Name, Vec<IpAddr>>,
+    cache_ttl: Option<Duration>,
+    cache: Arc<Mutex<CachedAddrs>>,
+}
+
+/// The resolved addresses of each name server hostname, with the instant until which they are valid
+type CachedAddrs = HashMap<Name, (Vec<IpAddr>, Instant)>;
+
+impl Bootstrap {
+    /// Creates a new bootstrap with the pinned addresses and cache TTL of the config
+    pub fn new(config: BootstrapConfig) -> Self {
+        let pinned_addrs = config
+            .pinned_addrs
+            .into_iter()
+            .map(|(host, addrs)| (fqdn(host), addrs))
+            .collect();
+
+        Self {
+            pinned_addrs,
+            cache_ttl: config.cache_ttl,
+            cache: Arc::default(),
+        }
+    }
+
+    /// Returns the addresses of the host
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - hostname of the encrypted name server
+    /// * `resolver` - resolver used to look up the host if it's neither pinned nor cached
+    pub async fn resolve<P: ConnectionProvider>(
+        &self,
+        host: &Name,
+        resolver: &Resolver<P>,
+    ) -> Result<Vec<IpAddr>, ResolveError> {
+        let host = fqdn(host.clone());
+        if let Some(addrs) = self.pinned_addrs.get(&host) {
+            return Ok(addrs.clone());
+        }
+
+        let now = Instant::now();
+        if let Some((addrs, valid_until)) = self.cache.lock().get(&host) {
+            if *valid_until > now {
+                return Ok(addrs.clone());
+            }
+        }
+
+        let lookup = resolver.lookup_ip(host.clone()).await?;
+        let addrs = lookup.iter().collect::<Vec<_>>();
+        let valid_until = match self.cache_ttl {
+            Some(ttl) => now + ttl,
+            None => lookup.valid_until(),
+        };
+
+        let mut cache = self.cache.lock();
+        cache.retain(|_, (_, valid_until)| *valid_until > now);
+        cache.insert(host, (addrs.clone(), valid_until));
+
+        Ok(addrs)
+    }
+
+    /// Returns the DNS-over-TLS name servers of the host, see [`Self::resolve`]
+    #[cfg(feature = "dns-over-tls")]
+    pub async fn tls_name_servers<P: ConnectionProvider>(
+        &self,
+        host: &Name,
+        port: u16,
+        trust_negative_responses: bool,
+        resolver: &Resolver<P>,
+    ) -> Result<NameServerConfigGroup, ResolveError> {
+        let addrs = self.resolve(host, resolver).await?;
+
+        Ok(NameServerConfigGroup::from_ips_tls(
+            &addrs,
+            port,
+            tls_dns_name(host),
+            trust_negative_responses,
+        ))
+    }
+
+    /// Returns the DNS-over-HTTPS name servers of the host, see [`Self::resolve`]
+    #[cfg(feature = "dns-over-https-rustls")]
+    pub async fn https_name_servers<P: ConnectionProvider>(
+        &self,
+        host: &Name,
+        port: u16,
+        trust_negative_responses: bool,
+        resolver: &Resolver<P>,
+    ) -> Result<NameServerConfigGroup, ResolveError> {
+        let addrs = self.resolve(host, resolver).await?;
+
+        Ok(NameServerConfigGroup::from_ips_https(
+            &addrs,
+            port,
+            tls_dns_name(host),
+            trust_negative_responses,
+        ))
+    }
+}
+
+fn fqdn(mut host: Name) -> Name {
+    host.set_fqdn(true);
+    host
+}
+
+#[cfg(any(feature = "dns-over-tls", feature = "dns-over-https-rustls"))]
+fn tls_dns_name(host: &Name) -> String {
+    let mut host = host.clone();
+    host.set_fqdn(false);
+    host.to_ascii()
+}
+
+#[cfg(test)]
+#[cfg(feature = "dns-over-tls")]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+
+    use tokio::runtime::Runtime;
+
+    use super::*;
+    use crate::config::{NameServerConfigGroup, ResolveHosts, ResolverConfig, ResolverOpts};
+    use crate::proto::xfer::Protocol;
+    use crate::TokioResolver;
+
+    #[test]
+    fn test_pinned_tls_name_servers() {
+        let io_loop = Runtime::new().unwrap();
+
+        // without any name server, any bootstrap query fails
+        let options = ResolverOpts {
+            use_hosts_file: ResolveHosts::Never,
+            ..ResolverOpts::default()
+        };
+        let resolver = TokioResolver::tokio(
+            ResolverConfig::from_parts(None, vec![], NameServerConfigGroup::new()),
+            options,
+        );
+
+        let host = Name::from_str("dns.example.com").unwrap();
+        let addrs = vec![
+            IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+            IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2)),
+        ];
+        let bootstrap = Bootstrap::new(BootstrapConfig {
+            pinned_addrs: HashMap::from([(host.clone(), addrs.clone())]),
+            cache_ttl: None,
+        });
+
+        let name_servers = io_loop
+            .block_on(bootstrap.tls_name_servers(&host, 853, false, &resolver))
+            .expect("pinned addresses should not be queried");
+
+        assert_eq!(name_servers.len(), 2);
+        for (name_server, addr) in name_servers.iter().zip(addrs) {
+            assert_eq!(name_server.socket_addr.ip(), addr);
+            assert_eq!(name_server.socket_addr.port(), 853);
+            assert_eq!(name_server.protocol, Protocol::Tls);
+            assert_eq!(name_server.tls_dns_name.as_deref(), Some("dns.example.com"));
+        }
+
+        // an unpinned host requires a bootstrap query
+        let other = Name::from_str("dns.example.net").unwrap();
+        assert!(io_loop
+            .block_on(bootstrap.tls_name_servers(&other, 853, false, &resolver))
+            .is_err());
+    }
+}
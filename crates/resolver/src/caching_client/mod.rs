@@ -7,41 +7,57 @@
 
 //! Caching related functionality for the Resolver.
 
+mod recent;
+mod refresh;
+mod response_log;
+mod scope;
+mod secondary;
+
 use std::{
     borrow::Cow,
+    fmt,
     future::Future,
     pin::Pin,
     sync::{
         atomic::{AtomicU8, Ordering},
         Arc,
     },
-    time::Instant,
+    time::{Duration, Instant},
 };
 
 use futures_util::future::TryFutureExt;
 use hickory_proto::error::ProtoErrorKind;
 use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use tracing::debug;
 
 use crate::{
-    dns_lru::{self, DnsLru, TtlConfig},
+    dns_lru::{self, CacheScope, DnsLru, ReadThrough, TtlConfig},
     error::ResolveError,
     lookup::Lookup,
     proto::{
         error::{ForwardNSData, ProtoError},
-        op::{Query, ResponseCode},
+        op::{Message, Query, ResponseCode},
         rr::{
             domain::usage::{
                 ResolverUsage, DEFAULT, INVALID, IN_ADDR_ARPA_127, IP6_ARPA_1, LOCAL,
                 LOCALHOST as LOCALHOST_usage, ONION,
             },
-            rdata::{A, AAAA, CNAME, PTR, SOA},
+            rdata::{opt::ClientSubnet, A, AAAA, CNAME, PTR, SOA},
             resource::RecordRef,
             DNSClass, Name, RData, Record, RecordType,
         },
         xfer::{DnsHandle, DnsRequestOptions, DnsResponse, FirstAnswer},
     },
+    timing::{LookupTimer, Phase, PhaseTimer, ValidationTimer},
 };
 
+use self::recent::RecentQueries;
+use self::refresh::BackgroundRefresh;
+use self::response_log::LogResponse;
+pub use self::response_log::{LoggedRecord, RedactionPolicy, ResponseLog, ResponseLogger};
+use self::scope::ScopedRecentQueries;
+
 const MAX_QUERY_DEPTH: u8 = 8; // arbitrarily chosen number...
 
 static LOCALHOST: Lazy<RData> =
@@ -66,6 +82,30 @@ impl Drop for DepthTracker {
     }
 }
 
+/// Inspects and optionally modifies a response before it's cached
+///
+/// Rewriting affects both what gets cached and what is returned to the caller, e.g. records
+///  stripped by the rewriter are neither cached nor returned. See
+///  [`Resolver::set_response_rewriter`](crate::Resolver::set_response_rewriter).
+pub type ResponseRewriter = Arc<dyn Fn(&mut Message) + Send + Sync>;
+
+#[derive(Clone)]
+struct RewriteResponse(ResponseRewriter);
+
+impl RewriteResponse {
+    fn rewrite(&self, response: DnsResponse) -> Result<DnsResponse, ProtoError> {
+        let mut message = response.into_message();
+        (self.0)(&mut message);
+        DnsResponse::from_message(message)
+    }
+}
+
+impl fmt::Debug for RewriteResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RewriteResponse").finish_non_exhaustive()
+    }
+}
+
 // TODO: need to consider this storage type as it compares to Authority in server...
 //       should it just be an variation on Authority?
 #[derive(Clone, Debug)]
@@ -76,8 +116,39 @@ where
 {
     lru: DnsLru,
     client: C,
+    /// Number of lookups in progress for the resolution, including the CNAME follow-ups
     query_depth: Arc<AtomicU8>,
+    /// Maximum number of CNAME follow-up queries of a resolution, see `ResolverOpts::max_cname_follow_ups`
+    max_cname_follow_ups: u8,
     preserve_intermediates: bool,
+    /// Minimum interval between identical upstream queries, see `ResolverOpts::min_query_interval`
+    min_query_interval: Option<Duration>,
+    /// Time and result of the last upstream query for each query, when `min_query_interval` is set
+    recent_queries: RecentQueries,
+    /// Runs the refreshes of the cache entries, e.g. served stale or prefetched
+    refresh: Option<BackgroundRefresh>,
+    /// Serves expired entries while refreshing them, see `StaleMode::StaleWhileRevalidate`
+    stale_while_revalidate: bool,
+    /// How long bogus results are cached, see `ResolverOpts::bogus_cache_ttl`
+    bogus_ttl: Option<Duration>,
+    /// Applied to all responses before they are cached
+    response_rewriter: Option<RewriteResponse>,
+    /// Audit logs all responses received from upstream
+    response_logger: Option<LogResponse>,
+    /// Drops out-of-bailiwick glue from responses, see `ResolverOpts::strict_glue`
+    strict_glue: bool,
+    /// Returns NODATA responses as `NXDomain`, see `ResolverOpts::nodata_as_nxdomain`
+    nodata_as_nxdomain: bool,
+    /// The recent queries of the lookups which aren't shared, see [`CacheScope`]
+    scoped_recent: Arc<Mutex<ScopedRecentQueries>>,
+    /// The shared cache, when the lookup is scoped but may be served shared responses
+    shared_lru: Option<DnsLru>,
+    /// The client subnet sent with all the queries, see `ResolverOpts::edns_client_subnet`
+    shared_client_subnet: Option<ClientSubnet>,
+    /// The secondary cache read on the misses of the cache, see `Resolver::set_secondary_cache`
+    read_through: Option<ReadThrough>,
+    /// Records the timing of the resolution, see `Resolver::lookup_with_timing`
+    timer: Option<Arc<LookupTimer>>,
 }
 
 impl<C> CachingClient<C>
@@ -95,29 +166,119 @@ where
 
     pub(crate) fn with_cache(lru: DnsLru, client: C, preserve_intermediates: bool) -> Self {
         let query_depth = Arc::new(AtomicU8::new(0));
+        let recent_queries = recent::recent_queries(lru.capacity());
         Self {
             lru,
             client,
             query_depth,
+            max_cname_follow_ups: MAX_QUERY_DEPTH,
             preserve_intermediates,
+            min_query_interval: None,
+            recent_queries,
+            refresh: None,
+            stale_while_revalidate: false,
+            bogus_ttl: None,
+            response_rewriter: None,
+            response_logger: None,
+            strict_glue: false,
+            nodata_as_nxdomain: false,
+            scoped_recent: Arc::new(Mutex::new(scope::scoped_recent_queries())),
+            shared_lru: None,
+            shared_client_subnet: None,
+            read_through: None,
+            timer: None,
         }
     }
 
+    /// Sets the rewriter applied to all responses before they are cached, see [`ResponseRewriter`]
+    pub(crate) fn set_response_rewriter(&mut self, rewriter: Option<ResponseRewriter>) {
+        self.response_rewriter = rewriter.map(RewriteResponse);
+    }
+
+    /// Sends the queries of this client through another handle, with the same cache and options
+    #[cfg(feature = "dnssec")]
+    pub(crate) fn with_client(mut self, client: C) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Caches results failing DNSSEC validation as `Bogus` errors for the TTL
+    ///
+    /// This avoids validating a known-bogus zone again on every query.
+    pub(crate) fn with_bogus_ttl(mut self, bogus_ttl: Option<Duration>) -> Self {
+        self.bogus_ttl = bogus_ttl;
+        self
+    }
+
+    /// Drops glue records which are not within the zone of their delegation from responses
+    ///
+    /// The name servers of the dropped glue must then be resolved separately.
+    pub(crate) fn with_strict_glue(mut self, strict_glue: bool) -> Self {
+        self.strict_glue = strict_glue;
+        self
+    }
+
+    /// Returns NODATA responses as `NXDomain` errors, for callers which only handle the latter
+    ///
+    /// The actual response code is cached, so this only affects the results of the lookups.
+    pub(crate) fn with_nodata_as_nxdomain(mut self, nodata_as_nxdomain: bool) -> Self {
+        self.nodata_as_nxdomain = nodata_as_nxdomain;
+        self
+    }
+
+    /// Limits the number of follow-up queries for the targets of CNAME records of a resolution
+    ///
+    /// Such queries are needed when the responses only contain the CNAME records, e.g. from name
+    ///  servers sending minimal responses.
+    pub(crate) fn with_max_cname_follow_ups(mut self, max_cname_follow_ups: u8) -> Self {
+        // the depth of the lookups must not overflow
+        self.max_cname_follow_ups = max_cname_follow_ups.min(u8::MAX - 1);
+        self
+    }
+
+    /// Records the timing of the lookups with the `timer`, carried by the requests sent upstream
+    pub(crate) fn with_timer(mut self, timer: Arc<LookupTimer>) -> Self {
+        self.timer = Some(timer);
+        self
+    }
+
     /// Perform a lookup against this caching client, looking first in the cache for a result
     pub fn lookup(
         &mut self,
         query: Query,
         options: DnsRequestOptions,
     ) -> Pin<Box<dyn Future<Output = Result<Lookup, ResolveError>> + Send>> {
+        let nodata_as_nxdomain = self.nodata_as_nxdomain;
+        let mut client = self.for_resolution();
+        let scope = CacheScope::of(&options, self.shared_client_subnet);
+        if !scope.is_shared() {
+            client.scope_to(scope);
+        }
+
         Box::pin(
-            Self::inner_lookup(query, options, self.clone(), vec![]).map_err(ResolveError::from),
+            Self::inner_lookup(query, options, client, vec![]).map_err(move |e| {
+                let e = ResolveError::from(e);
+                match nodata_as_nxdomain {
+                    true => e.nodata_as_nx_domain(),
+                    false => e,
+                }
+            }),
         )
     }
 
+    /// Returns a client for a new resolution, with its own count of CNAME follow-ups
+    ///
+    /// Concurrent resolutions thus don't limit each other's follow-ups.
+    fn for_resolution(&self) -> Self {
+        let mut client = self.clone();
+        client.query_depth = Arc::default();
+        client
+    }
+
     async fn inner_lookup(
         query: Query,
         options: DnsRequestOptions,
-        mut client: Self,
+        client: Self,
         preserved_records: Vec<(Record, u32)>,
     ) -> Result<Lookup, ProtoError> {
         // see https://tools.ietf.org/html/rfc6761
@@ -180,17 +341,81 @@ where
         let _tracker = DepthTracker::track(client.query_depth.clone());
         let is_dnssec = client.client.is_verifying_dnssec();
 
+        // records fetched with DNSSEC checking disabled must never be shared through the cache
+        let use_cache = !options.checking_disabled && !options.bypass_cache;
+
         // first transition any polling that is needed (mutable refs...)
-        if let Some(cached_lookup) = client.lookup_from_cache(&query) {
-            return cached_lookup;
+        if use_cache {
+            let timer = PhaseTimer::start(client.timer.as_ref(), Phase::Cache);
+            let cached_lookup = client
+                .lookup_from_cache(&query, options)
+                .or_else(|| client.lookup_from_shared(&query))
+                .or_else(|| client.lookup_from_secondary(&query))
+                .or_else(|| client.lookup_stale(&query, options).map(Ok))
+                .or_else(|| client.lookup_from_recent(&query, Instant::now()));
+            timer.stop();
+
+            if let Some(cached_lookup) = cached_lookup {
+                return cached_lookup;
+            }
+        }
+
+        // an entry is served stale when the upstream lookup fails, see `ResolverOpts::serve_stale_ttl`
+        let lru = use_cache.then(|| client.lru.clone());
+        let lookup = Self::lookup_upstream(
+            query.clone(),
+            options,
+            client,
+            preserved_records,
+            is_dnssec,
+            use_cache,
+        )
+        .await;
+
+        match (lookup, lru) {
+            (Err(e), Some(lru)) if is_upstream_failure(&e) => {
+                let Some(stale) = lru.get_stale_on_failure(&query, Instant::now()) else {
+                    return Err(e);
+                };
+                debug!("serving stale {}, the upstream lookup failed: {}", query, e);
+                stale
+            }
+            (lookup, _) => lookup,
+        }
+    }
+
+    /// Sends the query upstream, caching the response
+    async fn lookup_upstream(
+        query: Query,
+        options: DnsRequestOptions,
+        mut client: Self,
+        preserved_records: Vec<(Record, u32)>,
+        is_dnssec: bool,
+        use_cache: bool,
+    ) -> Result<Lookup, ProtoError> {
+        let validation = ValidationTimer::start(client.timer.as_ref().filter(|_| is_dnssec));
+        let response = match &client.timer {
+            Some(timer) => client
+                .client
+                .lookup_with_timer(query.clone(), options, timer.clone()),
+            None => client.client.lookup(query.clone(), options),
         };
+        let response_message = response.first_answer().await.map_err(ProtoError::into);
+        validation.stop();
 
-        let response_message = client
-            .client
-            .lookup(query.clone(), options)
-            .first_answer()
-            .await
-            .map_err(ProtoError::into);
+        if let (Ok(response), Some(logger)) = (&response_message, &client.response_logger) {
+            logger.log(&query, response);
+        }
+
+        let response_message = match (response_message, &client.response_rewriter) {
+            (Ok(response), Some(rewriter)) => rewriter.rewrite(response),
+            (response_message, _) => response_message,
+        };
+
+        let response_message = match response_message {
+            Ok(response) if client.strict_glue => strip_out_of_bailiwick_glue(&query, response),
+            response_message => response_message,
+        };
 
         // TODO: technically this might be duplicating work, as name_server already performs this evaluation.
         //  we may want to create a new type, if evaluated... but this is most generic to support any impl in LookupState...
@@ -244,23 +469,60 @@ where
             }
         };
 
+        let recent_query = match client.min_query_interval {
+            Some(_) if use_cache => Some(query.clone()),
+            _ => None,
+        };
+        let secondary_query = use_cache.then(|| query.clone());
+
         // after the request, evaluate if we have additional queries to perform
-        match records {
+        let lookup = match records {
             Ok(Records::CnameChain {
                 next: future,
                 min_ttl: ttl,
             }) => match future.await {
-                Ok(lookup) => client.cname(lookup, query, ttl),
+                Ok(lookup) if !use_cache => Ok(lookup),
+                Ok(lookup) => client.cname(lookup, query, with_min_ttl(ttl, options)),
+                Err(e) if !use_cache => Err(e),
                 Err(e) => client.cache(query, Err(e)),
             },
-            Ok(Records::Exists(rdata)) => client.cache(query, Ok(rdata)),
+            Ok(Records::Exists(rdata)) if !use_cache => Ok(Self::uncached(query, rdata)),
+            Ok(Records::Exists(rdata)) if client.is_bogus(&rdata) => Err(client.cache_bogus(query)),
+            Ok(Records::Exists(rdata)) => {
+                let rdata = rdata
+                    .into_iter()
+                    .map(|(record, ttl)| (record, with_min_ttl(ttl, options)))
+                    .collect();
+                client.cache(query, Ok(rdata))
+            }
+            Err(e) if !use_cache => Err(e),
             Err(e) => client.cache(query, Err(e)),
+        };
+
+        if let Some(query) = recent_query {
+            client.insert_recent(query, &lookup, Instant::now());
+        }
+        if let Some(query) = secondary_query {
+            client.insert_secondary(query, &lookup);
         }
+
+        lookup
+    }
+
+    /// True if bogus results are cached and any of the records failed DNSSEC validation
+    #[cfg(feature = "dnssec")]
+    fn is_bogus(&self, records: &[(Record, u32)]) -> bool {
+        self.bogus_ttl.is_some() && records.iter().any(|(record, _)| record.proof().is_bogus())
+    }
+
+    #[cfg(not(feature = "dnssec"))]
+    fn is_bogus(&self, _records: &[(Record, u32)]) -> bool {
+        false
     }
 
-    /// Check if this query is already cached
-    fn lookup_from_cache(&self, query: &Query) -> Option<Result<Lookup, ProtoError>> {
-        self.lru.get(query, Instant::now())
+    fn cache_bogus(&self, query: Query) -> ProtoError {
+        let ttl = self.bogus_ttl.unwrap_or_default();
+        self.lru.bogus(query, ttl, Instant::now())
     }
 
     /// See https://tools.ietf.org/html/rfc2308
@@ -449,7 +711,8 @@ where
         // TODO: for SRV records we *could* do an implicit lookup, but, this requires knowing the type of IP desired
         //    for now, we'll make the API require the user to perform a follow up to the lookups.
         // It was a CNAME, but not included in the request...
-        if was_cname && client.query_depth.load(Ordering::Acquire) < MAX_QUERY_DEPTH {
+        // the depth counts this lookup, so it is one more than the follow-ups made so far
+        if was_cname && client.query_depth.load(Ordering::Acquire) <= client.max_cname_follow_ups {
             let next_query = Query::query(search_name, query.query_type());
             Ok(Records::CnameChain {
                 next: Box::pin(Self::inner_lookup(
@@ -495,9 +758,108 @@ where
         }
     }
 
+    /// Builds a lookup from the records without storing it in the cache
+    fn uncached(query: Query, records_and_ttl: Vec<(Record, u32)>) -> Lookup {
+        let ttl = records_and_ttl
+            .iter()
+            .map(|(_, ttl)| *ttl)
+            .min()
+            .unwrap_or(dns_lru::MAX_TTL);
+        let records = records_and_ttl
+            .into_iter()
+            .map(|(record, _)| record)
+            .collect::<Vec<_>>();
+
+        let valid_until = Instant::now() + Duration::from_secs(u64::from(ttl));
+        Lookup::new_with_deadline(query, Arc::from(records), valid_until)
+    }
+
     /// Flushes/Removes all entries from the cache
     pub fn clear_cache(&self) {
         self.lru.clear();
+        self.scoped_recent.lock().clear();
+    }
+}
+
+/// Raises the TTL to the minimum TTL of the lookup, see `LookupOptions::min_ttl`
+fn with_min_ttl(ttl: u32, options: DnsRequestOptions) -> u32 {
+    match options.cache_min_ttl {
+        Some(min_ttl) => ttl.max(u32::try_from(min_ttl.as_secs()).unwrap_or(u32::MAX)),
+        None => ttl,
+    }
+}
+
+/// Removes the glue records which are out of the bailiwick of their delegation
+///
+/// Glue is an A or AAAA record in the additional section for the target of an NS record. It is in
+///  bailiwick when its name is within the zone delegated by the NS record, and the zone is the
+///  queried name or one of its ancestors. Other additional records are left untouched.
+fn strip_out_of_bailiwick_glue(
+    query: &Query,
+    response: DnsResponse,
+) -> Result<DnsResponse, ProtoError> {
+    let out_of_bailiwick = |glue: &Record| {
+        if !glue.record_type().is_ip_addr() {
+            return false;
+        }
+
+        let mut delegations = response
+            .answers()
+            .iter()
+            .chain(response.name_servers())
+            .filter(|ns| {
+                ns.data()
+                    .as_ns()
+                    .is_some_and(|ns_data| **ns_data == *glue.name())
+            })
+            .peekable();
+
+        // not the glue of any delegation
+        if delegations.peek().is_none() {
+            return false;
+        }
+
+        !delegations.any(|ns| ns.name().zone_of(query.name()) && ns.name().zone_of(glue.name()))
+    };
+
+    let dropped = response
+        .additionals()
+        .iter()
+        .map(out_of_bailiwick)
+        .collect::<Vec<_>>();
+    if !dropped.contains(&true) {
+        return Ok(response);
+    }
+
+    let mut message = response.into_message();
+    let mut dropped = dropped.into_iter();
+    message.additionals_mut().retain(|record| {
+        let out_of_bailiwick = dropped.next().unwrap_or_default();
+        if out_of_bailiwick {
+            debug!(
+                "ignoring out-of-bailiwick glue {} {} for query {}",
+                record.name(),
+                record.record_type(),
+                query
+            );
+        }
+        !out_of_bailiwick
+    });
+
+    DnsResponse::from_message(message)
+}
+
+/// True if the error is a failure to get a response, rather than a negative response
+///
+/// Negative responses and responses failing DNSSEC validation are never replaced by stale entries.
+fn is_upstream_failure(error: &ProtoError) -> bool {
+    match error.kind() {
+        ProtoErrorKind::NoRecordsFound { response_code, .. } => !matches!(
+            response_code,
+            ResponseCode::NoError | ResponseCode::NXDomain
+        ),
+        ProtoErrorKind::Bogus { .. } => false,
+        _ => true,
     }
 }
 
@@ -624,6 +986,117 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_bypass_cache() {
+        let cache = DnsLru::new(1, dns_lru::TtlConfig::default());
+        let query = Query::new();
+        cache.insert(
+            query.clone(),
+            vec![(
+                Record::from_rdata(
+                    query.name().clone(),
+                    u32::MAX,
+                    RData::A(A::new(127, 0, 0, 2)),
+                ),
+                u32::MAX,
+            )],
+            Instant::now(),
+        );
+
+        let mut options = DnsRequestOptions::default();
+        options.bypass_cache = true;
+
+        let client = mock(vec![v4_message()]);
+        let client = CachingClient::with_cache(cache.clone(), client, false);
+
+        // the cached entry is ignored
+        let ips = block_on(CachingClient::inner_lookup(
+            query.clone(),
+            options,
+            client,
+            vec![],
+        ))
+        .unwrap();
+
+        assert_eq!(
+            ips.iter().cloned().collect::<Vec<_>>(),
+            vec![RData::A(A::new(127, 0, 0, 1))]
+        );
+
+        // and isn't replaced by the fresh records
+        let cached = cache.get(&query, Instant::now()).unwrap().unwrap();
+        assert_eq!(
+            cached.iter().cloned().collect::<Vec<_>>(),
+            vec![RData::A(A::new(127, 0, 0, 2))]
+        );
+    }
+
+    #[test]
+    fn test_checking_disabled_bypasses_cache() {
+        let cache = DnsLru::new(1, dns_lru::TtlConfig::default());
+        let mut options = DnsRequestOptions::default();
+        options.checking_disabled = true;
+
+        let client = mock(vec![v4_message()]);
+        let client = CachingClient::with_cache(cache.clone(), client, false);
+
+        let ips = block_on(CachingClient::inner_lookup(
+            Query::new(),
+            options,
+            client,
+            vec![],
+        ))
+        .unwrap();
+
+        assert_eq!(
+            ips.iter().cloned().collect::<Vec<_>>(),
+            vec![RData::A(A::new(127, 0, 0, 1))]
+        );
+
+        // unchecked records must not have been cached
+        assert!(cache.get(&Query::new(), Instant::now()).is_none());
+    }
+
+    #[test]
+    fn test_serve_stale_on_upstream_failure() {
+        let ttls = dns_lru::TtlConfig {
+            serve_stale_ttl: Some(Duration::from_secs(3600)),
+            ..dns_lru::TtlConfig::default()
+        };
+        // the record expired a second ago
+        let cache = DnsLru::new(1, ttls);
+        let record = Record::from_rdata(Name::root(), 0, RData::A(A::new(127, 0, 0, 1)));
+        cache.insert(
+            Query::new(),
+            vec![(record, 0)],
+            Instant::now() - Duration::from_secs(1),
+        );
+
+        // the first query fails with an io error, the second is answered with an empty response
+        let client = mock(vec![error()]);
+        let client = CachingClient::with_cache(cache, client, false);
+
+        let lookup = || {
+            block_on(CachingClient::inner_lookup(
+                Query::new(),
+                DnsRequestOptions::default(),
+                client.clone(),
+                vec![],
+            ))
+        };
+
+        // the record is served stale as the upstream lookup fails
+        let stale = lookup().expect("lookup should be served stale");
+        assert!(stale.is_stale());
+        assert_eq!(stale.iter().next(), Some(&RData::A(A::new(127, 0, 0, 1))));
+        assert_eq!(stale.records()[0].ttl(), 1);
+
+        // a negative response is not replaced by the stale entry
+        assert!(lookup()
+            .expect_err("lookup should fail")
+            .is_no_records_found());
+    }
+
     #[allow(clippy::unnecessary_wraps)]
     pub(crate) fn cname_message() -> Result<DnsResponse, ProtoError> {
         let mut message = Message::new();
@@ -936,6 +1409,366 @@ mod tests {
         cname_ttl_test(2, 1);
     }
 
+    #[test]
+    fn test_response_rewriter() {
+        use crate::proto::rr::rdata::SOA;
+
+        let v4_query = Query::query(Name::root(), RecordType::A);
+        let v6_query = Query::query(Name::root(), RecordType::AAAA);
+        let soa = SOA::new(Name::root(), Name::root(), 1, 3600, 600, 86400, 300);
+
+        // the SOA makes the stripped response cacheable as a negative one
+        let mut v6_message = v6_message().unwrap().into_message();
+        v6_message.add_name_server(Record::from_rdata(Name::root(), 300, RData::SOA(soa)));
+        let v6_message = DnsResponse::from_message(v6_message).unwrap();
+
+        // any further query fails, the second lookups are answered from the cache
+        let client = mock(vec![error(), error(), v4_message(), Ok(v6_message)]);
+        let mut client = CachingClient::new(2, client, false);
+        client.set_response_rewriter(Some(Arc::new(|message: &mut Message| {
+            let answers = message.take_answers();
+            message.insert_answers(
+                answers
+                    .into_iter()
+                    .filter(|r| r.record_type() != RecordType::AAAA)
+                    .map(|mut r| {
+                        r.set_ttl(60);
+                        r
+                    })
+                    .collect(),
+            );
+        })));
+
+        for _ in 0..2 {
+            let err = block_on(client.lookup(v6_query.clone(), DnsRequestOptions::default()))
+                .expect_err("AAAA records should have been stripped");
+            assert!(err.is_no_records_found());
+        }
+        let cached = client.lru.get(&v6_query, Instant::now());
+        let Some(Err(cached)) = cached else {
+            panic!("stripped response should be cached: {cached:?}");
+        };
+        assert!(cached.is_no_records_found());
+
+        // other records are kept, with the TTL set by the rewriter
+        for _ in 0..2 {
+            let lookup = block_on(client.lookup(v4_query.clone(), DnsRequestOptions::default()))
+                .expect("lookup failed");
+            assert_eq!(
+                lookup.iter().cloned().collect::<Vec<_>>(),
+                vec![RData::A(A::new(127, 0, 0, 1))]
+            );
+            assert!(lookup.valid_until() <= Instant::now() + Duration::from_secs(60));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "dnssec")]
+    fn test_bogus_cache() {
+        use crate::error::ResolveErrorKind;
+        use crate::proto::rr::dnssec::Proof;
+
+        let query = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+        let mut record =
+            Record::from_rdata(query.name().clone(), 86400, RData::A(A::new(127, 0, 0, 1)));
+        record.set_proof(Proof::Bogus);
+
+        let mut message = Message::new();
+        message.add_query(query.clone());
+        message.insert_answers(vec![record]);
+
+        // the second query would fail with an io error, if it wasn't answered by the cache
+        let client = mock(vec![
+            error(),
+            Ok(DnsResponse::from_message(message).unwrap()),
+        ]);
+        let mut client =
+            CachingClient::new(1, client, false).with_bogus_ttl(Some(Duration::from_secs(60)));
+
+        for _ in 0..2 {
+            let error = block_on(client.lookup(query.clone(), DnsRequestOptions::default()))
+                .expect_err("bogus results should fail");
+            let ResolveErrorKind::Proto(error) = error.kind() else {
+                panic!("unexpected error: {error}");
+            };
+            assert!(
+                matches!(error.kind(), ProtoErrorKind::Bogus { query: bogus } if **bogus == query),
+                "unexpected error: {error}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_negative_name_servers_cached() {
+        use crate::proto::rr::rdata::SOA;
+
+        let zone = Name::from_str("example.com.").unwrap();
+        let query = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+        let ns_name = Name::from_str("ns1.example.com.").unwrap();
+        let soa = SOA::new(ns_name.clone(), zone.clone(), 1, 3600, 600, 86400, 300);
+        let ns = Record::from_rdata(zone.clone(), 3600, RData::NS(NS(ns_name.clone())));
+        let glue = Record::from_rdata(ns_name, 3600, RData::A(A::new(127, 0, 0, 53)));
+
+        let mut message = Message::new();
+        message.add_query(query.clone());
+        message.set_response_code(ResponseCode::NXDomain);
+        message.add_name_server(Record::from_rdata(zone, 300, RData::SOA(soa)));
+        message.add_name_server(ns.clone());
+        message.add_additional(glue.clone());
+
+        // the second query would fail with an io error, if it wasn't answered by the cache
+        let client = mock(vec![
+            error(),
+            Ok(DnsResponse::from_message(message).unwrap()),
+        ]);
+        let mut client = CachingClient::new(1, client, false);
+
+        for _ in 0..2 {
+            let error = block_on(client.lookup(query.clone(), DnsRequestOptions::default()))
+                .expect_err("lookup should be negative");
+            assert!(error.is_nx_domain());
+            let name_servers = error.name_servers().expect("no name servers");
+            assert_eq!(name_servers.len(), 1);
+            assert_eq!(name_servers[0].ns, ns);
+            assert_eq!(&*name_servers[0].glue, std::slice::from_ref(&glue));
+        }
+    }
+
+    #[test]
+    fn test_nodata_as_nxdomain() {
+        use crate::proto::rr::rdata::SOA;
+
+        let zone = Name::from_str("example.com.").unwrap();
+        let query = Query::query(
+            Name::from_str("www.example.com.").unwrap(),
+            RecordType::AAAA,
+        );
+        let soa = SOA::new(zone.clone(), zone.clone(), 1, 3600, 600, 86400, 300);
+
+        let mut message = Message::new();
+        message.add_query(query.clone());
+        message.set_response_code(ResponseCode::NoError);
+        message.add_name_server(Record::from_rdata(zone, 300, RData::SOA(soa)));
+        let nodata = || Ok(DnsResponse::from_message(message.clone()).unwrap());
+
+        // the NODATA response is returned as is by default
+        let mut client = CachingClient::new(1, mock(vec![nodata()]), false);
+        let err = block_on(client.lookup(query.clone(), DnsRequestOptions::default()))
+            .expect_err("lookup should be negative");
+        assert!(err.is_no_records_found());
+        assert!(!err.is_nx_domain());
+
+        // the second lookup is answered from the cache, which keeps the NODATA response code
+        let client = mock(vec![error(), nodata()]);
+        let mut client = CachingClient::new(1, client, false).with_nodata_as_nxdomain(true);
+        for _ in 0..2 {
+            let err = block_on(client.lookup(query.clone(), DnsRequestOptions::default()))
+                .expect_err("lookup should be negative");
+            assert!(err.is_nx_domain());
+        }
+        let cached = client.lru.get(&query, Instant::now());
+        let Some(Err(cached)) = cached else {
+            panic!("NODATA response should be cached: {cached:?}");
+        };
+        assert!(cached.is_no_records_found());
+        assert!(!cached.is_nx_domain());
+    }
+
+    #[test]
+    fn test_out_of_bailiwick_glue_ignored() {
+        let zone = Name::from_str("example.com.").unwrap();
+        let query = Query::query(zone.clone(), RecordType::NS);
+        let in_name = Name::from_str("ns1.example.com.").unwrap();
+        let out_name = Name::from_str("ns.example.net.").unwrap();
+        let in_glue = Record::from_rdata(in_name.clone(), 3600, RData::A(A::new(192, 0, 2, 1)));
+        let out_glue = Record::from_rdata(out_name.clone(), 3600, RData::A(A::new(192, 0, 2, 66)));
+
+        let mut message = Message::new();
+        message.add_query(query.clone());
+        message.add_answer(Record::from_rdata(
+            zone.clone(),
+            3600,
+            RData::NS(NS(in_name)),
+        ));
+        message.add_answer(Record::from_rdata(zone, 3600, RData::NS(NS(out_name))));
+        message.insert_additionals(vec![in_glue, out_glue]);
+
+        // the second query would fail with an io error, if it wasn't answered by the cache
+        let client = mock(vec![
+            error(),
+            Ok(DnsResponse::from_message(message).unwrap()),
+        ]);
+        let mut client = CachingClient::new(1, client, false).with_strict_glue(true);
+
+        for _ in 0..2 {
+            let lookup = block_on(client.lookup(query.clone(), DnsRequestOptions::default()))
+                .expect("lookup failed");
+            let glue = lookup
+                .iter()
+                .filter_map(RData::as_a)
+                .cloned()
+                .collect::<Vec<_>>();
+            assert_eq!(glue, vec![A::new(192, 0, 2, 1)]);
+            assert_eq!(
+                lookup
+                    .iter()
+                    .filter(|rdata| rdata.as_ns().is_some())
+                    .count(),
+                2
+            );
+        }
+    }
+
+    #[test]
+    fn test_negative_response_from_cache() {
+        use crate::error::{NegativeResponse, ResolveError};
+        use crate::proto::rr::rdata::SOA;
+
+        let zone = Name::from_str("example.com.").unwrap();
+        let query = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+        let soa = SOA::new(
+            Name::from_str("ns1.example.com.").unwrap(),
+            zone.clone(),
+            1,
+            3600,
+            600,
+            86400,
+            300,
+        );
+        let soa = Record::from_rdata(zone, 300, soa);
+
+        let mut message = Message::new();
+        message.add_query(query.clone());
+        message.set_response_code(ResponseCode::NXDomain);
+        message.add_name_server(soa.clone().into_record_of_rdata());
+
+        let client = mock(vec![
+            error(),
+            Ok(DnsResponse::from_message(message).unwrap()),
+        ]);
+        let mut client = CachingClient::new(1, client, false);
+
+        block_on(client.lookup(query.clone(), DnsRequestOptions::default()))
+            .expect_err("lookup should be negative");
+        let negative = block_on(client.lookup(query.clone(), DnsRequestOptions::default()))
+            .expect_err("lookup should be negative")
+            .negative_response()
+            .expect("not a negative response");
+
+        assert_eq!(negative.query, query);
+        assert_eq!(negative.response_code, ResponseCode::NXDomain);
+        assert_eq!(negative.soa, Some(soa));
+
+        let cached = ResolveError::from(
+            client
+                .lru
+                .get(&query, Instant::now())
+                .expect("not cached")
+                .expect_err("cached entry should be negative"),
+        )
+        .negative_response()
+        .expect("not a negative response");
+        let ttl = negative.negative_ttl.expect("no negative ttl");
+        let cached_ttl = cached.negative_ttl.expect("no negative ttl");
+        assert!(ttl <= 300);
+        // the time elapsed between both lookups may cross a second boundary
+        assert!(ttl.abs_diff(cached_ttl) <= 1, "{ttl} vs {cached_ttl}");
+        assert_eq!(
+            NegativeResponse {
+                negative_ttl: cached.negative_ttl,
+                ..negative
+            },
+            cached
+        );
+    }
+
+    #[test]
+    fn test_canonical_name() {
+        let query = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+        let actual = Name::from_str("actual.example.com.").unwrap();
+        let cname = Record::from_rdata(
+            query.name().clone(),
+            86400,
+            RData::CNAME(CNAME(actual.clone())),
+        );
+        let target = Record::from_rdata(actual.clone(), 86400, RData::A(A::new(127, 0, 0, 1)));
+        let message = |query: &Query, answers: Vec<Record>| {
+            let mut message = Message::new();
+            message.add_query(query.clone());
+            message.insert_answers(answers);
+            Ok(DnsResponse::from_message(message).unwrap())
+        };
+
+        for preserve_intermediates in [false, true] {
+            // the response contains the whole chain, the lookup is the one of the query
+            let client = mock(vec![message(&query, vec![cname.clone(), target.clone()])]);
+            let mut client = CachingClient::new(1, client, preserve_intermediates);
+            let lookup = block_on(client.lookup(query.clone(), DnsRequestOptions::default()))
+                .expect("lookup failed");
+            assert_eq!(lookup.query(), &query);
+            assert_eq!(lookup.canonical_name(), &actual);
+
+            // the CNAME is resolved with a follow-up query of its target, whose lookup is returned
+            let target_query = Query::query(actual.clone(), RecordType::A);
+            let client = mock(vec![
+                message(&target_query, vec![target.clone()]),
+                cname_message(),
+            ]);
+            let mut client = CachingClient::new(1, client, preserve_intermediates);
+            let lookup = block_on(client.lookup(query.clone(), DnsRequestOptions::default()))
+                .expect("lookup failed");
+            assert_eq!(lookup.query(), &target_query);
+            assert_eq!(lookup.canonical_name(), &actual);
+        }
+    }
+
+    #[test]
+    fn test_minimal_cname_response() {
+        let query = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+        let actual = Query::query(
+            Name::from_str("actual.example.com.").unwrap(),
+            RecordType::A,
+        );
+        let cname = Record::from_rdata(
+            query.name().clone(),
+            86400,
+            RData::CNAME(CNAME(actual.name().clone())),
+        );
+        let target =
+            Record::from_rdata(actual.name().clone(), 86400, RData::A(A::new(127, 0, 0, 1)));
+        let target_message = || {
+            let mut message = Message::new();
+            message.add_query(actual.clone());
+            message.insert_answers(vec![target.clone()]);
+            Ok(DnsResponse::from_message(message).unwrap())
+        };
+
+        // the response only contains the CNAME, its target is resolved with a follow-up query; the
+        //  later queries would fail with an io error, if they weren't answered by the cache
+        let client = mock(vec![error(), target_message(), cname_message()]);
+        let mut client = CachingClient::new(2, client, true);
+
+        for _ in 0..2 {
+            let lookup = block_on(client.lookup(query.clone(), DnsRequestOptions::default()))
+                .expect("lookup failed");
+            assert_eq!(
+                lookup.iter().cloned().collect::<Vec<_>>(),
+                vec![cname.data().clone(), target.data().clone()]
+            );
+        }
+
+        let lookup = block_on(client.lookup(actual.clone(), DnsRequestOptions::default()))
+            .expect("lookup failed");
+        assert!(lookup.iter().any(|rdata| rdata == target.data()));
+
+        // without follow-ups, the target is not resolved
+        let client = mock(vec![error(), target_message(), cname_message()]);
+        let mut client = CachingClient::new(2, client, true).with_max_cname_follow_ups(0);
+        let error = block_on(client.lookup(query, DnsRequestOptions::default()))
+            .expect_err("lookup should fail");
+        assert!(error.is_no_records_found());
+    }
+
     #[test]
     fn test_early_return_localhost() {
         let cache = DnsLru::new(0, dns_lru::TtlConfig::default());
@@ -0,0 +1,143 @@
+//! Reuse of the results of recent upstream queries, see `ResolverOpts::min_query_interval`
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use lru_cache::LruCache;
+use parking_lot::Mutex;
+
+use super::CachingClient;
+use crate::lookup::Lookup;
+use crate::proto::error::ProtoError;
+use crate::proto::op::Query;
+use crate::proto::xfer::DnsHandle;
+
+/// Time and result of the last upstream query of each query, see `ResolverOpts::min_query_interval`
+///
+/// Holds as many queries as the cache, the least recently used ones are evicted.
+pub(super) type RecentQueries = Arc<Mutex<LruCache<Query, (Instant, Result<Lookup, ProtoError>)>>>;
+
+/// Returns the recent queries of a cache with this capacity
+pub(super) fn recent_queries(capacity: usize) -> RecentQueries {
+    Arc::new(Mutex::new(LruCache::new(capacity)))
+}
+
+impl<C> CachingClient<C>
+where
+    C: DnsHandle + Send + 'static,
+{
+    /// Enforces a minimum interval between identical upstream queries
+    ///
+    /// Queries repeated within the interval return the result of the last upstream query.
+    pub(crate) fn with_min_query_interval(mut self, min_query_interval: Option<Duration>) -> Self {
+        self.min_query_interval = min_query_interval;
+        self
+    }
+
+    /// Check if this query was sent upstream within the minimum query interval
+    pub(super) fn lookup_from_recent(
+        &self,
+        query: &Query,
+        now: Instant,
+    ) -> Option<Result<Lookup, ProtoError>> {
+        let min_query_interval = self.min_query_interval?;
+        let mut recent_queries = self.recent_queries.lock();
+        let (queried_at, lookup) = recent_queries.get_mut(query)?;
+
+        if now.saturating_duration_since(*queried_at) < min_query_interval {
+            return Some(lookup.clone());
+        }
+
+        recent_queries.remove(query);
+        None
+    }
+
+    pub(super) fn insert_recent(
+        &self,
+        query: Query,
+        lookup: &Result<Lookup, ProtoError>,
+        now: Instant,
+    ) {
+        if self.min_query_interval.is_none() {
+            return;
+        }
+
+        self.recent_queries
+            .lock()
+            .insert(query, (now, lookup.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use futures_executor::block_on;
+
+    use crate::caching_client::CachingClient;
+    use crate::dns_lru::{DnsLru, TtlConfig};
+    use crate::lookup_ip::tests::*;
+    use crate::proto::error::ProtoError;
+    use crate::proto::op::{Message, Query};
+    use crate::proto::rr::rdata::A;
+    use crate::proto::rr::{Name, RData, Record, RecordType};
+    use crate::proto::xfer::{DnsRequestOptions, DnsResponse};
+
+    #[test]
+    fn test_min_query_interval() {
+        let message = |ip: A| {
+            let mut message = Message::new();
+            message.add_query(Query::new());
+            message.insert_answers(vec![Record::from_rdata(Name::root(), 0, RData::A(ip))]);
+            Ok(DnsResponse::from_message(message).unwrap())
+        };
+
+        let interval = Duration::from_secs(60);
+        let cache = DnsLru::new(1, TtlConfig::default());
+        let client = mock(vec![
+            message(A::new(127, 0, 0, 2)),
+            message(A::new(127, 0, 0, 1)),
+        ]);
+        let client =
+            CachingClient::with_cache(cache, client, false).with_min_query_interval(Some(interval));
+
+        let lookup = || {
+            block_on(CachingClient::inner_lookup(
+                Query::new(),
+                DnsRequestOptions::default(),
+                client.clone(),
+                vec![],
+            ))
+            .expect("lookup should succeed")
+            .iter()
+            .cloned()
+            .collect::<Vec<_>>()
+        };
+
+        // the records have a TTL of 0, repeated queries within the interval are not sent upstream
+        assert_eq!(lookup(), vec![RData::A(A::new(127, 0, 0, 1))]);
+        assert_eq!(lookup(), vec![RData::A(A::new(127, 0, 0, 1))]);
+
+        // the result of the last query is only reused within the interval
+        let now = Instant::now();
+        let recent = client.lookup_from_recent(&Query::new(), now + interval / 2);
+        assert!(recent.is_some());
+        let recent = client.lookup_from_recent(&Query::new(), now + interval);
+        assert!(recent.is_none());
+
+        // expired results are dropped once looked up
+        assert_eq!(client.recent_queries.lock().len(), 0);
+        assert_eq!(lookup(), vec![RData::A(A::new(127, 0, 0, 2))]);
+
+        // the recent queries are bounded by the capacity of the cache
+        let other = Query::query(Name::root(), RecordType::AAAA);
+        client.insert_recent(
+            other.clone(),
+            &Err(ProtoError::from("forced test failure")),
+            now,
+        );
+        assert_eq!(client.recent_queries.lock().len(), 1);
+        assert!(client.lookup_from_recent(&other, now).is_some());
+        assert!(client.lookup_from_recent(&Query::new(), now).is_none());
+    }
+}
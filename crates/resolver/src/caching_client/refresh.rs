@@ -0,0 +1,292 @@
+//! Refreshes of the cached entries in the background, before they expire or while they are
+//! served stale
+
+use std::collections::HashSet;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use tracing::debug;
+
+use super::CachingClient;
+use crate::lookup::Lookup;
+use crate::proto::error::ProtoError;
+use crate::proto::op::Query;
+use crate::proto::xfer::{DnsHandle, DnsRequestOptions};
+
+pub(super) type BgTask = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Spawns the background refreshes of cache entries, e.g. served stale or about to expire
+#[derive(Clone)]
+pub(super) struct BackgroundRefresh {
+    spawn: Arc<dyn Fn(BgTask) + Send + Sync>,
+    /// Queries with a refresh in flight, each query is only refreshed once at a time
+    pending: Arc<Mutex<HashSet<Query>>>,
+}
+
+impl fmt::Debug for BackgroundRefresh {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BackgroundRefresh")
+            .field("pending", &self.pending)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Marks the refresh of a query as no longer in flight once dropped, whether it completed or not
+struct PendingRefresh {
+    pending: Arc<Mutex<HashSet<Query>>>,
+    query: Query,
+}
+
+impl Drop for PendingRefresh {
+    fn drop(&mut self) {
+        self.pending.lock().remove(&self.query);
+    }
+}
+
+impl<C> CachingClient<C>
+where
+    C: DnsHandle + Send + 'static,
+{
+    /// Runs the refreshes of the cache entries in the background with `spawn`
+    ///
+    /// Entries are refreshed when served stale, see [`Self::with_stale_while_revalidate`], or
+    ///  about to expire, see [`Self::lookup_from_cache`].
+    pub(crate) fn with_background_refresh(
+        mut self,
+        spawn: impl Fn(BgTask) + Send + Sync + 'static,
+    ) -> Self {
+        self.refresh = Some(BackgroundRefresh {
+            spawn: Arc::new(spawn),
+            pending: Arc::default(),
+        });
+        self
+    }
+
+    /// Serves entries which expired no longer than `grace` ago stale, while refreshing them
+    ///
+    /// The refreshes are run in the background, see [`Self::with_background_refresh`].
+    pub(crate) fn with_stale_while_revalidate(mut self, grace: Duration) -> Self {
+        self.lru = self.lru.with_stale_grace(grace);
+        self.stale_while_revalidate = true;
+        self
+    }
+
+    /// Check if this query is already cached, spawning its refresh if the entry is about to expire
+    ///
+    /// Entries are only refreshed ahead of their expiry when a prefetch threshold is configured in
+    ///  the cache, and refreshes are run in the background, see
+    ///  [`DnsLru::get_with_prefetch_hint`](crate::dns_lru::DnsLru::get_with_prefetch_hint).
+    pub(super) fn lookup_from_cache(
+        &self,
+        query: &Query,
+        options: DnsRequestOptions,
+    ) -> Option<Result<Lookup, ProtoError>> {
+        let (lookup, hint) = self.lru.get_with_prefetch_hint(query, Instant::now())?;
+
+        if let Some(hint) = hint {
+            let spawned = self.spawn_refresh(query, |query, client| {
+                Box::pin(async move {
+                    // the entry is still current, it must not be served from the cache again
+                    let is_dnssec = client.client.is_verifying_dnssec();
+                    let _ = Self::lookup_upstream(query, options, client, vec![], is_dnssec, true)
+                        .await;
+                })
+            });
+            if spawned {
+                debug!(
+                    "prefetching {}, expiring in {:?}",
+                    query, hint.remaining_ttl
+                );
+                self.lru.mark_prefetched(query);
+            }
+        }
+
+        Some(lookup)
+    }
+
+    /// Check for a stale entry of this query, spawning its refresh if none is in flight
+    pub(super) fn lookup_stale(&self, query: &Query, options: DnsRequestOptions) -> Option<Lookup> {
+        if !self.stale_while_revalidate {
+            return None;
+        }
+        let lookup = self.lru.get_stale(query, Instant::now())?;
+
+        self.spawn_refresh(query, |query, client| {
+            Box::pin(async move {
+                // a successful result replaces the stale entry in the cache
+                let _ = Self::inner_lookup(query, options, client, vec![]).await;
+            })
+        });
+
+        Some(lookup)
+    }
+
+    /// Spawns the `refresh` of the query in the background, unless one is already in flight
+    ///
+    /// Returns true if the refresh was spawned.
+    fn spawn_refresh(&self, query: &Query, refresh: impl FnOnce(Query, Self) -> BgTask) -> bool {
+        let Some(background) = self.refresh.as_ref() else {
+            return false;
+        };
+        if !background.pending.lock().insert(query.clone()) {
+            return false;
+        }
+
+        let pending = PendingRefresh {
+            pending: Arc::clone(&background.pending),
+            query: query.clone(),
+        };
+
+        // the refresh must neither be served the stale entry again and spawn refreshes of its own,
+        //  nor be timed with the lookup spawning it
+        let mut client = self.for_resolution();
+        client.refresh = None;
+        client.stale_while_revalidate = false;
+        client.timer = None;
+
+        let refresh = refresh(query.clone(), client);
+        (background.spawn)(Box::pin(async move {
+            let _pending = pending;
+            refresh.await
+        }));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use futures_executor::block_on;
+    use parking_lot::Mutex;
+
+    use super::BgTask;
+    use crate::caching_client::CachingClient;
+    use crate::config::PrefetchThreshold;
+    use crate::dns_lru::{DnsLru, TtlConfig};
+    use crate::lookup::Lookup;
+    use crate::lookup_ip::tests::*;
+    use crate::proto::error::ProtoError;
+    use crate::proto::op::{Message, Query};
+    use crate::proto::rr::rdata::A;
+    use crate::proto::rr::{Name, RData, Record};
+    use crate::proto::xfer::{DnsHandle, DnsRequestOptions, DnsResponse};
+
+    fn message(ip: A, ttl: u32) -> Result<DnsResponse, ProtoError> {
+        let mut message = Message::new();
+        message.add_query(Query::new());
+        message.insert_answers(vec![Record::from_rdata(Name::root(), ttl, RData::A(ip))]);
+        Ok(DnsResponse::from_message(message).unwrap())
+    }
+
+    /// Returns the refreshes spawned by the returned spawner, to be run by the test
+    fn spawner() -> (
+        Arc<Mutex<Vec<BgTask>>>,
+        impl Fn(BgTask) + Send + Sync + 'static,
+    ) {
+        let refreshes = Arc::new(Mutex::new(Vec::<BgTask>::new()));
+        let spawn = {
+            let refreshes = Arc::clone(&refreshes);
+            move |task: BgTask| refreshes.lock().push(task)
+        };
+        (refreshes, spawn)
+    }
+
+    fn lookup<C: DnsHandle>(client: &CachingClient<C>) -> Lookup {
+        block_on(CachingClient::inner_lookup(
+            Query::new(),
+            DnsRequestOptions::default(),
+            client.clone(),
+            vec![],
+        ))
+        .expect("lookup should succeed")
+    }
+
+    #[test]
+    fn test_stale_while_revalidate() {
+        let (refreshes, spawn) = spawner();
+        let cache = DnsLru::new(1, TtlConfig::default());
+        let record = Record::from_rdata(Name::root(), 0, RData::A(A::new(127, 0, 0, 1)));
+        cache.insert(
+            Query::new(),
+            vec![(record, 0)],
+            Instant::now() - Duration::from_secs(1),
+        );
+        let client = mock(vec![message(A::new(127, 0, 0, 2), 86400)]);
+        let client = CachingClient::with_cache(cache, client, false)
+            .with_background_refresh(spawn)
+            .with_stale_while_revalidate(Duration::from_secs(3600));
+        let lookup = || lookup(&client);
+
+        // the record expired a second ago, it's served stale and refreshed only once
+        for _ in 0..2 {
+            let stale = lookup();
+            assert!(stale.is_stale());
+            assert_eq!(stale.iter().next(), Some(&RData::A(A::new(127, 0, 0, 1))));
+        }
+        assert_eq!(refreshes.lock().len(), 1);
+
+        let refresh = refreshes.lock().pop().unwrap();
+        block_on(refresh);
+
+        let fresh = lookup();
+        assert!(!fresh.is_stale());
+        assert_eq!(fresh.iter().next(), Some(&RData::A(A::new(127, 0, 0, 2))));
+    }
+
+    #[test]
+    fn test_prefetch() {
+        let (refreshes, spawn) = spawner();
+        // the entries are refreshed as soon as they are served from the cache
+        let ttls = TtlConfig::default().with_prefetch_threshold(PrefetchThreshold::new(1.0));
+        let cache = DnsLru::new(1, ttls);
+        let client = mock(vec![
+            message(A::new(127, 0, 0, 2), 86400),
+            message(A::new(127, 0, 0, 1), 1),
+        ]);
+        // refreshes ahead of the expiry don't require serving stale entries
+        let client = CachingClient::with_cache(cache, client, false).with_background_refresh(spawn);
+
+        let first = lookup(&client);
+        assert_eq!(first.iter().next(), Some(&RData::A(A::new(127, 0, 0, 1))));
+        assert!(refreshes.lock().is_empty());
+
+        // the cached entry is served while refreshed, only once
+        for _ in 0..2 {
+            let cached = lookup(&client);
+            assert!(!cached.is_stale());
+            assert_eq!(cached.iter().next(), Some(&RData::A(A::new(127, 0, 0, 1))));
+        }
+        assert_eq!(refreshes.lock().len(), 1);
+
+        let refresh = refreshes.lock().pop().unwrap();
+        block_on(refresh);
+
+        let refreshed = lookup(&client);
+        assert_eq!(
+            refreshed.iter().next(),
+            Some(&RData::A(A::new(127, 0, 0, 2)))
+        );
+    }
+
+    #[test]
+    fn test_prefetch_without_spawner() {
+        let ttls = TtlConfig::default().with_prefetch_threshold(PrefetchThreshold::new(1.0));
+        let cache = DnsLru::new(1, ttls);
+        let client = mock(vec![message(A::new(127, 0, 0, 1), 1)]);
+        let client = CachingClient::with_cache(cache.clone(), client, false);
+        lookup(&client);
+        lookup(&client);
+
+        // no refresh was spawned, the entry may still be prefetched
+        let (_, hint) = cache
+            .get_with_prefetch_hint(&Query::new(), Instant::now())
+            .expect("the entry should be cached");
+        assert!(hint.is_some());
+    }
+}
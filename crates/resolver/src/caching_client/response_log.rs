@@ -0,0 +1,237 @@
+//! Audit logging of the responses received from upstream, see [`ResponseLogger`]
+
+use std::fmt;
+use std::sync::Arc;
+
+use super::CachingClient;
+use crate::proto::op::{Message, Query, ResponseCode};
+use crate::proto::rr::{Name, Record, RecordType};
+use crate::proto::xfer::{DnsHandle, DnsResponse};
+
+/// Which names and records of logged responses are redacted, see [`ResponseLogger`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RedactionPolicy {
+    /// Record types which are redacted, e.g. `PTR` to mask the addresses and the target names
+    pub record_types: Vec<RecordType>,
+    /// Names whose records, and the records of their subdomains, are redacted
+    pub names: Vec<Name>,
+}
+
+impl RedactionPolicy {
+    /// Returns true if the name and the data of the records of this name and type must be redacted
+    pub fn redacts(&self, name: &Name, record_type: RecordType) -> bool {
+        self.record_types.contains(&record_type)
+            || self.names.iter().any(|redacted| redacted.zone_of(name))
+    }
+}
+
+/// A record of a logged response
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoggedRecord {
+    /// Name of the record, `None` if it was redacted
+    pub name: Option<Name>,
+    /// Type of the record
+    pub record_type: RecordType,
+    /// TTL of the record, in seconds
+    pub ttl: u32,
+    /// Presentation format of the record data, `None` if it was redacted
+    pub data: Option<String>,
+}
+
+impl LoggedRecord {
+    fn new(record: &Record, policy: &RedactionPolicy) -> Self {
+        let redacted = policy.redacts(record.name(), record.record_type());
+        Self {
+            name: (!redacted).then(|| record.name().clone()),
+            record_type: record.record_type(),
+            ttl: record.ttl(),
+            data: (!redacted).then(|| record.data().to_string()),
+        }
+    }
+}
+
+/// An audit log entry for a response received from upstream, redacted per a [`RedactionPolicy`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResponseLog {
+    /// Name of the query the response answers, `None` if it was redacted
+    pub query_name: Option<Name>,
+    /// Type of the query the response answers
+    pub query_type: RecordType,
+    /// Response code of the response
+    pub response_code: ResponseCode,
+    /// Records of the answer section
+    pub answers: Vec<LoggedRecord>,
+    /// Records of the authority section
+    pub name_servers: Vec<LoggedRecord>,
+    /// Records of the additional section
+    pub additionals: Vec<LoggedRecord>,
+}
+
+impl ResponseLog {
+    /// Creates the log entry of the response to the query, redacting names and records per the
+    ///  policy
+    pub fn new(query: &Query, response: &Message, policy: &RedactionPolicy) -> Self {
+        let log = |records: &[Record]| {
+            records
+                .iter()
+                .map(|record| LoggedRecord::new(record, policy))
+                .collect()
+        };
+        let redacted = policy.redacts(query.name(), query.query_type());
+
+        Self {
+            query_name: (!redacted).then(|| query.name().clone()),
+            query_type: query.query_type(),
+            response_code: response.response_code(),
+            answers: log(response.answers()),
+            name_servers: log(response.name_servers()),
+            additionals: log(response.additionals()),
+        }
+    }
+}
+
+/// Receives an audit log entry for each response received from upstream
+///
+/// Unlike tracing, entries are structured and redacted per a [`RedactionPolicy`], see
+///  [`Resolver::set_response_logger`](crate::Resolver::set_response_logger). Responses are logged
+///  as received, before any [`ResponseRewriter`](super::ResponseRewriter), and responses served
+///  from the cache are not logged again.
+pub type ResponseLogger = Arc<dyn Fn(&ResponseLog) + Send + Sync>;
+
+#[derive(Clone)]
+pub(super) struct LogResponse {
+    logger: ResponseLogger,
+    policy: RedactionPolicy,
+}
+
+impl LogResponse {
+    pub(super) fn log(&self, query: &Query, response: &DnsResponse) {
+        (self.logger)(&ResponseLog::new(query, response, &self.policy));
+    }
+}
+
+impl fmt::Debug for LogResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LogResponse")
+            .field("policy", &self.policy)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<C> CachingClient<C>
+where
+    C: DnsHandle + Send + 'static,
+{
+    /// Sets the logger receiving each response from upstream, redacted per the policy
+    pub(crate) fn set_response_logger(
+        &mut self,
+        logger: Option<ResponseLogger>,
+        policy: RedactionPolicy,
+    ) {
+        self.response_logger = logger.map(|logger| LogResponse { logger, policy });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    use futures_executor::block_on;
+    use parking_lot::Mutex;
+
+    use super::{LoggedRecord, RedactionPolicy, ResponseLog};
+    use crate::caching_client::CachingClient;
+    use crate::lookup_ip::tests::*;
+    use crate::proto::op::{Message, Query, ResponseCode};
+    use crate::proto::rr::rdata::{A, PTR};
+    use crate::proto::rr::{Name, RData, Record, RecordType};
+    use crate::proto::xfer::{DnsRequestOptions, DnsResponse};
+
+    #[test]
+    fn test_response_logger_redaction() {
+        let query = Query::query(
+            Name::from_str("10.2.0.192.in-addr.arpa.").unwrap(),
+            RecordType::PTR,
+        );
+        let mut message = Message::new();
+        message.add_query(query.clone());
+        message.insert_answers(vec![Record::from_rdata(
+            query.name().clone(),
+            86400,
+            RData::PTR(PTR(
+                Name::from_str("alice-laptop.corp.example.com.").unwrap()
+            )),
+        )]);
+        message.insert_additionals(vec![Record::from_rdata(
+            Name::from_str("ns.example.com.").unwrap(),
+            86400,
+            RData::A(A::new(192, 0, 2, 1)),
+        )]);
+
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        let mut client = CachingClient::new(
+            2,
+            mock(vec![Ok(DnsResponse::from_message(message).unwrap())]),
+            false,
+        );
+        client.set_response_logger(
+            Some(Arc::new({
+                let logs = logs.clone();
+                move |log: &ResponseLog| logs.lock().push(log.clone())
+            })),
+            RedactionPolicy {
+                record_types: vec![RecordType::PTR],
+                ..RedactionPolicy::default()
+            },
+        );
+        // the response is logged as received, before it's rewritten
+        client.set_response_rewriter(Some(Arc::new(|message: &mut Message| {
+            message.take_additionals();
+        })));
+
+        block_on(client.lookup(query.clone(), DnsRequestOptions::default()))
+            .expect("lookup failed");
+        // cached responses are not logged again
+        block_on(client.lookup(query.clone(), DnsRequestOptions::default()))
+            .expect("lookup failed");
+
+        // the queried address is redacted along with the target name
+        let logs = logs.lock();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].query_name, None);
+        assert_eq!(logs[0].query_type, RecordType::PTR);
+        assert_eq!(logs[0].response_code, ResponseCode::NoError);
+        assert_eq!(
+            logs[0].answers,
+            vec![LoggedRecord {
+                name: None,
+                record_type: RecordType::PTR,
+                ttl: 86400,
+                data: None,
+            }]
+        );
+        assert_eq!(
+            logs[0].additionals,
+            vec![LoggedRecord {
+                name: Some(Name::from_str("ns.example.com.").unwrap()),
+                record_type: RecordType::A,
+                ttl: 86400,
+                data: Some("192.0.2.1".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_redacted_names() {
+        let policy = RedactionPolicy {
+            names: vec![Name::from_str("corp.example.com.").unwrap()],
+            ..RedactionPolicy::default()
+        };
+
+        let name = |name: &str| Name::from_str(name).unwrap();
+        assert!(policy.redacts(&name("corp.example.com."), RecordType::A));
+        assert!(policy.redacts(&name("alice.corp.example.com."), RecordType::AAAA));
+        assert!(!policy.redacts(&name("www.example.com."), RecordType::A));
+    }
+}
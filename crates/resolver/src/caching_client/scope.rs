@@ -0,0 +1,251 @@
+//! Lookups whose responses aren't shared, see [`CacheScope`]
+
+use std::time::Instant;
+
+use lru_cache::LruCache;
+
+use super::recent::{recent_queries, RecentQueries};
+use super::CachingClient;
+use crate::dns_lru::{CacheScope, MAX_SCOPES};
+use crate::lookup::Lookup;
+use crate::proto::error::ProtoError;
+use crate::proto::op::Query;
+use crate::proto::rr::rdata::opt::ClientSubnet;
+use crate::proto::xfer::DnsHandle;
+
+/// The recent queries of each scope, see `CachingClient::scope_to`
+pub(super) type ScopedRecentQueries = LruCache<CacheScope, RecentQueries>;
+
+/// Returns the recent queries of the scopes, holding as many scopes as the cache
+pub(super) fn scoped_recent_queries() -> ScopedRecentQueries {
+    LruCache::new(MAX_SCOPES)
+}
+
+impl<C> CachingClient<C>
+where
+    C: DnsHandle + Send + 'static,
+{
+    /// Caches the responses to the queries sent with this client subnet in the shared cache
+    ///
+    /// This is the subnet the resolver sends with all its queries, only the lookups with another
+    ///  subnet are scoped, see `ResolverOpts::edns_client_subnet`.
+    pub(crate) fn with_shared_client_subnet(mut self, subnet: Option<ClientSubnet>) -> Self {
+        self.shared_client_subnet = subnet;
+        self
+    }
+
+    /// Caches the responses of the resolution apart from the shared cache
+    ///
+    /// This applies to the responses to the queries sent with a client subnet, which may differ
+    ///  for other subnets, and to the responses cached with a minimum TTL, which must not be
+    ///  served longer than their TTL to the other lookups. The cache of the scope takes its
+    ///  capacity from the shared cache, see [`DnsLru::scoped`](crate::dns_lru::DnsLru::scoped),
+    ///  and the lookups with a minimum TTL are still served the responses of the shared cache.
+    pub(super) fn scope_to(&mut self, scope: CacheScope) {
+        if scope.reads_shared() {
+            self.shared_lru = Some(self.lru.clone());
+        }
+        self.lru = self.lru.scoped(scope);
+        self.read_through = None;
+
+        let mut scoped_recent = self.scoped_recent.lock();
+        if let Some(recent) = scoped_recent.get_mut(&scope) {
+            self.recent_queries = recent.clone();
+        } else {
+            self.recent_queries = recent_queries(self.lru.capacity());
+            scoped_recent.insert(scope, self.recent_queries.clone());
+        }
+    }
+
+    /// Check the shared cache for this query, when the scope of the resolution reads it
+    pub(super) fn lookup_from_shared(&self, query: &Query) -> Option<Result<Lookup, ProtoError>> {
+        self.shared_lru.as_ref()?.get(query, Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use futures_executor::block_on;
+
+    use crate::caching_client::CachingClient;
+    use crate::dns_lru::{CacheScope, DnsLru, TtlConfig};
+    use crate::lookup_ip::tests::*;
+    use crate::proto::op::{Message, Query};
+    use crate::proto::rr::rdata::opt::ClientSubnet;
+    use crate::proto::rr::rdata::A;
+    use crate::proto::rr::{Name, RData, Record, RecordType};
+    use crate::proto::xfer::{DnsRequestOptions, DnsResponse};
+
+    #[test]
+    fn test_per_lookup_min_ttl() {
+        let mut message = Message::new();
+        message.add_query(Query::new());
+        message.insert_answers(vec![Record::from_rdata(
+            Name::root(),
+            5,
+            RData::A(A::new(127, 0, 0, 1)),
+        )]);
+        let response = || Ok(DnsResponse::from_message(message.clone()).unwrap());
+
+        let cache = DnsLru::new(64, TtlConfig::default());
+        let client = mock(vec![response(), response()]);
+        let mut client = CachingClient::with_cache(cache.clone(), client, false);
+
+        // the entry is cached for the minimum TTL of the lookup rather than the upstream TTL
+        let mut options = DnsRequestOptions::default();
+        options.cache_min_ttl = Some(Duration::from_secs(60));
+        let now = Instant::now();
+        let lookup = block_on(client.lookup(Query::new(), options)).expect("lookup failed");
+        assert!(lookup.valid_until() >= now + Duration::from_secs(60));
+        let scoped = cache.scoped(CacheScope::of(&options, None));
+        let expires_at = scoped.expires_at(&Query::new()).unwrap();
+        assert!(expires_at >= now + Duration::from_secs(60));
+
+        // the shared cache isn't affected, the other lookups use the upstream TTL
+        assert!(cache.expires_at(&Query::new()).is_none());
+        let lookup = block_on(client.lookup(Query::new(), DnsRequestOptions::default()))
+            .expect("lookup failed");
+        let now = Instant::now();
+        assert!(lookup.valid_until() <= now + Duration::from_secs(5));
+        assert!(cache.expires_at(&Query::new()).unwrap() <= now + Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_client_subnet() {
+        use crate::proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+
+        let requests = Arc::new(Mutex::new(vec![]));
+        let mut client = CachingClient::new(64, RecordingHandle(requests.clone()), false);
+        let query = Query::query(Name::root(), RecordType::A);
+        let subnet = ClientSubnet::new(Ipv4Addr::new(192, 0, 2, 0).into(), 24, 0);
+        let mut options = DnsRequestOptions::default();
+        options.client_subnet = Some(subnet);
+
+        assert!(block_on(client.lookup(query.clone(), options)).is_ok());
+        {
+            let requests = requests.lock().unwrap();
+            assert_eq!(requests.len(), 1);
+            let edns = requests[0].extensions().as_ref().expect("no OPT record");
+            assert_eq!(
+                edns.option(EdnsCode::Subnet),
+                Some(&EdnsOption::Subnet(subnet))
+            );
+        }
+
+        // the response is cached for the subnet
+        assert!(block_on(client.lookup(query.clone(), options)).is_ok());
+        assert_eq!(requests.lock().unwrap().len(), 1);
+
+        // but not for lookups without or with another subnet
+        assert!(block_on(client.lookup(query.clone(), DnsRequestOptions::default())).is_ok());
+        assert_eq!(requests.lock().unwrap().len(), 2);
+        assert!(requests.lock().unwrap()[1].extensions().is_none());
+
+        options.client_subnet = Some(ClientSubnet::new(
+            Ipv4Addr::new(198, 51, 100, 0).into(),
+            24,
+            0,
+        ));
+        assert!(block_on(client.lookup(query, options)).is_ok());
+        assert_eq!(requests.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_shared_client_subnet() {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let cache = DnsLru::new(64, TtlConfig::default());
+        let subnet = ClientSubnet::new(Ipv4Addr::new(192, 0, 2, 0).into(), 24, 0);
+        let mut client =
+            CachingClient::with_cache(cache.clone(), RecordingHandle(requests.clone()), false)
+                .with_shared_client_subnet(Some(subnet));
+        let query = Query::query(Name::root(), RecordType::A);
+        let mut options = DnsRequestOptions::default();
+        options.client_subnet = Some(subnet);
+
+        // the responses to the queries sent with the subnet of the resolver are shared
+        assert!(block_on(client.lookup(query.clone(), options)).is_ok());
+        assert_eq!(cache.len(), 1);
+        assert!(client.scoped_recent.lock().is_empty());
+        assert!(block_on(client.lookup(query.clone(), options)).is_ok());
+        assert_eq!(requests.lock().unwrap().len(), 1);
+
+        // only the lookups with another subnet are scoped
+        options.client_subnet = Some(ClientSubnet::new(
+            Ipv4Addr::new(198, 51, 100, 0).into(),
+            24,
+            0,
+        ));
+        assert!(block_on(client.lookup(query, options)).is_ok());
+        assert_eq!(requests.lock().unwrap().len(), 2);
+        assert_eq!(client.scoped_recent.lock().len(), 1);
+        assert_eq!(cache.scoped(CacheScope::of(&options, None)).len(), 1);
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_client_subnet_min_query_interval() {
+        let message = |ip: A| {
+            let mut message = Message::new();
+            message.add_query(Query::new());
+            message.insert_answers(vec![Record::from_rdata(Name::root(), 0, RData::A(ip))]);
+            Ok(DnsResponse::from_message(message).unwrap())
+        };
+
+        let client = mock(vec![
+            message(A::new(127, 0, 0, 2)),
+            message(A::new(127, 0, 0, 1)),
+        ]);
+        let mut client = CachingClient::new(64, client, false)
+            .with_min_query_interval(Some(Duration::from_secs(60)));
+        let mut lookup = |subnet: [u8; 4]| {
+            let mut options = DnsRequestOptions::default();
+            options.client_subnet = Some(ClientSubnet::new(Ipv4Addr::from(subnet).into(), 24, 0));
+            block_on(client.lookup(Query::new(), options))
+                .expect("lookup failed")
+                .iter()
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        // the records have a TTL of 0, repeated queries of a subnet are still not sent upstream
+        assert_eq!(lookup([192, 0, 2, 0]), vec![RData::A(A::new(127, 0, 0, 1))]);
+        assert_eq!(lookup([192, 0, 2, 0]), vec![RData::A(A::new(127, 0, 0, 1))]);
+
+        // the result of the query of a subnet is not reused for another subnet
+        assert_eq!(
+            lookup([198, 51, 100, 0]),
+            vec![RData::A(A::new(127, 0, 0, 2))]
+        );
+    }
+
+    #[test]
+    fn test_min_ttl_reads_shared() {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let cache = DnsLru::new(64, TtlConfig::default());
+        let mut client =
+            CachingClient::with_cache(cache.clone(), RecordingHandle(requests.clone()), false);
+        let query = Query::query(Name::root(), RecordType::A);
+
+        // the lookups with a minimum TTL are served the shared responses
+        assert!(block_on(client.lookup(query.clone(), DnsRequestOptions::default())).is_ok());
+        let mut options = DnsRequestOptions::default();
+        options.cache_min_ttl = Some(Duration::from_secs(60));
+        assert!(block_on(client.lookup(query.clone(), options)).is_ok());
+        assert_eq!(requests.lock().unwrap().len(), 1);
+        assert_eq!(cache.len(), 1);
+
+        // but not the lookups with a client subnet
+        options.client_subnet = Some(ClientSubnet::new(Ipv4Addr::new(192, 0, 2, 0).into(), 24, 0));
+        assert!(block_on(client.lookup(query.clone(), options)).is_ok());
+        assert_eq!(requests.lock().unwrap().len(), 2);
+
+        // clearing the cache clears the scopes
+        client.clear_cache();
+        assert_eq!(cache.len(), 0);
+        assert!(client.scoped_recent.lock().is_empty());
+    }
+}
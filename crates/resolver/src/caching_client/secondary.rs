@@ -0,0 +1,91 @@
+//! Secondary cache read on the misses of the cache, see [`ReadThrough`]
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use super::CachingClient;
+use crate::dns_lru::{DnsCache, ReadThrough};
+use crate::lookup::Lookup;
+use crate::proto::error::ProtoError;
+use crate::proto::op::Query;
+use crate::proto::xfer::DnsHandle;
+
+impl<C> CachingClient<C>
+where
+    C: DnsHandle + Send + 'static,
+{
+    /// Sets the secondary cache, read on the misses of the cache and storing the upstream lookups
+    ///
+    /// The lookups scoped apart from the shared cache are never read from nor stored in the
+    ///  secondary cache, see `CachingClient::scope_to`.
+    pub(crate) fn set_secondary_cache(&mut self, secondary: Option<Arc<dyn DnsCache>>) {
+        self.read_through =
+            secondary.map(|secondary| ReadThrough::new(self.lru.clone(), secondary));
+    }
+
+    /// Check the secondary cache for this query, storing the lookup in the cache
+    pub(super) fn lookup_from_secondary(
+        &self,
+        query: &Query,
+    ) -> Option<Result<Lookup, ProtoError>> {
+        self.read_through
+            .as_ref()?
+            .read_secondary(query, Instant::now())
+    }
+
+    /// Stores the lookup, or the negative response, received from upstream in the secondary cache
+    pub(super) fn insert_secondary(&self, query: Query, lookup: &Result<Lookup, ProtoError>) {
+        let Some(read_through) = &self.read_through else {
+            return;
+        };
+
+        match lookup {
+            Ok(_) => {}
+            Err(e) if e.is_no_records_found() => {}
+            Err(_) => return,
+        }
+        read_through
+            .secondary()
+            .insert(query, lookup.clone(), Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    use futures_executor::block_on;
+
+    use crate::caching_client::CachingClient;
+    use crate::dns_lru::{DnsLru, TtlConfig};
+    use crate::lookup_ip::tests::*;
+    use crate::proto::op::Query;
+    use crate::proto::rr::{Name, RData, RecordType};
+    use crate::proto::xfer::DnsRequestOptions;
+
+    #[test]
+    fn test_secondary_cache() {
+        let cache = DnsLru::new(64, TtlConfig::default());
+        let secondary = DnsLru::new(64, TtlConfig::default());
+        let mut client = CachingClient::with_cache(cache.clone(), mock(vec![v4_message()]), false);
+        client.set_secondary_cache(Some(Arc::new(secondary.clone())));
+
+        // the lookup received from upstream is stored in both caches
+        let query = Query::query(Name::root(), RecordType::A);
+        block_on(client.lookup(query.clone(), DnsRequestOptions::default()))
+            .expect("lookup failed");
+        assert!(cache.get(&query, Instant::now()).is_some());
+        assert!(secondary.get(&query, Instant::now()).is_some());
+
+        // the misses of the cache are read from the secondary cache, without querying upstream
+        cache.clear();
+        let lookup = block_on(client.lookup(query.clone(), DnsRequestOptions::default()))
+            .expect("the lookup should be read from the secondary cache");
+        assert_eq!(
+            lookup.iter().cloned().collect::<Vec<_>>(),
+            vec![RData::A(std::net::Ipv4Addr::LOCALHOST.into())]
+        );
+        assert!(cache.get(&query, Instant::now()).is_some());
+    }
+}
@@ -10,17 +10,25 @@
 
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::proto::rr::Name;
+#[cfg(feature = "dns-over-rustls")]
+use crate::proto::error::ProtoError;
+use crate::proto::rr::rdata::opt::ClientSubnet;
+use crate::proto::rr::{Name, RecordType};
+use crate::proto::serialize::binary::DEFAULT_MAX_POINTER_DEPTH;
 use crate::proto::xfer::Protocol;
 #[cfg(feature = "dns-over-rustls")]
-use rustls::ClientConfig;
+use rustls::{
+    client::{ClientSessionStore, Resumption},
+    pki_types::{CertificateDer, PrivateKeyDer},
+    ClientConfig, RootCertStore,
+};
 
 #[cfg(all(feature = "serde", feature = "dns-over-rustls"))]
 use serde::{
@@ -304,6 +312,118 @@ impl Default for ResolverConfig {
 #[derive(Clone)]
 pub struct TlsClientConfig(pub Arc<ClientConfig>);
 
+#[cfg(feature = "dns-over-rustls")]
+impl TlsClientConfig {
+    /// Returns the client config used for the name servers without a `tls_config`
+    ///
+    /// It trusts the roots of the `webpki-roots` or `native-certs` feature, and has SNI disabled,
+    ///  see [`TlsClientConfig::with_sni`].
+    pub fn default_config() -> Result<Self, ProtoError> {
+        Ok(Self(crate::tls::CLIENT_CONFIG.clone()?))
+    }
+
+    /// Returns a config trusting the `roots` only, like the default config otherwise
+    ///
+    /// This doesn't need the `webpki-roots` or `native-certs` feature, so that a custom CA bundle
+    ///  can be used with neither of them enabled. Set on all the TLS name servers, the default
+    ///  config, which fails without these features, is never built. Fails if `roots` is empty.
+    pub fn new_with_roots(roots: RootCertStore) -> Result<Self, ProtoError> {
+        if roots.is_empty() {
+            return Err(ProtoError::from("no root certificates configured"));
+        }
+
+        let client_config = crate::tls::roots_config(roots)?;
+        Ok(Self(Arc::new(client_config)))
+    }
+
+    /// Returns a copy of this config with SNI enabled or disabled
+    ///
+    /// SNI is disabled in the default config, so that the name of the name server isn't sent in
+    ///  clear text, but some providers serving several certificates on one address require it.
+    ///  The config is used as is for the name servers it is set on, e.g. to enable SNI for some
+    ///  of them only.
+    pub fn with_sni(&self, enable_sni: bool) -> Self {
+        let mut client_config = (*self.0).clone();
+        client_config.enable_sni = enable_sni;
+        Self(Arc::new(client_config))
+    }
+
+    /// Returns a copy of this config offering the ALPN protocols
+    ///
+    /// Without any protocol, which is the case of the default config, none is offered for DNS over
+    ///  TLS and `h2` for DNS over HTTPS. Setting them is needed e.g. when a server multiplexes both
+    ///  on the same port, in which case DNS over TLS should offer `dot` (RFC 8310 section 8.2).
+    pub fn with_alpn_protocols(&self, alpn_protocols: Vec<Vec<u8>>) -> Self {
+        let mut client_config = (*self.0).clone();
+        client_config.alpn_protocols = alpn_protocols;
+        Self(Arc::new(client_config))
+    }
+
+    /// Returns a copy of this config resuming TLS sessions from the `store`
+    ///
+    /// The default config keeps the sessions in the in-memory store of rustls, shared by all the
+    ///  name servers using it, so that reconnections skip the full handshake. A custom store can
+    ///  e.g. persist the sessions or count the resumptions. It is shared by all the connections
+    ///  using this config, and replaced by a cache of the upstream when
+    ///  `ResolverOpts::tls_session_cache_size` is set.
+    pub fn with_session_store(&self, store: Arc<dyn ClientSessionStore>) -> Self {
+        let mut client_config = (*self.0).clone();
+        client_config.resumption = Resumption::store(store);
+        Self(Arc::new(client_config))
+    }
+
+    /// Returns a config presenting the client certificate to the name servers, for mutual TLS
+    ///
+    /// The name servers are authenticated with the `roots`, and the config has SNI disabled like
+    ///  the default config. Fails with the rustls error if the private key is invalid or isn't
+    ///  supported, rather than on the first connection.
+    ///
+    /// # Arguments
+    ///
+    /// * `roots` - the trust anchors of the certificates of the name servers
+    /// * `cert_chain` - the client certificate, followed by its intermediate certificates
+    /// * `key` - the private key of the client certificate
+    pub fn new_with_client_auth(
+        roots: RootCertStore,
+        cert_chain: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+    ) -> Result<Self, ProtoError> {
+        let client_config = crate::tls::client_auth_config(roots, cert_chain, key)?;
+        Ok(Self(Arc::new(client_config)))
+    }
+
+    /// Returns a copy of this config pinning the public keys of the certificates of the name servers
+    ///
+    /// After the usual validation of the certificate chain with the `roots`, the connections over
+    ///  TLS and HTTPS are rejected with `ProtoErrorKind::SpkiPinMismatch` unless the SHA-256 hash of
+    ///  the SubjectPublicKeyInfo of the name server's certificate is one of `pinned_spki`. Pinning
+    ///  defends against a compromised CA, several pins allow for key rotation. An empty pin set
+    ///  disables pinning. The verifier of a config can't be read back, so the `roots` replace its
+    ///  trust anchors, while its other settings, e.g. the client certificate of
+    ///  [`TlsClientConfig::new_with_client_auth`], are kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `roots` - the trust anchors of the certificates of the name servers
+    /// * `pinned_spki` - the SHA-256 hashes of the DER encoded SubjectPublicKeyInfo of the accepted keys
+    pub fn with_pinned_spki(
+        &self,
+        roots: RootCertStore,
+        pinned_spki: Vec<[u8; 32]>,
+    ) -> Result<Self, ProtoError> {
+        let mut client_config = (*self.0).clone();
+        crate::tls::pin_spki(&mut client_config, roots, pinned_spki)?;
+        Ok(Self(Arc::new(client_config)))
+    }
+
+    /// Returns the pin of the public key of the certificate, see [`TlsClientConfig::with_pinned_spki`]
+    ///
+    /// This is `None` if the certificate can't be parsed.
+    pub fn spki_pin(cert: &CertificateDer<'_>) -> Option<[u8; 32]> {
+        crate::tls::spki_pin(cert)
+    }
+}
+
 #[cfg(feature = "dns-over-rustls")]
 impl std::cmp::PartialEq for TlsClientConfig {
     fn eq(&self, other: &Self) -> bool {
@@ -363,6 +483,17 @@ pub struct NameServerConfig {
     pub tls_config: Option<TlsClientConfig>,
     /// The client address (IP and port) to use for connecting to the server.
     pub bind_addr: Option<SocketAddr>,
+    /// Timeout of the queries sent to this name server, overriding [`ResolverOpts::timeout`]
+    ///
+    /// E.g. a remote name server over TLS may warrant a longer timeout than a local one. Defaults
+    /// to `None`, the timeout of the `ResolverOpts` is used.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub timeout: Option<Duration>,
+    /// Weight of this name server with [`ServerSelectionStrategy::Weighted`], higher is preferred
+    ///
+    /// Defaults to 0, name servers of equal weight are tried in the order they are configured.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub weight: u32,
 }
 
 impl NameServerConfig {
@@ -377,6 +508,8 @@ impl NameServerConfig {
             #[cfg(feature = "dns-over-rustls")]
             tls_config: None,
             bind_addr: None,
+            timeout: None,
+            weight: 0,
         }
     }
 }
@@ -463,6 +596,8 @@ impl NameServerConfigGroup {
                 #[cfg(feature = "dns-over-rustls")]
                 tls_config: None,
                 bind_addr: None,
+                timeout: None,
+                weight: 0,
             };
             let tcp = NameServerConfig {
                 socket_addr,
@@ -473,6 +608,8 @@ impl NameServerConfigGroup {
                 #[cfg(feature = "dns-over-rustls")]
                 tls_config: None,
                 bind_addr: None,
+                timeout: None,
+                weight: 0,
             };
 
             name_servers.push(udp);
@@ -504,6 +641,8 @@ impl NameServerConfigGroup {
                 #[cfg(feature = "dns-over-rustls")]
                 tls_config: None,
                 bind_addr: None,
+                timeout: None,
+                weight: 0,
             };
 
             name_servers.push(config);
@@ -745,6 +884,22 @@ impl From<Vec<NameServerConfig>> for NameServerConfigGroup {
     }
 }
 
+/// Configuration of the bootstrap resolution of encrypted name servers identified by hostname
+///
+/// See [`crate::Bootstrap`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BootstrapConfig {
+    /// Addresses pinned for a hostname, no bootstrap query is made for these hostnames
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub pinned_addrs: HashMap<Name, Vec<IpAddr>>,
+    /// Duration for which bootstrap results are cached
+    ///
+    /// If unset, the results are cached for the TTL of the returned records.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub cache_ttl: Option<Duration>,
+}
+
 /// The lookup ip strategy
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
@@ -768,6 +923,50 @@ impl Default for LookupIpStrategy {
     }
 }
 
+/// The address families returned by IP lookups, regardless of the records which were queried
+///
+/// Unlike [`LookupIpStrategy`], this doesn't change the queries, addresses of the other families
+///  are still cached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum AddressFamilyFilter {
+    /// Return both Ipv4 and Ipv6 addresses (default)
+    #[default]
+    Any,
+    /// Only return Ipv4 addresses
+    Ipv4Only,
+    /// Only return Ipv6 addresses
+    Ipv6Only,
+}
+
+impl AddressFamilyFilter {
+    /// Returns true if the address is of an allowed family
+    pub fn allows(&self, ip: &IpAddr) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Ipv4Only => ip.is_ipv4(),
+            Self::Ipv6Only => ip.is_ipv6(),
+        }
+    }
+}
+
+/// The order of the address families returned by IP lookups, see [`ResolverOpts::ip_family_preference`]
+///
+/// Like [`AddressFamilyFilter`], this doesn't change the queries, addresses of the other family
+///  are still cached. Use the [`ResolverOpts::address_family_filter`] to only return the addresses
+///  of a family.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum IpFamilyPreference {
+    /// Return the Ipv4 addresses before the Ipv6 addresses
+    V4First,
+    /// Return the Ipv6 addresses before the Ipv4 addresses
+    V6First,
+    /// Return the addresses in the order they were resolved with the [`LookupIpStrategy`] (default)
+    #[default]
+    System,
+}
+
 /// The strategy for establishing the query order of name servers in a pool.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
@@ -787,6 +986,308 @@ impl Default for ServerOrderingStrategy {
     }
 }
 
+/// How the name servers of a pool are selected to send a query to.
+///
+/// The next name servers are only tried if the selected ones fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ServerSelectionStrategy {
+    /// The name servers are queried one at a time, in the order of the [`ServerOrderingStrategy`].
+    ///
+    /// This is `Parallel` with a `ResolverOpts::num_concurrent_reqs` of 1, except that the name
+    /// servers are never shuffled.
+    Sequential,
+    /// Up to `ResolverOpts::num_concurrent_reqs` name servers are queried at once, in the order of
+    /// the [`ServerOrderingStrategy`].
+    #[default]
+    Parallel,
+    /// The name servers are queried one at a time, by decreasing `NameServerConfig::weight`.
+    ///
+    /// The [`ServerOrderingStrategy`] and `ResolverOpts::shuffle_dns_servers` are ignored.
+    Weighted,
+    /// The name servers are queried one at a time, the one with the lowest smoothed response time
+    /// first. The response time is an exponentially weighted moving average of the observed ones,
+    /// decaying while a name server isn't queried so that slow name servers are tried again.
+    ///
+    /// The [`ServerOrderingStrategy`] and `ResolverOpts::shuffle_dns_servers` are ignored.
+    LeastLatency,
+}
+
+/// How to handle a response received over TCP which is still marked as truncated, or which exceeds
+///  the `max_response_size` of the [`ResolverOpts`].
+///
+/// Such a response is never returned or cached as it only contains partial data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum TruncatedTcpPolicy {
+    /// Retry the query on the other TCP name servers of the pool, failing if none of them returns
+    /// a complete response.
+    #[default]
+    Retry,
+    /// Fail the query immediately.
+    Fail,
+}
+
+/// How to handle UDP responses larger than the payload size advertised in the query, see
+/// [`ResolverOpts::oversized_udp_policy`]
+///
+/// Non-conformant name servers may send such responses, which are detected as long as they fit in
+/// the receive buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum OversizedUdpPolicy {
+    /// Accept the response as received.
+    #[default]
+    Accept,
+    /// Treat the response as an error and retry the query over TCP.
+    Retry,
+}
+
+/// The TLS implementation of DNS over TLS connections, see [`ResolverOpts::tls_backend`]
+///
+/// Only the implementations enabled by the `dns-over-rustls`, `dns-over-native-tls` and
+/// `dns-over-openssl` features are available, so that a binary built with several of them can
+/// choose one at runtime.
+#[cfg(feature = "dns-over-tls")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
+pub enum TlsBackend {
+    /// rustls, configured with the `tls_config` of the name servers.
+    #[cfg(feature = "dns-over-rustls")]
+    Rustls,
+    /// The TLS library of the platform, the `tls_config` of the name servers is ignored.
+    #[cfg(feature = "dns-over-native-tls")]
+    NativeTls,
+    /// OpenSSL, the `tls_config` of the name servers is ignored.
+    #[cfg(feature = "dns-over-openssl")]
+    OpenSsl,
+}
+
+#[cfg(feature = "dns-over-tls")]
+impl Default for TlsBackend {
+    /// Returns the first backend enabled of rustls, native-tls and OpenSSL as the default.
+    fn default() -> Self {
+        crate::tls::DEFAULT_BACKEND
+    }
+}
+
+/// The transports over which queries are sent, see [`ResolverOpts::protocol_preference`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ProtocolPreference {
+    /// Queries are sent to the UDP name servers first, then over TCP when the response is
+    /// truncated or on errors, see `try_tcp_on_error`.
+    #[default]
+    UdpThenTcp,
+    /// Queries are only sent to the TCP name servers, or other stream transports, the UDP name
+    /// servers are never connected to, so no query is sent over UDP.
+    TcpOnly,
+}
+
+/// Which response is used when name servers queried in parallel return different answers
+///
+/// Only the responses of the name servers queried in parallel, see
+///  [`ResolverOpts::num_concurrent_reqs`], are compared. The response of a name server queried
+///  again after the others failed is used as is. See [`ResolverOpts::answer_discrepancy_policy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum AnswerDiscrepancyPolicy {
+    /// The first response received is used, without waiting for the other name servers
+    #[default]
+    First,
+    /// A response validated with DNSSEC by its name server, with the Authentic Data flag set, is
+    /// preferred over the first response received if the answers differ
+    PreferDnssecValidated,
+    /// The first response received is used, differing answers of the other name servers are
+    /// reported, see [`NameServerPool::set_discrepancy_reporter`](crate::name_server::NameServerPool::set_discrepancy_reporter)
+    Flag,
+}
+
+/// How to handle a response to a recursive query without the Recursion Available flag
+///
+/// Such a name server isn't recursing, e.g. a misconfigured forwarder, and may return a referral or
+/// partial answers. See [`ResolverOpts::recursion_unavailable_policy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum RecursionUnavailablePolicy {
+    /// Use the response as is
+    #[default]
+    Accept,
+    /// Use the response, logging a warning
+    Warn,
+    /// Reject the response and try the other name servers of the pool, failing with
+    /// `ProtoErrorKind::RecursionUnavailable` if none of them offers recursion
+    Failover,
+}
+
+/// How to handle a response with answer records of another type than the queried one
+///
+/// Some broken middleboxes return A records regardless of the queried type, which would otherwise be
+/// cached alongside the answer to the query. CNAME, DNAME and RRSIG records are always consistent
+/// with the query. See [`ResolverOpts::answer_type_mismatch_policy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum AnswerTypeMismatchPolicy {
+    /// Use the response as is
+    #[default]
+    Accept,
+    /// Reject the response with `ProtoErrorKind::AnswerTypeMismatch` before it is cached, the other
+    /// name servers of the pool are tried
+    Reject,
+}
+
+/// How to handle a response without an OPT record to a query with EDNS
+///
+/// Such a name server doesn't support EDNS, so features relying on it, e.g. DNSSEC or UDP responses
+/// larger than 512 bytes, won't work with it. See [`ResolverOpts::edns_absent_policy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum EdnsAbsentPolicy {
+    /// Use the response as is, and keep sending EDNS to the name server
+    #[default]
+    Accept,
+    /// Use the response, and remember that the name server doesn't support EDNS, later queries to
+    /// it are sent without EDNS until `reprobe_after` elapsed
+    ///
+    /// Queries sent without EDNS also lack the options relying on it, e.g. the DO bit, the client
+    /// subnet and cookies. EDNS is sent again once the interval elapsed, in case the response was
+    /// spoofed or the name server was upgraded. The name server is never downgraded when
+    /// `ResolverOpts::validate` is set, as DNSSEC requires EDNS, the response is used as is.
+    Downgrade {
+        /// Duration for which queries to the name server are sent without EDNS
+        reprobe_after: Duration,
+    },
+    /// Reject the response with `ProtoErrorKind::EdnsUnsupported`, the other name servers of the
+    /// pool are tried
+    Fail,
+}
+
+/// A maximum rate of queries, see [`ResolverOpts::global_rate`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Rate {
+    /// Maximum number of queries over the period, also the size of a burst of queries
+    pub limit: u32,
+    /// Period over which `limit` queries may be sent
+    pub period: Duration,
+}
+
+/// Configuration of the circuit breaker of each name server
+///
+/// After `failure_threshold` consecutive connection failures, e.g. timeouts, the name server is
+///  skipped for the `cooldown`. A single probe query is then sent to it, which either closes the
+///  circuit on success or opens it for another cooldown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CircuitBreakerConfig {
+    /// Number of consecutive failures after which the circuit opens
+    pub failure_threshold: u32,
+    /// Duration for which the name server is skipped once the circuit opens
+    pub cooldown: Duration,
+}
+
+/// How lookups handle expired entries of the cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum StaleMode {
+    /// Expired entries are never returned, the lookup waits for a fresh response.
+    #[default]
+    Disabled,
+    /// Entries which expired no longer than `grace` ago are returned immediately, flagged as stale,
+    /// while a background task refreshes them in the cache.
+    ///
+    /// Only positive responses are served stale. Refreshing requires a connection provider which
+    /// can spawn background tasks, see [`ConnectionProvider::spawn_bg`](crate::name_server::ConnectionProvider::spawn_bg).
+    StaleWhileRevalidate {
+        /// How long after their expiry entries may still be served
+        grace: Duration,
+    },
+}
+
+/// A fraction of the TTL of cached entries, below which they are refreshed ahead of their expiry
+///
+/// See [`ResolverOpts::prefetch_threshold`].
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "f32", into = "f32")
+)]
+pub struct PrefetchThreshold(f32);
+
+impl PrefetchThreshold {
+    /// Returns the threshold of the `fraction` of the TTL, `None` unless it is within `0.0..=1.0`
+    pub fn new(fraction: f32) -> Option<Self> {
+        (0.0..=1.0).contains(&fraction).then_some(Self(fraction))
+    }
+
+    /// The fraction of the TTL
+    pub fn fraction(self) -> f32 {
+        self.0
+    }
+}
+
+// the fraction is never NaN
+impl Eq for PrefetchThreshold {}
+
+impl TryFrom<f32> for PrefetchThreshold {
+    type Error = &'static str;
+
+    fn try_from(fraction: f32) -> Result<Self, Self::Error> {
+        Self::new(fraction).ok_or("the prefetch threshold must be within 0.0..=1.0")
+    }
+}
+
+impl From<PrefetchThreshold> for f32 {
+    fn from(threshold: PrefetchThreshold) -> Self {
+        threshold.0
+    }
+}
+
+/// The positive responses to which the `positive_min_ttl` of [`ResolverOpts`] is applied
+///
+/// The scope is decided on the records cached, which carry their DNSSEC validation state. A
+///  cached lookup may merge the records of several responses, e.g. along a CNAME chain, from
+///  different name servers, so scopes on the flags of a response, e.g. Authoritative Answer, or
+///  on the name server which sent it aren't supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum MinTtlScope {
+    /// The minimum TTL is applied to all positive responses
+    #[default]
+    All,
+    /// The minimum TTL is only applied to responses with records not validated by DNSSEC, secure
+    ///  responses keep the TTL of their records
+    Unsigned,
+}
+
+/// How the resolver is constructed when the config has no name servers
+///
+/// See [`Resolver::try_new`](crate::Resolver::try_new).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum EmptyNameServers {
+    /// Constructing the resolver fails, unless the hosts file is used
+    #[default]
+    Error,
+    /// The Google public name servers are used, see [`NameServerConfigGroup::google`]
+    Google,
+    /// The Cloudflare public name servers are used, see [`NameServerConfigGroup::cloudflare`]
+    Cloudflare,
+}
+
+impl EmptyNameServers {
+    /// Returns the name servers to use instead of an empty list, if any
+    pub(crate) fn fallback(self) -> Option<NameServerConfigGroup> {
+        match self {
+            Self::Error => None,
+            Self::Google => Some(NameServerConfigGroup::google()),
+            Self::Cloudflare => Some(NameServerConfigGroup::cloudflare()),
+        }
+    }
+}
+
 /// Whether the system hosts file should be respected by the resolver.
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
@@ -821,25 +1322,94 @@ pub struct ResolverOpts {
     /// Number of retries after lookup failure before giving up. Defaults to 2
     pub attempts: usize,
     /// Rotate through the resource records in the response (if there is more than one for a given name)
+    ///
+    /// The records of the queried type returned from the cache are rotated by one position on each
+    ///  hit, for crude load balancing, while they stay in the same order in the cache.
     pub rotate: bool,
     /// Validate the names in the response, not implemented don't really see the point unless you need to support
     ///  badly configured DNS
     pub check_names: bool,
     /// Enable edns, for larger records
     pub edns0: bool,
+    /// Custom EDNS options, as `(option code, data)`, added to the OPT record of every query
+    ///
+    /// This is intended for experimental or vendor-specific options, an OPT record is added to
+    ///  queries even if `edns0` is disabled.
+    pub edns_options: Vec<(u16, Vec<u8>)>,
+    /// An edns-client-subnet option added to the queries, see RFC 7871
+    ///
+    /// This lets CDNs answer with addresses close to the clients of this network rather than to
+    ///  the name servers. A source prefix of 0, e.g. `ClientSubnet::new(Ipv4Addr::UNSPECIFIED.into(),
+    ///  0, 0)`, only sends the family, asking the name servers not to add any address of the client
+    ///  for privacy, see RFC 7871 section 7.1.2. The scope prefix of the responses is only logged,
+    ///  the responses are cached in the shared cache, as all the queries are sent with this
+    ///  prefix. Overridden by [`LookupOptions::client_subnet`], defaults to `None`.
+    pub edns_client_subnet: Option<ClientSubnet>,
+    /// Send DNS cookies with the queries over UDP, see RFC 7873
+    ///
+    /// Each name server is sent a random client cookie, and the server cookie it returns is sent
+    ///  back with the later queries, which mitigates spoofing by off-path attackers. Responses
+    ///  with another client cookie are discarded while waiting for the genuine one, and responses with the `BADCOOKIE` response code
+    ///  are retried once with the server cookie they contain. Like `edns_options`, an OPT record is
+    ///  added to queries even if `edns0` is disabled. Defaults to false.
+    pub use_dns_cookies: bool,
     /// Use DNSSEC to validate the request
     pub validate: bool,
+    /// Verify the signatures of RRsets with at least this many RRSIGs on the blocking thread pool
+    ///
+    /// This keeps the executor responsive under DNSSEC validation load, see `validate`.
+    pub dnssec_verification_offload: Option<usize>,
+    /// Cache results which failed DNSSEC validation for this long, instead of validating them again
+    ///
+    /// Cached bogus results are returned as `ProtoErrorKind::Bogus` errors, see `validate`.
+    pub bogus_cache_ttl: Option<Duration>,
+    /// Ignore glue records which are out of the bailiwick of their delegation
+    ///
+    /// Glue A and AAAA records are only used and cached when their name is within the zone
+    ///  delegated to the name server, which must be the queried name or one of its ancestors.
+    ///  The name servers of ignored glue must be resolved separately. Defaults to `false`.
+    pub strict_glue: bool,
     /// The ip_strategy for the Resolver to use when lookup Ipv4 or Ipv6 addresses
     pub ip_strategy: LookupIpStrategy,
+    /// Only query for A records in IP lookups when the system has no IPv6 route
+    ///
+    /// On networks where IPv6 is present but broken, AAAA records resolve to unreachable addresses.
+    ///  The route is detected as for `skip_unreachable_address_families`, without sending any
+    ///  packet, and AAAA records are still queried with [`ProtocolPreference::TcpOnly`]. The
+    ///  [`LookupIpStrategy::Ipv4Only`] and [`LookupIpStrategy::Ipv6Only`] strategies are never
+    ///  overridden. Defaults to `false`.
+    pub aaaa_requires_ipv6_route: bool,
+    /// The address families returned by IP lookups, filtered after resolution with the `ip_strategy`
+    ///
+    /// IP lookups left without any address fail with a `NoRecordsFound` error.
+    pub address_family_filter: AddressFamilyFilter,
+    /// The order of the address families returned by IP lookups, applied after the `address_family_filter`
+    pub ip_family_preference: IpFamilyPreference,
     /// Cache size is in number of records (some records can be large)
     pub cache_size: usize,
     /// Check /etc/hosts file before dns requery (only works for unix like OS)
     pub use_hosts_file: ResolveHosts,
+    /// Number of TLS sessions cached per encrypted upstream, to resume them when reconnecting
+    ///
+    ///  Resuming a session avoids a full handshake, see
+    ///  [`NameServer::tls_resumptions`](crate::name_server::NameServer::tls_resumptions). Defaults
+    ///  to `None`, the sessions are cached by the store of the TLS client config, see
+    ///  [`TlsClientConfig::with_session_store`], which is shared by all upstreams by default.
+    pub tls_session_cache_size: Option<usize>,
+    /// TLS implementation of the DNS over TLS connections, when several are enabled
+    ///
+    /// Defaults to the first backend enabled of rustls, native-tls and OpenSSL.
+    #[cfg(feature = "dns-over-tls")]
+    pub tls_backend: TlsBackend,
+    /// Behavior when the config has no name servers, see [`Resolver::try_new`](crate::Resolver::try_new)
+    pub empty_name_servers: EmptyNameServers,
     /// Optional minimum TTL for positive responses.
     ///
     /// If this is set, any positive responses with a TTL lower than this value will have a TTL of
     /// `positive_min_ttl` instead. Otherwise, this will default to 0 seconds.
     pub positive_min_ttl: Option<Duration>,
+    /// The positive responses to which `positive_min_ttl` is applied, all of them by default
+    pub positive_min_ttl_scope: MinTtlScope,
     /// Optional minimum TTL for negative (`NXDOMAIN`) responses.
     ///
     /// If this is set, any negative responses with a TTL lower than this value will have a TTL of
@@ -859,17 +1429,139 @@ pub struct ResolverOpts {
     ///
     /// [`MAX_TTL`]: ../dns_lru/const.MAX_TTL.html
     pub negative_max_ttl: Option<Duration>,
+    /// Record types whose negative responses are never cached
+    ///
+    /// Queries of these types are sent again on each miss, e.g. for `SRV` records which change
+    ///  frequently. Defaults to none.
+    pub negative_cache_excluded_types: Vec<RecordType>,
+    /// Minimum number of records of a positive response of each type for it to be cached
+    ///
+    /// Responses with fewer records are returned but not cached, e.g. a single `A` record for a
+    ///  name which usually has several of them, as it may come from a poisoning attempt. Only
+    ///  the records of the queried type are counted, not their signatures nor the CNAME records
+    ///  leading to them. Types without a minimum are always cached. Defaults to none.
+    pub min_cached_records: HashMap<RecordType, usize>,
+    /// Duration for which positive responses are served stale after they expire, when the upstream
+    ///  lookup fails
+    ///
+    /// This keeps names resolving during outages of the name servers. Entries served stale have a
+    ///  TTL of 1 second and are flagged with `Lookup::is_stale`. Defaults to `None`.
+    pub serve_stale_ttl: Option<Duration>,
+    /// Minimum interval between identical upstream queries
+    ///
+    /// Queries which miss the cache, e.g. for records with a TTL of 0, and are repeated within this
+    ///  interval return the result of the last upstream query instead of querying again. This
+    ///  protects upstream servers from callers looping on a query. Defaults to `None`, disabled.
+    pub min_query_interval: Option<Duration>,
+    /// How lookups handle expired entries of the cache, see [`StaleMode`]
+    pub stale_mode: StaleMode,
+    /// Fraction of the TTL remaining below which cached entries are refreshed in the background
+    ///
+    /// Entries looked up during the last fraction of their TTL are refreshed once, before they
+    ///  expire, so that popular entries don't miss the cache. Negative responses are never
+    ///  refreshed. This is independent of the `stale_mode`. Refreshing requires a connection
+    ///  provider which can spawn background tasks, see
+    ///  [`ConnectionProvider::spawn_bg`](crate::name_server::ConnectionProvider::spawn_bg).
+    ///  Defaults to `None`, disabled.
+    pub prefetch_threshold: Option<PrefetchThreshold>,
     /// Number of concurrent requests per query
     ///
     /// Where more than one nameserver is configured, this configures the resolver to send queries
     /// to a number of servers in parallel. Defaults to 2; 0 or 1 will execute requests serially.
     pub num_concurrent_reqs: usize,
+    /// Which response is used when the name servers queried in parallel return different answers
+    ///
+    /// Except with [`AnswerDiscrepancyPolicy::First`], the responses of all the name servers
+    ///  queried in parallel are awaited and their answers compared, which may detect split-horizon
+    ///  or poisoned name servers. The lookup then takes as long as the slowest of these name
+    ///  servers, up to the timeout. Retries after failures aren't compared. See
+    ///  `num_concurrent_reqs`.
+    pub answer_discrepancy_policy: AnswerDiscrepancyPolicy,
+    /// How responses to recursive queries without the Recursion Available flag are handled, see
+    ///  [`RecursionUnavailablePolicy`]
+    pub recursion_unavailable_policy: RecursionUnavailablePolicy,
+    /// How responses without an OPT record to queries with EDNS are handled, see
+    ///  [`EdnsAbsentPolicy`]
+    pub edns_absent_policy: EdnsAbsentPolicy,
     /// Preserve all intermediate records in the lookup response, such as CNAME records
     pub preserve_intermediates: bool,
+    /// Report NODATA responses, i.e. `NoError` without records of the queried type, as `NXDomain`
+    ///
+    /// This is a compatibility shim for legacy callers which only handle `NXDOMAIN`, and is off by
+    ///  default. The distinction between a name which doesn't exist and a name which only has
+    ///  records of other types is lost: callers can no longer tell that the name exists, e.g. that
+    ///  a host only has IPv4 addresses when looking up its IPv6 addresses. The cache still stores
+    ///  the actual response code.
+    pub nodata_as_nxdomain: bool,
+    /// Maximum number of follow-up queries for the targets of CNAME records, per resolution
+    ///
+    /// Name servers sending minimal responses only return the CNAME records, without the records
+    ///  of the target. The target is then resolved with a follow-up query, and both are cached.
+    ///  A resolution which requires more follow-ups fails, e.g. for CNAME loops. Defaults to 8.
+    pub max_cname_follow_ups: u8,
     /// Try queries over TCP if they fail over UDP.
     pub try_tcp_on_error: bool,
+    /// Transports over which queries are sent, e.g. only TCP on networks blocking UDP port 53
+    ///
+    /// With [`ProtocolPreference::TcpOnly`], the configuration must include TCP name servers, as the
+    ///  name server groups for IPs do. Defaults to [`ProtocolPreference::UdpThenTcp`].
+    pub protocol_preference: ProtocolPreference,
+    /// Retry queries over TCP, or other stream transports, if the UDP name servers respond with SERVFAIL.
+    ///
+    /// Some middleboxes mangle UDP traffic, in which case a stream transport may yield a clean answer.
+    ///  This is independent from the retry of truncated responses over TCP. Defaults to false.
+    pub retry_servfail_other_transport: bool,
+    /// Skip name servers of an address family for which this host appears to have no route.
+    ///
+    /// Name servers of an unreachable family, e.g. IPv6 name servers on an IPv4-only host, are then
+    ///  skipped rather than waiting for each query to them to time out. The local connectivity is
+    ///  detected when first needed, and again after queries failed. It is never detected with
+    ///  [`ProtocolPreference::TcpOnly`], as the detection uses UDP sockets. Defaults to false.
+    pub skip_unreachable_address_families: bool,
+    /// Policy for responses received over TCP which are still truncated.
+    pub truncated_tcp_policy: TruncatedTcpPolicy,
+    /// Maximum size in bytes of the responses received over TCP, or other stream transports.
+    ///
+    /// Larger responses are handled according to the `truncated_tcp_policy`. Defaults to `None`, i.e.
+    ///  any response size is accepted.
+    pub max_response_size: Option<usize>,
+    /// Policy for UDP responses larger than the advertised EDNS payload size.
+    pub oversized_udp_policy: OversizedUdpPolicy,
+    /// Reject responses that do not contain exactly one query, matching the query of the request.
+    ///
+    /// Such responses are treated as errors before they are cached. Defaults to false.
+    pub strict_response_query: bool,
+    /// How responses with answer records of another type than the queried one are handled, see
+    ///  [`AnswerTypeMismatchPolicy`]
+    pub answer_type_mismatch_policy: AnswerTypeMismatchPolicy,
+    /// Maximum number of compression pointers followed to read a single name of a response
+    ///
+    /// The transports always decode responses with a limit of [`DEFAULT_MAX_POINTER_DEPTH`], which
+    ///  is the default, so a higher limit is clamped to it: crafted pointer chains exceeding the
+    ///  default limit fail to decode regardless. A lower limit rejects responses with shorter chains,
+    ///  at the cost of decoding them again.
+    pub max_pointer_depth: usize,
     /// The server ordering strategy that the resolver should use.
+    ///
+    /// Only used with [`ServerSelectionStrategy::Sequential`] and
+    ///  [`ServerSelectionStrategy::Parallel`], the other strategies order the name servers
+    ///  themselves.
     pub server_ordering_strategy: ServerOrderingStrategy,
+    /// How the name servers are selected for each query, see [`ServerSelectionStrategy`]
+    ///
+    /// `Weighted` and `LeastLatency` take precedence over the `server_ordering_strategy`.
+    pub server_selection_strategy: ServerSelectionStrategy,
+    /// Stop sending queries to name servers which are consistently failing, see [`CircuitBreakerConfig`]
+    ///
+    /// Defaults to `None`, name servers are never skipped.
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    /// Maximum rate of queries sent to all name servers together
+    ///
+    /// Each query sent to a name server counts, including the queries sent in parallel, see
+    ///  `num_concurrent_reqs`, and the retries. Queries exceeding the rate are rejected with a `ProtoErrorKind::Busy` error rather than
+    ///  sent, see [`NameServerPool::global_tokens`](crate::name_server::NameServerPool::global_tokens).
+    ///  Defaults to `None`, the rate is not limited.
+    pub global_rate: Option<Rate>,
     /// Request upstream recursive resolvers to not perform any recursion.
     ///
     /// This is true by default, disabling this is useful for requesting single records, but may prevent successful resolution.
@@ -877,6 +1569,9 @@ pub struct ResolverOpts {
     /// This is true by default, disabling this is useful for requesting single records, but may prevent successful resolution.
     pub authentic_data: bool,
     /// Shuffle DNS servers before each query.
+    ///
+    /// Only used with [`ServerSelectionStrategy::Parallel`], the other strategies query the name
+    ///  servers in their own order.
     pub shuffle_dns_servers: bool,
     /// Local UDP ports to avoid when making outgoing queries
     pub avoid_local_udp_ports: Arc<HashSet<u16>>,
@@ -894,21 +1589,58 @@ impl Default for ResolverOpts {
             rotate: false,
             check_names: true,
             edns0: false,
+            edns_options: Vec::new(),
+            edns_client_subnet: None,
+            use_dns_cookies: false,
             validate: false,
+            dnssec_verification_offload: None,
+            bogus_cache_ttl: None,
+            strict_glue: false,
             ip_strategy: LookupIpStrategy::default(),
+            aaaa_requires_ipv6_route: false,
+            address_family_filter: AddressFamilyFilter::default(),
+            ip_family_preference: IpFamilyPreference::default(),
             cache_size: 32,
             use_hosts_file: ResolveHosts::default(),
+            tls_session_cache_size: None,
+            #[cfg(feature = "dns-over-tls")]
+            tls_backend: TlsBackend::default(),
+            empty_name_servers: EmptyNameServers::default(),
             positive_min_ttl: None,
+            positive_min_ttl_scope: MinTtlScope::default(),
             negative_min_ttl: None,
             positive_max_ttl: None,
             negative_max_ttl: None,
+            negative_cache_excluded_types: vec![],
+            min_cached_records: HashMap::new(),
+            serve_stale_ttl: None,
+            min_query_interval: None,
+            stale_mode: StaleMode::default(),
+            prefetch_threshold: None,
             num_concurrent_reqs: 2,
+            answer_discrepancy_policy: AnswerDiscrepancyPolicy::default(),
+            recursion_unavailable_policy: RecursionUnavailablePolicy::default(),
+            edns_absent_policy: EdnsAbsentPolicy::default(),
 
             // Defaults to `true` to match the behavior of dig and nslookup.
             preserve_intermediates: true,
+            nodata_as_nxdomain: false,
+            max_cname_follow_ups: 8,
 
             try_tcp_on_error: false,
+            protocol_preference: ProtocolPreference::default(),
+            retry_servfail_other_transport: false,
+            skip_unreachable_address_families: false,
+            truncated_tcp_policy: TruncatedTcpPolicy::default(),
+            max_response_size: None,
+            oversized_udp_policy: OversizedUdpPolicy::default(),
+            strict_response_query: false,
+            answer_type_mismatch_policy: AnswerTypeMismatchPolicy::default(),
+            max_pointer_depth: DEFAULT_MAX_POINTER_DEPTH,
             server_ordering_strategy: ServerOrderingStrategy::default(),
+            server_selection_strategy: ServerSelectionStrategy::default(),
+            circuit_breaker: None,
+            global_rate: None,
             recursion_desired: true,
             authentic_data: false,
             shuffle_dns_servers: false,
@@ -917,6 +1649,85 @@ impl Default for ResolverOpts {
     }
 }
 
+/// Options for a single lookup, these take precedence over the [`ResolverOpts`] for that call only
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct LookupOptions {
+    /// Disable DNSSEC validation for this lookup
+    ///
+    /// The CD (checking disabled) bit is set on the request so that upstream resolvers return the
+    ///  records even when they fail validation. Records are still annotated with their `Proof`,
+    ///  e.g. bogus records will be returned marked as bogus rather than producing an error. These
+    ///  results are neither read from nor stored in the cache.
+    pub checking_disabled: bool,
+    /// Correlation data of the originating request, attached to the tracing span of this lookup
+    ///
+    /// This takes precedence over the context of the resolver, see [`LookupContext`].
+    pub context: Option<LookupContext>,
+    /// The instant by which this lookup must complete, or fail with a timeout
+    ///
+    /// The deadline bounds all the queries of the lookup, including those issued to build the
+    ///  DNSSEC chain of trust when validating, e.g. the DS and DNSKEY records of the parent zones.
+    pub deadline: Option<Instant>,
+    /// A minimum TTL for which the records resolved by this lookup are cached
+    ///
+    /// This raises the TTL of the records below it, e.g. to keep a critical name cached longer than
+    ///  its upstream TTL, without changing the `positive_min_ttl` of the cache. The TTL is still
+    ///  capped by `positive_max_ttl`, and negative responses are cached as usual. The records are
+    ///  cached apart from the shared cache, so the lookups without this minimum TTL, or with
+    ///  another one, are not served the records past their upstream TTL.
+    pub min_ttl: Option<Duration>,
+    /// Only returns the records of the looked up type whose data matches the filter
+    ///
+    /// Other records, e.g. the CNAMEs followed to the records, are returned unfiltered. The cache
+    ///  still stores all the records, so that other lookups of the same name are unaffected. The
+    ///  lookup fails with a `NoRecordsFound` error if no record of the type matches, and with a
+    ///  message error if the filter is not registered with the resolver.
+    pub record_filter: Option<RecordFilter>,
+    /// Always sends the queries of this lookup upstream, without reading from or storing in the cache
+    ///
+    /// This is meant for lookups which must reflect the current state of the upstream servers, e.g.
+    ///  health checks, without evicting or refreshing the entries shared with other lookups.
+    pub bypass_cache: bool,
+    /// Fail unless all the records of this lookup are DNSSEC validated as secure
+    ///
+    /// The lookup then returns a `ResolveErrorKind::NotSecure` error for insecure, bogus or
+    ///  indeterminate records, and for negative responses whose nonexistence proof isn't secure.
+    ///  Its queries set the DO bit, and are validated regardless of the `validate` policy of the
+    ///  resolver. If the resolver doesn't validate others, records of its cache which aren't
+    ///  secure are queried again. This always fails without the `dnssec` feature.
+    pub require_secure: bool,
+    /// Send the queries of this lookup with an edns-client-subnet option of this prefix, rather than
+    ///  the [`ResolverOpts::edns_client_subnet`]
+    ///
+    /// This is meant e.g. to test the geo-targeted answers of a CDN for clients of another
+    ///  network. The responses are cached for this prefix only, apart from the lookups without
+    ///  or with another prefix.
+    pub client_subnet: Option<ClientSubnet>,
+}
+
+/// A predicate on the data of the records returned by a lookup, see [`LookupOptions::record_filter`]
+///
+/// This is a handle to a predicate registered with
+///  [`Resolver::add_record_filter`](crate::Resolver::add_record_filter), which keeps the
+///  [`LookupOptions`] `Copy`. Filters are equal if they are handles to the same predicate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RecordFilter(pub(crate) u64);
+
+/// Correlation data for a lookup, e.g. the trace of the request which triggered it
+///
+/// The identifiers are recorded, in hex, as the `trace_id` and `span_id` fields of the `lookup`
+///  span, which encloses the events emitted by the cache and the name servers during the lookup.
+///  The context is set for a single lookup with [`LookupOptions::context`], or for all the lookup
+///  methods of a resolver with [`Resolver::with_context`](crate::Resolver::with_context).
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub struct LookupContext {
+    /// Identifier of the trace of the originating request
+    pub trace_id: u128,
+    /// Identifier of the span of the originating request
+    pub span_id: u64,
+}
+
 /// IP addresses for Google Public DNS
 pub const GOOGLE_IPS: &[IpAddr] = &[
     IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)),
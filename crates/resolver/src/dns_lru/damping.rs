@@ -0,0 +1,181 @@
+//! Damping of the TTL of flapping entries
+//!
+//! See [`TtlConfig::with_flapping_damping`](super::TtlConfig::with_flapping_damping).
+
+use std::time::Duration;
+
+use super::pinning::PinsGuard;
+use super::{DnsLru, LruValue};
+use crate::lookup::same_records;
+use crate::proto::op::Query;
+use crate::proto::rr::Record;
+
+/// Extends the TTL of entries whose records change on each refresh
+///
+/// See [`TtlConfig::with_flapping_damping`](super::TtlConfig::with_flapping_damping).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FlappingDamping {
+    /// Number of consecutive changes of the records from which an entry is flapping
+    pub threshold: u32,
+    /// Maximum TTL of flapping entries
+    pub max_ttl: Duration,
+}
+
+/// The flapping status of a cached entry, see [`DnsLru::flapping_status`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FlappingStatus {
+    /// Number of consecutive insertions which changed the records, 0 if they were unchanged
+    pub consecutive_changes: u32,
+    /// True if the TTL of the entry was extended by the configured [`FlappingDamping`]
+    pub dampened: bool,
+}
+
+impl DnsLru {
+    /// Returns the number of consecutive changes of the records of the query once they are replaced by `records`
+    ///
+    /// `pinned` are the locked pinned entries, if any, which are then stored into by the caller.
+    pub(super) fn flaps_after(
+        &self,
+        query: &Query,
+        records: &[Record],
+        pinned: Option<&PinsGuard<'_>>,
+    ) -> u32 {
+        let key = self.key(query);
+        let query = &*key;
+        let flaps = |value: &LruValue| match &value.lookup {
+            Ok(lookup) if !same_records(lookup.records(), records) => value.flaps.saturating_add(1),
+            _ => 0,
+        };
+
+        if let Some(flaps) = pinned.and_then(|pinned| pinned.get(query).map(flaps)) {
+            return flaps;
+        }
+        self.shard(query).inspect(query, flaps).unwrap_or(0)
+    }
+
+    /// Extends the TTL of a flapping entry, see [`FlappingDamping`]
+    ///
+    /// The extended TTL is still capped by the maximum positive TTL of the cache.
+    pub(super) fn dampened(&self, ttl: Duration, flaps: u32) -> Duration {
+        let Some(damping) = self
+            .flapping_damping
+            .filter(|damping| flaps >= damping.threshold)
+        else {
+            return ttl;
+        };
+
+        let doublings = (flaps - damping.threshold + 1).min(31);
+        ttl.saturating_mul(1 << doublings)
+            .min(damping.max_ttl)
+            .min(self.positive_max_ttl)
+            .max(ttl)
+    }
+
+    /// Returns the flapping status of the cached entry of the query
+    ///
+    /// Changes of the records are counted for all positive entries, whether or not a
+    ///  [`FlappingDamping`] is configured.
+    pub fn flapping_status(&self, query: &Query) -> Option<FlappingStatus> {
+        let key = self.key(query);
+        let query = &*key;
+        let status = |value: &LruValue| FlappingStatus {
+            consecutive_changes: value.flaps,
+            dampened: self
+                .flapping_damping
+                .is_some_and(|damping| value.flaps >= damping.threshold),
+        };
+
+        if let Some(status) = self.pinned.inspect(query, status) {
+            return Some(status);
+        }
+        self.shard(query).inspect(query, status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::time::{Duration, Instant};
+
+    use super::{FlappingDamping, FlappingStatus};
+    use crate::dns_lru::{DnsLru, TtlConfig};
+    use crate::proto::op::Query;
+    use crate::proto::rr::rdata::A;
+    use crate::proto::rr::{Name, RData, Record, RecordType};
+
+    #[test]
+    fn test_flapping_damping() {
+        let now = Instant::now();
+        let ttls = TtlConfig::default().with_flapping_damping(Some(FlappingDamping {
+            threshold: 2,
+            max_ttl: Duration::from_secs(60),
+        }));
+        let lru = DnsLru::new(8, ttls);
+        let name = Name::from_str("www.example.com.").unwrap();
+        let query = Query::query(name.clone(), RecordType::A);
+        let insert = |last_octet: u8, now: Instant| {
+            let record =
+                Record::from_rdata(name.clone(), 5, RData::A(A::new(127, 0, 0, last_octet)));
+            let lookup = lru.insert(query.clone(), vec![(record, 5)], now);
+            lookup.valid_until() - now
+        };
+
+        // alternating answers are refreshed once expired, the TTL is doubled from the threshold
+        let mut at = now;
+        let mut ttls = vec![];
+        for octet in [1, 2, 1, 2, 1, 2] {
+            assert!(lru.get(&query, at).is_none());
+            let ttl = insert(octet, at);
+            ttls.push(ttl.as_secs());
+            at += ttl + Duration::from_secs(1);
+        }
+        assert_eq!(ttls, vec![5, 5, 10, 20, 40, 60]);
+        assert_eq!(
+            lru.flapping_status(&query),
+            Some(FlappingStatus {
+                consecutive_changes: 5,
+                dampened: true,
+            })
+        );
+
+        // an unchanged answer stops the damping
+        assert_eq!(insert(2, at), Duration::from_secs(5));
+        assert_eq!(
+            lru.flapping_status(&query),
+            Some(FlappingStatus {
+                consecutive_changes: 0,
+                dampened: false,
+            })
+        );
+    }
+
+    #[test]
+    fn test_flapping_damping_positive_max_ttl() {
+        let now = Instant::now();
+        let ttls = TtlConfig {
+            positive_max_ttl: Some(Duration::from_secs(15)),
+            ..TtlConfig::default()
+        }
+        .with_flapping_damping(Some(FlappingDamping {
+            threshold: 1,
+            max_ttl: Duration::from_secs(60),
+        }));
+        let lru = DnsLru::new(8, ttls);
+        let name = Name::from_str("www.example.com.").unwrap();
+        let query = Query::query(name.clone(), RecordType::A);
+
+        // the dampened TTL is capped by the maximum positive TTL, not by the damping
+        let mut at = now;
+        let mut ttls = vec![];
+        for octet in [1, 2, 1, 2] {
+            let record = Record::from_rdata(name.clone(), 5, RData::A(A::new(127, 0, 0, octet)));
+            let ttl = lru
+                .insert(query.clone(), vec![(record, 5)], at)
+                .valid_until()
+                - at;
+            ttls.push(ttl.as_secs());
+            at += ttl + Duration::from_secs(1);
+        }
+        assert_eq!(ttls, vec![5, 10, 15, 15]);
+    }
+}
@@ -0,0 +1,4313 @@
+// Copyright 2015-2017 Benjamin Fry <benjaminfry@me.com>
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// https://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// https://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! An LRU cache designed for work with DNS lookups
+
+mod damping;
+mod pinning;
+mod scope;
+mod stale;
+
+use std::any::Any;
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use hickory_proto::error::{ProtoError, ProtoErrorKind};
+#[cfg(feature = "dnssec")]
+use hickory_proto::rr::dnssec::rdata::RRSIG;
+use linked_hash_map::LinkedHashMap;
+use lru_cache::LruCache;
+use parking_lot::{Mutex, MutexGuard, RwLock, RwLockWriteGuard};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::proto::op::Query;
+#[cfg(feature = "dnssec")]
+use crate::proto::rr::RecordData;
+use crate::proto::rr::{Name, RData, Record, RecordType};
+use crate::proto::runtime::Time;
+
+use crate::config;
+use crate::lookup::Lookup;
+
+pub use self::damping::{FlappingDamping, FlappingStatus};
+use self::pinning::{Pins, PinsGuard};
+use self::scope::Scopes;
+pub(crate) use self::scope::{CacheScope, MAX_SCOPES};
+
+/// Maximum TTL. This is set to one day (in seconds).
+///
+/// [RFC 2181, section 8](https://tools.ietf.org/html/rfc2181#section-8) says
+/// that the maximum TTL value is 2147483647, but implementations may place an
+/// upper bound on received TTLs.
+pub(crate) const MAX_TTL: u32 = 86400_u32;
+
+/// Minimum capacity of each shard when the number of shards is chosen by [`DnsLru::new`]
+const MIN_SHARD_CAPACITY: usize = 1024;
+
+/// Maximum number of shards chosen by [`DnsLru::new`]
+const MAX_DEFAULT_SHARDS: usize = 16;
+
+/// How cached entries are served, see [`LruValue::current_lookup_at`]
+#[derive(Clone, Copy)]
+struct ServeOptions {
+    /// True if the TTLs are decremented to the remaining TTL of the entry
+    decrement_ttl: bool,
+    /// Age beyond which entries are never served, whatever their TTL
+    max_age: Option<Duration>,
+    /// True if the records of the entries are rotated on each hit
+    rotate: bool,
+}
+
+#[derive(Debug)]
+struct LruValue {
+    // In the Err case, this represents an NXDomain
+    lookup: Result<Lookup, ProtoError>,
+    stored_at: Instant,
+    valid_until: Instant,
+    /// Application specific data attached to the entry, opaque to the cache
+    metadata: Option<Arc<dyn Any + Send + Sync>>,
+    /// Set once a [`PrefetchHint`] was returned for this entry
+    prefetched: AtomicBool,
+    /// Number of times the records were rotated, see [`LruValue::rotated`]
+    rotation: AtomicUsize,
+    /// Number of consecutive insertions which changed the records, see [`DnsLru::flapping_status`]
+    flaps: u32,
+}
+
+impl LruValue {
+    /// Returns true if this set of ips is still valid
+    fn is_current(&self, now: Instant) -> bool {
+        now <= self.valid_until
+    }
+
+    /// Returns true if this value should be kept, it is current or expired for no longer than `grace`
+    fn is_retained(&self, now: Instant, grace: Duration) -> bool {
+        match self.valid_until.checked_add(grace) {
+            Some(retained_until) => now <= retained_until,
+            None => true,
+        }
+    }
+
+    /// Returns true if this value should be removed, it expired longer than `grace` ago or it is
+    ///  older than `max_age`
+    fn is_evictable(&self, now: Instant, grace: Duration, max_age: Option<Duration>) -> bool {
+        !self.is_retained(now, grace) || self.is_older_than(now, max_age)
+    }
+
+    /// Returns the ttl as a Duration of time remaining.
+    fn ttl(&self, now: Instant) -> Duration {
+        self.valid_until.saturating_duration_since(now)
+    }
+
+    /// The TTL the entry was cached with
+    fn original_ttl(&self) -> Duration {
+        self.valid_until.saturating_duration_since(self.stored_at)
+    }
+
+    /// Returns the cached lookup with TTLs updated to `now`, or `None` if it has expired
+    ///
+    /// Unless `decrement_ttl`, the TTLs are the TTL the entry was cached with instead. Either way,
+    ///  the TTLs don't exceed the time left before the entry is older than `max_age`.
+    fn lookup_at(
+        &self,
+        now: Instant,
+        decrement_ttl: bool,
+        max_age: Option<Duration>,
+    ) -> Option<Result<Lookup, ProtoError>> {
+        if !self.is_current(now) {
+            return None;
+        }
+
+        let ttl = match decrement_ttl {
+            true => self.ttl(now),
+            false => self.original_ttl(),
+        };
+        let ttl = match max_age {
+            Some(max_age) => {
+                ttl.min(max_age.saturating_sub(now.saturating_duration_since(self.stored_at)))
+            }
+            None => ttl,
+        };
+        let mut result = self.with_ttl(ttl).lookup;
+        if let Err(err) = &mut result {
+            DnsLru::nx_error_with_ttl(err, ttl);
+        }
+        Some(result)
+    }
+
+    /// Returns true if the entry was stored longer than `max_age` ago
+    fn is_older_than(&self, now: Instant, max_age: Option<Duration>) -> bool {
+        max_age.is_some_and(|max_age| now.saturating_duration_since(self.stored_at) > max_age)
+    }
+
+    /// Returns the current lookup
+    ///
+    /// Entries stored longer than the `max_age` of `serve` ago are treated as expired. Expired
+    /// entries are only served by [`DnsLru::get_stale`] and [`DnsLru::get_stale_on_failure`].
+    fn current_lookup_at(
+        &self,
+        now: Instant,
+        serve: ServeOptions,
+    ) -> Option<Result<Lookup, ProtoError>> {
+        if self.is_older_than(now, serve.max_age) {
+            return None;
+        }
+
+        if !self.is_current(now) {
+            return None;
+        }
+
+        let lookup = self.lookup_at(now, serve.decrement_ttl, serve.max_age)?;
+        match serve.rotate {
+            true => Some(lookup.map(|lookup| self.rotated(lookup))),
+            false => Some(lookup),
+        }
+    }
+
+    /// Returns the lookup with the records of the queried type rotated by one more position than on
+    ///  the previous call, the other records, e.g. CNAMEs, are kept in place
+    ///
+    /// Only the order of the returned lookup changes, the cached records are kept in their order,
+    ///  and the lookup keeps its flags, e.g. stale or partial.
+    fn rotated(&self, lookup: Lookup) -> Lookup {
+        let query_type = lookup.query().query_type();
+        let positions = lookup
+            .records()
+            .iter()
+            .enumerate()
+            .filter(|(_, record)| record.record_type() == query_type)
+            .map(|(position, _)| position)
+            .collect::<Vec<_>>();
+        if positions.len() < 2 {
+            return lookup;
+        }
+
+        let shift = self.rotation.fetch_add(1, Ordering::Relaxed) % positions.len();
+        let mut records = lookup.records().to_vec();
+        for (i, position) in positions.iter().enumerate() {
+            let rotated = positions[(i + shift) % positions.len()];
+            records[*position] = lookup.records()[rotated].clone();
+        }
+        lookup.with_records(records)
+    }
+
+    /// Returns a hint to refresh this entry if less than `threshold` of its TTL remains
+    ///
+    /// The hint is returned until the entry is marked as prefetched, the next hint is then for the
+    ///  entry replacing it. Negative responses are never prefetched.
+    fn prefetch_hint(
+        &self,
+        now: Instant,
+        threshold: config::PrefetchThreshold,
+    ) -> Option<PrefetchHint> {
+        if self.lookup.is_err() || !self.is_current(now) || self.prefetched.load(Ordering::Relaxed)
+        {
+            return None;
+        }
+
+        let ttl = self.valid_until.saturating_duration_since(self.stored_at);
+        let remaining_ttl = self.ttl(now);
+        if remaining_ttl.as_secs_f32() >= ttl.as_secs_f32() * threshold.fraction() {
+            return None;
+        }
+
+        Some(PrefetchHint { remaining_ttl })
+    }
+
+    /// Returns a copy of the entry whose records all have the TTL
+    fn with_ttl(&self, ttl: Duration) -> Self {
+        let lookup = match &self.lookup {
+            Ok(lookup) => {
+                let records = lookup
+                    .records()
+                    .iter()
+                    .map(|record| {
+                        let mut record = record.clone();
+                        record.set_ttl(ttl.as_secs() as u32);
+                        record
+                    })
+                    .collect::<Vec<Record>>();
+                Ok(lookup.with_records(records).with_deadline(self.valid_until))
+            }
+            Err(e) => Err(e.clone()),
+        };
+        Self {
+            lookup,
+            stored_at: self.stored_at,
+            valid_until: self.valid_until,
+            metadata: self.metadata.clone(),
+            prefetched: AtomicBool::new(self.prefetched.load(Ordering::Relaxed)),
+            rotation: AtomicUsize::new(self.rotation.load(Ordering::Relaxed)),
+            flaps: self.flaps,
+        }
+    }
+}
+
+/// The eviction strategy and locking used by each shard of a [`DnsLru`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CacheBackend {
+    /// Strict least-recently-used eviction
+    ///
+    /// Every read updates the recency of the entry, so all accesses to a shard are serialized by a mutex.
+    #[default]
+    Lru,
+    /// Clock, or second-chance, eviction
+    ///
+    /// Reads only take a shared lock and mark the entry as referenced, allowing concurrent reads.
+    ///  On insertion into a full shard, referenced entries are given a second chance and the first
+    ///  unreferenced entry is evicted. This approximates LRU and suits read-heavy workloads.
+    Clock,
+    /// Segmented LRU eviction, considering the access frequency of entries
+    ///
+    /// New entries are inserted in a probationary segment, and are promoted to a protected segment
+    ///  when read again. Entries are only evicted from the probationary segment, so entries which
+    ///  are read repeatedly survive a burst of new entries which would evict them under plain LRU.
+    #[cfg(feature = "segmented-lru")]
+    SegmentedLru,
+}
+
+/// The lookup of a query in a [`Shard`], see [`Shard::get`]
+#[derive(Default)]
+struct ShardLookup {
+    /// The lookup along with its prefetch hint, if the entry may be served
+    lookup: Option<(Result<Lookup, ProtoError>, Option<PrefetchHint>)>,
+    /// Whether the entry was removed because it was out of date
+    removed: bool,
+}
+
+/// A single shard of the cache, using one of the [`CacheBackend`]s
+#[derive(Debug)]
+enum Shard {
+    Lru(Mutex<LruMap>),
+    Clock(RwLock<ClockCache>),
+    #[cfg(feature = "segmented-lru")]
+    SegmentedLru(Mutex<SegmentedLruCache>),
+}
+
+impl Shard {
+    fn new(backend: CacheBackend, capacity: usize) -> Self {
+        match backend {
+            CacheBackend::Lru => Self::Lru(Mutex::new(LruMap::new(capacity))),
+            CacheBackend::Clock => Self::Clock(RwLock::new(ClockCache::new(capacity))),
+            #[cfg(feature = "segmented-lru")]
+            CacheBackend::SegmentedLru => {
+                Self::SegmentedLru(Mutex::new(SegmentedLruCache::new(capacity)))
+            }
+        }
+    }
+
+    /// Inserts the value, returning the queries of the entries evicted to make room for it
+    ///
+    /// When the shard is full, up to `batch` entries are evicted at once, so that the next
+    ///  insertions don't evict.
+    fn insert(&self, query: Query, value: LruValue, batch: usize) -> Vec<Query> {
+        match self {
+            Self::Lru(cache) => {
+                let mut cache = cache.lock();
+                let mut evicted = vec![];
+                if !cache.contains_key(&query) && cache.len() >= cache.capacity() {
+                    while evicted.len() < batch {
+                        let Some((evicted_query, _)) = cache.remove_lru() else {
+                            break;
+                        };
+                        evicted.push(evicted_query);
+                    }
+                }
+                cache.insert(query, value);
+                evicted
+            }
+            Self::Clock(cache) => cache.write().insert(query, value, batch),
+            #[cfg(feature = "segmented-lru")]
+            Self::SegmentedLru(cache) => cache.lock().insert(query, value, batch),
+        }
+    }
+
+    /// Returns the current lookup, or the lookup served stale as allowed by `serve`, along with a
+    ///  hint to refresh it if `prefetch` is set, see [`LruValue::prefetch_hint`]
+    ///
+    /// Expired entries are removed unless they are within `grace`, which must cover the stale windows.
+    fn get(
+        &self,
+        query: &Query,
+        now: Instant,
+        grace: Duration,
+        serve: ServeOptions,
+        prefetch: Option<config::PrefetchThreshold>,
+    ) -> ShardLookup {
+        let hinted = |value: &LruValue| {
+            let lookup = value.current_lookup_at(now, serve)?;
+            let hint = prefetch.and_then(|threshold| value.prefetch_hint(now, threshold));
+            Some((lookup, hint))
+        };
+
+        match self {
+            Self::Lru(cache) => {
+                let mut cache = cache.lock();
+                let Some(value) = cache.get_mut(query) else {
+                    return ShardLookup::default();
+                };
+                let lookup = hinted(value);
+
+                // in this case, we can preemptively remove out of date elements
+                // this assumes time is always moving forward, this would only not be true in contrived situations where now
+                //  is not current time, like tests...
+                let removed = lookup.is_none() && value.is_evictable(now, grace, serve.max_age);
+                if removed {
+                    cache.remove(query);
+                }
+
+                ShardLookup { lookup, removed }
+            }
+            Self::Clock(cache) => {
+                let (lookup, evictable) = {
+                    let cache = cache.read();
+                    let Some(value) = cache.get(query) else {
+                        return ShardLookup::default();
+                    };
+                    (hinted(value), value.is_evictable(now, grace, serve.max_age))
+                };
+
+                // only take the write lock when there is an out of date element to remove
+                let removed = evictable
+                    && cache
+                        .write()
+                        .remove_expired(query, now, grace, serve.max_age);
+                ShardLookup { lookup, removed }
+            }
+            #[cfg(feature = "segmented-lru")]
+            Self::SegmentedLru(cache) => {
+                let mut cache = cache.lock();
+                let Some(value) = cache.get_mut(query) else {
+                    return ShardLookup::default();
+                };
+                let lookup = hinted(value);
+
+                let removed = lookup.is_none() && value.is_evictable(now, grace, serve.max_age);
+                if removed {
+                    cache.remove(query);
+                }
+
+                ShardLookup { lookup, removed }
+            }
+        }
+    }
+
+    fn metadata(&self, query: &Query, now: Instant) -> Option<Arc<dyn Any + Send + Sync>> {
+        self.inspect(query, |value| {
+            value.metadata.clone().filter(|_| value.is_current(now))
+        })?
+    }
+
+    /// Calls `f` with the entry of the query, including an expired one
+    ///
+    /// The entry is peeked, its recency is not updated, so that introspection doesn't affect eviction.
+    fn inspect<T>(&self, query: &Query, f: impl FnOnce(&LruValue) -> T) -> Option<T> {
+        match self {
+            Self::Lru(cache) => cache.lock().peek(query).map(f),
+            Self::Clock(cache) => cache.read().peek(query).map(f),
+            #[cfg(feature = "segmented-lru")]
+            Self::SegmentedLru(cache) => cache.lock().peek(query).map(f),
+        }
+    }
+
+    fn expires_at(&self, query: &Query) -> Option<Instant> {
+        self.inspect(query, |value| value.valid_until)
+    }
+
+    fn remove(&self, query: &Query) -> Option<LruValue> {
+        match self {
+            Self::Lru(cache) => cache.lock().remove(query),
+            Self::Clock(cache) => cache.write().remove(query),
+            #[cfg(feature = "segmented-lru")]
+            Self::SegmentedLru(cache) => cache.lock().remove(query),
+        }
+    }
+
+    /// Calls `f` with each entry, including expired ones
+    fn for_each(&self, mut f: impl FnMut(&Query, &LruValue)) {
+        match self {
+            Self::Lru(cache) => cache
+                .lock()
+                .iter()
+                .for_each(|(query, value)| f(query, value)),
+            Self::Clock(cache) => cache
+                .read()
+                .entries
+                .iter()
+                .for_each(|entry| f(&entry.query, &entry.value)),
+            #[cfg(feature = "segmented-lru")]
+            Self::SegmentedLru(cache) => {
+                let cache = cache.lock();
+                cache
+                    .probation
+                    .iter()
+                    .chain(cache.protected.iter())
+                    .for_each(|(query, value)| f(query, value))
+            }
+        }
+    }
+
+    fn clear(&self) {
+        match self {
+            Self::Lru(cache) => cache.lock().clear(),
+            Self::Clock(cache) => cache.write().clear(),
+            #[cfg(feature = "segmented-lru")]
+            Self::SegmentedLru(cache) => cache.lock().clear(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Self::Lru(cache) => cache.lock().len(),
+            Self::Clock(cache) => cache.read().entries.len(),
+            #[cfg(feature = "segmented-lru")]
+            Self::SegmentedLru(cache) => cache.lock().len(),
+        }
+    }
+
+    /// Returns an empty shard of the same backend and capacity
+    fn backend(&self) -> CacheBackend {
+        match self {
+            Self::Lru(_) => CacheBackend::Lru,
+            Self::Clock(_) => CacheBackend::Clock,
+            #[cfg(feature = "segmented-lru")]
+            Self::SegmentedLru(_) => CacheBackend::SegmentedLru,
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        match self {
+            Self::Lru(cache) => cache.lock().capacity(),
+            Self::Clock(cache) => cache.read().capacity,
+            #[cfg(feature = "segmented-lru")]
+            Self::SegmentedLru(cache) => cache.lock().capacity(),
+        }
+    }
+
+    /// Changes the capacity, evicting entries if the shard holds more than the new capacity
+    fn set_capacity(&self, capacity: usize) {
+        match self {
+            Self::Lru(cache) => cache.lock().set_capacity(capacity),
+            Self::Clock(cache) => cache.write().set_capacity(capacity),
+            #[cfg(feature = "segmented-lru")]
+            Self::SegmentedLru(cache) => cache.lock().set_capacity(capacity),
+        }
+    }
+
+    /// Locks the shard for writing, e.g. to update several entries at once
+    fn write(&self) -> ShardGuard<'_> {
+        match self {
+            Self::Lru(cache) => ShardGuard::Lru(cache.lock()),
+            Self::Clock(cache) => ShardGuard::Clock(cache.write()),
+            #[cfg(feature = "segmented-lru")]
+            Self::SegmentedLru(cache) => ShardGuard::SegmentedLru(cache.lock()),
+        }
+    }
+}
+
+/// A shard locked for writing, see [`Shard::write`]
+enum ShardGuard<'a> {
+    Lru(MutexGuard<'a, LruMap>),
+    Clock(RwLockWriteGuard<'a, ClockCache>),
+    #[cfg(feature = "segmented-lru")]
+    SegmentedLru(MutexGuard<'a, SegmentedLruCache>),
+}
+
+impl ShardGuard<'_> {
+    /// Inserts the value, returning the queries of the entries evicted to make room for it
+    fn insert(&mut self, query: Query, value: LruValue) -> Vec<Query> {
+        match self {
+            Self::Lru(cache) => {
+                // evict explicitly, the cache would otherwise evict without returning the entry
+                let mut evicted = vec![];
+                if !cache.contains_key(&query) && cache.len() >= cache.capacity() {
+                    evicted.extend(cache.remove_lru().map(|(query, _)| query));
+                }
+                cache.insert(query, value);
+                evicted
+            }
+            Self::Clock(cache) => cache.insert(query, value, 1),
+            #[cfg(feature = "segmented-lru")]
+            Self::SegmentedLru(cache) => cache.insert(query, value, 1),
+        }
+    }
+
+    fn remove(&mut self, query: &Query) -> Option<LruValue> {
+        match self {
+            Self::Lru(cache) => cache.remove(query),
+            Self::Clock(cache) => cache.remove(query),
+            #[cfg(feature = "segmented-lru")]
+            Self::SegmentedLru(cache) => cache.remove(query),
+        }
+    }
+
+    /// Returns the queries of all the entries for the name
+    fn queries_of(&self, name: &Name) -> Vec<Query> {
+        match self {
+            Self::Lru(cache) => cache.names.queries_of(name).to_vec(),
+            Self::Clock(cache) => cache.names.queries_of(name).to_vec(),
+            #[cfg(feature = "segmented-lru")]
+            Self::SegmentedLru(cache) => {
+                let mut queries = cache.probation.names.queries_of(name).to_vec();
+                queries.extend_from_slice(cache.protected.names.queries_of(name));
+                queries
+            }
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        match self {
+            Self::Lru(cache) => cache.capacity(),
+            Self::Clock(cache) => cache.capacity,
+            #[cfg(feature = "segmented-lru")]
+            Self::SegmentedLru(cache) => cache.capacity(),
+        }
+    }
+
+    fn set_capacity(&mut self, capacity: usize) {
+        match self {
+            Self::Lru(cache) => cache.set_capacity(capacity),
+            Self::Clock(cache) => cache.set_capacity(capacity),
+            #[cfg(feature = "segmented-lru")]
+            Self::SegmentedLru(cache) => cache.set_capacity(capacity),
+        }
+    }
+}
+
+/// The queries of the entries of each name, to find them without scanning all the entries
+#[derive(Debug, Default)]
+struct NameIndex(HashMap<Name, Vec<Query>>);
+
+impl NameIndex {
+    fn insert(&mut self, query: &Query) {
+        let queries = self.0.entry(query.name().clone()).or_default();
+        if !queries.contains(query) {
+            queries.push(query.clone());
+        }
+    }
+
+    fn remove(&mut self, query: &Query) {
+        let Some(queries) = self.0.get_mut(query.name()) else {
+            return;
+        };
+        queries.retain(|q| q != query);
+        if queries.is_empty() {
+            self.0.remove(query.name());
+        }
+    }
+
+    fn queries_of(&self, name: &Name) -> &[Query] {
+        self.0.get(name).map_or(&[], Vec::as_slice)
+    }
+
+    fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+/// A cache with least-recently-used eviction
+///
+/// Unlike [`lru_cache::LruCache`], entries can be peeked without updating their recency.
+#[derive(Debug)]
+struct LruMap {
+    map: LinkedHashMap<Query, LruValue>,
+    names: NameIndex,
+    capacity: usize,
+}
+
+impl LruMap {
+    fn new(capacity: usize) -> Self {
+        Self {
+            map: LinkedHashMap::new(),
+            names: NameIndex::default(),
+            capacity,
+        }
+    }
+
+    /// Returns the entry of the query, marking it as the most recently used
+    fn get_mut(&mut self, query: &Query) -> Option<&mut LruValue> {
+        self.map.get_refresh(query)
+    }
+
+    /// Returns the entry of the query, without updating its recency
+    fn peek(&self, query: &Query) -> Option<&LruValue> {
+        self.map.get(query)
+    }
+
+    fn contains_key(&self, query: &Query) -> bool {
+        self.map.contains_key(query)
+    }
+
+    /// Inserts the value as the most recently used entry, evicting the least recently used one if
+    ///  the cache is full
+    fn insert(&mut self, query: Query, value: LruValue) -> Option<LruValue> {
+        self.names.insert(&query);
+        let old_value = self.map.insert(query, value);
+        if self.map.len() > self.capacity {
+            self.remove_lru();
+        }
+        old_value
+    }
+
+    fn remove(&mut self, query: &Query) -> Option<LruValue> {
+        let value = self.map.remove(query)?;
+        self.names.remove(query);
+        Some(value)
+    }
+
+    fn remove_lru(&mut self) -> Option<(Query, LruValue)> {
+        let (query, value) = self.map.pop_front()?;
+        self.names.remove(&query);
+        Some((query, value))
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&Query, &LruValue)> {
+        self.map.iter()
+    }
+
+    fn clear(&mut self) {
+        self.map.clear();
+        self.names.clear();
+    }
+
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn set_capacity(&mut self, capacity: usize) {
+        while self.map.len() > capacity {
+            self.remove_lru();
+        }
+        self.capacity = capacity;
+    }
+}
+
+#[derive(Debug)]
+struct ClockEntry {
+    query: Query,
+    value: LruValue,
+    /// Set on each read, cleared when the clock hand passes over the entry
+    referenced: AtomicBool,
+}
+
+/// A cache with clock eviction, lookups only need shared access
+#[derive(Debug)]
+struct ClockCache {
+    capacity: usize,
+    index: HashMap<Query, usize>,
+    names: NameIndex,
+    entries: Vec<ClockEntry>,
+    hand: usize,
+}
+
+impl ClockCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            index: HashMap::with_capacity(capacity),
+            names: NameIndex::default(),
+            entries: Vec::with_capacity(capacity),
+            hand: 0,
+        }
+    }
+
+    fn get(&self, query: &Query) -> Option<&LruValue> {
+        let entry = &self.entries[*self.index.get(query)?];
+        entry.referenced.store(true, Ordering::Relaxed);
+        Some(&entry.value)
+    }
+
+    /// Returns the entry of the query, without marking it as referenced
+    fn peek(&self, query: &Query) -> Option<&LruValue> {
+        Some(&self.entries[*self.index.get(query)?].value)
+    }
+
+    /// Inserts the value, returning the queries of the entries evicted to make room for it
+    ///
+    /// When full, up to `batch` entries are evicted at once.
+    fn insert(&mut self, query: Query, value: LruValue, batch: usize) -> Vec<Query> {
+        if let Some(&i) = self.index.get(&query) {
+            let entry = &mut self.entries[i];
+            entry.value = value;
+            *entry.referenced.get_mut() = true;
+            return vec![];
+        }
+
+        if self.capacity == 0 {
+            return vec![];
+        }
+
+        let entry = ClockEntry {
+            query: query.clone(),
+            value,
+            referenced: AtomicBool::new(false),
+        };
+        self.names.insert(&query);
+
+        if self.entries.len() < self.capacity {
+            self.index.insert(query, self.entries.len());
+            self.entries.push(entry);
+            return vec![];
+        }
+
+        // advance the hand, giving referenced entries a second chance, until an entry can be evicted
+        let mut evicted = loop {
+            let hand = self.hand;
+            self.hand = (hand + 1) % self.entries.len();
+
+            if !std::mem::take(self.entries[hand].referenced.get_mut()) {
+                let old = std::mem::replace(&mut self.entries[hand], entry);
+                self.index.remove(&old.query);
+                self.names.remove(&old.query);
+                self.index.insert(query.clone(), hand);
+                break vec![old.query];
+            }
+        };
+
+        if batch > 1 {
+            evicted.extend(self.evict_batch(batch - 1, &query));
+        }
+        evicted
+    }
+
+    /// Evicts up to `count` entries the way the hand selects them, sparing the entry of `spared`
+    ///
+    /// Each entry is removed in place as the hand reaches it, the last entry taking its slot,
+    ///  like [`ClockCache::remove`]. Returns the queries of the evicted entries.
+    fn evict_batch(&mut self, count: usize, spared: &Query) -> Vec<Query> {
+        let mut count = count.min(self.entries.len().saturating_sub(2));
+        let mut evicted = vec![];
+
+        while count > 0 {
+            let hand = self.hand;
+            let candidate = &mut self.entries[hand];
+            if candidate.query == *spared || std::mem::take(candidate.referenced.get_mut()) {
+                self.hand = (hand + 1) % self.entries.len();
+                continue;
+            }
+
+            // the hand stays on the slot, now holding the entry moved from the end
+            let query = candidate.query.clone();
+            self.remove(&query);
+            evicted.push(query);
+            count -= 1;
+        }
+
+        evicted
+    }
+
+    /// Removes the entry if it is still out of date, it may have been replaced since it was read
+    ///
+    /// Returns true if the entry was removed.
+    fn remove_expired(
+        &mut self,
+        query: &Query,
+        now: Instant,
+        grace: Duration,
+        max_age: Option<Duration>,
+    ) -> bool {
+        let expired = self
+            .index
+            .get(query)
+            .is_some_and(|&i| self.entries[i].value.is_evictable(now, grace, max_age));
+        if expired {
+            self.remove(query);
+        }
+        expired
+    }
+
+    fn remove(&mut self, query: &Query) -> Option<LruValue> {
+        let i = self.index.remove(query)?;
+        self.names.remove(query);
+        let entry = self.entries.swap_remove(i);
+        if let Some(moved) = self.entries.get(i) {
+            self.index.insert(moved.query.clone(), i);
+        }
+        if self.hand >= self.entries.len() {
+            self.hand = 0;
+        }
+
+        Some(entry.value)
+    }
+
+    fn set_capacity(&mut self, capacity: usize) {
+        while self.entries.len() > capacity {
+            let query = self.entries[self.entries.len() - 1].query.clone();
+            self.remove(&query);
+        }
+        self.capacity = capacity;
+    }
+
+    fn clear(&mut self) {
+        self.index.clear();
+        self.names.clear();
+        self.entries.clear();
+        self.hand = 0;
+    }
+}
+
+/// A cache with segmented LRU eviction
+///
+/// Entries read while in the probationary segment are promoted to the protected segment, the least
+///  recently used protected entry being demoted back to the probationary segment if it is full.
+#[cfg(feature = "segmented-lru")]
+#[derive(Debug)]
+struct SegmentedLruCache {
+    probation: LruMap,
+    protected: LruMap,
+}
+
+#[cfg(feature = "segmented-lru")]
+impl SegmentedLruCache {
+    fn new(capacity: usize) -> Self {
+        let protected = Self::protected_capacity(capacity);
+        Self {
+            probation: LruMap::new(capacity - protected),
+            protected: LruMap::new(protected),
+        }
+    }
+
+    fn protected_capacity(capacity: usize) -> usize {
+        // the protected segment holds 80% of the entries, as commonly used for segmented LRU
+        capacity * 4 / 5
+    }
+
+    fn set_capacity(&mut self, capacity: usize) {
+        let protected = Self::protected_capacity(capacity);
+        while self.protected.len() > protected {
+            if let Some((demoted, demoted_value)) = self.protected.remove_lru() {
+                self.probation.insert(demoted, demoted_value);
+            }
+        }
+
+        self.protected.set_capacity(protected);
+        self.probation.set_capacity(capacity - protected);
+    }
+
+    fn get_mut(&mut self, query: &Query) -> Option<&mut LruValue> {
+        if self.protected.contains_key(query) {
+            return self.protected.get_mut(query);
+        }
+
+        if self.protected.capacity() == 0 {
+            return self.probation.get_mut(query);
+        }
+
+        let value = self.probation.remove(query)?;
+        if self.protected.len() >= self.protected.capacity() {
+            if let Some((demoted, demoted_value)) = self.protected.remove_lru() {
+                self.probation.insert(demoted, demoted_value);
+            }
+        }
+
+        self.protected.insert(query.clone(), value);
+        self.protected.get_mut(query)
+    }
+
+    /// Returns the entry of the query, without promoting it or updating its recency
+    fn peek(&self, query: &Query) -> Option<&LruValue> {
+        self.protected
+            .peek(query)
+            .or_else(|| self.probation.peek(query))
+    }
+
+    /// Inserts the value, returning the queries of the entries evicted to make room for it
+    ///
+    /// When the probationary segment is full, up to `batch` of its entries are evicted at once.
+    fn insert(&mut self, query: Query, value: LruValue, batch: usize) -> Vec<Query> {
+        if self.protected.contains_key(&query) {
+            self.protected.insert(query, value);
+            return vec![];
+        }
+
+        let mut evicted = vec![];
+        if !self.probation.contains_key(&query) && self.probation.len() >= self.probation.capacity()
+        {
+            while evicted.len() < batch {
+                let Some((evicted_query, _)) = self.probation.remove_lru() else {
+                    break;
+                };
+                evicted.push(evicted_query);
+            }
+        }
+        self.probation.insert(query, value);
+        evicted
+    }
+
+    fn remove(&mut self, query: &Query) -> Option<LruValue> {
+        self.protected
+            .remove(query)
+            .or_else(|| self.probation.remove(query))
+    }
+
+    fn clear(&mut self) {
+        self.probation.clear();
+        self.protected.clear();
+    }
+
+    fn len(&self) -> usize {
+        self.probation.len() + self.protected.len()
+    }
+
+    fn capacity(&self) -> usize {
+        self.probation.capacity() + self.protected.capacity()
+    }
+}
+
+/// An LRU eviction cache specifically for storing DNS records
+#[derive(Clone, Debug)]
+pub struct DnsLru {
+    /// Independent LRU shards, each query is always stored in the same shard based on the hash of
+    ///  its name, so that the entries of a name share a shard, see [`DnsLru::replace_name`]
+    shards: Arc<[Shard]>,
+    /// Entries which are never evicted, see [`DnsLru::pin`]
+    pinned: Arc<Pins>,
+    /// The caches of the lookups whose responses aren't shared, see [`DnsLru::scoped`]
+    scopes: Arc<Scopes>,
+    /// Number of entries not cached because the whole capacity is pinned
+    rejected_full_pins: Arc<AtomicU64>,
+    /// Counters of the cache operations, see [`DnsLru::stats`]
+    stats: Arc<StatsCounters>,
+    /// When false, inserted entries are not stored, see [`DnsLru::set_insert_enabled`]
+    insert_enabled: Arc<AtomicBool>,
+    /// A minimum TTL value for positive responses.
+    ///
+    /// Positive responses with TTLs under `positive_min_ttl` will use
+    /// `positive_min_ttl` instead.
+    ///
+    /// If this value is not set on the `TtlConfig` used to construct this
+    /// `DnsLru`, it will default to 0.
+    positive_min_ttl: Duration,
+    /// The positive responses to which `positive_min_ttl` is applied
+    positive_min_ttl_scope: config::MinTtlScope,
+    /// A minimum TTL value for negative (`NXDOMAIN`) responses.
+    ///
+    /// `NXDOMAIN` responses with TTLs under `negative_min_ttl` will use
+    /// `negative_min_ttl` instead.
+    ///
+    /// If this value is not set on the `TtlConfig` used to construct this
+    /// `DnsLru`, it will default to 0.
+    negative_min_ttl: Duration,
+    /// A maximum TTL value for positive responses.
+    ///
+    /// Positive responses with TTLs over `positive_max_ttl` will use
+    /// `positive_max_ttl` instead.
+    ///
+    ///  If this value is not set on the `TtlConfig` used to construct this
+    /// `DnsLru`, it will default to [`MAX_TTL`] seconds.
+    ///
+    /// [`MAX_TTL`]: const.MAX_TTL.html
+    positive_max_ttl: Duration,
+    /// A maximum TTL value for negative (`NXDOMAIN`) responses.
+    ///
+    /// `NXDOMAIN` responses with TTLs over `negative_max_ttl` will use
+    /// `negative_max_ttl` instead.
+    ///
+    ///  If this value is not set on the `TtlConfig` used to construct this
+    /// `DnsLru`, it will default to [`MAX_TTL`] seconds.
+    ///
+    /// [`MAX_TTL`]: const.MAX_TTL.html
+    negative_max_ttl: Duration,
+    /// Record types whose negative responses are never cached
+    negative_excluded_types: Arc<[RecordType]>,
+    /// Minimum number of records of the positive entries of each type
+    min_cached_records: Arc<HashMap<RecordType, usize>>,
+    /// Duration for which expired positive entries are served stale by [`DnsLru::get_stale_on_failure`]
+    serve_stale_ttl: Duration,
+    /// Duration for which expired negative entries are served stale by [`DnsLru::get_stale_on_failure`]
+    negative_serve_stale_ttl: Duration,
+    /// Fraction of the TTL remaining below which entries should be refreshed, see [`TtlConfig`]
+    prefetch_threshold: Option<config::PrefetchThreshold>,
+    /// Maximum duration subtracted from the TTL of positive entries, see [`TtlConfig::with_ttl_jitter`]
+    ttl_jitter: Option<Duration>,
+    /// State of the generator of the TTL jitter, see [`DnsLru::with_jitter_seed`]
+    jitter_rng: Arc<Mutex<StdRng>>,
+    /// Extends the TTL of flapping entries, see [`TtlConfig::with_flapping_damping`]
+    flapping_damping: Option<FlappingDamping>,
+    /// Serves entries with the TTL they were cached with, see [`TtlConfig::with_preserve_ttl`]
+    preserve_ttl: bool,
+    /// Maximum age of the entries served, see [`TtlConfig::with_max_entry_age`]
+    max_entry_age: Option<Duration>,
+    /// Rotates the records of the entries served, see [`TtlConfig::with_rotate`]
+    rotate: bool,
+    /// Duration for which expired entries are kept, to be served stale, see [`DnsLru::get_stale`]
+    stale_grace: Duration,
+    /// Fraction of the capacity of a shard evicted at once, see [`DnsLru::with_eviction_batch`]
+    eviction_batch: Option<f32>,
+    /// Reports TTLs above a threshold, see [`DnsLru::with_suspicious_ttl`]
+    suspicious_ttl: Option<SuspiciousTtl>,
+    /// Notified of the evicted entries, see [`DnsLru::with_evict_callback`]
+    on_evict: Option<EvictCallback>,
+    /// Maps the queries to the keys of their entries, see [`DnsLru::with_key_normalizer`]
+    key_normalizer: Option<KeyNormalizer>,
+}
+
+/// A summary of a cached entry, see [`DnsLru::entries`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CacheEntryInfo {
+    /// Number of records of the entry, zero for negative entries
+    pub record_count: usize,
+    /// True for records, false for a negative response, e.g. `NXDOMAIN`
+    pub positive: bool,
+    /// The TTL remaining before the entry expires
+    pub ttl: Duration,
+}
+
+/// A positive entry of the cache which survives restarts, see [`DnsLru::export`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CacheSnapshotEntry {
+    /// The query the records are stored under
+    pub query: Query,
+    /// The records of the entry
+    pub records: Vec<Record>,
+    /// The expiry of the entry, as the duration since the UNIX epoch
+    pub expires_at: Duration,
+}
+
+/// A hint that a cached entry is about to expire, see [`DnsLru::get_with_prefetch_hint`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PrefetchHint {
+    /// The TTL remaining before the entry expires
+    pub remaining_ttl: Duration,
+}
+
+/// A snapshot of the statistics of a cache, see [`DnsLru::stats`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of lookups which returned a current entry
+    pub hits: u64,
+    /// Number of lookups which found no entry, or an out of date one
+    pub misses: u64,
+    /// Number of entries stored, including replacements of existing entries
+    pub insertions: u64,
+    /// Number of entries evicted to make room for new ones
+    pub evictions: u64,
+    /// Number of insertions which evicted entries, each evicting one or more entries at once
+    pub eviction_rounds: u64,
+}
+
+#[derive(Debug, Default)]
+struct StatsCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    insertions: AtomicU64,
+    evictions: AtomicU64,
+    eviction_rounds: AtomicU64,
+}
+
+/// A name cached both as an alias and with records of another type, see [`DnsLru::check_consistency`]
+///
+/// A name with a CNAME record can't have other data, records of the target should be cached
+///  under the target name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Inconsistency {
+    /// The name of the CNAME record
+    pub name: Name,
+    /// The target of the CNAME record
+    pub target: Name,
+    /// The type of the record also cached for the name
+    pub record_type: RecordType,
+}
+
+/// Callback reporting a suspicious TTL, see [`DnsLru::with_suspicious_ttl`]
+#[derive(Clone)]
+struct SuspiciousTtl {
+    threshold: Duration,
+    report: ReportTtl,
+}
+
+/// Function called with the query and the TTL of its suspicious records
+type ReportTtl = Arc<dyn Fn(&Query, u32) + Send + Sync>;
+
+impl fmt::Debug for SuspiciousTtl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SuspiciousTtl")
+            .field("threshold", &self.threshold)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Callback notified of evicted entries, see [`DnsLru::with_evict_callback`]
+#[derive(Clone)]
+struct EvictCallback(Arc<dyn Fn(&Query) + Send + Sync>);
+
+impl fmt::Debug for EvictCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EvictCallback")
+    }
+}
+
+/// Function mapping a query to the key of its entry, see [`DnsLru::with_key_normalizer`]
+#[derive(Clone)]
+struct KeyNormalizer(Arc<dyn Fn(&Query) -> Query + Send + Sync>);
+
+impl fmt::Debug for KeyNormalizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("KeyNormalizer")
+    }
+}
+
+/// The time-to-live, TTL, configuration for use by the cache.
+///
+/// It should be understood that the TTL in DNS is expressed with a u32.
+///   We use Duration here for tracking this which can express larger values
+///   than the DNS standard. Generally a Duration greater than u32::MAX_VALUE
+///   shouldn't cause any issue as this will never be used in serialization,
+///   but understand that this would be outside the standard range.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TtlConfig {
+    /// An optional minimum TTL value for positive responses.
+    ///
+    /// Positive responses with TTLs under `positive_min_ttl` will use
+    /// `positive_min_ttl` instead.
+    pub(crate) positive_min_ttl: Option<Duration>,
+    /// The positive responses to which `positive_min_ttl` is applied
+    pub(crate) positive_min_ttl_scope: config::MinTtlScope,
+    /// An optional minimum TTL value for negative (`NXDOMAIN`) responses.
+    ///
+    /// `NXDOMAIN` responses with TTLs under `negative_min_ttl will use
+    /// `negative_min_ttl` instead.
+    pub(crate) negative_min_ttl: Option<Duration>,
+    /// An optional maximum TTL value for positive responses.
+    ///
+    /// Positive responses with TTLs positive `positive_max_ttl` will use
+    /// `positive_max_ttl` instead.
+    pub(crate) positive_max_ttl: Option<Duration>,
+    /// An optional maximum TTL value for negative (`NXDOMAIN`) responses.
+    ///
+    /// `NXDOMAIN` responses with TTLs over `negative_max_ttl` will use
+    /// `negative_max_ttl` instead.
+    pub(crate) negative_max_ttl: Option<Duration>,
+    /// An optional duration for which positive responses are served stale after they expire
+    ///
+    /// Stale lookups are returned by [`DnsLru::get`], flagged with [`Lookup::is_stale`] and with a
+    /// TTL of 1 second.
+    pub(crate) serve_stale_ttl: Option<Duration>,
+    /// An optional duration for which negative responses are served stale after they expire
+    ///
+    /// See [`TtlConfig::with_negative_serve_stale_ttl`].
+    pub(crate) negative_serve_stale_ttl: Option<Duration>,
+    /// An optional fraction of the TTL remaining below which positive entries should be refreshed
+    ///
+    /// See [`DnsLru::get_with_prefetch_hint`].
+    pub(crate) prefetch_threshold: Option<config::PrefetchThreshold>,
+    /// An optional maximum random duration subtracted from the TTL of positive responses
+    ///
+    /// See [`TtlConfig::with_ttl_jitter`].
+    pub(crate) ttl_jitter: Option<Duration>,
+    /// An optional damping of the TTL of positive entries whose records keep changing
+    ///
+    /// See [`TtlConfig::with_flapping_damping`].
+    pub(crate) flapping_damping: Option<FlappingDamping>,
+    /// Serve records with the TTL they were cached with, rather than their remaining TTL
+    ///
+    /// See [`TtlConfig::with_preserve_ttl`].
+    pub(crate) preserve_ttl: bool,
+    /// An optional maximum age of the entries served, whatever their TTL
+    ///
+    /// See [`TtlConfig::with_max_entry_age`].
+    pub(crate) max_entry_age: Option<Duration>,
+    /// Rotate the records of the entries on each cache hit
+    ///
+    /// See [`TtlConfig::with_rotate`].
+    pub(crate) rotate: bool,
+}
+
+impl TtlConfig {
+    /// Construct the LRU based on the ResolverOpts configuration
+    pub fn from_opts(opts: &config::ResolverOpts) -> Self {
+        Self {
+            positive_min_ttl: opts.positive_min_ttl,
+            positive_min_ttl_scope: opts.positive_min_ttl_scope,
+            negative_min_ttl: opts.negative_min_ttl,
+            positive_max_ttl: opts.positive_max_ttl,
+            negative_max_ttl: opts.negative_max_ttl,
+            serve_stale_ttl: opts.serve_stale_ttl,
+            negative_serve_stale_ttl: None,
+            prefetch_threshold: opts.prefetch_threshold,
+            ttl_jitter: None,
+            flapping_damping: None,
+            preserve_ttl: false,
+            max_entry_age: None,
+            rotate: opts.rotate,
+        }
+    }
+
+    /// Hints to refresh positive entries once less than `threshold` of their TTL remains
+    ///
+    /// The threshold is a fraction of the TTL, e.g. `0.1` to refresh entries during the last 10%
+    ///  of their TTL, see [`DnsLru::get_with_prefetch_hint`].
+    pub fn with_prefetch_threshold(mut self, threshold: Option<config::PrefetchThreshold>) -> Self {
+        self.prefetch_threshold = threshold;
+        self
+    }
+
+    /// Serves negative responses, e.g. `NXDOMAIN` and `NODATA`, stale for up to `ttl` after they
+    ///  expire, independently of the `serve_stale_ttl` of positive responses
+    ///
+    /// Serving a stale negative response can be more harmful than serving stale records, e.g.
+    ///  hiding a name which was just created, so this window is usually shorter, as allowed by
+    ///  RFC 8767. Stale negative responses are only returned by [`DnsLru::get_stale_on_failure`],
+    ///  with a negative TTL of 1 second. Defaults to `None`, negative responses are not served stale.
+    pub fn with_negative_serve_stale_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.negative_serve_stale_ttl = ttl;
+        self
+    }
+
+    /// Shortens the TTL of positive entries by a random duration of up to `jitter`
+    ///
+    /// Entries fetched at the same time then expire at different times, rather than all being
+    ///  refreshed at once. Jitter only ever shortens the TTL, never extends it, and doesn't reduce
+    ///  it below `positive_min_ttl`. The random durations are drawn from a seedable generator, see
+    ///  [`DnsLru::with_jitter_seed`].
+    pub fn with_ttl_jitter(mut self, jitter: Option<Duration>) -> Self {
+        self.ttl_jitter = jitter;
+        self
+    }
+
+    /// Extends the TTL of positive entries whose records changed on the last `threshold`
+    ///  insertions, e.g. a name alternating between two address sets
+    ///
+    /// The TTL of a flapping entry is doubled for each consecutive change from the threshold, up
+    ///  to `max_ttl`, so that clients churn less between the address sets. The TTL is never
+    ///  shortened, and the damping stops as soon as the records are unchanged by an insertion.
+    ///  Expired entries are retained for up to `max_ttl`, to be compared with their refresh.
+    pub fn with_flapping_damping(mut self, damping: Option<FlappingDamping>) -> Self {
+        self.flapping_damping = damping;
+        self
+    }
+
+    /// Serves cached entries with the TTL they were cached with, rather than the TTL remaining
+    ///  before they expire
+    ///
+    /// This is meant for forwarders whose clients expect the TTLs they would get from the upstream
+    ///  servers. The tradeoff is that downstream caches then keep the records for up to twice the
+    ///  TTL, as they cache them again for the whole TTL however long they were cached here. This
+    ///  also applies to the negative TTL of negative responses, stale entries keep a TTL of 1
+    ///  second. Defaults to `false`, TTLs are decremented.
+    pub fn with_preserve_ttl(mut self, preserve: bool) -> Self {
+        self.preserve_ttl = preserve;
+        self
+    }
+
+    /// Never serves entries stored more than `max_age` ago, even if their TTL is longer
+    ///
+    /// This guarantees the freshness of the answers, e.g. for compliance: an entry older than
+    ///  `max_age` is treated as expired and removed by [`DnsLru::get`], and is not served stale
+    ///  either. The TTLs served never exceed the time left before the entry reaches `max_age`, so
+    ///  that downstream caches don't keep it longer. Defaults to `None`, entries are served until
+    ///  they expire.
+    pub fn with_max_entry_age(mut self, max_age: Option<Duration>) -> Self {
+        self.max_entry_age = max_age;
+        self
+    }
+
+    /// Rotates the records of the queried type of an entry by one position on each cache hit, for
+    ///  crude load balancing across the addresses of a name
+    ///
+    /// The records stay in their order in the cache, only the returned lookups are rotated, including
+    ///  the lookups of entries served stale. See `ResolverOpts::rotate`, defaults to `false`.
+    pub fn with_rotate(mut self, rotate: bool) -> Self {
+        self.rotate = rotate;
+        self
+    }
+}
+
+impl DnsLru {
+    /// Construct a new cache
+    ///
+    /// The number of shards is chosen based on the capacity, small caches use a single shard.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - size in number of records, this can be the max size of 2048 (record size) * `capacity`
+    /// * `ttl_cfg` - force minimums and maximums for cached records
+    pub fn new(capacity: usize, ttl_cfg: TtlConfig) -> Self {
+        Self::new_with_backend(capacity, CacheBackend::Lru, ttl_cfg)
+    }
+
+    /// Construct a new cache using the specified eviction backend
+    ///
+    /// The number of shards is chosen as in [`DnsLru::new`].
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - size in number of records, this can be the max size of 2048 (record size) * `capacity`
+    /// * `backend` - the eviction strategy, [`CacheBackend::Clock`] allows concurrent reads
+    /// * `ttl_cfg` - force minimums and maximums for cached records
+    pub fn new_with_backend(capacity: usize, backend: CacheBackend, ttl_cfg: TtlConfig) -> Self {
+        let shards = (capacity / MIN_SHARD_CAPACITY).clamp(1, MAX_DEFAULT_SHARDS);
+        Self::build(capacity, shards, backend, ttl_cfg)
+    }
+
+    /// Construct a new cache split into independently locked shards
+    ///
+    /// Each shard is a separate LRU holding `capacity / shards` entries, queries are assigned to a
+    ///  shard by the hash of their name. This reduces lock contention when the cache is used concurrently,
+    ///  at the cost of eviction only being least-recently-used within a shard.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - total size in number of records across all shards
+    /// * `shards` - number of shards, clamped between 1 and `capacity`
+    /// * `ttl_cfg` - force minimums and maximums for cached records
+    pub fn new_sharded(capacity: usize, shards: usize, ttl_cfg: TtlConfig) -> Self {
+        Self::build(capacity, shards, CacheBackend::Lru, ttl_cfg)
+    }
+
+    fn build(capacity: usize, shards: usize, backend: CacheBackend, ttl_cfg: TtlConfig) -> Self {
+        let TtlConfig {
+            positive_min_ttl,
+            positive_min_ttl_scope,
+            negative_min_ttl,
+            positive_max_ttl,
+            negative_max_ttl,
+            serve_stale_ttl,
+            negative_serve_stale_ttl,
+            prefetch_threshold,
+            ttl_jitter,
+            flapping_damping,
+            preserve_ttl,
+            max_entry_age,
+            rotate,
+        } = ttl_cfg;
+
+        // spread the remainder over the first shards so that the total capacity is preserved
+        let shards = shards.clamp(1, capacity.max(1));
+        let shards = (0..shards)
+            .map(|i| {
+                let extra = usize::from(i < capacity % shards);
+                Shard::new(backend, capacity / shards + extra)
+            })
+            .collect();
+
+        Self {
+            shards,
+            pinned: Arc::default(),
+            scopes: Arc::new(Mutex::new(LruCache::new(MAX_SCOPES))),
+            rejected_full_pins: Arc::default(),
+            stats: Arc::default(),
+            insert_enabled: Arc::new(AtomicBool::new(true)),
+            positive_min_ttl: positive_min_ttl.unwrap_or_else(|| Duration::from_secs(0)),
+            positive_min_ttl_scope,
+            negative_min_ttl: negative_min_ttl.unwrap_or_else(|| Duration::from_secs(0)),
+            positive_max_ttl: positive_max_ttl
+                .unwrap_or_else(|| Duration::from_secs(u64::from(MAX_TTL))),
+            negative_max_ttl: negative_max_ttl
+                .unwrap_or_else(|| Duration::from_secs(u64::from(MAX_TTL))),
+            negative_excluded_types: Arc::from([]),
+            min_cached_records: Arc::default(),
+            serve_stale_ttl: serve_stale_ttl.unwrap_or_default(),
+            negative_serve_stale_ttl: negative_serve_stale_ttl.unwrap_or_default(),
+            prefetch_threshold,
+            ttl_jitter,
+            jitter_rng: Arc::new(Mutex::new(StdRng::from_entropy())),
+            flapping_damping,
+            preserve_ttl,
+            max_entry_age,
+            rotate,
+            stale_grace: Duration::from_secs(0),
+            eviction_batch: None,
+            suspicious_ttl: None,
+            on_evict: None,
+            key_normalizer: None,
+        }
+    }
+
+    /// Reports records inserted with a TTL above `threshold`, e.g. to monitor misconfigured or
+    ///  malicious zones
+    ///
+    /// `report` is called with the query and the largest raw TTL of the records, before the TTL is
+    ///  clamped to the configured maximum. This doesn't change how the records are cached. The
+    ///  records are checked on every insertion path, including [`DnsLru::insert_records`],
+    ///  [`DnsLru::replace_name`] which also replaces the pinned entries, and [`DnsLru::import`]
+    ///  where the TTL is the remainder of the snapshot entry.
+    pub fn with_suspicious_ttl(
+        mut self,
+        threshold: Duration,
+        report: impl Fn(&Query, u32) + Send + Sync + 'static,
+    ) -> Self {
+        self.suspicious_ttl = Some(SuspiciousTtl {
+            threshold,
+            report: Arc::new(report),
+        });
+        self
+    }
+
+    /// Calls `on_evict` with the query of each entry evicted to make room for a new entry, or
+    ///  removed by [`DnsLru::get`] once out of date
+    ///
+    /// The callback is called once the cache is unlocked, so it may call back into the cache, e.g.
+    ///  to maintain a secondary index of the cached names.
+    pub fn with_evict_callback(
+        mut self,
+        on_evict: impl Fn(&Query) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_evict = Some(EvictCallback(Arc::new(on_evict)));
+        self
+    }
+
+    /// Stores and looks up the entries under the query returned by `normalize`, rather than the
+    ///  query itself
+    ///
+    /// Queries normalized to the same key share their entry, e.g. names differing by a search
+    ///  domain, so a lookup may return the records inserted for another query of the key. Names
+    ///  are compared case-insensitively already, which is the only normalization by default. The
+    ///  functions taking a name rather than a query, e.g. [`DnsLru::invalidate`], match the
+    ///  normalized names, and the queries reported to [`DnsLru::with_evict_callback`] or returned
+    ///  by [`DnsLru::entries`] are the keys.
+    pub fn with_key_normalizer(
+        mut self,
+        normalize: impl Fn(&Query) -> Query + Send + Sync + 'static,
+    ) -> Self {
+        self.key_normalizer = Some(KeyNormalizer(Arc::new(normalize)));
+        self
+    }
+
+    /// Never caches the negative responses of queries of these types, e.g. for `SRV` records which
+    ///  change frequently, see [`config::ResolverOpts::negative_cache_excluded_types`]
+    pub fn with_negative_excluded_types(
+        mut self,
+        types: impl IntoIterator<Item = RecordType>,
+    ) -> Self {
+        self.negative_excluded_types = types.into_iter().collect();
+        self
+    }
+
+    /// Only caches the positive responses of each type holding at least its minimum number of
+    ///  records, see [`config::ResolverOpts::min_cached_records`]
+    pub fn with_min_cached_records(mut self, min_records: HashMap<RecordType, usize>) -> Self {
+        self.min_cached_records = Arc::new(min_records);
+        self
+    }
+
+    /// Evicts entries in batches of `fraction` of the capacity, rather than one at a time
+    ///
+    /// When a shard is full, inserting a new entry evicts the least recently used entries
+    ///  representing `fraction` of its capacity, e.g. `0.1` for 10%, so that the next insertions
+    ///  don't evict. This reduces the churn of a cache under capacity pressure, at the cost of
+    ///  keeping fewer entries. At least one entry is evicted at once.
+    pub fn with_eviction_batch(mut self, fraction: f32) -> Self {
+        self.eviction_batch = Some(fraction.clamp(0.0, 1.0));
+        self
+    }
+
+    /// Seeds the generator of the TTL jitter, e.g. to make tests reproducible
+    ///
+    /// The generator is seeded randomly by default, see [`TtlConfig::with_ttl_jitter`].
+    pub fn with_jitter_seed(mut self, seed: u64) -> Self {
+        self.jitter_rng = Arc::new(Mutex::new(StdRng::seed_from_u64(seed)));
+        self
+    }
+
+    pub(crate) fn clear(&self) {
+        for shard in self.shards.iter() {
+            shard.clear();
+        }
+
+        // give the capacity of the pinned entries back to their shards
+        for (query, _) in self.pinned.lock().drain() {
+            let shard = self.shard(&query);
+            shard.set_capacity(shard.capacity() + 1);
+        }
+
+        self.clear_scopes();
+    }
+
+    /// Removes the entries of all the queries of the name, whatever their type and class
+    ///
+    /// Pinned entries of the name are removed as well, giving their capacity back to their shard,
+    ///  and so are the entries of the scopes, see [`DnsLru::scoped`].
+    ///
+    /// Returns the number of removed entries.
+    pub fn invalidate(&self, name: &Name) -> usize {
+        // always lock the pinned entries before the shards, as `pin` does
+        let mut pinned = self.pinned.lock();
+        let mut removed = 0;
+
+        for shard in self.shards.iter() {
+            let mut shard = shard.write();
+            for query in shard.queries_of(name) {
+                removed += usize::from(shard.remove(&query).is_some());
+            }
+        }
+
+        pinned.retain(|query, _| {
+            if query.name() != name {
+                return true;
+            }
+
+            let shard = self.shard(query);
+            shard.set_capacity(shard.capacity() + 1);
+            removed += 1;
+            false
+        });
+        drop(pinned);
+
+        self.for_each_scope(|scope| removed += scope.invalidate(name));
+        removed
+    }
+
+    /// Removes the entry of the query, even if it is pinned, in this cache and in its scopes
+    ///
+    /// Returns false if the query isn't cached.
+    pub fn invalidate_query(&self, query: &Query) -> bool {
+        let mut removed = false;
+        self.for_each_scope(|scope| removed |= scope.invalidate_query(query));
+
+        let key = self.key(query);
+        let query = &*key;
+        let shard = self.shard(query);
+        if self.pinned.lock().remove(query).is_some() {
+            shard.set_capacity(shard.capacity() + 1);
+            return true;
+        }
+
+        shard.remove(query).is_some() || removed
+    }
+
+    /// Returns the number of cached entries, including the pinned ones and the ones of the scopes
+    ///
+    /// Expired entries which are not yet evicted are counted.
+    pub fn len(&self) -> usize {
+        let pinned = self.pinned.lock().len();
+        let mut scoped = 0;
+        self.for_each_scope(|scope| scoped += scope.len());
+        pinned + scoped + self.shards.iter().map(Shard::len).sum::<usize>()
+    }
+
+    /// Returns true if no entry is cached, see [`DnsLru::len`]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the maximum number of cached entries, the capacity the cache was constructed with
+    pub fn capacity(&self) -> usize {
+        let mut scoped = 0;
+        self.for_each_scope(|scope| scoped += scope.own_capacity());
+        self.own_capacity() + scoped
+    }
+
+    /// Returns the maximum TTL of the positive responses, e.g. to cache the DS records validated
+    ///  by the `DnssecDnsHandle` no longer than the responses
+    #[cfg(feature = "dnssec")]
+    pub(crate) fn positive_max_ttl(&self) -> Duration {
+        self.positive_max_ttl
+    }
+
+    /// Returns the capacity of this cache, without the capacity taken by its scopes
+    fn own_capacity(&self) -> usize {
+        // pinned entries are taken from the capacity of their shard
+        let pinned = self.pinned.lock().len();
+        pinned + self.shards.iter().map(Shard::capacity).sum::<usize>()
+    }
+
+    /// Returns a snapshot of the statistics of the cache since it was constructed
+    ///
+    /// Hits and misses are counted by [`DnsLru::get`].
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.stats.hits.load(Ordering::Relaxed),
+            misses: self.stats.misses.load(Ordering::Relaxed),
+            insertions: self.stats.insertions.load(Ordering::Relaxed),
+            evictions: self.stats.evictions.load(Ordering::Relaxed),
+            eviction_rounds: self.stats.eviction_rounds.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Enables or disables storing inserted entries, e.g. to freeze the cache while an upstream
+    ///  is known to return bad data
+    ///
+    /// While disabled, the insertion functions still return the lookup or error built from the
+    ///  records, and existing entries are still served. Clones of the cache share this setting.
+    pub fn set_insert_enabled(&self, enabled: bool) {
+        self.insert_enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Stores the value of the query, replacing the pinned entry if the query is pinned
+    ///
+    /// The value is dropped rather than evicting a pinned entry when the whole capacity is pinned.
+    fn store(&self, query: Query, value: LruValue) {
+        self.store_with_pins(query, value, self.pinned.lock_any());
+    }
+
+    /// Stores the value of the query like [`DnsLru::store`], with the pinned entries already locked
+    ///  by [`Pins::lock_any`]
+    fn store_with_pins(&self, query: Query, value: LruValue, pinned: Option<PinsGuard<'_>>) {
+        if !self.insert_enabled.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let query = self.owned_key(query);
+        let shard = self.shard(&query);
+        let evicted = match pinned {
+            None => {
+                let evicted = self.store_in_shard(shard, query.clone(), value, false);
+                // the query may have been pinned meanwhile, its pinned entry is then replaced
+                self.adopt_pinned(&query);
+                evicted
+            }
+            // the query can't be pinned between the check and the insertion under the same guard
+            Some(mut pinned) => {
+                if let Some(entry) = pinned.get_mut(&query) {
+                    *entry = value;
+                    self.stats.insertions.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                self.store_in_shard(shard, query, value, true)
+            }
+        };
+        self.report_evicted(&evicted);
+    }
+
+    /// Inserts the value into the shard of the query, returning the evicted queries
+    fn store_in_shard(
+        &self,
+        shard: &Shard,
+        query: Query,
+        value: LruValue,
+        any_pinned: bool,
+    ) -> Vec<Query> {
+        if shard.capacity() == 0 {
+            if any_pinned {
+                self.rejected_full_pins.fetch_add(1, Ordering::Relaxed);
+            }
+            return Vec::new();
+        }
+
+        self.stats.insertions.fetch_add(1, Ordering::Relaxed);
+        let batch = match self.eviction_batch {
+            Some(fraction) => ((shard.capacity() as f32 * fraction).ceil() as usize).max(1),
+            None => 1,
+        };
+        let evicted = shard.insert(query, value, batch);
+        if !evicted.is_empty() {
+            self.stats
+                .evictions
+                .fetch_add(evicted.len() as u64, Ordering::Relaxed);
+            self.stats.eviction_rounds.fetch_add(1, Ordering::Relaxed);
+        }
+        evicted
+    }
+
+    /// Calls the callback of [`DnsLru::with_evict_callback`], the cache must not be locked
+    fn report_evicted(&self, evicted: &[Query]) {
+        if let Some(EvictCallback(on_evict)) = &self.on_evict {
+            for query in evicted {
+                on_evict(query);
+            }
+        }
+    }
+
+    /// Atomically replaces all the entries of the name with the new entries
+    ///
+    /// The entries of a name are stored in a single shard, locked once, so readers observe either
+    ///  the old or the new entries of the name, and never a missing entry for a query which is in
+    ///  both. The entries of the name are found by the index of their shard. Each new entry is valid
+    ///  for its duration as is, without applying the configured TTL bounds. Pinned entries of the
+    ///  name are replaced in place if there is a new entry for their query, and removed otherwise.
+    ///  Nothing is changed while inserts are disabled, see [`DnsLru::set_insert_enabled`].
+    pub fn replace_name(
+        &self,
+        name: &Name,
+        new_entries: Vec<(Query, Lookup, Duration)>,
+        now: Instant,
+    ) {
+        if !self.insert_enabled.load(Ordering::Relaxed) {
+            return;
+        }
+
+        // always lock the pinned entries before the shards, as `pin` does
+        let mut pinned = self.pinned.lock();
+        let mut shard = self.shards[self.name_shard_index(name)].write();
+
+        for query in shard.queries_of(name) {
+            shard.remove(&query);
+        }
+
+        let mut new_entries = new_entries
+            .into_iter()
+            .map(|(query, lookup, ttl)| {
+                self.report_suspicious_ttl(&query, ttl);
+                let value = LruValue {
+                    lookup: Ok(lookup),
+                    stored_at: now,
+                    valid_until: now + ttl,
+                    metadata: None,
+                    prefetched: AtomicBool::new(false),
+                    rotation: AtomicUsize::new(0),
+                    flaps: 0,
+                };
+                (self.owned_key(query), value)
+            })
+            .collect::<HashMap<_, _>>();
+
+        // unpinned entries give their capacity back to their shard
+        pinned.retain(|query, value| {
+            if query.name() != name {
+                return true;
+            }
+
+            match new_entries.remove(query) {
+                Some(new_value) => {
+                    *value = new_value;
+                    true
+                }
+                None => {
+                    let capacity = shard.capacity();
+                    shard.set_capacity(capacity + 1);
+                    false
+                }
+            }
+        });
+
+        let mut evicted = vec![];
+        for (query, value) in new_entries {
+            if shard.capacity() == 0 {
+                if !pinned.is_empty() {
+                    self.rejected_full_pins.fetch_add(1, Ordering::Relaxed);
+                }
+                continue;
+            }
+
+            evicted.extend(shard.insert(query, value));
+        }
+
+        drop(shard);
+        drop(pinned);
+        self.report_evicted(&evicted);
+    }
+
+    /// Returns the key the entry of the query is stored under, see [`DnsLru::with_key_normalizer`]
+    fn key<'q>(&self, query: &'q Query) -> Cow<'q, Query> {
+        match &self.key_normalizer {
+            Some(KeyNormalizer(normalize)) => Cow::Owned(normalize(query)),
+            None => Cow::Borrowed(query),
+        }
+    }
+
+    /// Like [`DnsLru::key`], taking the query by value
+    fn owned_key(&self, query: Query) -> Query {
+        match &self.key_normalizer {
+            Some(KeyNormalizer(normalize)) => normalize(&query),
+            None => query,
+        }
+    }
+
+    /// Returns the shard responsible for storing the query
+    fn shard(&self, query: &Query) -> &Shard {
+        &self.shards[self.shard_index(query)]
+    }
+
+    fn shard_index(&self, query: &Query) -> usize {
+        self.name_shard_index(query.name())
+    }
+
+    /// Returns the index of the shard storing all the entries of the name
+    fn name_shard_index(&self, name: &Name) -> usize {
+        if self.shards.len() == 1 {
+            return 0;
+        }
+
+        let mut hasher = DefaultHasher::new();
+        name.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    pub(crate) fn insert(
+        &self,
+        query: Query,
+        records_and_ttl: Vec<(Record, u32)>,
+        now: Instant,
+    ) -> Lookup {
+        self.insert_value(query, records_and_ttl, now, None)
+    }
+
+    /// Inserts the records for the query along with application specific metadata
+    ///
+    /// The metadata is opaque to the cache, it can be retrieved with [`DnsLru::get_metadata`] for as
+    ///  long as the records are cached. Replacing the records of the query discards the metadata.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - the query the records are stored under
+    /// * `records_and_ttl` - the records and their TTLs, the minimum TTL is used for the entry
+    /// * `now` - current time for use in associating TTLs
+    /// * `metadata` - e.g. a tenant id, or a trace id
+    pub fn insert_with_metadata(
+        &self,
+        query: Query,
+        records_and_ttl: Vec<(Record, u32)>,
+        now: Instant,
+        metadata: Arc<dyn Any + Send + Sync>,
+    ) -> Lookup {
+        self.insert_value(query, records_and_ttl, now, Some(metadata))
+    }
+
+    fn insert_value(
+        &self,
+        query: Query,
+        records_and_ttl: Vec<(Record, u32)>,
+        now: Instant,
+        metadata: Option<Arc<dyn Any + Send + Sync>>,
+    ) -> Lookup {
+        if let Some(ttl) = records_and_ttl.iter().map(|(_, ttl)| *ttl).max() {
+            self.report_suspicious_ttl(&query, Duration::from_secs(u64::from(ttl)));
+        }
+
+        let len = records_and_ttl.len();
+        // collapse the values, we're going to take the Minimum TTL as the correct one
+        let (records, ttl): (Vec<Record>, Duration) = records_and_ttl.into_iter().fold(
+            (Vec::with_capacity(len), self.positive_max_ttl),
+            |(mut records, mut min_ttl), (record, ttl)| {
+                records.push(record);
+                let ttl = Duration::from_secs(u64::from(ttl));
+                min_ttl = min_ttl.min(ttl);
+                (records, min_ttl)
+            },
+        );
+
+        // If the cache was configured with a minimum TTL, and that value is higher
+        // than the minimum TTL in the values, use it instead.
+        let min_ttl = match self.positive_min_ttl_scope {
+            config::MinTtlScope::Unsigned if Self::is_secure(&records) => Duration::ZERO,
+            _ => self.positive_min_ttl,
+        };
+
+        // fewer records of the type than expected are returned but not cached, the signatures and
+        //  the CNAME chain are not counted
+        let query_type = query.query_type();
+        let below_min = self
+            .min_cached_records
+            .get(&query_type)
+            .is_some_and(|min_records| {
+                let of_type = records
+                    .iter()
+                    .filter(|record| query_type.is_any() || record.record_type() == query_type);
+                of_type.count() < *min_records
+            });
+        if below_min {
+            let valid_until = now + min_ttl.max(ttl);
+            return Lookup::new_with_deadline(query, Arc::from(records), valid_until);
+        }
+
+        // the pinned entries are locked once, to count the flaps and to store the entry
+        let pinned = self.pinned.lock_any();
+        let flaps = self.flaps_after(&query, &records, pinned.as_ref());
+        let ttl = self.dampened(min_ttl.max(ttl), flaps);
+        let ttl = self.jittered(ttl, min_ttl);
+        let valid_until = now + ttl;
+
+        let lookup = Lookup::new_with_deadline(query.clone(), Arc::from(records), valid_until);
+
+        // insert into the LRU
+        self.store_with_pins(
+            query,
+            LruValue {
+                lookup: Ok(lookup.clone()),
+                stored_at: now,
+                valid_until,
+                metadata,
+                prefetched: AtomicBool::new(false),
+                rotation: AtomicUsize::new(0),
+                flaps,
+            },
+            pinned,
+        );
+
+        lookup
+    }
+
+    /// Subtracts a random duration of up to the configured jitter from the TTL, without going below `min_ttl`
+    /// Calls the report of [`DnsLru::with_suspicious_ttl`] if `ttl` is above its threshold
+    fn report_suspicious_ttl(&self, query: &Query, ttl: Duration) {
+        if let Some(suspicious_ttl) = &self.suspicious_ttl {
+            if ttl > suspicious_ttl.threshold {
+                let ttl = u32::try_from(ttl.as_secs()).unwrap_or(u32::MAX);
+                (suspicious_ttl.report)(query, ttl);
+            }
+        }
+    }
+
+    fn jittered(&self, ttl: Duration, min_ttl: Duration) -> Duration {
+        let Some(jitter) = self.ttl_jitter else {
+            return ttl;
+        };
+
+        // the generator is shared by the clones of the cache
+        let fraction = self.jitter_rng.lock().gen::<f64>();
+        ttl.saturating_sub(jitter.mul_f64(fraction)).max(min_ttl)
+    }
+
+    /// True if all the records were validated by DNSSEC
+    #[cfg(feature = "dnssec")]
+    fn is_secure(records: &[Record]) -> bool {
+        !records.is_empty() && records.iter().all(|record| record.proof().is_secure())
+    }
+
+    #[cfg(not(feature = "dnssec"))]
+    fn is_secure(_records: &[Record]) -> bool {
+        false
+    }
+
+    /// inserts a record based on the name and type.
+    ///
+    /// # Arguments
+    ///
+    /// * `original_query` - is used for matching the records that should be returned
+    /// * `records` - the records will be partitioned by type and name for storage in the cache
+    /// * `now` - current time for use in associating TTLs
+    ///
+    /// # Return
+    ///
+    /// This should always return some records, but will be None if there are no records or the original_query matches none
+    pub fn insert_records(
+        &self,
+        original_query: Query,
+        records: impl Iterator<Item = Record>,
+        now: Instant,
+    ) -> Option<Lookup> {
+        // collect all records by name
+        let records = records.fold(
+            HashMap::<Query, Vec<(Record, u32)>>::new(),
+            |mut map, record| {
+                // it's not useful to cache RRSIGs on their own using `name()` as a key because
+                // there can be multiple RRSIG associated to the same domain name where each
+                // RRSIG is *covering* a different record type
+                //
+                // an example of this is shown below
+                //
+                // ``` console
+                // $ dig @a.iana-servers.net. +norecurse +dnssec A example.com.
+                // example.com.     3600    IN  A   93.184.215.14
+                // example.com.     3600    IN  RRSIG   A 13 2 3600 20240705065834 (..)
+                //
+                // $ dig @a.iana-servers.net. +norecurse +dnssec A example.com.
+                // example.com.     86400   IN  NS  a.iana-servers.net.
+                // example.com.     86400   IN  NS  b.iana-servers.net.
+                // example.com.     86400   IN  RRSIG   NS 13 2 86400 20240705060635 (..)
+                // ```
+                //
+                // note that there are two RRSIG records associated to `example.com.` but they are
+                // covering different record types. the first RRSIG covers the
+                // `A example.com.` record. the second RRSIG covers two `NS example.com.` records
+                //
+                // if we use ("example.com.", RecordType::RRSIG) as a key in our cache these two
+                // consecutive queries will cause the entry to be overwriten, losing the RRSIG
+                // covering the A record
+                //
+                // to avoid this problem, we'll cache the RRSIG along the record it covers using
+                // the record's type along the record's `name()` as the key in the cache
+                //
+                // For CNAME records, we want to preserve the original request query type, since
+                // that's what would be used to retrieve the cached query.
+                let rtype = match record.record_type() {
+                    RecordType::CNAME => original_query.query_type(),
+                    #[cfg(feature = "dnssec")]
+                    RecordType::RRSIG => match RRSIG::try_borrow(record.data()) {
+                        Some(rrsig) => rrsig.type_covered(),
+                        None => record.record_type(),
+                    },
+                    _ => record.record_type(),
+                };
+
+                let mut query = Query::query(record.name().clone(), rtype);
+                query.set_query_class(record.dns_class());
+
+                let ttl = record.ttl();
+
+                map.entry(query).or_default().push((record, ttl));
+
+                map
+            },
+        );
+
+        // now insert by record type and name
+        let mut lookup = None;
+        for (query, records_and_ttl) in records {
+            let is_query = original_query == query;
+            let inserted = self.insert(query, records_and_ttl, now);
+
+            if is_query {
+                lookup = Some(inserted)
+            }
+        }
+
+        lookup
+    }
+
+    /// Generally for inserting a set of records that have already been cached, but with a different Query.
+    pub(crate) fn duplicate(&self, query: Query, lookup: Lookup, ttl: u32, now: Instant) -> Lookup {
+        let ttl = Duration::from_secs(u64::from(ttl));
+        self.report_suspicious_ttl(&query, ttl);
+        let valid_until = now + ttl;
+
+        self.store(
+            query,
+            LruValue {
+                lookup: Ok(lookup.clone()),
+                stored_at: now,
+                valid_until,
+                metadata: None,
+                prefetched: AtomicBool::new(false),
+                rotation: AtomicUsize::new(0),
+                flaps: 0,
+            },
+        );
+
+        lookup
+    }
+
+    /// This converts the Error to set the inner negative_ttl value to be the
+    ///  current expiration ttl.
+    fn nx_error_with_ttl(error: &mut ProtoError, new_ttl: Duration) {
+        let ProtoError { kind, .. } = error;
+
+        if let ProtoErrorKind::NoRecordsFound { negative_ttl, .. } = kind.as_mut() {
+            *negative_ttl = Some(u32::try_from(new_ttl.as_secs()).unwrap_or(MAX_TTL));
+        }
+    }
+
+    pub(crate) fn negative(&self, query: Query, mut error: ProtoError, now: Instant) -> ProtoError {
+        let ProtoError { kind, .. } = &error;
+
+        // TODO: if we are getting a negative response, should we instead fallback to cache?
+        //   this would cache indefinitely, probably not correct
+        if let ProtoErrorKind::NoRecordsFound {
+            negative_ttl: Some(ttl),
+            ..
+        } = kind.as_ref()
+        {
+            let ttl_duration = Duration::from_secs(u64::from(*ttl))
+                // Clamp the TTL so that it's between the cache's configured
+                // minimum and maximum TTLs for negative responses.
+                .clamp(self.negative_min_ttl, self.negative_max_ttl);
+            let valid_until = now + ttl_duration;
+
+            // a zero TTL means the response must not be cached, unless `negative_min_ttl` is set;
+            //  a valid positive entry takes precedence, e.g. over the response of a racing query
+            if !ttl_duration.is_zero()
+                && !self.negative_excluded_types.contains(&query.query_type())
+                && !self.is_positive(&query, now)
+            {
+                let error = error.clone();
+
+                self.store(
+                    query,
+                    LruValue {
+                        lookup: Err(error),
+                        stored_at: now,
+                        valid_until,
+                        metadata: None,
+                        prefetched: AtomicBool::new(false),
+                        rotation: AtomicUsize::new(0),
+                        flaps: 0,
+                    },
+                );
+            }
+
+            Self::nx_error_with_ttl(&mut error, ttl_duration);
+        }
+
+        error
+    }
+
+    /// True if the query has a positive entry which is still valid
+    ///
+    /// The entry is peeked, so that checking it doesn't count as a read of the entry.
+    fn is_positive(&self, query: &Query, now: Instant) -> bool {
+        let key = self.key(query);
+        let query = &*key;
+        let positive = |value: &LruValue| {
+            value.lookup.is_ok()
+                && value.is_current(now)
+                && !value.is_older_than(now, self.max_entry_age)
+        };
+
+        if let Some(positive) = self.pinned.inspect(query, positive) {
+            return positive;
+        }
+        self.shard(query).inspect(query, positive).unwrap_or(false)
+    }
+
+    /// Caches the failed DNSSEC validation of the query for the TTL, returning the `Bogus` error
+    pub(crate) fn bogus(&self, query: Query, ttl: Duration, now: Instant) -> ProtoError {
+        let error = ProtoError::from(ProtoErrorKind::Bogus {
+            query: Box::new(query.clone()),
+        });
+
+        self.store(
+            query,
+            LruValue {
+                lookup: Err(error.clone()),
+                stored_at: now,
+                valid_until: now + ttl,
+                metadata: None,
+                prefetched: AtomicBool::new(false),
+                rotation: AtomicUsize::new(0),
+                flaps: 0,
+            },
+        );
+
+        error
+    }
+
+    /// Reports names which are cached both as an alias and with records of another type
+    ///
+    /// This is a diagnostic for debugging, inconsistent entries are cached and served as is.
+    ///  Expired entries which are still held by the cache are also checked. DNSSEC records, which
+    ///  can exist along a CNAME, are ignored.
+    pub fn check_consistency(&self) -> Vec<Inconsistency> {
+        let mut aliases = HashMap::<Name, Vec<Name>>::new();
+        let mut types = HashMap::<Name, Vec<RecordType>>::new();
+        let mut collect = |_: &Query, value: &LruValue| {
+            let Ok(lookup) = &value.lookup else {
+                return;
+            };
+
+            for record in lookup.record_iter() {
+                match record.data() {
+                    RData::CNAME(cname) => aliases
+                        .entry(record.name().clone())
+                        .or_default()
+                        .push(cname.0.clone()),
+                    _ if matches!(
+                        record.record_type(),
+                        RecordType::RRSIG | RecordType::NSEC | RecordType::NSEC3
+                    ) => {}
+                    _ => types
+                        .entry(record.name().clone())
+                        .or_default()
+                        .push(record.record_type()),
+                }
+            }
+        };
+
+        for shard in self.shards.iter() {
+            shard.for_each(&mut collect);
+        }
+        for (query, value) in self.pinned.lock().iter() {
+            collect(query, value);
+        }
+
+        let mut inconsistencies = Vec::new();
+        for (name, targets) in aliases {
+            let Some(record_types) = types.get(&name) else {
+                continue;
+            };
+
+            for target in targets {
+                for record_type in record_types {
+                    let inconsistency = Inconsistency {
+                        name: name.clone(),
+                        target: target.clone(),
+                        record_type: *record_type,
+                    };
+                    if !inconsistencies.contains(&inconsistency) {
+                        inconsistencies.push(inconsistency);
+                    }
+                }
+            }
+        }
+
+        inconsistencies.sort_by(|a, b| {
+            (&a.name, &a.target, a.record_type).cmp(&(&b.name, &b.target, b.record_type))
+        });
+        inconsistencies
+    }
+
+    /// Dumps the valid entries as text resembling a zone file, for debugging
+    ///
+    /// Each entry starts with a comment line of its query, followed by its records with their
+    ///  remaining TTL. Negative entries are only a comment line with the response code and the
+    ///  remaining TTL. Entries are sorted by name and type.
+    pub fn dump_text(&self, now: Instant) -> String {
+        let mut entries = Vec::new();
+        let mut collect = |query: &Query, value: &LruValue| {
+            if let Some(lookup) = value.lookup_at(now, true, self.max_entry_age) {
+                entries.push((query.clone(), value.ttl(now), lookup));
+            }
+        };
+
+        for shard in self.shards.iter() {
+            shard.for_each(&mut collect);
+        }
+        for (query, value) in self.pinned.lock().iter() {
+            collect(query, value);
+        }
+
+        entries.sort_by(|(a, ..), (b, ..)| {
+            (a.name(), a.query_type(), a.query_class()).cmp(&(
+                b.name(),
+                b.query_type(),
+                b.query_class(),
+            ))
+        });
+
+        let mut text = String::new();
+        for (query, ttl, lookup) in entries {
+            // writing to a String never fails
+            let _ = match lookup {
+                Ok(lookup) => {
+                    let _ = writeln!(text, "; {query}");
+                    lookup
+                        .record_iter()
+                        .try_for_each(|record| writeln!(text, "{record}"))
+                }
+                Err(error) => match error.kind() {
+                    ProtoErrorKind::NoRecordsFound { response_code, .. } => writeln!(
+                        text,
+                        "; {query} {response_code:?} {ttl}",
+                        ttl = ttl.as_secs()
+                    ),
+                    kind => writeln!(text, "; {query} {kind} {ttl}", ttl = ttl.as_secs()),
+                },
+            };
+        }
+
+        text
+    }
+
+    /// Returns a snapshot of the queries of the valid entries, with a summary of each entry
+    ///
+    /// Each shard is locked once while it is copied, the caller iterates over the snapshot. This
+    ///  is read-only, the recency of the entries for eviction is unchanged.
+    pub fn entries(&self) -> Vec<(Query, CacheEntryInfo)> {
+        let now = Instant::now();
+        let mut entries = Vec::new();
+        let mut collect = |query: &Query, value: &LruValue| {
+            if !value.is_current(now) {
+                return;
+            }
+
+            let info = CacheEntryInfo {
+                record_count: value
+                    .lookup
+                    .as_ref()
+                    .map_or(0, |lookup| lookup.records().len()),
+                positive: value.lookup.is_ok(),
+                ttl: value.ttl(now),
+            };
+            entries.push((query.clone(), info));
+        };
+
+        for shard in self.shards.iter() {
+            shard.for_each(&mut collect);
+        }
+        for (query, value) in self.pinned.lock().iter() {
+            collect(query, value);
+        }
+
+        entries
+    }
+
+    /// Returns a snapshot of the valid positive entries, to be imported on restart
+    ///
+    /// The expiry of the entries is measured from the UNIX epoch, so that it can be serialized and
+    ///  compared with the wall clock of another process, see [`DnsLru::import`]. Negative entries
+    ///  aren't exported.
+    pub fn export(&self) -> Vec<CacheSnapshotEntry> {
+        let now = Instant::now();
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut entries = Vec::new();
+        let mut collect = |query: &Query, value: &LruValue| {
+            let Ok(lookup) = &value.lookup else {
+                return;
+            };
+            if !value.is_current(now) {
+                return;
+            }
+
+            entries.push(CacheSnapshotEntry {
+                query: query.clone(),
+                records: lookup.records().to_vec(),
+                expires_at: since_epoch + value.ttl(now),
+            });
+        };
+
+        for shard in self.shards.iter() {
+            shard.for_each(&mut collect);
+        }
+        for (query, value) in self.pinned.lock().iter() {
+            collect(query, value);
+        }
+
+        entries
+    }
+
+    /// Stores the entries of a snapshot, see [`DnsLru::export`]
+    ///
+    /// The entries are stored for the remainder of their TTL according to the wall clock, those
+    ///  which already expired are dropped. The TTL configuration of this cache isn't applied again.
+    pub fn import(&self, entries: Vec<CacheSnapshotEntry>, now: Instant) {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        for entry in entries {
+            let Some(ttl) = entry
+                .expires_at
+                .checked_sub(since_epoch)
+                .filter(|ttl| !ttl.is_zero())
+            else {
+                continue;
+            };
+
+            self.report_suspicious_ttl(&entry.query, ttl);
+            let valid_until = now + ttl;
+            let lookup = Lookup::new_with_deadline(
+                entry.query.clone(),
+                Arc::from(entry.records),
+                valid_until,
+            );
+            self.store(
+                entry.query,
+                LruValue {
+                    lookup: Ok(lookup),
+                    stored_at: now,
+                    valid_until,
+                    metadata: None,
+                    prefetched: AtomicBool::new(false),
+                    rotation: AtomicUsize::new(0),
+                    flaps: 0,
+                },
+            );
+        }
+    }
+
+    /// Based on the query, see if there are any records available
+    ///
+    /// Expired entries are only returned by [`DnsLru::get_stale_on_failure`] and
+    ///  [`DnsLru::get_stale`].
+    pub fn get(&self, query: &Query, now: Instant) -> Option<Result<Lookup, ProtoError>> {
+        self.count(self.lookup(query, now, None))
+            .map(|(lookup, _)| lookup)
+    }
+
+    /// Like [`DnsLru::get`], also returning a hint when the entry should be refreshed
+    ///
+    /// The hint is returned for current positive entries once less than the `prefetch_threshold`
+    ///  of the [`TtlConfig`] remains of their TTL, so that the caller refreshes the entry before it
+    ///  expires. Once the caller starts the refresh, it marks the entry with
+    ///  [`DnsLru::mark_prefetched`] so that no other hint is returned until the entry is replaced.
+    ///  The cache itself never queries upstream.
+    pub fn get_with_prefetch_hint(
+        &self,
+        query: &Query,
+        now: Instant,
+    ) -> Option<(Result<Lookup, ProtoError>, Option<PrefetchHint>)> {
+        self.count(self.lookup(query, now, self.prefetch_threshold))
+    }
+
+    /// Marks the entry of the query as being refreshed, see [`DnsLru::get_with_prefetch_hint`]
+    pub fn mark_prefetched(&self, query: &Query) {
+        let key = self.key(query);
+        let query = &*key;
+        let mark = |value: &LruValue| value.prefetched.store(true, Ordering::Relaxed);
+        if self.pinned.inspect(query, mark).is_none() {
+            self.shard(query).inspect(query, mark);
+        }
+    }
+
+    /// Counts the lookup as a hit or a miss
+    fn count<T>(&self, lookup: Option<T>) -> Option<T> {
+        let counter = match lookup {
+            Some(_) => &self.stats.hits,
+            None => &self.stats.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        lookup
+    }
+
+    /// Returns the current lookup of the query, without counting a hit or miss
+    fn lookup(
+        &self,
+        query: &Query,
+        now: Instant,
+        prefetch: Option<config::PrefetchThreshold>,
+    ) -> Option<(Result<Lookup, ProtoError>, Option<PrefetchHint>)> {
+        let key = self.key(query);
+        let query = &*key;
+        let serve = ServeOptions {
+            decrement_ttl: !self.preserve_ttl,
+            max_age: self.max_entry_age,
+            rotate: self.rotate,
+        };
+        // expired entries are kept while they may be served stale, or compared to their refresh
+        let damping_grace = self
+            .flapping_damping
+            .map_or(Duration::ZERO, |damping| damping.max_ttl);
+        let grace = self
+            .stale_grace
+            .max(self.serve_stale_ttl)
+            .max(self.negative_serve_stale_ttl)
+            .max(damping_grace);
+        let pinned = self.pinned.inspect(query, |value| {
+            let lookup = value.current_lookup_at(now, serve);
+            let hint = prefetch.and_then(|threshold| value.prefetch_hint(now, threshold));
+            lookup.map(|lookup| (lookup, hint))
+        });
+        match pinned {
+            Some(Some(found)) => return Some(found),
+            Some(None) => {
+                if self.evict_expired_pin(query, now, grace) {
+                    self.report_evicted(std::slice::from_ref(query));
+                }
+                return None;
+            }
+            None => {}
+        }
+
+        let ShardLookup { lookup, removed } =
+            self.shard(query).get(query, now, grace, serve, prefetch);
+        if removed {
+            self.report_evicted(std::slice::from_ref(query));
+        }
+        lookup
+    }
+
+    /// Returns the metadata attached to the cached entry of the query, see [`DnsLru::insert_with_metadata`]
+    pub fn get_metadata(&self, query: &Query, now: Instant) -> Option<Arc<dyn Any + Send + Sync>> {
+        let key = self.key(query);
+        let query = &*key;
+        if let Some(metadata) = self.pinned.inspect(query, |value| {
+            value.metadata.clone().filter(|_| value.is_current(now))
+        }) {
+            return metadata;
+        }
+
+        self.shard(query).metadata(query, now)
+    }
+
+    /// Returns the instant at which the cached entry of the query expires
+    ///
+    /// The instant may be in the past, for expired entries which are still retained, e.g. to be
+    ///  served stale.
+    pub fn expires_at(&self, query: &Query) -> Option<Instant> {
+        let key = self.key(query);
+        let query = &*key;
+        if let Some(valid_until) = self.pinned.inspect(query, |value| value.valid_until) {
+            return Some(valid_until);
+        }
+
+        self.shard(query).expires_at(query)
+    }
+
+    /// Returns a future completing when the cached entry of the query expires, see [`DnsLru::expires_at`]
+    ///
+    /// This allows a refresher to sleep until the entry must be resolved again, rather than poll
+    ///  the cache. The expiry is read when this is called, so the future is not affected by later
+    ///  updates of the entry, it sleeps for the time left at `now` from its first poll. It
+    ///  completes immediately if the query is not cached.
+    pub fn wait_until_expired<T: Time + 'static>(
+        &self,
+        query: &Query,
+        now: Instant,
+    ) -> impl Future<Output = ()> + Send + 'static {
+        let delay = self
+            .expires_at(query)
+            .map(|expires_at| expires_at.saturating_duration_since(now));
+
+        async move {
+            if let Some(delay) = delay {
+                T::delay_for(delay).await;
+            }
+        }
+    }
+}
+
+/// A cache of the lookups and negative responses of queries
+///
+/// This allows layering caches, e.g. a [`DnsLru`] in front of a cache shared by several processes,
+///  see [`ReadThrough`] and [`Resolver::set_secondary_cache`](crate::Resolver::set_secondary_cache).
+pub trait DnsCache: Send + Sync {
+    /// Returns the cached lookup or negative response of the query, if any
+    fn get(&self, query: &Query, now: Instant) -> Option<Result<Lookup, ProtoError>>;
+
+    /// Stores the lookup or negative response of the query
+    ///
+    /// Lookups are stored until their `valid_until`, negative responses for their negative TTL.
+    fn insert(&self, query: Query, lookup: Result<Lookup, ProtoError>, now: Instant);
+}
+
+impl DnsCache for DnsLru {
+    fn get(&self, query: &Query, now: Instant) -> Option<Result<Lookup, ProtoError>> {
+        Self::get(self, query, now)
+    }
+
+    fn insert(&self, query: Query, lookup: Result<Lookup, ProtoError>, now: Instant) {
+        match lookup {
+            Ok(lookup) => {
+                let ttl = lookup
+                    .valid_until()
+                    .saturating_duration_since(now)
+                    .as_secs();
+                let ttl = u32::try_from(ttl).unwrap_or(u32::MAX);
+                let records = lookup
+                    .records()
+                    .iter()
+                    .map(|record| (record.clone(), ttl))
+                    .collect();
+                Self::insert(self, query, records, now);
+            }
+            Err(error) => {
+                self.negative(query, error, now);
+            }
+        }
+    }
+}
+
+/// A local [`DnsLru`] backed by a secondary cache, e.g. shared over the network
+///
+/// Lookups missing from the local cache are read from the secondary cache, and stored in the local
+///  cache for the remainder of their TTL. Inserted lookups are stored in both caches.
+#[derive(Clone)]
+pub struct ReadThrough {
+    local: DnsLru,
+    secondary: Arc<dyn DnsCache>,
+}
+
+impl ReadThrough {
+    /// Creates a read-through cache from the local cache and the secondary cache
+    pub fn new(local: DnsLru, secondary: Arc<dyn DnsCache>) -> Self {
+        Self { local, secondary }
+    }
+
+    /// Returns the local cache
+    pub fn local(&self) -> &DnsLru {
+        &self.local
+    }
+
+    /// Returns the secondary cache
+    pub fn secondary(&self) -> &Arc<dyn DnsCache> {
+        &self.secondary
+    }
+
+    /// Reads the lookup from the secondary cache, storing it in the local cache
+    pub(crate) fn read_secondary(
+        &self,
+        query: &Query,
+        now: Instant,
+    ) -> Option<Result<Lookup, ProtoError>> {
+        let lookup = self.secondary.get(query, now)?;
+        // stale lookups are returned without being stored, they are already expired
+        if !matches!(&lookup, Ok(lookup) if lookup.is_stale()) {
+            DnsCache::insert(&self.local, query.clone(), lookup.clone(), now);
+        }
+
+        Some(lookup)
+    }
+}
+
+impl fmt::Debug for ReadThrough {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReadThrough")
+            .field("local", &self.local)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DnsCache for ReadThrough {
+    fn get(&self, query: &Query, now: Instant) -> Option<Result<Lookup, ProtoError>> {
+        self.local
+            .get(query, now)
+            .or_else(|| self.read_secondary(query, now))
+    }
+
+    fn insert(&self, query: Query, lookup: Result<Lookup, ProtoError>, now: Instant) {
+        self.secondary.insert(query.clone(), lookup.clone(), now);
+        DnsCache::insert(&self.local, query, lookup, now);
+    }
+}
+
+// see also the lookup_tests.rs in integration-tests crate
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::time::*;
+
+    use crate::proto::op::{Query, ResponseCode};
+    use crate::proto::rr::rdata::{A, CNAME};
+    use crate::proto::rr::{DNSClass, Name, RData, RecordType};
+
+    use super::*;
+
+    #[test]
+    fn test_is_current() {
+        let now = Instant::now();
+        let not_the_future = now + Duration::from_secs(4);
+        let future = now + Duration::from_secs(5);
+        let past_the_future = now + Duration::from_secs(6);
+
+        let value = LruValue {
+            lookup: Err(ProtoErrorKind::Message("test error").into()),
+            stored_at: now,
+            valid_until: future,
+            metadata: None,
+            prefetched: AtomicBool::new(false),
+            rotation: AtomicUsize::new(0),
+            flaps: 0,
+        };
+
+        assert!(value.is_current(now));
+        assert!(value.is_current(not_the_future));
+        assert!(value.is_current(future));
+        assert!(!value.is_current(past_the_future));
+    }
+
+    #[test]
+    fn test_lookup_uses_positive_min_ttl() {
+        let now = Instant::now();
+
+        let name = Name::from_str("www.example.com.").unwrap();
+        let query = Query::query(name.clone(), RecordType::A);
+        // record should have TTL of 1 second.
+        let ips_ttl = vec![(
+            Record::from_rdata(name.clone(), 1, RData::A(A::new(127, 0, 0, 1))),
+            1,
+        )];
+        let ips = [RData::A(A::new(127, 0, 0, 1))];
+
+        // configure the cache with a minimum TTL of 2 seconds.
+        let ttls = TtlConfig {
+            positive_min_ttl: Some(Duration::from_secs(2)),
+            ..TtlConfig::default()
+        };
+        let lru = DnsLru::new(1, ttls);
+
+        let rc_ips = lru.insert(query.clone(), ips_ttl, now);
+        assert_eq!(*rc_ips.iter().next().unwrap(), ips[0]);
+        // the returned lookup should use the cache's min TTL, since the
+        // query's TTL was below the minimum.
+        assert_eq!(rc_ips.valid_until(), now + Duration::from_secs(2));
+
+        // record should have TTL of 3 seconds.
+        let ips_ttl = vec![(
+            Record::from_rdata(name, 3, RData::A(A::new(127, 0, 0, 1))),
+            3,
+        )];
+
+        let rc_ips = lru.insert(query, ips_ttl, now);
+        assert_eq!(*rc_ips.iter().next().unwrap(), ips[0]);
+        // the returned lookup should use the record's TTL, since it's
+        // greater than the cache's minimum.
+        assert_eq!(rc_ips.valid_until(), now + Duration::from_secs(3));
+    }
+
+    #[test]
+    fn test_error_uses_negative_min_ttl() {
+        let now = Instant::now();
+
+        let name = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+
+        // configure the cache with a maximum TTL of 2 seconds.
+        let ttls = TtlConfig {
+            negative_min_ttl: Some(Duration::from_secs(2)),
+            ..TtlConfig::default()
+        };
+        let lru = DnsLru::new(1, ttls);
+
+        // neg response should have TTL of 1 seconds.
+        let err = ProtoErrorKind::NoRecordsFound {
+            query: Box::new(name.clone()),
+            soa: None,
+            ns: None,
+            negative_ttl: Some(1),
+            response_code: ResponseCode::NoError,
+            trusted: false,
+            authorities: None,
+        };
+        let nx_error = lru.negative(name.clone(), err.into(), now);
+        match nx_error.kind() {
+            &ProtoErrorKind::NoRecordsFound { negative_ttl, .. } => {
+                let valid_until = negative_ttl.expect("resolve error should have a deadline");
+                // the error's `valid_until` field should have been limited to 2 seconds.
+                assert_eq!(valid_until, 2);
+            }
+            other => panic!("expected ProtoErrorKind::NoRecordsFound, got {:?}", other),
+        }
+
+        // neg response should have TTL of 3 seconds.
+        let err = ProtoErrorKind::NoRecordsFound {
+            query: Box::new(name.clone()),
+            soa: None,
+            ns: None,
+            negative_ttl: Some(3),
+            response_code: ResponseCode::NoError,
+            trusted: false,
+            authorities: None,
+        };
+        let nx_error = lru.negative(name, err.into(), now);
+        match nx_error.kind() {
+            &ProtoErrorKind::NoRecordsFound { negative_ttl, .. } => {
+                let negative_ttl = negative_ttl.expect("ProtoError should have a deadline");
+                // the error's `valid_until` field should not have been limited, as it was
+                // over the min TTL.
+                assert_eq!(negative_ttl, 3);
+            }
+            other => panic!("expected ProtoErrorKind::NoRecordsFound, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_lookup_uses_positive_max_ttl() {
+        let now = Instant::now();
+
+        let name = Name::from_str("www.example.com.").unwrap();
+        let query = Query::query(name.clone(), RecordType::A);
+        // record should have TTL of 62 seconds.
+        let ips_ttl = vec![(
+            Record::from_rdata(name.clone(), 62, RData::A(A::new(127, 0, 0, 1))),
+            62,
+        )];
+        let ips = [RData::A(A::new(127, 0, 0, 1))];
+
+        // configure the cache with a maximum TTL of 60 seconds.
+        let ttls = TtlConfig {
+            positive_max_ttl: Some(Duration::from_secs(60)),
+            ..TtlConfig::default()
+        };
+        let lru = DnsLru::new(1, ttls);
+
+        let rc_ips = lru.insert(query.clone(), ips_ttl, now);
+        assert_eq!(*rc_ips.iter().next().unwrap(), ips[0]);
+        // the returned lookup should use the cache's min TTL, since the
+        // query's TTL was above the maximum.
+        assert_eq!(rc_ips.valid_until(), now + Duration::from_secs(60));
+
+        // record should have TTL of 59 seconds.
+        let ips_ttl = vec![(
+            Record::from_rdata(name, 59, RData::A(A::new(127, 0, 0, 1))),
+            59,
+        )];
+
+        let rc_ips = lru.insert(query, ips_ttl, now);
+        assert_eq!(*rc_ips.iter().next().unwrap(), ips[0]);
+        // the returned lookup should use the record's TTL, since it's
+        // below than the cache's maximum.
+        assert_eq!(rc_ips.valid_until(), now + Duration::from_secs(59));
+    }
+
+    #[test]
+    fn test_error_uses_negative_max_ttl() {
+        let now = Instant::now();
+
+        let name = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+
+        // configure the cache with a maximum TTL of 60 seconds.
+        let ttls = TtlConfig {
+            negative_max_ttl: Some(Duration::from_secs(60)),
+            ..TtlConfig::default()
+        };
+        let lru = DnsLru::new(1, ttls);
+
+        // neg response should have TTL of 62 seconds.
+        let err: ProtoErrorKind = ProtoErrorKind::NoRecordsFound {
+            query: Box::new(name.clone()),
+            soa: None,
+            ns: None,
+            negative_ttl: Some(62),
+            response_code: ResponseCode::NoError,
+            trusted: false,
+            authorities: None,
+        };
+        let nx_error = lru.negative(name.clone(), err.into(), now);
+        match nx_error.kind() {
+            &ProtoErrorKind::NoRecordsFound { negative_ttl, .. } => {
+                let negative_ttl = negative_ttl.expect("resolve error should have a deadline");
+                // the error's `valid_until` field should have been limited to 60 seconds.
+                assert_eq!(negative_ttl, 60);
+            }
+            other => panic!("expected ProtoErrorKind::NoRecordsFound, got {:?}", other),
+        }
+
+        // neg response should have TTL of 59 seconds.
+        let err = ProtoErrorKind::NoRecordsFound {
+            query: Box::new(name.clone()),
+            soa: None,
+            ns: None,
+            negative_ttl: Some(59),
+            response_code: ResponseCode::NoError,
+            trusted: false,
+            authorities: None,
+        };
+        let nx_error = lru.negative(name, err.into(), now);
+        match nx_error.kind() {
+            &ProtoErrorKind::NoRecordsFound { negative_ttl, .. } => {
+                let negative_ttl = negative_ttl.expect("resolve error should have a deadline");
+                // the error's `valid_until` field should not have been limited, as it was
+                // under the max TTL.
+                assert_eq!(negative_ttl, 59);
+            }
+            other => panic!("expected ProtoErrorKind::NoRecordsFound, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_insert() {
+        let now = Instant::now();
+
+        let name = Name::from_str("www.example.com.").unwrap();
+        let query = Query::query(name.clone(), RecordType::A);
+        let ips_ttl = vec![(
+            Record::from_rdata(name, 1, RData::A(A::new(127, 0, 0, 1))),
+            1,
+        )];
+        let ips = [RData::A(A::new(127, 0, 0, 1))];
+        let lru = DnsLru::new(1, TtlConfig::default());
+
+        let rc_ips = lru.insert(query.clone(), ips_ttl, now);
+        assert_eq!(*rc_ips.iter().next().unwrap(), ips[0]);
+
+        let rc_ips = lru.get(&query, now).unwrap().expect("records should exist");
+        assert_eq!(*rc_ips.iter().next().unwrap(), ips[0]);
+    }
+
+    #[test]
+    fn test_update_ttl() {
+        let now = Instant::now();
+
+        let name = Name::from_str("www.example.com.").unwrap();
+        let query = Query::query(name.clone(), RecordType::A);
+        let ips_ttl = vec![(
+            Record::from_rdata(name, 10, RData::A(A::new(127, 0, 0, 1))),
+            10,
+        )];
+        let ips = [RData::A(A::new(127, 0, 0, 1))];
+        let lru = DnsLru::new(1, TtlConfig::default());
+
+        let rc_ips = lru.insert(query.clone(), ips_ttl, now);
+        assert_eq!(*rc_ips.iter().next().unwrap(), ips[0]);
+
+        let ttl = lru
+            .get(&query, now + Duration::from_secs(2))
+            .unwrap()
+            .expect("records should exist")
+            .record_iter()
+            .next()
+            .unwrap()
+            .ttl();
+        assert!(ttl <= 8);
+    }
+
+    #[test]
+    fn test_insert_ttl() {
+        let now = Instant::now();
+        let name = Name::from_str("www.example.com.").unwrap();
+        let query = Query::query(name.clone(), RecordType::A);
+        // TTL should be 1
+        let ips_ttl = vec![
+            (
+                Record::from_rdata(name.clone(), 1, RData::A(A::new(127, 0, 0, 1))),
+                1,
+            ),
+            (
+                Record::from_rdata(name, 2, RData::A(A::new(127, 0, 0, 2))),
+                2,
+            ),
+        ];
+        let ips = vec![
+            RData::A(A::new(127, 0, 0, 1)),
+            RData::A(A::new(127, 0, 0, 2)),
+        ];
+        let lru = DnsLru::new(1, TtlConfig::default());
+
+        lru.insert(query.clone(), ips_ttl, now);
+
+        // still valid
+        let rc_ips = lru
+            .get(&query, now + Duration::from_secs(1))
+            .unwrap()
+            .expect("records should exist");
+        assert_eq!(*rc_ips.iter().next().unwrap(), ips[0]);
+
+        // 2 should be one too far
+        let rc_ips = lru.get(&query, now + Duration::from_secs(2));
+        assert!(rc_ips.is_none());
+    }
+
+    #[test]
+    fn test_insert_positive_min_ttl() {
+        let now = Instant::now();
+        let name = Name::from_str("www.example.com.").unwrap();
+        let query = Query::query(name.clone(), RecordType::A);
+        // TTL should be 1
+        let ips_ttl = vec![
+            (
+                Record::from_rdata(name.clone(), 1, RData::A(A::new(127, 0, 0, 1))),
+                1,
+            ),
+            (
+                Record::from_rdata(name, 2, RData::A(A::new(127, 0, 0, 2))),
+                2,
+            ),
+        ];
+        let ips = vec![
+            RData::A(A::new(127, 0, 0, 1)),
+            RData::A(A::new(127, 0, 0, 2)),
+        ];
+
+        // this cache should override the TTL of 1 seconds with the configured
+        // minimum TTL of 3 seconds.
+        let ttls = TtlConfig {
+            positive_min_ttl: Some(Duration::from_secs(3)),
+            ..TtlConfig::default()
+        };
+        let lru = DnsLru::new(1, ttls);
+        lru.insert(query.clone(), ips_ttl, now);
+
+        // still valid
+        let rc_ips = lru
+            .get(&query, now + Duration::from_secs(1))
+            .unwrap()
+            .expect("records should exist");
+        for (rc_ip, ip) in rc_ips.iter().zip(ips.iter()) {
+            assert_eq!(rc_ip, ip, "after 1 second");
+        }
+
+        let rc_ips = lru
+            .get(&query, now + Duration::from_secs(2))
+            .unwrap()
+            .expect("records should exist");
+        for (rc_ip, ip) in rc_ips.iter().zip(ips.iter()) {
+            assert_eq!(rc_ip, ip, "after 2 seconds");
+        }
+
+        let rc_ips = lru
+            .get(&query, now + Duration::from_secs(3))
+            .unwrap()
+            .expect("records should exist");
+        for (rc_ip, ip) in rc_ips.iter().zip(ips.iter()) {
+            assert_eq!(rc_ip, ip, "after 3 seconds");
+        }
+
+        // after 4 seconds, the records should be invalid.
+        let rc_ips = lru.get(&query, now + Duration::from_secs(4));
+        assert!(rc_ips.is_none());
+    }
+
+    #[test]
+    fn test_suspicious_ttl() {
+        const DAY: u32 = 86400;
+
+        let now = Instant::now();
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let lru = DnsLru::new(2, TtlConfig::default()).with_suspicious_ttl(
+            Duration::from_secs(u64::from(7 * DAY)),
+            {
+                let reported = reported.clone();
+                move |query, ttl| reported.lock().push((query.clone(), ttl))
+            },
+        );
+
+        let name = Name::from_str("www.example.com.").unwrap();
+        let query = Query::query(name.clone(), RecordType::A);
+        let record = Record::from_rdata(name.clone(), 30 * DAY, RData::A(A::new(127, 0, 0, 1)));
+        let lookup = lru.insert(query.clone(), vec![(record, 30 * DAY)], now);
+
+        // reported with the raw TTL, while still clamped to the maximum TTL
+        assert_eq!(*reported.lock(), vec![(query, 30 * DAY)]);
+        assert_eq!(
+            lookup.valid_until(),
+            now + Duration::from_secs(u64::from(MAX_TTL))
+        );
+
+        let other = Query::query(Name::from_str("other.example.com.").unwrap(), RecordType::A);
+        let record = Record::from_rdata(name, DAY, RData::A(A::new(127, 0, 0, 2)));
+        lru.insert(other, vec![(record, DAY)], now);
+        assert_eq!(reported.lock().len(), 1);
+
+        // every other insertion path is checked too
+        reported.lock().clear();
+        let query = |name: &str| Query::query(Name::from_str(name).unwrap(), RecordType::A);
+        let record = |query: &Query| {
+            Record::from_rdata(
+                query.name().clone(),
+                30 * DAY,
+                RData::A(A::new(127, 0, 0, 1)),
+            )
+        };
+
+        let records = query("records.example.com.");
+        lru.insert_records(records.clone(), [record(&records)].into_iter(), now);
+
+        let pinned = query("pinned.example.com.");
+        lru.insert(pinned.clone(), vec![(record(&pinned), DAY)], now);
+        assert!(lru.pin(&pinned));
+        let ttl = Duration::from_secs(u64::from(30 * DAY));
+        let lookup =
+            Lookup::new_with_deadline(pinned.clone(), Arc::from([record(&pinned)]), now + ttl);
+        lru.replace_name(pinned.name(), vec![(pinned.clone(), lookup, ttl)], now);
+
+        let imported = query("imported.example.com.");
+        let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        lru.import(
+            vec![CacheSnapshotEntry {
+                query: imported.clone(),
+                records: vec![record(&imported)],
+                expires_at: since_epoch + ttl,
+            }],
+            now,
+        );
+
+        let reported = reported.lock();
+        let reported = reported.iter().map(|(query, _)| query).collect::<Vec<_>>();
+        assert_eq!(reported, vec![&records, &pinned, &imported]);
+    }
+
+    #[test]
+    #[cfg(feature = "dnssec")]
+    fn test_positive_min_ttl_unsigned_scope() {
+        use crate::proto::rr::dnssec::Proof;
+
+        let now = Instant::now();
+        let ttls = TtlConfig {
+            positive_min_ttl: Some(Duration::from_secs(300)),
+            positive_min_ttl_scope: config::MinTtlScope::Unsigned,
+            ..TtlConfig::default()
+        };
+        let lru = DnsLru::new(2, ttls);
+
+        let name = Name::from_str("www.example.com.").unwrap();
+        let record = Record::from_rdata(name.clone(), 1, RData::A(A::new(127, 0, 0, 1)));
+
+        // the minimum TTL is applied to the unsigned response
+        let unsigned = lru.insert(
+            Query::query(name.clone(), RecordType::A),
+            vec![(record.clone(), 1)],
+            now,
+        );
+        assert_eq!(unsigned.valid_until(), now + Duration::from_secs(300));
+
+        // but not to the response validated by DNSSEC
+        let mut secure = record;
+        secure.set_proof(Proof::Secure);
+        let secure = lru.insert(Query::query(name, RecordType::A), vec![(secure, 1)], now);
+        assert_eq!(secure.valid_until(), now + Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_insert_positive_max_ttl() {
+        let now = Instant::now();
+        let name = Name::from_str("www.example.com.").unwrap();
+        let query = Query::query(name.clone(), RecordType::A);
+        // TTL should be 500
+        let ips_ttl = vec![
+            (
+                Record::from_rdata(name.clone(), 400, RData::A(A::new(127, 0, 0, 1))),
+                400,
+            ),
+            (
+                Record::from_rdata(name, 500, RData::A(A::new(127, 0, 0, 2))),
+                500,
+            ),
+        ];
+        let ips = vec![
+            RData::A(A::new(127, 0, 0, 1)),
+            RData::A(A::new(127, 0, 0, 2)),
+        ];
+
+        // this cache should override the TTL of 500 seconds with the configured
+        // minimum TTL of 2 seconds.
+        let ttls = TtlConfig {
+            positive_max_ttl: Some(Duration::from_secs(2)),
+            ..TtlConfig::default()
+        };
+        let lru = DnsLru::new(1, ttls);
+        lru.insert(query.clone(), ips_ttl, now);
+
+        // still valid
+        let rc_ips = lru
+            .get(&query, now + Duration::from_secs(1))
+            .unwrap()
+            .expect("records should exist");
+        for (rc_ip, ip) in rc_ips.iter().zip(ips.iter()) {
+            assert_eq!(rc_ip, ip, "after 1 second");
+        }
+
+        let rc_ips = lru
+            .get(&query, now + Duration::from_secs(2))
+            .unwrap()
+            .expect("records should exist");
+        for (rc_ip, ip) in rc_ips.iter().zip(ips.iter()) {
+            assert_eq!(rc_ip, ip, "after 2 seconds");
+        }
+
+        // after 3 seconds, the records should be invalid.
+        let rc_ips = lru.get(&query, now + Duration::from_secs(3));
+        assert!(rc_ips.is_none());
+    }
+
+    #[test]
+    fn test_sharded_capacity() {
+        let lru = DnsLru::new_sharded(10, 4, TtlConfig::default());
+        assert_eq!(lru.shards.len(), 4);
+        let capacity = lru.shards.iter().map(|s| s.capacity()).sum::<usize>();
+        assert_eq!(capacity, 10);
+
+        // never more shards than entries
+        let lru = DnsLru::new_sharded(2, 8, TtlConfig::default());
+        assert_eq!(lru.shards.len(), 2);
+
+        // small caches keep a single shard
+        let lru = DnsLru::new(32, TtlConfig::default());
+        assert_eq!(lru.shards.len(), 1);
+    }
+
+    #[test]
+    fn test_sharded_concurrent() {
+        const THREADS: usize = 8;
+        const NAMES: usize = 256;
+
+        let now = Instant::now();
+        let lru = DnsLru::new_sharded(2 * THREADS * NAMES, THREADS, TtlConfig::default());
+
+        std::thread::scope(|scope| {
+            for t in 0..THREADS {
+                let lru = &lru;
+                scope.spawn(move || {
+                    for i in 0..NAMES {
+                        let name =
+                            Name::from_str(&format!("host{i}.thread{t}.example.com.")).unwrap();
+                        let query = Query::query(name.clone(), RecordType::A);
+                        let ip = RData::A(A::new(10, t as u8, (i >> 8) as u8, i as u8));
+                        lru.insert(
+                            query.clone(),
+                            vec![(Record::from_rdata(name, 300, ip.clone()), 300)],
+                            now,
+                        );
+
+                        let lookup = lru.get(&query, now).unwrap().expect("records should exist");
+                        assert_eq!(lookup.iter().next().unwrap(), &ip);
+                    }
+                });
+            }
+        });
+
+        // every shard was used and nothing was evicted
+        assert!(lru.shards.iter().all(|shard| shard.len() > 0));
+        let len = lru.shards.iter().map(|s| s.len()).sum::<usize>();
+        assert_eq!(len, THREADS * NAMES);
+
+        lru.clear();
+        assert!(lru.shards.iter().all(|shard| shard.len() == 0));
+    }
+
+    pub(super) fn insert_a(lru: &DnsLru, host: &str, now: Instant) -> Query {
+        let name = Name::from_str(host).unwrap();
+        let query = Query::query(name.clone(), RecordType::A);
+        let record = Record::from_rdata(name, 300, RData::A(A::new(127, 0, 0, 1)));
+        lru.insert(query.clone(), vec![(record, 300)], now);
+        query
+    }
+
+    /// Returns a trusted `NXDOMAIN` response to the query, with a negative TTL of `negative_ttl`
+    pub(super) fn nx_domain(query: &Query, negative_ttl: u32) -> ProtoError {
+        ProtoError::from(ProtoErrorKind::NoRecordsFound {
+            query: Box::new(query.clone()),
+            soa: None,
+            ns: None,
+            negative_ttl: Some(negative_ttl),
+            response_code: ResponseCode::NXDomain,
+            trusted: true,
+            authorities: None,
+        })
+    }
+
+    #[test]
+    fn test_clock_second_chance() {
+        let now = Instant::now();
+        let lru = DnsLru::new_with_backend(2, CacheBackend::Clock, TtlConfig::default());
+
+        let first = insert_a(&lru, "first.example.com.", now);
+        let second = insert_a(&lru, "second.example.com.", now);
+
+        // reading the first entry gives it a second chance, so the second one is evicted
+        assert!(lru.get(&first, now).is_some());
+        let third = insert_a(&lru, "third.example.com.", now);
+
+        assert!(lru.get(&first, now).is_some());
+        assert!(lru.get(&second, now).is_none());
+        assert!(lru.get(&third, now).is_some());
+
+        // out of date entries are removed on read
+        assert!(lru.get(&first, now + Duration::from_secs(301)).is_none());
+        assert_eq!(lru.shards[0].len(), 1);
+        assert!(lru.get(&third, now).is_some());
+    }
+
+    #[test]
+    fn test_introspection_does_not_affect_eviction() {
+        let now = Instant::now();
+
+        // the capacities leave room for two entries before evicting, the probationary segment of
+        //  the segmented LRU holds a fifth of the capacity
+        for (backend, capacity) in [
+            (CacheBackend::Lru, 2),
+            (CacheBackend::Clock, 2),
+            #[cfg(feature = "segmented-lru")]
+            (CacheBackend::SegmentedLru, 10),
+        ] {
+            let lru = DnsLru::new_with_backend(capacity, backend, TtlConfig::default());
+            let first = insert_a(&lru, "first.example.com.", now);
+            let second = insert_a(&lru, "second.example.com.", now);
+
+            // none of these count as a read of the first entry, so it is still evicted first
+            assert!(lru.expires_at(&first).is_some());
+            assert!(lru.get_metadata(&first, now).is_none());
+            assert!(lru.get_stale(&first, now).is_none());
+            assert!(lru.flapping_status(&first).is_some());
+            assert!(lru.is_positive(&first, now));
+            let third = insert_a(&lru, "third.example.com.", now);
+
+            assert!(lru.get(&first, now).is_none(), "{backend:?}");
+            assert!(lru.get(&second, now).is_some(), "{backend:?}");
+            assert!(lru.get(&third, now).is_some(), "{backend:?}");
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "segmented-lru")]
+    fn test_segmented_lru_keeps_frequent_entries() {
+        let now = Instant::now();
+
+        for (backend, survives) in [
+            (CacheBackend::Lru, false),
+            (CacheBackend::SegmentedLru, true),
+        ] {
+            let lru = DnsLru::new_with_backend(4, backend, TtlConfig::default());
+            assert_eq!(lru.shards[0].capacity(), 4);
+
+            // the entry is read repeatedly, before a burst of new entries fills the cache
+            let hot = insert_a(&lru, "hot.example.com.", now);
+            for _ in 0..3 {
+                assert!(lru.get(&hot, now).is_some());
+            }
+            for i in 0..4 {
+                insert_a(&lru, &format!("host{i}.example.com."), now);
+            }
+
+            assert_eq!(lru.get(&hot, now).is_some(), survives, "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn test_ttl_jitter() {
+        let now = Instant::now();
+        let ttls = TtlConfig {
+            positive_min_ttl: Some(Duration::from_secs(250)),
+            ..TtlConfig::default()
+        }
+        .with_ttl_jitter(Some(Duration::from_secs(100)));
+        let expirations = |seed: u64| {
+            let lru = DnsLru::new(64, ttls).with_jitter_seed(seed);
+            (0..32)
+                .map(|i| {
+                    let query = insert_a(&lru, &format!("www{i}.example.com."), now);
+                    lru.expires_at(&query).unwrap()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // jitter only shortens the TTL of 300 seconds, down to the minimum TTL
+        let first = expirations(42);
+        assert!(first.iter().all(|expires| {
+            *expires <= now + Duration::from_secs(300) && *expires >= now + Duration::from_secs(250)
+        }));
+        assert!(first.iter().any(|expires| *expires != first[0]));
+
+        // the same seed draws the same jitter
+        assert_eq!(expirations(42), first);
+        assert_ne!(expirations(43), first);
+    }
+
+    #[test]
+    fn test_evict_callback() {
+        let now = Instant::now();
+        let evicted = Arc::new(Mutex::new(vec![]));
+        let lru = DnsLru::new(2, TtlConfig::default()).with_evict_callback({
+            let evicted = Arc::clone(&evicted);
+            move |query| evicted.lock().push(query.clone())
+        });
+
+        // the least recently used entry is evicted to make room for the new one
+        let first = insert_a(&lru, "first.example.com.", now);
+        let second = insert_a(&lru, "second.example.com.", now);
+        assert!(evicted.lock().is_empty());
+        insert_a(&lru, "third.example.com.", now);
+        assert_eq!(*evicted.lock(), vec![first]);
+
+        // out of date entries are removed on lookups
+        assert!(lru.get(&second, now + Duration::from_secs(301)).is_none());
+        assert_eq!(evicted.lock().len(), 2);
+        assert_eq!(evicted.lock()[1], second);
+    }
+
+    #[test]
+    fn test_len_and_capacity() {
+        let now = Instant::now();
+        let lru = DnsLru::new_sharded(10, 4, TtlConfig::default());
+        assert!(lru.is_empty());
+        assert_eq!(lru.capacity(), 10);
+
+        let first = insert_a(&lru, "first.example.com.", now);
+        insert_a(&lru, "second.example.com.", now);
+        assert!(!lru.is_empty());
+        assert_eq!(lru.len(), 2);
+
+        // pinned entries are still counted, and taken from the capacity of their shard
+        assert!(lru.pin(&first));
+        assert_eq!(lru.len(), 2);
+        assert_eq!(lru.capacity(), 10);
+
+        lru.clear();
+        assert!(lru.is_empty());
+        assert_eq!(lru.capacity(), 10);
+    }
+
+    #[test]
+    fn test_invalidate() {
+        let now = Instant::now();
+        let lru = DnsLru::new_sharded(64, 8, TtlConfig::default());
+        let name = Name::from_str("www.example.com.").unwrap();
+        let a = insert_a(&lru, "www.example.com.", now);
+        let other = insert_a(&lru, "other.example.com.", now);
+
+        let record = Record::from_rdata(name.clone(), 300, RData::A(A::new(127, 0, 0, 1)));
+        let mut queries = vec![a.clone()];
+        for record_type in [RecordType::AAAA, RecordType::MX, RecordType::TXT] {
+            let query = Query::query(name.clone(), record_type);
+            lru.insert(query.clone(), vec![(record.clone(), 300)], now);
+            queries.push(query);
+        }
+        let mut chaos = Query::query(name.clone(), RecordType::A);
+        chaos.set_query_class(DNSClass::CH);
+        lru.insert(chaos.clone(), vec![(record, 300)], now);
+        queries.push(chaos);
+        assert!(lru.pin(&queries[1]));
+
+        // every type and class of the name is removed, including the pinned entry
+        assert_eq!(lru.invalidate(&name), 5);
+        for query in &queries {
+            assert!(lru.get(query, now).is_none(), "{query} still cached");
+        }
+        assert!(lru.get(&other, now).is_some());
+        assert_eq!(lru.invalidate(&name), 0);
+
+        assert!(lru.invalidate_query(&other));
+        assert!(lru.get(&other, now).is_none());
+        assert!(!lru.invalidate_query(&other));
+
+        let pinned = insert_a(&lru, "pinned.example.com.", now);
+        assert!(lru.pin(&pinned));
+        assert!(lru.invalidate_query(&pinned));
+        assert!(lru.get(&pinned, now).is_none());
+    }
+
+    #[test]
+    fn test_key_normalizer() {
+        let now = Instant::now();
+        let suffix = Name::from_str("lan.").unwrap();
+        let lru = DnsLru::new(8, TtlConfig::default()).with_key_normalizer(move |query| {
+            let name = query.name().to_lowercase();
+            let name = if suffix.zone_of(&name) && name.num_labels() > suffix.num_labels() {
+                let labels = name.num_labels() - suffix.num_labels();
+                Name::from_labels(name.iter().take(usize::from(labels))).unwrap()
+            } else {
+                name
+            };
+
+            let mut key = Query::query(name, query.query_type());
+            key.set_query_class(query.query_class());
+            key
+        });
+
+        insert_a(&lru, "WWW.Example.com.LAN.", now);
+        let query = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+        let lookup = lru.get(&query, now).unwrap().expect("should be cached");
+        assert_eq!(lookup.records().len(), 1);
+        assert_eq!(lru.len(), 1);
+
+        // the other spelling replaces the same entry
+        insert_a(&lru, "www.EXAMPLE.com.", now);
+        assert_eq!(lru.len(), 1);
+        let entries = lru.entries();
+        assert_eq!(entries[0].0, query);
+        assert_eq!(entries[0].0.name().to_string(), "www.example.com.");
+
+        assert!(lru.invalidate_query(&Query::query(
+            Name::from_str("www.example.com.lan.").unwrap(),
+            RecordType::A
+        )));
+        assert!(lru.get(&query, now).is_none());
+    }
+
+    #[test]
+    fn test_insert_disabled() {
+        let now = Instant::now();
+        let lru = DnsLru::new(2, TtlConfig::default());
+        let cached = insert_a(&lru, "cached.example.com.", now);
+
+        lru.set_insert_enabled(false);
+        let name = Name::from_str("www.example.com.").unwrap();
+        let query = Query::query(name.clone(), RecordType::A);
+        let record = Record::from_rdata(name, 300, RData::A(A::new(127, 0, 0, 2)));
+        let lookup = lru
+            .insert_records(query.clone(), vec![record.clone()].into_iter(), now)
+            .expect("no lookup returned");
+        assert_eq!(lookup.record_iter().collect::<Vec<_>>(), vec![&record]);
+
+        let error = nx_domain(&query, 300);
+        assert!(lru.negative(query.clone(), error, now).is_nx_domain());
+
+        // nothing was stored, existing entries are still served
+        assert!(lru.get(&query, now).is_none());
+        assert!(lru.get(&cached, now).is_some());
+
+        lru.set_insert_enabled(true);
+        lru.insert(query.clone(), vec![(record, 300)], now);
+        assert!(lru.get(&query, now).is_some());
+    }
+
+    #[test]
+    fn test_replace_name() {
+        use crate::proto::rr::rdata::AAAA;
+
+        let now = Instant::now();
+        let lru = DnsLru::new_sharded(64, 8, TtlConfig::default());
+        let name = Name::from_str("www.example.com.").unwrap();
+        let other = insert_a(&lru, "other.example.com.", now);
+        let ttl = Duration::from_secs(300);
+
+        // the generation of the entries is the last byte of the addresses
+        let entries = |generation: u8| {
+            [
+                RData::A(A::new(127, 0, 0, generation)),
+                RData::AAAA(AAAA::new(0, 0, 0, 0, 0, 0, 0, generation.into())),
+            ]
+            .into_iter()
+            .map(|rdata| {
+                let query = Query::query(name.clone(), rdata.record_type());
+                let record = Record::from_rdata(name.clone(), 300, rdata);
+                let lookup =
+                    Lookup::new_with_deadline(query.clone(), Arc::from([record]), now + ttl);
+                (query, lookup, ttl)
+            })
+            .collect::<Vec<_>>()
+        };
+        let generation_of = |lookup: &Lookup| match lookup.record_iter().next().unwrap().data() {
+            RData::A(a) => a.0.octets()[3],
+            RData::AAAA(aaaa) => aaaa.0.segments()[7] as u8,
+            data => panic!("unexpected data: {data:?}"),
+        };
+
+        lru.replace_name(&name, entries(1), now);
+
+        // readers always find both entries, and never an older generation after a newer one
+        std::thread::scope(|scope| {
+            let reader = scope.spawn(|| {
+                let mut last = 1;
+                for _ in 0..10_000 {
+                    for record_type in [RecordType::A, RecordType::AAAA] {
+                        let lookup = lru
+                            .get(&Query::query(name.clone(), record_type), now)
+                            .expect("missing entry")
+                            .expect("negative entry");
+                        let generation = generation_of(&lookup);
+                        assert!(generation >= last, "{generation} after {last}");
+                        last = generation;
+                    }
+                }
+            });
+
+            for generation in 2..=100 {
+                lru.replace_name(&name, entries(generation), now);
+            }
+            reader.join().unwrap();
+        });
+
+        // entries of the name which are not in the new entries are removed, other names are kept
+        lru.replace_name(&name, entries(101).split_off(1), now);
+        assert!(lru
+            .get(&Query::query(name.clone(), RecordType::A), now)
+            .is_none());
+        let aaaa = lru
+            .get(&Query::query(name.clone(), RecordType::AAAA), now)
+            .expect("missing entry")
+            .expect("negative entry");
+        assert_eq!(generation_of(&aaaa), 101);
+        assert!(lru.get(&other, now).is_some());
+    }
+
+    #[test]
+    fn test_name_index() {
+        let now = Instant::now();
+
+        for backend in [
+            CacheBackend::Lru,
+            CacheBackend::Clock,
+            #[cfg(feature = "segmented-lru")]
+            CacheBackend::SegmentedLru,
+        ] {
+            // some of the entries are evicted, the unread ones are all probationary with the
+            //  segmented LRU
+            let lru = DnsLru::new_with_backend(10, backend, TtlConfig::default());
+            let names = (0..20)
+                .map(|i| Name::from_str(&format!("host{i}.example.com.")).unwrap())
+                .collect::<Vec<_>>();
+            for name in &names {
+                insert_a(&lru, &name.to_string(), now);
+            }
+
+            // the index holds exactly the queries of the entries of each name
+            let mut entries = std::collections::HashSet::new();
+            lru.shards[0].for_each(|query, _| {
+                entries.insert(query.clone());
+            });
+            assert!(!entries.is_empty(), "{backend:?}");
+            assert!(entries.len() < names.len(), "{backend:?}");
+
+            let shard = lru.shards[0].write();
+            for name in &names {
+                let indexed = shard.queries_of(name);
+                let expected = entries
+                    .iter()
+                    .filter(|query| query.name() == name)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                assert_eq!(indexed, expected, "{backend:?} {name}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_zero_negative_ttl_not_cached() {
+        let now = Instant::now();
+        let query = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+        let error = ProtoError::from(ProtoErrorKind::NoRecordsFound {
+            query: Box::new(query.clone()),
+            soa: None,
+            ns: None,
+            negative_ttl: Some(0),
+            response_code: ResponseCode::NoError,
+            trusted: true,
+            authorities: None,
+        });
+
+        let lru = DnsLru::new(1, TtlConfig::default());
+        let nodata = lru.negative(query.clone(), error.clone(), now);
+        assert!(matches!(
+            nodata.kind(),
+            ProtoErrorKind::NoRecordsFound {
+                negative_ttl: Some(0),
+                ..
+            }
+        ));
+        // nothing is cached, so the next lookup queries again
+        assert!(lru.get(&query, now).is_none());
+
+        // the minimum negative TTL still applies
+        let ttls = TtlConfig {
+            negative_min_ttl: Some(Duration::from_secs(5)),
+            ..TtlConfig::default()
+        };
+        let lru = DnsLru::new(1, ttls);
+        lru.negative(query.clone(), error, now);
+        assert!(matches!(lru.get(&query, now), Some(Err(_))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    #[cfg(feature = "tokio-runtime")]
+    async fn test_wait_until_expired() {
+        use futures_util::FutureExt;
+
+        use crate::proto::runtime::TokioTime;
+
+        let now = Instant::now();
+        let lru = DnsLru::new(2, TtlConfig::default());
+        let query = insert_a(&lru, "www.example.com.", now);
+        let expires_at = lru.expires_at(&query).expect("query is not cached");
+        assert_eq!(expires_at, now + Duration::from_secs(300));
+
+        // the future completes once the TTL left at `now` elapsed, on the paused clock
+        let expired = lru.wait_until_expired::<TokioTime>(&query, now);
+        tokio::pin!(expired);
+        assert!(expired.as_mut().now_or_never().is_none());
+        tokio::time::advance(Duration::from_secs(299)).await;
+        assert!(expired.as_mut().now_or_never().is_none());
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(expired.now_or_never().is_some());
+
+        // there is nothing to wait for without an entry
+        let missing = Query::query(
+            Name::from_str("missing.example.com.").unwrap(),
+            RecordType::A,
+        );
+        assert!(lru.expires_at(&missing).is_none());
+        assert!(lru
+            .wait_until_expired::<TokioTime>(&missing, now)
+            .now_or_never()
+            .is_some());
+    }
+
+    #[test]
+    fn test_export_import() {
+        let now = Instant::now();
+        let lru = DnsLru::new(2, TtlConfig::default());
+        let query = insert_a(&lru, "www.example.com.", now);
+
+        let mut snapshot = lru.export();
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot[0].query, query);
+        assert_eq!(
+            snapshot[0].records,
+            vec![Record::from_rdata(
+                query.name().clone(),
+                300,
+                RData::A(A::new(127, 0, 0, 1))
+            )]
+        );
+
+        // an entry which expired before the snapshot is imported
+        let expired = Query::query(
+            Name::from_str("expired.example.com.").unwrap(),
+            RecordType::A,
+        );
+        snapshot.push(CacheSnapshotEntry {
+            query: expired.clone(),
+            records: snapshot[0].records.clone(),
+            expires_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .saturating_sub(Duration::from_secs(10)),
+        });
+
+        let restarted = DnsLru::new(2, TtlConfig::default());
+        let now = Instant::now();
+        restarted.import(snapshot, now);
+
+        let lookup = restarted
+            .get(&query, now)
+            .expect("the entry should have been imported")
+            .expect("the entry should be positive");
+        assert_eq!(
+            lookup.iter().cloned().collect::<Vec<_>>(),
+            vec![RData::A(A::new(127, 0, 0, 1))]
+        );
+        let ttl = restarted.expires_at(&query).unwrap() - now;
+        assert!(ttl > Duration::from_secs(290) && ttl <= Duration::from_secs(300));
+
+        assert!(restarted.get(&expired, now).is_none());
+        assert_eq!(restarted.len(), 1);
+    }
+
+    #[test]
+    fn test_read_through() {
+        let now = Instant::now();
+        let local = DnsLru::new(2, TtlConfig::default());
+        let secondary = DnsLru::new(2, TtlConfig::default());
+        let query = insert_a(&secondary, "www.example.com.", now);
+
+        let cache = ReadThrough::new(local.clone(), Arc::new(secondary));
+        assert!(local.get(&query, now).is_none());
+
+        // the local miss is read from the secondary cache
+        let lookup = DnsCache::get(&cache, &query, now)
+            .expect("the secondary cache should have the entry")
+            .expect("the entry should be positive");
+        assert_eq!(
+            lookup.iter().cloned().collect::<Vec<_>>(),
+            vec![RData::A(A::new(127, 0, 0, 1))]
+        );
+
+        // and is now in the local cache, until it expires in the secondary cache
+        let lookup = local
+            .get(&query, now)
+            .expect("the local cache should have been populated")
+            .expect("the entry should be positive");
+        assert_eq!(
+            lookup.iter().cloned().collect::<Vec<_>>(),
+            vec![RData::A(A::new(127, 0, 0, 1))]
+        );
+        assert_eq!(
+            local.expires_at(&query),
+            Some(now + Duration::from_secs(300))
+        );
+    }
+
+    #[test]
+    fn test_preserve_ttl() {
+        let now = Instant::now();
+        let later = now + Duration::from_secs(5);
+        let ttl = |lru: &DnsLru, query: &Query| {
+            let lookup = lru.get(query, later).unwrap().unwrap();
+            lookup.records()[0].ttl()
+        };
+
+        // the remaining TTL by default
+        let lru = DnsLru::new(1, TtlConfig::default());
+        let query = insert_a(&lru, "www.example.com.", now);
+        assert_eq!(ttl(&lru, &query), 295);
+
+        // the original TTL
+        let lru = DnsLru::new(1, TtlConfig::default().with_preserve_ttl(true));
+        let query = insert_a(&lru, "www.example.com.", now);
+        assert_eq!(ttl(&lru, &query), 300);
+        assert_eq!(
+            lru.get(&query, later).unwrap().unwrap().valid_until(),
+            now + Duration::from_secs(300)
+        );
+    }
+
+    #[test]
+    fn test_entries() {
+        let now = Instant::now();
+        let lru = DnsLru::new_with_backend(3, CacheBackend::Clock, TtlConfig::default());
+        let first = insert_a(&lru, "first.example.com.", now);
+        let second = insert_a(&lru, "second.example.com.", now);
+
+        let missing = Query::query(
+            Name::from_str("missing.example.com.").unwrap(),
+            RecordType::A,
+        );
+        let error = nx_domain(&missing, 60);
+        lru.negative(missing.clone(), error, now);
+
+        let mut entries = lru.entries();
+        entries.sort_by(|(a, _), (b, _)| a.name().cmp(b.name()));
+        let queries = entries.iter().map(|(query, _)| query).collect::<Vec<_>>();
+        assert_eq!(queries, vec![&first, &missing, &second]);
+
+        let (_, info) = &entries[0];
+        assert_eq!(info.record_count, 1);
+        assert!(info.positive);
+        assert!(info.ttl <= Duration::from_secs(300));
+        assert!(info.ttl > Duration::from_secs(290));
+
+        let (_, info) = &entries[1];
+        assert_eq!(info.record_count, 0);
+        assert!(!info.positive);
+        assert!(info.ttl <= Duration::from_secs(60));
+
+        // the snapshot doesn't reference the entries, so the first entry is evicted next
+        insert_a(&lru, "third.example.com.", now);
+        assert!(lru.get(&first, now).is_none());
+        assert!(lru.get(&second, now).is_some());
+    }
+
+    #[test]
+    fn test_eviction_batch() {
+        let now = Instant::now();
+        let fill = |lru: &DnsLru| {
+            for i in 0..300 {
+                insert_a(lru, &format!("host{i}.example.com."), now);
+                assert!(lru.shards[0].len() <= 100);
+            }
+        };
+
+        // one entry is evicted for each insertion past the capacity
+        let lru = DnsLru::new(100, TtlConfig::default());
+        fill(&lru);
+        let stats = lru.stats();
+        assert_eq!(stats.evictions, 200);
+        assert_eq!(stats.eviction_rounds, 200);
+
+        // 10 entries are evicted at once, making room for the next 9 insertions
+        let lru = DnsLru::new(100, TtlConfig::default()).with_eviction_batch(0.1);
+        fill(&lru);
+        let stats = lru.stats();
+        assert_eq!(stats.evictions, 200);
+        assert_eq!(stats.eviction_rounds, 20);
+
+        // the most recent entries are kept
+        let last = insert_a(&lru, "host299.example.com.", now);
+        assert!(lru.get(&last, now).is_some());
+        let first = insert_a(&lru, "host0.example.com.", now);
+        assert_eq!(lru.stats().eviction_rounds, 21);
+        assert!(lru.get(&first, now).is_some());
+
+        let lru = DnsLru::new_with_backend(100, CacheBackend::Clock, TtlConfig::default())
+            .with_eviction_batch(0.1);
+        fill(&lru);
+        let stats = lru.stats();
+        assert_eq!(stats.evictions, 200);
+        assert_eq!(stats.eviction_rounds, 20);
+    }
+
+    #[test]
+    fn test_prefetch_hint() {
+        // the threshold is a fraction of the TTL
+        assert!(config::PrefetchThreshold::new(-0.1).is_none());
+        assert!(config::PrefetchThreshold::new(1.5).is_none());
+        assert!(config::PrefetchThreshold::new(f32::NAN).is_none());
+
+        let now = Instant::now();
+        let ttls =
+            TtlConfig::default().with_prefetch_threshold(config::PrefetchThreshold::new(0.1));
+        let lru = DnsLru::new(2, ttls);
+        let query = insert_a(&lru, "www.example.com.", now);
+        let hint = |at: u64| {
+            let (lookup, hint) = lru
+                .get_with_prefetch_hint(&query, now + Duration::from_secs(at))
+                .expect("the entry should be cached");
+            assert!(lookup.is_ok());
+            hint
+        };
+
+        // more than 10% of the TTL of 300s remains
+        assert_eq!(hint(250), None);
+
+        let remaining_ttl = Duration::from_secs(20);
+        assert_eq!(hint(280), Some(PrefetchHint { remaining_ttl }));
+
+        // the hint is returned until the refresh is started, then only once per entry
+        assert!(hint(282).is_some());
+        lru.mark_prefetched(&query);
+        assert_eq!(hint(285), None);
+
+        // until the entry is replaced, e.g. by the refresh
+        insert_a(&lru, "www.example.com.", now + Duration::from_secs(290));
+        assert_eq!(hint(300), None);
+        assert!(hint(565).is_some());
+
+        // without a threshold, there is never any hint
+        let lru = DnsLru::new(2, TtlConfig::default());
+        let query = insert_a(&lru, "www.example.com.", now);
+        let (_, hint) = lru
+            .get_with_prefetch_hint(&query, now + Duration::from_secs(299))
+            .expect("the entry should be cached");
+        assert_eq!(hint, None);
+    }
+
+    #[test]
+    fn test_stats() {
+        let now = Instant::now();
+        let lru = DnsLru::new(2, TtlConfig::default());
+        assert_eq!(lru.stats(), CacheStats::default());
+
+        let first = insert_a(&lru, "first.example.com.", now);
+        let second = insert_a(&lru, "second.example.com.", now);
+        assert!(lru.get(&first, now).is_some());
+        // the expired entry is removed, making room without an eviction
+        assert!(lru.get(&second, now + Duration::from_secs(301)).is_none());
+        insert_a(&lru, "third.example.com.", now);
+        assert!(lru.get(&second, now).is_none());
+
+        let query = Query::query(
+            Name::from_str("missing.example.com.").unwrap(),
+            RecordType::A,
+        );
+        let error = nx_domain(&query, 60);
+        lru.negative(query, error, now);
+
+        assert_eq!(
+            lru.stats(),
+            CacheStats {
+                hits: 1,
+                misses: 2,
+                insertions: 4,
+                evictions: 1,
+                eviction_rounds: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_dump_text() {
+        let now = Instant::now();
+        let lru = DnsLru::new(4, TtlConfig::default());
+
+        let name = Name::from_str("www.example.com.").unwrap();
+        let query = Query::query(name.clone(), RecordType::A);
+        let records = vec![
+            (
+                Record::from_rdata(name.clone(), 300, RData::A(A::new(192, 0, 2, 1))),
+                300,
+            ),
+            (
+                Record::from_rdata(name, 300, RData::A(A::new(192, 0, 2, 2))),
+                300,
+            ),
+        ];
+        lru.insert(query, records, now);
+
+        let missing = Query::query(
+            Name::from_str("missing.example.com.").unwrap(),
+            RecordType::A,
+        );
+        let error = nx_domain(&missing, 60);
+        lru.negative(missing, error, now);
+
+        // the remaining TTLs are dumped, the negative entry has expired
+        let text = lru.dump_text(now + Duration::from_secs(100));
+        assert_eq!(
+            text,
+            "; www.example.com. IN A\n\
+             www.example.com. 200 IN A 192.0.2.1\n\
+             www.example.com. 200 IN A 192.0.2.2\n"
+        );
+
+        let text = lru.dump_text(now + Duration::from_secs(10));
+        assert!(
+            text.starts_with("; missing.example.com. IN A NXDomain 50\n"),
+            "{text}"
+        );
+        assert!(
+            text.contains("www.example.com. 290 IN A 192.0.2.1\n"),
+            "{text}"
+        );
+    }
+
+    #[test]
+    fn test_negative_excluded_types() {
+        let now = Instant::now();
+        let nxdomain = |query: &Query| nx_domain(query, 300);
+
+        let lru =
+            DnsLru::new(2, TtlConfig::default()).with_negative_excluded_types([RecordType::SRV]);
+
+        let a = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+        lru.negative(a.clone(), nxdomain(&a), now);
+        assert!(matches!(lru.get(&a, now), Some(Err(_))));
+
+        let srv = Query::query(
+            Name::from_str("_ldap._tcp.example.com.").unwrap(),
+            RecordType::SRV,
+        );
+        let error = lru.negative(srv.clone(), nxdomain(&srv), now);
+        assert!(error.is_nx_domain());
+        // the SRV query is sent again on the next lookup
+        assert!(lru.get(&srv, now).is_none());
+    }
+
+    #[test]
+    fn test_min_cached_records() {
+        let now = Instant::now();
+        let lru = DnsLru::new(2, TtlConfig::default())
+            .with_min_cached_records(HashMap::from([(RecordType::A, 2)]));
+        let record = |name: &Name, host: u8| {
+            let record = Record::from_rdata(name.clone(), 300, RData::A(A::new(192, 0, 2, host)));
+            (record, 300)
+        };
+
+        // a single record is returned, but the query is sent again on the next lookup
+        let single = Name::from_str("single.example.com.").unwrap();
+        let query = Query::query(single.clone(), RecordType::A);
+        let lookup = lru.insert(query.clone(), vec![record(&single, 1)], now);
+        assert_eq!(lookup.records().len(), 1);
+        assert!(lru.get(&query, now).is_none());
+
+        let several = Name::from_str("several.example.com.").unwrap();
+        let query = Query::query(several.clone(), RecordType::A);
+        lru.insert(
+            query.clone(),
+            vec![record(&several, 1), record(&several, 2)],
+            now,
+        );
+        let lookup = lru.get(&query, now).unwrap().unwrap();
+        assert_eq!(lookup.records().len(), 2);
+    }
+
+    #[test]
+    fn test_min_cached_records_cname() {
+        let now = Instant::now();
+        let lru = DnsLru::new(2, TtlConfig::default())
+            .with_min_cached_records(HashMap::from([(RecordType::A, 2)]));
+        let alias = Name::from_str("alias.example.com.").unwrap();
+        let target = Name::from_str("target.example.com.").unwrap();
+        let query = Query::query(alias.clone(), RecordType::A);
+        let cname = Record::from_rdata(alias, 300, RData::CNAME(CNAME(target.clone())));
+        let a = Record::from_rdata(target, 300, RData::A(A::new(192, 0, 2, 1)));
+
+        // the CNAME record doesn't count towards the minimum number of A records
+        let lookup = lru.insert(query.clone(), vec![(cname, 300), (a, 300)], now);
+        assert_eq!(lookup.records().len(), 2);
+        assert!(lru.get(&query, now).is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "dnssec")]
+    fn test_min_cached_records_rrsig() {
+        use crate::proto::rr::dnssec::rdata::DNSSECRData;
+        use crate::proto::rr::dnssec::Algorithm;
+
+        let now = Instant::now();
+        let lru = DnsLru::new(2, TtlConfig::default())
+            .with_min_cached_records(HashMap::from([(RecordType::A, 2)]));
+        let name = Name::from_str("www.example.com.").unwrap();
+        let query = Query::query(name.clone(), RecordType::A);
+        let rrsig = RRSIG::new(
+            RecordType::A,
+            Algorithm::ECDSAP256SHA256,
+            3,
+            300,
+            0,
+            0,
+            0,
+            Name::from_str("example.com.").unwrap(),
+            vec![],
+        );
+        let rrsig = Record::from_rdata(name.clone(), 300, RData::DNSSEC(DNSSECRData::RRSIG(rrsig)));
+        let a = Record::from_rdata(name, 300, RData::A(A::new(192, 0, 2, 1)));
+
+        // the signature doesn't count towards the minimum number of A records
+        let lookup = lru.insert(query.clone(), vec![(a, 300), (rrsig, 300)], now);
+        assert_eq!(lookup.records().len(), 2);
+        assert!(lru.get(&query, now).is_none());
+    }
+
+    #[test]
+    fn test_max_entry_age() {
+        let now = Instant::now();
+        let ttls = TtlConfig {
+            serve_stale_ttl: Some(Duration::from_secs(600)),
+            ..TtlConfig::default()
+        }
+        .with_max_entry_age(Some(Duration::from_secs(60)));
+        let lru = DnsLru::new(1, ttls);
+        let query = insert_a(&lru, "www.example.com.", now);
+
+        // within the max age, the TTL is capped at the time left before the entry is too old
+        let lookup = lru
+            .get(&query, now + Duration::from_secs(30))
+            .unwrap()
+            .unwrap();
+        assert_eq!(lookup.records()[0].ttl(), 30);
+
+        // beyond the max age, the entry is evicted although its TTL isn't expired
+        assert!(lru.get(&query, now + Duration::from_secs(61)).is_none());
+        assert_eq!(lru.len(), 0);
+
+        // it isn't served stale either
+        let query = insert_a(&lru, "www.example.com.", now);
+        assert!(lru
+            .get_stale_on_failure(&query, now + Duration::from_secs(400))
+            .is_none());
+        // the cap also applies to the TTLs preserved as cached
+        let lru = DnsLru::new(1, ttls.with_preserve_ttl(true));
+        let query = insert_a(&lru, "www.example.com.", now);
+        let lookup = lru
+            .get(&query, now + Duration::from_secs(45))
+            .unwrap()
+            .unwrap();
+        assert_eq!(lookup.records()[0].ttl(), 15);
+    }
+
+    #[test]
+    fn test_rotate() {
+        use crate::proto::rr::rdata::CNAME;
+
+        let now = Instant::now();
+        let lru = DnsLru::new(1, TtlConfig::default().with_rotate(true));
+        let alias = Name::from_str("alias.example.com.").unwrap();
+        let name = Name::from_str("www.example.com.").unwrap();
+        let query = Query::query(alias.clone(), RecordType::A);
+        let cname = Record::from_rdata(alias, 300, RData::CNAME(CNAME(name.clone())));
+        let a = |host: u8| Record::from_rdata(name.clone(), 300, RData::A(A::new(192, 0, 2, host)));
+        lru.insert(
+            query.clone(),
+            vec![(cname.clone(), 300), (a(1), 300), (a(2), 300), (a(3), 300)],
+            now,
+        );
+
+        // the addresses are rotated on each hit, the CNAME record stays first
+        let records = || lru.get(&query, now).unwrap().unwrap().records().to_vec();
+        assert_eq!(records(), vec![cname.clone(), a(1), a(2), a(3)]);
+        assert_eq!(records(), vec![cname.clone(), a(2), a(3), a(1)]);
+        assert_eq!(records(), vec![cname.clone(), a(3), a(1), a(2)]);
+        assert_eq!(records(), vec![cname, a(1), a(2), a(3)]);
+    }
+
+    #[test]
+    fn test_rotate_partial_and_stale() {
+        let now = Instant::now();
+        let lru = DnsLru::new(1, TtlConfig::default().with_rotate(true))
+            .with_stale_grace(Duration::from_secs(60));
+        let name = Name::from_str("www.example.com.").unwrap();
+        let query = Query::query(name.clone(), RecordType::A);
+        let a = |host: u8| Record::from_rdata(name.clone(), 300, RData::A(A::new(192, 0, 2, host)));
+        let lookup = Lookup::new_with_max_ttl(query.clone(), Arc::from([a(1), a(2)]));
+        lru.replace_name(
+            &name,
+            vec![(
+                query.clone(),
+                lookup.into_partial(),
+                Duration::from_secs(300),
+            )],
+            now,
+        );
+
+        // the rotated lookup is still partial
+        let lookup = lru.get(&query, now).unwrap().unwrap();
+        assert!(lookup.is_partial());
+        assert_eq!(lookup.records(), [a(1), a(2)]);
+        let lookup = lru.get(&query, now).unwrap().unwrap();
+        assert!(lookup.is_partial());
+        assert_eq!(lookup.records(), [a(2), a(1)]);
+
+        // the records of the expired entry are still rotated when it is served stale
+        let expired = now + Duration::from_secs(330);
+        let stale = || lru.get_stale(&query, expired).unwrap();
+        let lookup = stale();
+        assert!(lookup.is_stale() && lookup.is_partial());
+        assert_eq!(
+            lookup.iter().collect::<Vec<_>>(),
+            [a(1).data(), a(2).data()]
+        );
+        assert_eq!(
+            stale().iter().collect::<Vec<_>>(),
+            [a(2).data(), a(1).data()]
+        );
+    }
+
+    #[test]
+    fn test_positive_precedes_negative() {
+        let now = Instant::now();
+        let lru = DnsLru::new(2, TtlConfig::default());
+        let query = insert_a(&lru, "www.example.com.", now);
+
+        let error = nx_domain(&query, 600);
+        assert!(lru
+            .negative(query.clone(), error.clone(), now)
+            .is_nx_domain());
+
+        // the positive entry is served while valid, and the negative one isn't served after it
+        assert!(matches!(lru.get(&query, now), Some(Ok(_))));
+        assert!(lru.get(&query, now + Duration::from_secs(301)).is_none());
+
+        // once the positive entry expired, negative responses are cached
+        let later = now + Duration::from_secs(301);
+        lru.negative(query.clone(), error, later);
+        assert!(matches!(lru.get(&query, later), Some(Err(_))));
+    }
+
+    #[test]
+    fn test_check_consistency() {
+        use crate::proto::rr::rdata::CNAME;
+
+        let now = Instant::now();
+        let lru = DnsLru::new(8, TtlConfig::default());
+
+        let alias = Name::from_str("www.example.com.").unwrap();
+        let target = Name::from_str("target.example.com.").unwrap();
+        let cname = Record::from_rdata(alias.clone(), 300, RData::CNAME(CNAME(target.clone())));
+        let a = Record::from_rdata(target.clone(), 300, RData::A(A::new(127, 0, 0, 1)));
+        lru.insert_records(
+            Query::query(alias.clone(), RecordType::A),
+            vec![cname.clone(), a].into_iter(),
+            now,
+        );
+        assert!(lru.check_consistency().is_empty());
+
+        // an A record cached directly for the alias
+        insert_a(&lru, "www.example.com.", now);
+        lru.insert(
+            Query::query(alias.clone(), RecordType::CNAME),
+            vec![(cname, 300)],
+            now,
+        );
+        assert_eq!(
+            lru.check_consistency(),
+            vec![Inconsistency {
+                name: alias,
+                target,
+                record_type: RecordType::A,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_metadata() {
+        let now = Instant::now();
+        let lru = DnsLru::new(2, TtlConfig::default());
+
+        let name = Name::from_str("www.example.com.").unwrap();
+        let query = Query::query(name.clone(), RecordType::A);
+        let record = Record::from_rdata(name, 1, RData::A(A::new(127, 0, 0, 1)));
+        lru.insert_with_metadata(
+            query.clone(),
+            vec![(record.clone(), 1)],
+            now,
+            Arc::new("warmup"),
+        );
+
+        // the metadata is kept with the entry
+        assert!(lru.get(&query, now).is_some());
+        let metadata = lru
+            .get_metadata(&query, now)
+            .expect("metadata should be attached");
+        assert_eq!(metadata.downcast_ref::<&str>(), Some(&"warmup"));
+        assert!(metadata.downcast_ref::<String>().is_none());
+
+        // not after the entry expired
+        assert!(lru
+            .get_metadata(&query, now + Duration::from_secs(2))
+            .is_none());
+
+        // nor after the entry was replaced
+        lru.insert(query.clone(), vec![(record, 1)], now);
+        assert!(lru.get_metadata(&query, now).is_none());
+
+        let other = insert_a(&lru, "other.example.com.", now);
+        assert!(lru.get_metadata(&other, now).is_none());
+    }
+
+    /// Compares the read throughput of the backends, run with:
+    ///  `cargo test --release -p hickory-resolver -- --ignored --nocapture bench_concurrent_reads`
+    #[test]
+    #[ignore]
+    #[allow(clippy::print_stdout)]
+    fn bench_concurrent_reads() {
+        const THREADS: usize = 8;
+        const NAMES: usize = 64;
+        const READS: usize = 100_000;
+
+        let now = Instant::now();
+        for backend in [CacheBackend::Lru, CacheBackend::Clock] {
+            let lru = DnsLru::new_with_backend(NAMES, backend, TtlConfig::default());
+            let queries = (0..NAMES)
+                .map(|i| insert_a(&lru, &format!("host{i}.example.com."), now))
+                .collect::<Vec<_>>();
+
+            let start = Instant::now();
+            std::thread::scope(|scope| {
+                for t in 0..THREADS {
+                    let (lru, queries) = (&lru, &queries);
+                    scope.spawn(move || {
+                        for i in 0..READS {
+                            assert!(lru.get(&queries[(i + t) % NAMES], now).is_some());
+                        }
+                    });
+                }
+            });
+            let elapsed = start.elapsed();
+
+            println!(
+                "{backend:?}: {:.0} reads/s",
+                (THREADS * READS) as f64 / elapsed.as_secs_f64()
+            );
+        }
+    }
+}
@@ -0,0 +1,233 @@
+//! Entries which are never evicted, see [`DnsLru::pin`]
+
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use parking_lot::{Mutex, MutexGuard};
+
+use super::{DnsLru, LruValue};
+use crate::proto::op::Query;
+
+/// The pinned entries of a cache
+///
+/// The number of pinned entries is tracked apart from the map, so that caches without pins never
+///  take its lock.
+#[derive(Debug, Default)]
+pub(super) struct Pins {
+    entries: Mutex<HashMap<Query, LruValue>>,
+    /// Number of pinned entries, incremented before an entry is moved to the map, see [`DnsLru::pin`]
+    count: AtomicUsize,
+}
+
+impl Pins {
+    /// True if no entry is pinned, or about to be
+    pub(super) fn is_empty(&self) -> bool {
+        self.count.load(Ordering::Acquire) == 0
+    }
+
+    /// Calls `f` with the pinned entry of the query, if any
+    pub(super) fn inspect<T>(&self, query: &Query, f: impl FnOnce(&LruValue) -> T) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+        self.entries.lock().get(query).map(f)
+    }
+
+    /// Locks the pinned entries, always lock them before the shards
+    pub(super) fn lock(&self) -> PinsGuard<'_> {
+        PinsGuard {
+            entries: self.entries.lock(),
+            count: &self.count,
+        }
+    }
+
+    /// Locks the pinned entries unless none is pinned, see [`Pins::lock`]
+    pub(super) fn lock_any(&self) -> Option<PinsGuard<'_>> {
+        (!self.is_empty()).then(|| self.lock())
+    }
+}
+
+/// Locked pinned entries, updating their count once unlocked
+pub(super) struct PinsGuard<'a> {
+    entries: MutexGuard<'a, HashMap<Query, LruValue>>,
+    count: &'a AtomicUsize,
+}
+
+impl PinsGuard<'_> {
+    /// Counts an entry about to be pinned, before it is removed from its shard
+    ///
+    /// A concurrent [`DnsLru::store`] of the query into its shard then sees the pin, and moves the
+    ///  stored value to the pinned entries.
+    fn reserve(&self) {
+        self.count.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
+impl Deref for PinsGuard<'_> {
+    type Target = HashMap<Query, LruValue>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.entries
+    }
+}
+
+impl DerefMut for PinsGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.entries
+    }
+}
+
+impl Drop for PinsGuard<'_> {
+    fn drop(&mut self) {
+        self.count.store(self.entries.len(), Ordering::Release);
+    }
+}
+
+impl DnsLru {
+    /// Pins the cached entry of the query, it is never evicted until it is unpinned
+    ///
+    /// Pinned entries count against the capacity of the shard holding the query, once its whole
+    ///  capacity is pinned new entries of the shard are not cached, see
+    ///  [`DnsLru::rejected_full_pins`]. Pinned entries still expire, inserting the query again
+    ///  refreshes the pinned entry.
+    ///
+    /// Returns false if the query isn't cached.
+    pub fn pin(&self, query: &Query) -> bool {
+        let key = self.key(query);
+        let query = &*key;
+        let mut pinned = self.pinned.lock();
+        if pinned.contains_key(query) {
+            return true;
+        }
+
+        pinned.reserve();
+        let shard = self.shard(query);
+        let Some(value) = shard.remove(query) else {
+            return false;
+        };
+
+        shard.set_capacity(shard.capacity().saturating_sub(1));
+        pinned.insert(query.clone(), value);
+        true
+    }
+
+    /// Unpins the entry of the query, making it evictable again
+    pub fn unpin(&self, query: &Query) {
+        let key = self.key(query);
+        let query = &*key;
+        let Some(value) = self.pinned.lock().remove(query) else {
+            return;
+        };
+
+        let shard = self.shard(query);
+        shard.set_capacity(shard.capacity() + 1);
+        let evicted = shard.insert(query.clone(), value, 1);
+        self.report_evicted(&evicted);
+    }
+
+    /// Moves the value stored in the shard of the query to its pinned entry, if the query was
+    ///  pinned since [`DnsLru::store`] checked the pins
+    pub(super) fn adopt_pinned(&self, query: &Query) {
+        if self.pinned.is_empty() {
+            return;
+        }
+
+        let mut pinned = self.pinned.lock();
+        let Some(entry) = pinned.get_mut(query) else {
+            return;
+        };
+        if let Some(value) = self.shard(query).remove(query) {
+            *entry = value;
+        }
+    }
+
+    /// Removes the pinned entry of the query once it can't be served anymore, even stale, giving
+    ///  its capacity back to its shard
+    ///
+    /// Returns true if the entry was removed.
+    pub(super) fn evict_expired_pin(&self, query: &Query, now: Instant, grace: Duration) -> bool {
+        let mut pinned = self.pinned.lock();
+        let evictable = pinned
+            .get(query)
+            .is_some_and(|value| value.is_evictable(now, grace, self.max_entry_age));
+        if !evictable {
+            return false;
+        }
+
+        pinned.remove(query);
+        let shard = self.shard(query);
+        shard.set_capacity(shard.capacity() + 1);
+        true
+    }
+
+    /// Returns the number of entries which were not cached because the whole capacity is pinned
+    pub fn rejected_full_pins(&self) -> u64 {
+        self.rejected_full_pins.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::time::{Duration, Instant};
+
+    use crate::dns_lru::tests::insert_a;
+    use crate::dns_lru::{DnsLru, TtlConfig};
+    use crate::proto::op::Query;
+    use crate::proto::rr::rdata::A;
+    use crate::proto::rr::{Name, RData, Record, RecordType};
+
+    #[test]
+    fn test_full_pins_reject_insert() {
+        let now = Instant::now();
+        let lru = DnsLru::new(2, TtlConfig::default());
+
+        let first = insert_a(&lru, "first.example.com.", now);
+        let second = insert_a(&lru, "second.example.com.", now);
+        assert!(lru.pin(&first));
+        assert!(lru.pin(&second));
+
+        // the whole capacity is pinned, the lookup is returned without being cached
+        let name = Name::from_str("third.example.com.").unwrap();
+        let third = Query::query(name.clone(), RecordType::A);
+        let record = Record::from_rdata(name, 300, RData::A(A::new(127, 0, 0, 3)));
+        let lookup = lru.insert(third.clone(), vec![(record.clone(), 300)], now);
+        assert_eq!(lookup.record_iter().collect::<Vec<_>>(), vec![&record]);
+        assert_eq!(lru.rejected_full_pins(), 1);
+        assert!(lru.get(&third, now).is_none());
+        assert!(lru.get(&first, now).is_some());
+        assert!(lru.get(&second, now).is_some());
+
+        // once unpinned, entries can be evicted again
+        lru.unpin(&first);
+        lru.insert(third.clone(), vec![(record, 300)], now);
+        assert_eq!(lru.rejected_full_pins(), 1);
+        assert!(lru.get(&third, now).is_some());
+        assert!(lru.get(&first, now).is_none());
+        assert!(lru.get(&second, now).is_some());
+    }
+
+    #[test]
+    fn test_expired_pins_are_evicted() {
+        let now = Instant::now();
+        let lru = DnsLru::new(2, TtlConfig::default());
+
+        let first = insert_a(&lru, "first.example.com.", now);
+        let second = insert_a(&lru, "second.example.com.", now);
+        assert!(lru.pin(&first));
+        assert!(lru.pin(&second));
+
+        // once expired, the pinned entry is removed and its capacity given back to its shard
+        let later = now + Duration::from_secs(301);
+        assert!(lru.get(&first, later).is_none());
+        assert_eq!(lru.len(), 1);
+        assert_eq!(lru.capacity(), 2);
+        assert!(!lru.pin(&first));
+
+        let third = insert_a(&lru, "third.example.com.", later);
+        assert_eq!(lru.rejected_full_pins(), 0);
+        assert!(lru.get(&third, later).is_some());
+    }
+}
@@ -0,0 +1,261 @@
+//! Caches of the lookups whose responses aren't shared, see [`CacheScope`]
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use lru_cache::LruCache;
+use parking_lot::Mutex;
+
+use super::{DnsLru, Shard};
+use crate::proto::rr::rdata::opt::ClientSubnet;
+use crate::proto::xfer::DnsRequestOptions;
+
+/// Maximum number of scopes whose responses are cached apart, see [`CacheScope`]
+pub(crate) const MAX_SCOPES: usize = 16;
+
+/// The lookups whose responses are cached apart from the shared cache, see [`DnsLru::scoped`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub(crate) struct CacheScope {
+    /// The client subnet sent with the queries, see [`DnsRequestOptions::client_subnet`]
+    client_subnet: Option<ClientSubnet>,
+    /// The minimum TTL of the cached responses, see [`DnsRequestOptions::cache_min_ttl`]
+    min_ttl: Option<Duration>,
+}
+
+impl CacheScope {
+    /// Returns the scope of the lookup, the responses to the queries sent with the shared client
+    ///  subnet are shared, see `CachingClient::with_shared_client_subnet`
+    pub(crate) fn of(
+        options: &DnsRequestOptions,
+        shared_client_subnet: Option<ClientSubnet>,
+    ) -> Self {
+        // the scope prefix is only set in responses, and the bits beyond the source prefix are
+        //  not sent, see RFC 7871 section 6
+        let query_prefix = |subnet: ClientSubnet| {
+            let prefix = subnet.source_prefix();
+            ClientSubnet::new(mask(subnet.addr(), prefix), prefix, 0)
+        };
+        Self {
+            client_subnet: options
+                .client_subnet
+                .map(query_prefix)
+                .filter(|subnet| Some(*subnet) != shared_client_subnet.map(query_prefix)),
+            min_ttl: options.cache_min_ttl,
+        }
+    }
+
+    /// True if the responses are cached in the shared cache
+    pub(crate) fn is_shared(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// True if the lookups may be served the responses of the shared cache
+    ///
+    /// The responses to the queries of another client subnet may differ, while the shared
+    ///  responses are only served for less than the minimum TTL of the scope.
+    pub(crate) fn reads_shared(&self) -> bool {
+        self.client_subnet.is_none()
+    }
+}
+
+/// Returns the address with the bits beyond the prefix cleared
+fn mask(addr: IpAddr, prefix: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(addr) => {
+            let mask = u32::MAX
+                .checked_shl(32 - u32::from(prefix.min(32)))
+                .unwrap_or(0);
+            IpAddr::V4(Ipv4Addr::from(u32::from(addr) & mask))
+        }
+        IpAddr::V6(addr) => {
+            let mask = u128::MAX
+                .checked_shl(128 - u32::from(prefix.min(128)))
+                .unwrap_or(0);
+            IpAddr::V6(Ipv6Addr::from(u128::from(addr) & mask))
+        }
+    }
+}
+
+/// The caches of the scopes of a cache, the least recently used are dropped beyond [`MAX_SCOPES`]
+pub(super) type Scopes = Mutex<LruCache<CacheScope, DnsLru>>;
+
+impl DnsLru {
+    /// Returns the cache of the responses of the scope, creating it if needed
+    ///
+    /// Each scope takes `1 / (2 * MAX_SCOPES)` of the capacity of this cache, at least one entry,
+    ///  from the shards of this cache, so that the scopes never hold more than half of it. The
+    ///  capacity is given back when the least recently used scope is dropped for a new one, or
+    ///  when the cache is cleared. The scopes share the statistics and the insertion setting of
+    ///  this cache, and its functions removing entries, e.g. [`DnsLru::invalidate`], cover them.
+    pub(crate) fn scoped(&self, scope: CacheScope) -> Self {
+        let mut scopes = self.scopes.lock();
+        if let Some(scoped) = scopes.get_mut(&scope) {
+            return scoped.clone();
+        }
+
+        if scopes.len() >= MAX_SCOPES {
+            if let Some((_, dropped)) = scopes.remove_lru() {
+                self.give_capacity(dropped.own_capacity());
+            }
+        }
+
+        // the scopes lock is held, see `DnsLru::capacity`
+        let total = self.own_capacity()
+            + scopes
+                .iter()
+                .map(|(_, scoped)| scoped.own_capacity())
+                .sum::<usize>();
+        let capacity = (total / (2 * MAX_SCOPES)).max(1);
+        self.take_capacity(capacity);
+        let scoped = Self {
+            shards: Arc::from([Shard::new(self.shards[0].backend(), capacity)]),
+            pinned: Arc::default(),
+            rejected_full_pins: Arc::default(),
+            scopes: Arc::new(Mutex::new(LruCache::new(0))),
+            ..self.clone()
+        };
+        scopes.insert(scope, scoped.clone());
+        scoped
+    }
+
+    /// Drops the caches of all the scopes, giving their capacity back to the shards
+    pub(super) fn clear_scopes(&self) {
+        let mut scopes = self.scopes.lock();
+        while let Some((_, dropped)) = scopes.remove_lru() {
+            self.give_capacity(dropped.own_capacity());
+        }
+    }
+
+    /// Calls `f` with the cache of each scope
+    pub(super) fn for_each_scope(&self, mut f: impl FnMut(&Self)) {
+        for (_, scoped) in self.scopes.lock().iter() {
+            f(scoped);
+        }
+    }
+
+    /// Takes the capacity from the largest shards, one entry at a time
+    fn take_capacity(&self, capacity: usize) {
+        for _ in 0..capacity {
+            if let Some(shard) = self.shards.iter().max_by_key(|shard| shard.capacity()) {
+                shard.set_capacity(shard.capacity().saturating_sub(1));
+            }
+        }
+    }
+
+    /// Gives the capacity back to the smallest shards, one entry at a time
+    fn give_capacity(&self, capacity: usize) {
+        for _ in 0..capacity {
+            if let Some(shard) = self.shards.iter().min_by_key(|shard| shard.capacity()) {
+                shard.set_capacity(shard.capacity() + 1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::Instant;
+
+    use super::{CacheScope, MAX_SCOPES};
+    use crate::dns_lru::tests::insert_a;
+    use crate::dns_lru::{DnsLru, TtlConfig};
+    use crate::proto::rr::rdata::opt::ClientSubnet;
+    use crate::proto::xfer::DnsRequestOptions;
+
+    #[test]
+    fn test_masked_client_subnet() {
+        let scope = |addr: IpAddr, prefix| {
+            let mut options = DnsRequestOptions::default();
+            options.client_subnet = Some(ClientSubnet::new(addr, prefix, 0));
+            CacheScope::of(&options, None)
+        };
+
+        // the addresses of the same subnet share the scope
+        assert_eq!(
+            scope(Ipv4Addr::new(192, 0, 2, 5).into(), 24),
+            scope(Ipv4Addr::new(192, 0, 2, 0).into(), 24)
+        );
+        assert_ne!(
+            scope(Ipv4Addr::new(192, 0, 2, 5).into(), 32),
+            scope(Ipv4Addr::new(192, 0, 2, 0).into(), 32)
+        );
+        assert_eq!(
+            scope("2001:db8:1::5".parse().unwrap(), 48),
+            scope("2001:db8:1::".parse().unwrap(), 48)
+        );
+        assert_eq!(
+            scope(Ipv4Addr::new(192, 0, 2, 5).into(), 0),
+            scope(Ipv4Addr::new(198, 51, 100, 0).into(), 0)
+        );
+    }
+
+    fn subnet_scope(octet: u8) -> CacheScope {
+        let mut options = DnsRequestOptions::default();
+        options.client_subnet = Some(ClientSubnet::new(
+            Ipv4Addr::new(192, 0, octet, 0).into(),
+            24,
+            0,
+        ));
+        CacheScope::of(&options, None)
+    }
+
+    #[test]
+    fn test_scopes_share_capacity() {
+        let now = Instant::now();
+        let lru = DnsLru::new(64, TtlConfig::default());
+
+        // the scope takes its capacity from the shared cache
+        let scoped = lru.scoped(subnet_scope(0));
+        assert_eq!(scoped.capacity(), 2);
+        assert_eq!(lru.capacity(), 64);
+
+        // and shares its statistics
+        let query = insert_a(&scoped, "www.example.com.", now);
+        assert!(scoped.get(&query, now).is_some());
+        assert!(lru.get(&query, now).is_none());
+        assert_eq!(lru.len(), 1);
+        assert_eq!(lru.stats().insertions, 1);
+        assert_eq!(lru.stats().hits, 1);
+        assert_eq!(lru.stats().misses, 1);
+
+        // the least recently used scopes are dropped, the total capacity is unchanged
+        for octet in 1..=MAX_SCOPES as u8 {
+            lru.scoped(subnet_scope(octet));
+        }
+        assert_eq!(lru.capacity(), 64);
+        assert_eq!(lru.len(), 0);
+        assert!(lru.scoped(subnet_scope(0)).get(&query, now).is_none());
+    }
+
+    #[test]
+    fn test_clear_scopes() {
+        let now = Instant::now();
+        let lru = DnsLru::new(64, TtlConfig::default());
+        let scoped = lru.scoped(subnet_scope(0));
+        let query = insert_a(&scoped, "www.example.com.", now);
+
+        lru.clear();
+        assert_eq!(lru.len(), 0);
+        assert_eq!(lru.capacity(), 64);
+        assert!(lru.scoped(subnet_scope(0)).get(&query, now).is_none());
+    }
+
+    #[test]
+    fn test_invalidate_scopes() {
+        let now = Instant::now();
+        let lru = DnsLru::new(64, TtlConfig::default());
+        let scoped = lru.scoped(subnet_scope(0));
+        let query = insert_a(&scoped, "www.example.com.", now);
+        insert_a(&lru, "www.example.com.", now);
+
+        assert_eq!(lru.invalidate(query.name()), 2);
+        assert!(scoped.get(&query, now).is_none());
+
+        insert_a(&scoped, "www.example.com.", now);
+        assert!(lru.invalidate_query(&query));
+        assert!(scoped.get(&query, now).is_none());
+        assert!(!lru.invalidate_query(&query));
+    }
+}
@@ -0,0 +1,214 @@
+//! Serving of expired entries, see [`DnsLru::get_stale`] and [`DnsLru::get_stale_on_failure`]
+
+use std::time::{Duration, Instant};
+
+use hickory_proto::error::ProtoError;
+
+use super::{DnsLru, LruValue};
+use crate::lookup::Lookup;
+use crate::proto::op::Query;
+use crate::proto::rr::Record;
+
+/// TTL of the records of entries served stale, see [`LruValue::stale_at`]
+const SERVE_STALE_TTL: Duration = Duration::from_secs(1);
+
+impl LruValue {
+    /// Returns the expired lookup, flagged as stale, if it expired no longer than `window` ago
+    ///
+    /// Stale lookups are flagged as such, their records have a TTL of [`SERVE_STALE_TTL`]. Stale
+    /// negative responses have a negative TTL of [`SERVE_STALE_TTL`].
+    fn stale_at(&self, now: Instant, window: Duration) -> Option<Result<Lookup, ProtoError>> {
+        if window.is_zero() || self.is_current(now) || !self.is_retained(now, window) {
+            return None;
+        }
+
+        let lookup = match &self.lookup {
+            Ok(lookup) => lookup,
+            Err(error) => {
+                let mut error = error.clone();
+                DnsLru::nx_error_with_ttl(&mut error, SERVE_STALE_TTL);
+                return Some(Err(error));
+            }
+        };
+        let records = lookup
+            .records()
+            .iter()
+            .map(|record| {
+                let mut record = record.clone();
+                record.set_ttl(SERVE_STALE_TTL.as_secs() as u32);
+                record
+            })
+            .collect::<Vec<Record>>();
+        let lookup = lookup
+            .with_records(records)
+            .with_deadline(now + SERVE_STALE_TTL);
+        Some(Ok(lookup.into_stale()))
+    }
+}
+
+impl DnsLru {
+    /// Keeps expired entries for the grace period, so that they can be served stale
+    ///
+    /// See [`DnsLru::get_stale`].
+    pub fn with_stale_grace(mut self, grace: Duration) -> Self {
+        self.stale_grace = grace;
+        self
+    }
+
+    /// Returns the lookup of an expired entry, flagged as stale, if it is within the stale grace period
+    ///
+    /// Only positive responses are returned, see [`DnsLru::with_stale_grace`].
+    pub fn get_stale(&self, query: &Query, now: Instant) -> Option<Lookup> {
+        self.stale(query, now, self.stale_grace, Duration::ZERO)?
+            .ok()
+    }
+
+    /// Returns the lookup of an expired entry, flagged as stale, if it is within the `serve_stale_ttl`
+    ///  of the [`TtlConfig`], to be served as the upstream lookup of the query failed
+    ///
+    /// Expired negative responses are returned within the `negative_serve_stale_ttl` instead, see
+    ///  [`TtlConfig::with_negative_serve_stale_ttl`]. RFC 8767 allows serving stale data only once
+    ///  the name servers can't be reached, this keeps names resolving during outages. The records
+    ///  of stale lookups have a TTL of 1 second.
+    pub fn get_stale_on_failure(
+        &self,
+        query: &Query,
+        now: Instant,
+    ) -> Option<Result<Lookup, ProtoError>> {
+        self.stale(
+            query,
+            now,
+            self.serve_stale_ttl,
+            self.negative_serve_stale_ttl,
+        )
+    }
+
+    /// Returns the lookup of the entry served stale within the `positive` or `negative` window,
+    ///  depending on the kind of the entry, entries older than the max entry age are never served
+    ///  stale
+    fn stale(
+        &self,
+        query: &Query,
+        now: Instant,
+        positive: Duration,
+        negative: Duration,
+    ) -> Option<Result<Lookup, ProtoError>> {
+        let key = self.key(query);
+        let query = &*key;
+        let stale = |value: &LruValue| {
+            if value.is_older_than(now, self.max_entry_age) {
+                return None;
+            }
+            let window = match value.lookup {
+                Ok(_) => positive,
+                Err(_) => negative,
+            };
+            let stale = value.stale_at(now, window)?;
+            match self.rotate {
+                true => Some(stale.map(|lookup| value.rotated(lookup))),
+                false => Some(stale),
+            }
+        };
+
+        if let Some(stale) = self.pinned.inspect(query, stale) {
+            return stale;
+        }
+        self.shard(query).inspect(query, stale)?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::time::{Duration, Instant};
+
+    use crate::dns_lru::tests::{insert_a, nx_domain};
+    use crate::dns_lru::{DnsLru, TtlConfig};
+    use crate::proto::op::Query;
+    use crate::proto::rr::rdata::A;
+    use crate::proto::rr::{Name, RData, RecordType};
+    use hickory_proto::error::ProtoErrorKind;
+
+    #[test]
+    fn test_serve_stale() {
+        let now = Instant::now();
+        let ttls = TtlConfig {
+            serve_stale_ttl: Some(Duration::from_secs(60)),
+            ..TtlConfig::default()
+        };
+        let lru = DnsLru::new(2, ttls);
+        let query = insert_a(&lru, "www.example.com.", now);
+
+        let lookup = lru.get(&query, now).unwrap().expect("records should exist");
+        assert!(!lookup.is_stale());
+        assert!(lru.get_stale_on_failure(&query, now).is_none());
+
+        // expired, but within the serve stale window, it's only served once upstream failed
+        let expired = now + Duration::from_secs(330);
+        assert!(lru.get(&query, expired).is_none());
+        let lookup = lru
+            .get_stale_on_failure(&query, expired)
+            .expect("the entry should be served stale")
+            .expect("records should exist");
+        assert!(lookup.is_stale());
+        assert_eq!(lookup.records()[0].ttl(), 1);
+        assert_eq!(lookup.iter().next(), Some(&RData::A(A::new(127, 0, 0, 1))));
+
+        // the serve stale window doesn't widen the stale grace
+        assert!(lru.get_stale(&query, expired).is_none());
+
+        // beyond the serve stale window, the entry is evicted
+        assert!(lru.get(&query, now + Duration::from_secs(361)).is_none());
+        assert!(lru.get_stale_on_failure(&query, expired).is_none());
+
+        // negative entries are never served stale
+        let missing = Query::query(
+            Name::from_str("missing.example.com.").unwrap(),
+            RecordType::A,
+        );
+        let error = nx_domain(&missing, 60);
+        lru.negative(missing.clone(), error, now);
+        assert!(lru
+            .get_stale_on_failure(&missing, now + Duration::from_secs(90))
+            .is_none());
+    }
+
+    #[test]
+    fn test_negative_serve_stale() {
+        let now = Instant::now();
+        let ttls = TtlConfig {
+            serve_stale_ttl: Some(Duration::from_secs(60)),
+            ..TtlConfig::default()
+        }
+        .with_negative_serve_stale_ttl(Some(Duration::from_secs(10)));
+        let lru = DnsLru::new(2, ttls);
+
+        let missing = Query::query(
+            Name::from_str("missing.example.com.").unwrap(),
+            RecordType::A,
+        );
+        let error = nx_domain(&missing, 60);
+        lru.negative(missing.clone(), error, now);
+
+        // expired, but within the negative serve stale window, it's only served once upstream
+        //  failed, with the minimal negative TTL
+        assert!(lru.get(&missing, now + Duration::from_secs(65)).is_none());
+        let error = lru
+            .get_stale_on_failure(&missing, now + Duration::from_secs(65))
+            .expect("the entry should be served stale")
+            .expect_err("the entry should be negative");
+        assert!(error.is_nx_domain());
+        assert!(matches!(
+            error.kind(),
+            ProtoErrorKind::NoRecordsFound {
+                negative_ttl: Some(1),
+                ..
+            }
+        ));
+
+        // the positive window doesn't apply to negative entries
+        assert!(lru
+            .get_stale_on_failure(&missing, now + Duration::from_secs(75))
+            .is_none());
+    }
+}
@@ -13,7 +13,8 @@ use thiserror::Error;
 
 use crate::proto::rr::{rdata::SOA, Record};
 use crate::proto::{
-    error::{ProtoError, ProtoErrorKind},
+    error::{ForwardNSData, ProtoError, ProtoErrorKind},
+    op::{Query, ResponseCode},
     xfer::retry_dns_handle::RetryableError,
 };
 
@@ -39,6 +40,19 @@ pub enum ResolveErrorKind {
     /// An error got returned by the hickory-proto crate
     #[error("proto error: {0}")]
     Proto(#[from] ProtoError),
+
+    /// The resolver was configured without any name server nor fallback
+    #[error("no name servers configured")]
+    NoNameServers,
+
+    /// The records of a lookup were not all validated as secure by DNSSEC
+    ///
+    /// See [`LookupOptions::require_secure`](crate::config::LookupOptions::require_secure).
+    #[error("the records of {query} are not DNSSEC secure")]
+    NotSecure {
+        /// Query whose records are not secure
+        query: Box<Query>,
+    },
 }
 
 impl Clone for ResolveErrorKind {
@@ -47,12 +61,33 @@ impl Clone for ResolveErrorKind {
         match self {
             Message(msg) => Message(msg),
             Msg(msg) => Msg(msg.clone()),
+            NoNameServers => NoNameServers,
+            NotSecure { query } => NotSecure {
+                query: query.clone(),
+            },
             // foreign
             Proto(proto) => Self::from(proto.clone()),
         }
     }
 }
 
+/// The details of a negative response, e.g. for a forwarder to reconstruct it for its clients
+///
+/// See [`Resolver::lookup_or_negative`](crate::Resolver::lookup_or_negative).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NegativeResponse {
+    /// The query without any record
+    pub query: Query,
+    /// `NXDomain` if the name doesn't exist, `NoError` if it has no record of the queried type
+    pub response_code: ResponseCode,
+    /// The SOA record of the authority section, if any
+    pub soa: Option<Record<SOA>>,
+    /// Remaining negative TTL of the response in seconds, only present with the SOA
+    ///
+    /// For responses served from the cache, this is the time left until the cached entry expires.
+    pub negative_ttl: Option<u32>,
+}
+
 /// The error type for errors that get returned in the crate
 #[derive(Debug, Clone, Error)]
 pub struct ResolveError {
@@ -94,6 +129,46 @@ impl ResolveError {
             .unwrap_or(false)
     }
 
+    /// Returns the NS records of the authority section of a negative response, see
+    ///  [`ProtoError::name_servers`]
+    pub fn name_servers(&self) -> Option<&[ForwardNSData]> {
+        self.proto()?.name_servers()
+    }
+
+    /// Returns the details of the negative response, if this is one
+    pub fn negative_response(&self) -> Option<NegativeResponse> {
+        match self.proto()?.kind() {
+            ProtoErrorKind::NoRecordsFound {
+                query,
+                soa,
+                negative_ttl,
+                response_code,
+                ..
+            } => Some(NegativeResponse {
+                query: query.as_ref().clone(),
+                response_code: *response_code,
+                soa: soa.as_deref().cloned(),
+                negative_ttl: *negative_ttl,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Reports a NODATA response, i.e. `NoError` without records, as `NXDomain`
+    ///
+    /// See `ResolverOpts::nodata_as_nxdomain`, other errors are returned unchanged.
+    pub(crate) fn nodata_as_nx_domain(mut self) -> Self {
+        if let ResolveErrorKind::Proto(proto) = &mut self.kind {
+            if let ProtoErrorKind::NoRecordsFound { response_code, .. } = proto.kind.as_mut() {
+                if *response_code == ResponseCode::NoError {
+                    *response_code = ResponseCode::NXDomain;
+                }
+            }
+        }
+
+        self
+    }
+
     /// Returns the SOA record, if the error contains one
     pub fn into_soa(self) -> Option<Box<Record<SOA>>> {
         match self.kind {
@@ -106,7 +181,10 @@ impl ResolveError {
 impl RetryableError for ResolveError {
     fn should_retry(&self) -> bool {
         match self.kind() {
-            ResolveErrorKind::Message(_) | ResolveErrorKind::Msg(_) => false,
+            ResolveErrorKind::Message(_)
+            | ResolveErrorKind::Msg(_)
+            | ResolveErrorKind::NoNameServers
+            | ResolveErrorKind::NotSecure { .. } => false,
             ResolveErrorKind::Proto(proto) => proto.should_retry(),
         }
     }
@@ -114,6 +192,7 @@ impl RetryableError for ResolveError {
     fn attempted(&self) -> bool {
         match self.kind() {
             ResolveErrorKind::Proto(e) => e.attempted(),
+            ResolveErrorKind::NoNameServers => false,
             _ => true,
         }
     }
@@ -0,0 +1,228 @@
+//! Connection to the addresses of a [`LookupIp`] racing the address families, see
+//! [RFC 8305](https://tools.ietf.org/html/rfc8305)
+//!
+//! Connecting to the first address only stalls on hosts with broken IPv6 connectivity, until the
+//! connection attempt times out. Here, the next address is tried when the previous attempt fails,
+//! or after a short delay if it's still in progress, and the first connection established is used.
+//!
+//! ```no_run
+//! # #[tokio::main]
+//! # async fn main() -> std::io::Result<()> {
+//! use hickory_resolver::happy_eyeballs::connect_happy_eyeballs;
+//! use hickory_resolver::TokioResolver;
+//!
+//! let resolver = TokioResolver::tokio_from_system_conf()?;
+//! let lookup_ip = resolver.lookup_ip("www.example.com.").await?;
+//! let stream = connect_happy_eyeballs(lookup_ip, 80).await?;
+//! # drop(stream);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io;
+use std::net::SocketAddr;
+use std::pin::pin;
+use std::time::Duration;
+
+use futures_util::future::{self, Either};
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use tokio::net::TcpStream;
+use tokio::time::sleep;
+use tracing::debug;
+
+use crate::lookup_ip::LookupIp;
+
+/// Delay between the start of two connection attempts recommended by RFC 8305, section 5
+pub const DEFAULT_CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Configuration of [`connect_happy_eyeballs_with`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HappyEyeballsConfig {
+    /// Delay after which the next address is tried while the previous attempts are in progress
+    pub attempt_delay: Duration,
+    /// Whether the IPv6 addresses are tried first, rather than the IPv4 addresses
+    pub prefer_ipv6: bool,
+    /// Number of addresses of the preferred family tried before the families are alternated
+    ///
+    /// At least one address of the preferred family is tried first, see RFC 8305, section 4.
+    pub first_address_family_count: usize,
+}
+
+impl Default for HappyEyeballsConfig {
+    /// IPv6 addresses first, alternated after the first one, with [`DEFAULT_CONNECTION_ATTEMPT_DELAY`]
+    fn default() -> Self {
+        Self {
+            attempt_delay: DEFAULT_CONNECTION_ATTEMPT_DELAY,
+            prefer_ipv6: true,
+            first_address_family_count: 1,
+        }
+    }
+}
+
+/// Connects to `port` of the addresses of `lookup_ip` with the default [`HappyEyeballsConfig`]
+pub async fn connect_happy_eyeballs(lookup_ip: LookupIp, port: u16) -> io::Result<TcpStream> {
+    connect_happy_eyeballs_with(lookup_ip, port, HappyEyeballsConfig::default()).await
+}
+
+/// Connects to `port` of the addresses of `lookup_ip`, returning the first connection established
+///
+/// The addresses of both families are interleaved as configured, the order of the addresses of a
+///  family is kept. A connection attempt is started once the previous one failed, or once the
+///  `attempt_delay` elapsed since it started. The other attempts are cancelled once a connection
+///  is established. If all the attempts fail, the error of the last one is returned.
+pub async fn connect_happy_eyeballs_with(
+    lookup_ip: LookupIp,
+    port: u16,
+    config: HappyEyeballsConfig,
+) -> io::Result<TcpStream> {
+    // the socket addresses keep the scope ids of the link-local addresses of the hosts file
+    let mut pending = interleave(lookup_ip.socket_addrs(port), config).into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_error = None;
+
+    loop {
+        // `None` once the delay elapsed, `Some(None)` if no attempt is in progress
+        let completed = if pending.len() == 0 {
+            Some(attempts.next().await)
+        } else {
+            match future::select(attempts.next(), pin!(sleep(config.attempt_delay))).await {
+                Either::Left((completed, _)) => Some(completed),
+                Either::Right(_) => None,
+            }
+        };
+
+        match completed {
+            // dropping the attempts in progress cancels them
+            Some(Some(Ok(stream))) => return Ok(stream),
+            Some(Some(Err(error))) => {
+                debug!("connection attempt failed: {error}");
+                last_error = Some(error);
+            }
+            Some(None) if pending.len() == 0 => {
+                return Err(last_error.unwrap_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, "no address to connect to")
+                }))
+            }
+            _ => {}
+        }
+
+        if let Some(addr) = pending.next() {
+            debug!("connecting to {addr}");
+            attempts.push(TcpStream::connect(addr));
+        }
+    }
+}
+
+/// Sorts the addresses, alternating the families after the first addresses of the preferred family
+fn interleave(
+    addrs: impl Iterator<Item = SocketAddr>,
+    config: HappyEyeballsConfig,
+) -> Vec<SocketAddr> {
+    let (preferred, other): (Vec<_>, Vec<_>) =
+        addrs.partition(|addr| addr.is_ipv6() == config.prefer_ipv6);
+    let mut preferred = preferred.into_iter();
+    let mut other = other.into_iter();
+
+    let mut sorted = preferred
+        .by_ref()
+        .take(config.first_address_family_count.max(1))
+        .collect::<Vec<_>>();
+    loop {
+        match (other.next(), preferred.next()) {
+            (None, None) => return sorted,
+            (other, preferred) => sorted.extend(other.into_iter().chain(preferred)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddrV6};
+    use std::sync::Arc;
+
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::lookup::Lookup;
+    use crate::proto::op::Query;
+    use crate::proto::rr::{Name, RData, Record, RecordType};
+
+    fn lookup_ip(addrs: &[IpAddr]) -> LookupIp {
+        let records = addrs
+            .iter()
+            .map(|ip| {
+                let rdata = match ip {
+                    IpAddr::V4(ip) => RData::A((*ip).into()),
+                    IpAddr::V6(ip) => RData::AAAA((*ip).into()),
+                };
+                Record::from_rdata(Name::root(), 300, rdata)
+            })
+            .collect::<Vec<_>>();
+        let query = Query::query(Name::root(), RecordType::A);
+        LookupIp::from(Lookup::new_with_max_ttl(query, Arc::from(records)))
+    }
+
+    #[test]
+    fn test_interleave() {
+        let v4 = |i| SocketAddr::new(Ipv4Addr::new(192, 0, 2, i).into(), 53);
+        let v6 = |i| SocketAddr::new(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, i).into(), 53);
+        let addrs = [v4(1), v4(2), v4(3), v6(1), v6(2)];
+
+        let config = HappyEyeballsConfig::default();
+        assert_eq!(
+            interleave(addrs.into_iter(), config),
+            vec![v6(1), v4(1), v6(2), v4(2), v4(3)]
+        );
+
+        let config = HappyEyeballsConfig {
+            prefer_ipv6: false,
+            first_address_family_count: 2,
+            ..config
+        };
+        assert_eq!(
+            interleave(addrs.into_iter(), config),
+            vec![v4(1), v4(2), v6(1), v4(3), v6(2)]
+        );
+    }
+
+    #[test]
+    fn test_interleave_scope_ids() {
+        let link_local = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+        let lookup = lookup_ip(&[Ipv4Addr::LOCALHOST.into(), link_local.into()])
+            .with_scope_ids(Some(Arc::new(HashMap::from([(link_local, 2)]))));
+
+        assert_eq!(
+            interleave(lookup.socket_addrs(53), HappyEyeballsConfig::default()),
+            vec![
+                SocketAddr::V6(SocketAddrV6::new(link_local, 53, 0, 2)),
+                SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 53),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connect_after_failure() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // nothing listens on the IPv6 loopback, tried first, or it is unavailable
+        let lookup = lookup_ip(&[Ipv4Addr::LOCALHOST.into(), Ipv6Addr::LOCALHOST.into()]);
+        let config = HappyEyeballsConfig {
+            attempt_delay: Duration::from_secs(60),
+            ..HappyEyeballsConfig::default()
+        };
+        let stream = connect_happy_eyeballs_with(lookup, port, config)
+            .await
+            .expect("the IPv4 address should be connected");
+        assert_eq!(
+            stream.peer_addr().unwrap(),
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port)
+        );
+
+        let error = connect_happy_eyeballs(lookup_ip(&[]), port)
+            .await
+            .expect_err("there is no address to connect to");
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+}
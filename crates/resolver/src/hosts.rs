@@ -2,7 +2,7 @@
 
 use std::collections::HashMap;
 use std::io;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv6Addr};
 use std::path::Path;
 use std::str::FromStr;
 use std::sync::Arc;
@@ -22,6 +22,8 @@ struct LookupType {
     a: Option<Lookup>,
     /// represents the AAAA record type
     aaaa: Option<Lookup>,
+    /// Scope ids of the IPv6 addresses with a zone, e.g. `fe80::1%2`
+    scope_ids: HashMap<Ipv6Addr, u32>,
 }
 
 /// Configuration for the local hosts file
@@ -101,6 +103,16 @@ impl Hosts {
         }
     }
 
+    /// Returns the scope ids of the IPv6 addresses of `name`, if any address has a zone
+    pub(crate) fn scope_ids(&self, name: &Name) -> Option<Arc<HashMap<Ipv6Addr, u32>>> {
+        let scope_ids = &self.by_name.get(name)?.scope_ids;
+        if scope_ids.is_empty() {
+            return None;
+        }
+
+        Some(Arc::new(scope_ids.clone()))
+    }
+
     /// Insert a new Lookup for the associated `Name` and `RecordType`
     pub fn insert(&mut self, name: Name, record_type: RecordType, lookup: Lookup) {
         assert!(record_type == RecordType::A || record_type == RecordType::AAAA);
@@ -156,13 +168,35 @@ impl Hosts {
             if fields.len() < 2 {
                 continue;
             }
-            let addr = if let Ok(a) = IpAddr::from_str(fields[0]) {
-                RData::from(a)
+            // link-local IPv6 addresses may have a numeric zone, e.g. `fe80::1%2`
+            let (addr, zone) = match fields[0].split_once('%') {
+                Some((addr, zone)) => (addr, Some(zone)),
+                None => (fields[0], None),
+            };
+            let addr = if let Ok(a) = IpAddr::from_str(addr) {
+                a
             } else {
                 warn!("could not parse an IP from hosts file");
                 continue;
             };
 
+            let scope_id = match (addr, zone) {
+                (IpAddr::V6(_), Some(zone)) => match scope_id(zone) {
+                    Some(scope_id) => Some(scope_id),
+                    None => {
+                        // the address is unusable without the interface it belongs to
+                        warn!("ignoring {addr} with unknown zone {zone} in hosts file");
+                        continue;
+                    }
+                },
+                (IpAddr::V4(_), Some(zone)) => {
+                    warn!("ignoring {addr} with unsupported zone {zone} in hosts file");
+                    continue;
+                }
+                (_, None) => None,
+            };
+            let addr = RData::from(addr);
+
             for domain in fields.iter().skip(1).map(|domain| domain.to_lowercase()) {
                 if let Ok(name) = Name::from_str(&domain) {
                     let record = Record::from_rdata(name.clone(), dns_lru::MAX_TTL, addr.clone());
@@ -173,10 +207,15 @@ impl Hosts {
                             let lookup = Lookup::new_with_max_ttl(query, Arc::from([record]));
                             self.insert(name.clone(), RecordType::A, lookup);
                         }
-                        RData::AAAA(..) => {
+                        RData::AAAA(ip) => {
                             let query = Query::query(name.clone(), RecordType::AAAA);
                             let lookup = Lookup::new_with_max_ttl(query, Arc::from([record]));
                             self.insert(name.clone(), RecordType::AAAA, lookup);
+                            if let (Some(scope_id), Some(lookup_type)) =
+                                (scope_id, self.by_name.get_mut(&name))
+                            {
+                                lookup_type.scope_ids.insert(ip.0, scope_id);
+                            }
                         }
                         _ => {
                             warn!("unsupported IP type from Hosts file: {:#?}", addr);
@@ -193,6 +232,11 @@ impl Hosts {
     }
 }
 
+/// Returns the scope id of a zone, only numeric ids like `fe80::1%2` are supported
+fn scope_id(zone: &str) -> Option<u32> {
+    u32::from_str(zone).ok()
+}
+
 #[cfg(unix)]
 fn hosts_path() -> &'static str {
     "/etc/hosts"
@@ -323,4 +367,30 @@ mod tests {
             .collect::<Vec<RData>>();
         assert_eq!(rdatas, vec![RData::PTR(PTR("localhost".parse().unwrap())),]);
     }
+
+    #[test]
+    fn test_read_hosts_conf_zones() {
+        let mut hosts = Hosts::default();
+        hosts
+            .read_hosts_conf("fe80::1%2 numeric\nfe80::2%eth0 unknown\n10.0.0.1%2 v4\n".as_bytes())
+            .unwrap();
+
+        let name = Name::from_str("numeric").unwrap();
+        let scope_ids = hosts.scope_ids(&name).unwrap();
+        assert_eq!(
+            scope_ids.get(&Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1)),
+            Some(&2)
+        );
+
+        // addresses with an interface name or an IPv4 address with a zone are skipped
+        for name in ["unknown", "v4"] {
+            let name = Name::from_str(name).unwrap();
+            assert!(hosts
+                .lookup_static_host(&Query::query(name.clone(), RecordType::AAAA))
+                .is_none());
+            assert!(hosts
+                .lookup_static_host(&Query::query(name, RecordType::A))
+                .is_none());
+        }
+    }
 }
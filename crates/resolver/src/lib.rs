@@ -215,6 +215,8 @@ pub use hickory_proto as proto;
 // reexports from proto
 pub use proto::rr::{IntoName, Name};
 
+mod bootstrap;
+pub use bootstrap::Bootstrap;
 pub mod caching_client;
 pub mod config;
 pub mod dns_lru;
@@ -223,6 +225,8 @@ pub mod error;
 mod h2;
 #[cfg(feature = "dns-over-h3")]
 mod h3;
+#[cfg(feature = "tokio-runtime")]
+pub mod happy_eyeballs;
 mod hosts;
 pub use hosts::Hosts;
 pub mod lookup;
@@ -240,8 +244,12 @@ pub use resolver::Resolver;
 #[cfg(feature = "tokio-runtime")]
 pub use resolver::TokioResolver;
 pub mod system_conf;
+mod timing;
+pub use timing::LookupTiming;
 #[cfg(feature = "dns-over-tls")]
 mod tls;
+#[cfg(feature = "dns-over-rustls")]
+pub use tls::set_crypto_provider;
 
 #[doc(hidden)]
 #[deprecated(since = "0.25.0", note = "use `Resolver` instead")]
@@ -9,6 +9,8 @@
 
 use std::{
     cmp::min,
+    net::IpAddr,
+    ops::RangeInclusive,
     pin::Pin,
     slice::Iter,
     sync::Arc,
@@ -17,24 +19,26 @@ use std::{
 };
 
 use futures_util::{
-    future::{self, Future},
+    future::{self, Either, Future},
     stream::Stream,
     FutureExt,
 };
+use rand::Rng;
 
 use crate::{
     caching_client::CachingClient,
+    config::LookupIpStrategy,
     dns_lru::MAX_TTL,
     error::*,
     hosts::Hosts,
-    lookup_ip::LookupIpIter,
+    lookup_ip::{LookupIp, LookupIpIter},
     name_server::{ConnectionProvider, NameServerPool},
     proto::{
         error::ProtoError,
         op::Query,
         rr::{
             rdata::{self, A, AAAA, NS, PTR},
-            Name, RData, Record, RecordType,
+            Name, RData, Record, RecordData, RecordType,
         },
         xfer::{DnsRequest, DnsRequestOptions, DnsResponse},
         DnsHandle, RetryDnsHandle,
@@ -52,6 +56,8 @@ pub struct Lookup {
     query: Query,
     records: Arc<[Record]>,
     valid_until: Instant,
+    stale: bool,
+    partial: bool,
 }
 
 impl Lookup {
@@ -68,6 +74,8 @@ impl Lookup {
             query,
             records,
             valid_until,
+            stale: false,
+            partial: false,
         }
     }
 
@@ -77,6 +85,8 @@ impl Lookup {
             query,
             records,
             valid_until,
+            stale: false,
+            partial: false,
         }
     }
 
@@ -85,6 +95,43 @@ impl Lookup {
         &self.query
     }
 
+    /// Returns the name which produced the answer, after following all aliases
+    ///
+    /// This is the target of the last CNAME in the chain starting at the queried name, or the
+    ///  queried name itself if it wasn't aliased. For CNAME and ANY queries this is always the
+    ///  queried name.
+    pub fn canonical_name(&self) -> &Name {
+        let query_type = self.query.query_type();
+        let mut name = self.query.name();
+        if query_type.is_cname() || query_type.is_any() {
+            return name;
+        }
+
+        // the chain can't be longer than the records, this also stops on CNAME loops
+        for _ in 0..self.records.len() {
+            let cname = self.records.iter().find_map(|r| match r.data() {
+                RData::CNAME(cname) if r.name() == name => Some(&cname.0),
+                _ => None,
+            });
+
+            match cname {
+                Some(cname) => name = cname,
+                None => break,
+            }
+        }
+
+        // intermediate CNAMEs aren't preserved by default, the answers then carry the final name
+        let mut answers = self
+            .records
+            .iter()
+            .filter(|r| r.record_type() == query_type);
+        if answers.clone().any(|r| r.name() == name) {
+            return name;
+        }
+
+        answers.next().map_or(name, Record::name)
+    }
+
     /// Returns an iterator over the data of all records returned during the query.
     ///
     /// It may include additional record types beyond the queried type, e.g. CNAME.
@@ -92,6 +139,13 @@ impl Lookup {
         LookupIter(self.records.iter())
     }
 
+    /// Returns a borrowed iterator of the returned data downcast to the record data type `T`
+    ///
+    /// Records of any other type, e.g. CNAME, are skipped.
+    pub fn typed_iter<'a, T: RecordData + 'a>(&'a self) -> impl Iterator<Item = &'a T> + 'a {
+        self.iter().filter_map(T::try_borrow)
+    }
+
     /// Returns a borrowed iterator of the returned data wrapped in a dnssec Proven type
     #[cfg(feature = "dnssec")]
     pub fn dnssec_iter(&self) -> DnssecIter<'_> {
@@ -116,6 +170,61 @@ impl Lookup {
         self.valid_until
     }
 
+    /// Returns the remaining TTL in seconds at `now`, clamped to `range`, e.g. for an HTTP `max-age`
+    ///
+    /// This is the minimum of the record TTLs, bounded by the time remaining until
+    ///  [`Self::valid_until`]. An empty lookup is clamped from 0.
+    ///
+    /// # Panics
+    ///
+    /// If the start of `range` is greater than its end.
+    pub fn max_age(&self, now: Instant, range: RangeInclusive<u32>) -> u32 {
+        let remaining = self.valid_until.saturating_duration_since(now).as_secs();
+        let remaining = u32::try_from(remaining).unwrap_or(u32::MAX);
+        let ttl = self
+            .records
+            .iter()
+            .map(Record::ttl)
+            .min()
+            .map_or(0, |ttl| ttl.min(remaining));
+
+        ttl.clamp(*range.start(), *range.end())
+    }
+
+    /// Returns true if this `Lookup` was served from an expired cache entry
+    ///
+    /// See [`StaleMode::StaleWhileRevalidate`](crate::config::StaleMode::StaleWhileRevalidate).
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    /// Flags this `Lookup` as served from an expired cache entry
+    pub(crate) fn into_stale(mut self) -> Self {
+        self.stale = true;
+        self
+    }
+
+    /// Returns true if only some of the queries of this `Lookup` succeeded
+    ///
+    /// This is the case of IP lookups with [`LookupIpStrategy::Ipv4AndIpv6`](crate::config::LookupIpStrategy::Ipv4AndIpv6)
+    ///  when the query for one of the address families failed, e.g. timed out, rather than
+    ///  returning no record. The addresses of the other family are returned rather than an error.
+    pub fn is_partial(&self) -> bool {
+        self.partial
+    }
+
+    /// Returns this `Lookup` valid until another deadline
+    pub(crate) fn with_deadline(mut self, valid_until: Instant) -> Self {
+        self.valid_until = valid_until;
+        self
+    }
+
+    /// Flags this `Lookup` as the result of only some of its queries
+    pub(crate) fn into_partial(mut self) -> Self {
+        self.partial = true;
+        self
+    }
+
     #[doc(hidden)]
     pub fn is_empty(&self) -> bool {
         self.records.is_empty()
@@ -139,7 +248,37 @@ impl Lookup {
 
         // Choose the sooner deadline of the two lookups.
         let valid_until = min(self.valid_until(), other.valid_until());
-        Self::new_with_deadline(self.query.clone(), Arc::from(records), valid_until)
+        Self {
+            query: self.query.clone(),
+            records: Arc::from(records),
+            valid_until,
+            stale: self.stale || other.stale,
+            partial: self.partial || other.partial,
+        }
+    }
+
+    /// Returns a copy of this lookup with only the records to keep
+    pub(crate) fn filter_records(&self, keep: impl Fn(&Record) -> bool) -> Self {
+        let records = self
+            .records
+            .iter()
+            .filter(|record| keep(record))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        self.with_records(records)
+    }
+
+    /// Returns a copy of this lookup with other records, e.g. reordered, keeping its query,
+    ///  deadline and flags
+    pub(crate) fn with_records(&self, records: Vec<Record>) -> Self {
+        Self {
+            query: self.query.clone(),
+            records: Arc::from(records),
+            valid_until: self.valid_until,
+            stale: self.stale,
+            partial: self.partial,
+        }
     }
 
     /// Add new records to this lookup, without creating a new Lookup
@@ -233,6 +372,42 @@ impl Iterator for LookupIntoIter {
     }
 }
 
+/// Record data of a type known statically, which can be looked up with
+///  [`AsyncResolver::lookup_typed`](crate::AsyncResolver::lookup_typed)
+pub trait TypedRecordData: RecordData {
+    /// The record type of all the record data of this type
+    const RECORD_TYPE: RecordType;
+}
+
+macro_rules! typed_record_data {
+    ($($name: ident),+) => {
+        $(
+            impl TypedRecordData for rdata::$name {
+                const RECORD_TYPE: RecordType = RecordType::$name;
+            }
+        )+
+    };
+}
+
+typed_record_data!(
+    A, AAAA, ANAME, CAA, CERT, CNAME, CSYNC, HINFO, HTTPS, MX, NAPTR, NS, OPENPGPKEY, PTR, SOA,
+    SRV, SSHFP, SVCB, TLSA, TXT
+);
+
+#[cfg(feature = "dnssec")]
+macro_rules! typed_dnssec_record_data {
+    ($($name: ident),+) => {
+        $(
+            impl TypedRecordData for crate::proto::rr::dnssec::rdata::$name {
+                const RECORD_TYPE: RecordType = RecordType::$name;
+            }
+        )+
+    };
+}
+
+#[cfg(feature = "dnssec")]
+typed_dnssec_record_data!(CDNSKEY, CDS, DNSKEY, DS, KEY, NSEC, NSEC3, NSEC3PARAM, RRSIG, SIG, TSIG);
+
 /// Different lookup options for the lookup attempts and validation
 #[derive(Clone)]
 #[doc(hidden)]
@@ -418,6 +593,93 @@ impl SrvLookup {
     pub fn as_lookup(&self) -> &Lookup {
         &self.0
     }
+
+    /// Returns the SRV records, in the order they should be tried, with the addresses of their targets
+    ///
+    /// Addresses found in this lookup are used, `resolve` is called concurrently for the other targets.
+    pub(crate) async fn resolve_targets<F, R>(&self, resolve: F) -> Vec<(rdata::SRV, Vec<IpAddr>)>
+    where
+        F: Fn(Name) -> R,
+        R: Future<Output = Result<LookupIp, ResolveError>>,
+    {
+        let mut srvs = {
+            let mut rng = rand::thread_rng();
+            self.iter()
+                .map(|srv| (weighted_key(&mut rng, srv.weight().into()), srv.clone()))
+                .collect::<Vec<_>>()
+        };
+
+        // lowest priority first, then by weight, see RFC 2782
+        srvs.sort_by(|(a_key, a), (b_key, b)| {
+            a.priority()
+                .cmp(&b.priority())
+                .then_with(|| b_key.total_cmp(a_key))
+        });
+
+        let resolved = srvs.into_iter().map(|(_, srv)| {
+            let glue = self
+                .0
+                .record_iter()
+                .filter(|record| record.name() == srv.target())
+                .filter_map(|record| record.data().ip_addr())
+                .collect::<Vec<_>>();
+
+            // a target of "." means that the service is not available
+            let addresses = if !glue.is_empty() || srv.target().is_root() {
+                Either::Left(future::ready(glue))
+            } else {
+                Either::Right(
+                    resolve(srv.target().clone())
+                        .map(|result| result.map(|ips| ips.iter().collect()).unwrap_or_default()),
+                )
+            };
+
+            addresses.map(move |addresses| (srv, addresses))
+        });
+
+        future::join_all(resolved).await
+    }
+}
+
+/// Returns the addresses of the glue which `lookup_ip` would return with the strategy, if the glue
+///  has the addresses of every family it looks up
+///
+/// The second family of the `Ipv4thenIpv6` and `Ipv6thenIpv4` strategies is only looked up when
+///  the first one has no address.
+fn glue_of_strategy(glue: &[IpAddr], strategy: LookupIpStrategy) -> Option<Vec<IpAddr>> {
+    let of_family = |ipv4: bool| {
+        let addresses = glue
+            .iter()
+            .filter(|ip| ip.is_ipv4() == ipv4)
+            .copied()
+            .collect::<Vec<_>>();
+        (!addresses.is_empty()).then_some(addresses)
+    };
+
+    match strategy {
+        LookupIpStrategy::Ipv4Only | LookupIpStrategy::Ipv4thenIpv6 => of_family(true),
+        LookupIpStrategy::Ipv6Only | LookupIpStrategy::Ipv6thenIpv4 => of_family(false),
+        LookupIpStrategy::Ipv4AndIpv6 => {
+            (of_family(true).is_some() && of_family(false).is_some()).then(|| glue.to_vec())
+        }
+    }
+}
+
+/// Returns the key of an item of `weight`, sorting the items by decreasing key gives a weighted
+///  random order
+///
+/// Sorting on u^(1/weight) gives a weighted random permutation (Efraimidis-Spirakis), items with a
+///  weight of 0 are always sorted last.
+pub(crate) fn weighted_key(rng: &mut impl Rng, weight: u32) -> f64 {
+    match weight {
+        0 => -1.0,
+        weight => rng.gen::<f64>().powf(1.0 / f64::from(weight)),
+    }
+}
+
+/// True if both sets of records are equal, regardless of their order and TTLs
+pub(crate) fn same_records(first: &[Record], other: &[Record]) -> bool {
+    first.len() == other.len() && first.iter().all(|record| other.contains(record))
 }
 
 impl From<Lookup> for SrvLookup {
@@ -604,9 +866,63 @@ lookup_type!(
 );
 lookup_type!(NsLookup, NsLookupIter, NsLookupIntoIter, RData::NS, NS);
 
+impl MxLookup {
+    /// Returns the preferences and exchanges of the MX records, most preferred first, with their
+    ///  addresses
+    ///
+    /// Addresses found in this lookup are used when they cover the address families looked up with
+    ///  the `strategy`, `resolve` is called concurrently for the other exchanges. The addresses found
+    ///  in this lookup are kept if the exchange fails to resolve.
+    pub(crate) async fn resolve_exchanges<F, R>(
+        &self,
+        strategy: LookupIpStrategy,
+        resolve: F,
+    ) -> Vec<(u16, Name, Vec<IpAddr>)>
+    where
+        F: Fn(Name) -> R,
+        R: Future<Output = Result<LookupIp, ResolveError>>,
+    {
+        let mut mxs = self.iter().cloned().collect::<Vec<_>>();
+        // the lowest preference is the most preferred, see RFC 5321 section 5.1
+        mxs.sort_by_key(|mx| mx.preference());
+
+        let resolved = mxs.into_iter().map(|mx| {
+            let glue = self
+                .0
+                .record_iter()
+                .filter(|record| record.name() == mx.exchange())
+                .filter_map(|record| record.data().ip_addr())
+                .filter(|ip| match strategy {
+                    LookupIpStrategy::Ipv4Only => ip.is_ipv4(),
+                    LookupIpStrategy::Ipv6Only => ip.is_ipv6(),
+                    _ => true,
+                })
+                .collect::<Vec<_>>();
+
+            // an exchange of "." means that the domain doesn't accept mail, see RFC 7505
+            let addresses = if mx.exchange().is_root() {
+                Either::Left(future::ready(glue))
+            } else if let Some(addresses) = glue_of_strategy(&glue, strategy) {
+                Either::Left(future::ready(addresses))
+            } else {
+                Either::Right(resolve(mx.exchange().clone()).map(move |result| {
+                    match result.map(|ips| ips.iter().collect::<Vec<_>>()) {
+                        Ok(addresses) if !addresses.is_empty() => addresses,
+                        _ => glue,
+                    }
+                }))
+            };
+
+            addresses.map(move |addresses| (mx.preference(), mx.exchange().clone(), addresses))
+        });
+
+        future::join_all(resolved).await
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
-    use std::net::{IpAddr, Ipv4Addr};
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
     use std::str::FromStr;
     use std::sync::{Arc, Mutex};
 
@@ -720,6 +1036,107 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn test_lookup_typed_iter() {
+        let name = Name::from_str("www.example.com.").unwrap();
+        let records: Arc<[Record]> = Arc::from(vec![
+            Record::from_rdata(
+                name.clone(),
+                86400,
+                RData::CNAME(rdata::CNAME(Name::from_str("example.com.").unwrap())),
+            ),
+            Record::from_rdata(name.clone(), 86400, RData::A(A::new(127, 0, 0, 1))),
+            Record::from_rdata(
+                name.clone(),
+                86400,
+                RData::AAAA(AAAA::from(Ipv6Addr::LOCALHOST)),
+            ),
+            Record::from_rdata(name.clone(), 86400, RData::A(A::new(127, 0, 0, 2))),
+        ]);
+        let lookup = Lookup::new_with_max_ttl(Query::query(name, RecordType::A), records);
+
+        assert_eq!(
+            lookup.typed_iter::<A>().cloned().collect::<Vec<_>>(),
+            vec![A::new(127, 0, 0, 1), A::new(127, 0, 0, 2)]
+        );
+        assert_eq!(
+            lookup.typed_iter::<AAAA>().cloned().collect::<Vec<_>>(),
+            vec![AAAA::from(Ipv6Addr::LOCALHOST)]
+        );
+    }
+
+    #[test]
+    fn test_lookup_canonical_name() {
+        let name = Name::from_str("www.example.com.").unwrap();
+        let alias = Name::from_str("alias.example.com.").unwrap();
+        let target = Name::from_str("target.example.com.").unwrap();
+        let query = Query::query(name.clone(), RecordType::A);
+        let cname = |from: &Name, to: &Name| {
+            Record::from_rdata(from.clone(), 86400, RData::CNAME(rdata::CNAME(to.clone())))
+        };
+        let a = Record::from_rdata(target.clone(), 86400, RData::A(A::new(127, 0, 0, 1)));
+
+        // the chain is followed regardless of the order of the records
+        let lookup = Lookup::new_with_max_ttl(
+            query.clone(),
+            Arc::from(vec![
+                a.clone(),
+                cname(&alias, &target),
+                cname(&name, &alias),
+            ]),
+        );
+        assert_eq!(lookup.canonical_name(), &target);
+
+        // without the intermediate CNAMEs, the name of the answers is the canonical name
+        let lookup = Lookup::new_with_max_ttl(query.clone(), Arc::from(vec![a]));
+        assert_eq!(lookup.canonical_name(), &target);
+
+        let lookup = Lookup::from_rdata(query.clone(), RData::A(A::new(127, 0, 0, 1)));
+        assert_eq!(lookup.canonical_name(), &name);
+
+        // a CNAME loop doesn't hang
+        let lookup = Lookup::new_with_max_ttl(
+            query,
+            Arc::from(vec![cname(&name, &alias), cname(&alias, &name)]),
+        );
+        lookup.canonical_name();
+    }
+
+    #[test]
+    fn test_max_age() {
+        let now = Instant::now();
+        let name = Name::from_str("www.example.com.").unwrap();
+        let records: Arc<[Record]> = Arc::from(vec![
+            Record::from_rdata(name.clone(), 300, RData::A(A::new(127, 0, 0, 1))),
+            Record::from_rdata(name.clone(), 120, RData::A(A::new(127, 0, 0, 2))),
+            Record::from_rdata(name.clone(), 600, RData::A(A::new(127, 0, 0, 3))),
+        ]);
+        let query = Query::query(name, RecordType::A);
+        let lookup = Lookup::new_with_deadline(
+            query.clone(),
+            records.clone(),
+            now + Duration::from_secs(300),
+        );
+
+        // the minimum record TTL
+        assert_eq!(lookup.max_age(now, 0..=u32::MAX), 120);
+        assert_eq!(lookup.max_age(now, 0..=60), 60);
+        assert_eq!(lookup.max_age(now, 180..=3600), 180);
+
+        // bounded by the deadline of the lookup
+        assert_eq!(
+            lookup.max_age(now + Duration::from_secs(250), 0..=u32::MAX),
+            50
+        );
+        assert_eq!(
+            lookup.max_age(now + Duration::from_secs(400), 0..=u32::MAX),
+            0
+        );
+
+        let empty = Lookup::new_with_deadline(query, Arc::from(vec![]), now);
+        assert_eq!(empty.max_age(now, 30..=60), 30);
+    }
+
     #[test]
     fn test_error() {
         assert!(block_on(LookupFuture::lookup(
@@ -801,6 +1218,8 @@ pub mod tests {
             query: Query::default(),
             records: Arc::from([a1.clone(), a2.clone()]),
             valid_until: Instant::now(),
+            stale: false,
+            partial: false,
         };
 
         let mut lookup = lookup.dnssec_iter();
@@ -815,4 +1234,169 @@ pub mod tests {
         );
         assert_eq!(lookup.next(), None);
     }
+
+    #[test]
+    fn test_mx_resolve_exchanges() {
+        use crate::config::LookupIpStrategy::*;
+
+        let name = |name: &str| Name::from_str(name).unwrap();
+        let v4 = IpAddr::from(Ipv4Addr::new(192, 0, 2, 1));
+        let v6 = IpAddr::from(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1));
+        let glue_v4 = IpAddr::from(Ipv4Addr::new(192, 0, 2, 2));
+        let glue_v6 = IpAddr::from(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2));
+
+        // the primary exchange has IPv4 glue only, the backup one IPv6 glue only
+        let mx = |preference, exchange| {
+            Record::from_rdata(
+                name("example.com."),
+                300,
+                RData::MX(rdata::MX::new(preference, name(exchange))),
+            )
+        };
+        let lookup = MxLookup::from(Lookup::new_with_max_ttl(
+            Query::query(name("example.com."), RecordType::MX),
+            Arc::from([
+                mx(20, "backup.example.com."),
+                mx(10, "mail.example.com."),
+                Record::from_rdata(name("mail.example.com."), 300, RData::from(glue_v4)),
+                Record::from_rdata(name("backup.example.com."), 300, RData::from(glue_v6)),
+            ]),
+        ));
+
+        // the primary exchange resolves to an address of each family, the backup one fails
+        let exchanges = |strategy| {
+            let resolved = Mutex::new(Vec::new());
+            let exchanges = block_on(lookup.resolve_exchanges(strategy, |exchange| {
+                resolved.lock().unwrap().push(exchange.to_string());
+                let result = if exchange == name("mail.example.com.") {
+                    let records = [v4, v6]
+                        .map(|ip| Record::from_rdata(exchange.clone(), 300, RData::from(ip)));
+                    let query = Query::query(exchange, RecordType::A);
+                    Ok(LookupIp::from(Lookup::new_with_max_ttl(
+                        query,
+                        Arc::from(records),
+                    )))
+                } else {
+                    Err(ResolveError::from("no addresses"))
+                };
+                future::ready(result)
+            }));
+
+            let mut resolved = resolved.into_inner().unwrap();
+            resolved.sort();
+            (exchanges, resolved)
+        };
+
+        let mail = |addresses| (10, name("mail.example.com."), addresses);
+        let backup = |addresses| (20, name("backup.example.com."), addresses);
+
+        // the glue of the primary exchange covers the strategy, the backup exchange has none
+        assert_eq!(
+            exchanges(Ipv4Only),
+            (
+                vec![mail(vec![glue_v4]), backup(vec![])],
+                vec!["backup.example.com.".to_owned()],
+            )
+        );
+        assert_eq!(
+            exchanges(Ipv4thenIpv6),
+            (
+                vec![mail(vec![glue_v4]), backup(vec![glue_v6])],
+                vec!["backup.example.com.".to_owned()],
+            )
+        );
+
+        // the glue misses a family, the backup exchange keeps its glue as it fails to resolve
+        assert_eq!(
+            exchanges(Ipv4AndIpv6),
+            (
+                vec![mail(vec![v4, v6]), backup(vec![glue_v6])],
+                vec![
+                    "backup.example.com.".to_owned(),
+                    "mail.example.com.".to_owned()
+                ],
+            )
+        );
+        for strategy in [Ipv6Only, Ipv6thenIpv4] {
+            assert_eq!(
+                exchanges(strategy),
+                (
+                    vec![mail(vec![v4, v6]), backup(vec![glue_v6])],
+                    vec!["mail.example.com.".to_owned()],
+                ),
+                "{strategy:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_srv_resolve_targets() {
+        let name = |name: &str| Name::from_str(name).unwrap();
+        let srv = |priority, weight, target| {
+            Record::from_rdata(
+                name("_xmpp-client._tcp.example.com."),
+                300,
+                RData::SRV(rdata::SRV::new(priority, weight, 5222, name(target))),
+            )
+        };
+
+        let lookup = SrvLookup::from(Lookup::new_with_max_ttl(
+            Query::query(name("_xmpp-client._tcp.example.com."), RecordType::SRV),
+            Arc::from([
+                srv(20, 10, "backup.example.com."),
+                srv(30, 10, "."),
+                srv(10, 0, "broken.example.com."),
+                srv(10, 10, "glue.example.com."),
+                // glue from the additional section
+                Record::from_rdata(
+                    name("glue.example.com."),
+                    300,
+                    RData::A(A::new(10, 0, 0, 1)),
+                ),
+            ]),
+        ));
+
+        let resolved = Mutex::new(Vec::new());
+        let targets = block_on(lookup.resolve_targets(|target| {
+            resolved.lock().unwrap().push(target.clone());
+            future::ready(if target == name("backup.example.com.") {
+                Ok(LookupIp::from(Lookup::from_rdata(
+                    Query::query(target, RecordType::A),
+                    RData::A(A::new(10, 0, 0, 2)),
+                )))
+            } else {
+                Err(ResolveError::from("no addresses"))
+            })
+        }));
+
+        let targets = targets
+            .into_iter()
+            .map(|(srv, ips)| (srv.priority(), srv.target().clone(), ips))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            targets,
+            vec![
+                (
+                    10,
+                    name("glue.example.com."),
+                    vec![IpAddr::from(Ipv4Addr::new(10, 0, 0, 1))]
+                ),
+                (10, name("broken.example.com."), vec![]),
+                (
+                    20,
+                    name("backup.example.com."),
+                    vec![IpAddr::from(Ipv4Addr::new(10, 0, 0, 2))]
+                ),
+                (30, Name::root(), vec![]),
+            ]
+        );
+
+        // only targets without glue were resolved
+        let mut resolved = resolved.into_inner().unwrap();
+        resolved.sort();
+        assert_eq!(
+            resolved,
+            vec![name("backup.example.com."), name("broken.example.com.")]
+        );
+    }
 }
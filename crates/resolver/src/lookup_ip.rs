@@ -9,12 +9,13 @@
 //!
 //! At it's heart LookupIp uses Lookup for performing all lookups. It is unlike other standard lookups in that there are customizations around A and AAAA resolutions.
 
+use std::collections::HashMap;
 use std::future::Future;
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use futures_util::{future, future::Either, FutureExt};
 use tracing::debug;
@@ -24,53 +25,206 @@ use crate::proto::rr::{Name, RData, Record, RecordType};
 use crate::proto::xfer::{DnsHandle, DnsRequestOptions};
 
 use crate::caching_client::CachingClient;
-use crate::config::LookupIpStrategy;
+use crate::config::{AddressFamilyFilter, IpFamilyPreference, LookupIpStrategy};
 use crate::dns_lru::MAX_TTL;
 use crate::error::*;
 use crate::hosts::Hosts;
-use crate::lookup::{Lookup, LookupIntoIter, LookupIter};
+use crate::lookup::{weighted_key, Lookup, LookupIntoIter, LookupIter};
 
 /// Result of a DNS query when querying for A or AAAA records.
 ///
 /// When resolving IP records, there can be many IPs that match a given name. A consumer of this should expect that there are more than a single address potentially returned. Generally there are multiple IPs stored for a given service in DNS so that there is a form of high availability offered for a given name. The service implementation is responsible for the semantics around which IP should be used and when, but in general if a connection fails to one, the next in the list should be attempted.
 #[derive(Debug, Clone)]
-pub struct LookupIp(Lookup);
+pub struct LookupIp {
+    lookup: Lookup,
+    /// Scope ids of link-local IPv6 addresses, e.g. configured with a zone in the hosts file
+    scope_ids: Option<Arc<HashMap<Ipv6Addr, u32>>>,
+}
 
 impl LookupIp {
     /// Returns an iterator over the response records.
     ///
     /// Only IP records will be returned, either A or AAAA record types.
     pub fn iter(&self) -> LookupIpIter<'_> {
-        LookupIpIter(self.0.iter())
+        LookupIpIter(self.lookup.iter())
     }
 
     /// Returns a reference to the `Query` that was used to produce this result.
     pub fn query(&self) -> &Query {
-        self.0.query()
+        self.lookup.query()
     }
 
     /// Returns the `Instant` at which this lookup is no longer valid.
     pub fn valid_until(&self) -> Instant {
-        self.0.valid_until()
+        self.lookup.valid_until()
+    }
+
+    /// Returns true if the addresses of only one family were resolved, see [`Lookup::is_partial`]
+    pub fn is_partial(&self) -> bool {
+        self.lookup.is_partial()
     }
 
     /// Return a reference to the inner lookup
     ///
     /// This can be useful for getting all records from the request
     pub fn as_lookup(&self) -> &Lookup {
-        &self.0
+        &self.lookup
+    }
+
+    /// Returns the scope id of the IPv6 address, e.g. configured with a zone in the hosts file
+    ///
+    /// Link-local addresses are only usable with the scope id of the interface they belong to.
+    pub fn scope_id(&self, ip: &Ipv6Addr) -> Option<u32> {
+        self.scope_ids.as_ref()?.get(ip).copied()
+    }
+
+    /// Returns an iterator over the addresses as socket addresses with the port
+    ///
+    /// IPv6 socket addresses carry the scope id of the address, see [`LookupIp::scope_id`].
+    pub fn socket_addrs(&self, port: u16) -> impl Iterator<Item = SocketAddr> + '_ {
+        self.iter().map(move |ip| match ip {
+            IpAddr::V4(ip) => SocketAddr::V4(SocketAddrV4::new(ip, port)),
+            IpAddr::V6(ip) => SocketAddr::V6(SocketAddrV6::new(
+                ip,
+                port,
+                0,
+                self.scope_id(&ip).unwrap_or(0),
+            )),
+        })
+    }
+
+    /// Returns the addresses paired with their remaining TTL at `now`
+    ///
+    /// The TTL of an address is the minimum TTL of its RRset, i.e. of the records with the same
+    /// name and type. The record TTLs are taken as of the creation of this lookup, which expires
+    /// at [`LookupIp::valid_until`] with its smallest TTL.
+    pub fn addresses_with_ttl(&self, now: Instant) -> Vec<(IpAddr, Duration)> {
+        let min_ttl = self
+            .lookup
+            .record_iter()
+            .map(Record::ttl)
+            .min()
+            .unwrap_or(0);
+        let rrset_ttl = |record: &Record| {
+            self.lookup
+                .record_iter()
+                .filter(|other| {
+                    other.name() == record.name() && other.record_type() == record.record_type()
+                })
+                .map(Record::ttl)
+                .min()
+                .unwrap_or_else(|| record.ttl())
+        };
+
+        self.lookup
+            .record_iter()
+            .filter_map(|record| {
+                let ip = record.data().ip_addr()?;
+                let expires = self.valid_until()
+                    + Duration::from_secs(u64::from(rrset_ttl(record) - min_ttl));
+                Some((ip, expires.saturating_duration_since(now)))
+            })
+            .collect()
+    }
+
+    pub(crate) fn with_scope_ids(mut self, scope_ids: Option<Arc<HashMap<Ipv6Addr, u32>>>) -> Self {
+        self.scope_ids = scope_ids;
+        self
+    }
+
+    /// Returns a copy of this lookup with the addresses in a weighted random order
+    ///
+    /// An address is placed first with a probability proportional to its `weight`, each following
+    /// position is drawn the same way from the remaining addresses. Addresses with a weight of 0
+    /// are always placed last. Any other records, e.g. CNAMEs, are kept in order before the
+    /// addresses. A new order is drawn on each call, and this lookup is left unchanged.
+    pub fn weighted_order<F: Fn(&IpAddr) -> u32>(&self, weight: F) -> Self {
+        let mut rng = rand::thread_rng();
+        let mut records = Vec::with_capacity(self.lookup.len());
+        let mut weighted = Vec::new();
+
+        for record in self.lookup.record_iter() {
+            let ip = match record.data() {
+                RData::A(ip) => IpAddr::from(Ipv4Addr::from(*ip)),
+                RData::AAAA(ip) => IpAddr::from(Ipv6Addr::from(*ip)),
+                _ => {
+                    records.push(record.clone());
+                    continue;
+                }
+            };
+
+            weighted.push((weighted_key(&mut rng, weight(&ip)), record.clone()));
+        }
+
+        weighted.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        records.extend(weighted.into_iter().map(|(_, record)| record));
+
+        Self {
+            lookup: Lookup::new_with_deadline(
+                self.query().clone(),
+                Arc::from(records),
+                self.valid_until(),
+            ),
+            scope_ids: self.scope_ids.clone(),
+        }
+    }
+
+    /// Returns a copy of this lookup without the addresses rejected by the filter
+    ///
+    /// Any other records, e.g. CNAMEs, are kept. The lookup is empty if no address is left.
+    pub fn filter_family(&self, filter: AddressFamilyFilter) -> Self {
+        let lookup = self
+            .lookup
+            .filter_records(|record| match record.data().ip_addr() {
+                Some(ip) => filter.allows(&ip),
+                None => true,
+            });
+
+        Self {
+            lookup,
+            scope_ids: self.scope_ids.clone(),
+        }
+    }
+
+    /// Returns a copy of this lookup with the addresses ordered per the preference
+    ///
+    /// The addresses of the preferred family are placed first, each family keeping its order.
+    ///  Any other records, e.g. CNAMEs, are kept in order before the addresses. Use
+    ///  [`LookupIp::filter_family`] to drop the addresses of a family.
+    pub fn prefer_family(&self, preference: IpFamilyPreference) -> Self {
+        let v4_first = match preference {
+            IpFamilyPreference::V4First => true,
+            IpFamilyPreference::V6First => false,
+            IpFamilyPreference::System => return self.clone(),
+        };
+
+        // the sort is stable, keeping the order within each family
+        let mut records = self.lookup.record_iter().cloned().collect::<Vec<_>>();
+        records.sort_by_key(|record| match record.data().ip_addr() {
+            Some(ip) if ip.is_ipv4() == v4_first => 1,
+            Some(_) => 2,
+            None => 0,
+        });
+
+        Self {
+            lookup: self.lookup.with_records(records),
+            scope_ids: self.scope_ids.clone(),
+        }
     }
 }
 
 impl From<Lookup> for LookupIp {
     fn from(lookup: Lookup) -> Self {
-        Self(lookup)
+        Self {
+            lookup,
+            scope_ids: None,
+        }
     }
 }
 
 impl From<LookupIp> for Lookup {
     fn from(lookup: LookupIp) -> Self {
-        lookup.0
+        lookup.lookup
     }
 }
 
@@ -96,7 +250,7 @@ impl IntoIterator for LookupIp {
 
     /// This is not a free conversion, because the `RData`s are cloned.
     fn into_iter(self) -> Self::IntoIter {
-        LookupIpIntoIter(self.0.into_iter())
+        LookupIpIntoIter(self.lookup.into_iter())
     }
 }
 
@@ -127,7 +281,7 @@ where
     names: Vec<Name>,
     strategy: LookupIpStrategy,
     options: DnsRequestOptions,
-    query: Pin<Box<dyn Future<Output = Result<Lookup, ResolveError>> + Send>>,
+    query: Pin<Box<dyn Future<Output = Result<LookupIp, ResolveError>> + Send>>,
     hosts: Option<Arc<Hosts>>,
     finally_ip_addr: Option<RData>,
 }
@@ -150,7 +304,7 @@ where
                 // If the query returned a successful lookup, we will attempt
                 // to retry if the lookup is empty. Otherwise, we will return
                 // that lookup.
-                Poll::Ready(Ok(lookup)) => lookup.is_empty(),
+                Poll::Ready(Ok(lookup)) => lookup.as_lookup().is_empty(),
                 // If the query failed, we will attempt to retry.
                 Poll::Ready(Err(_)) => true,
             };
@@ -182,7 +336,7 @@ where
             // If we didn't have to retry the query, or we weren't able to
             // retry because we've exhausted the names to search and have no
             // fallback IP address, return the current query.
-            return query.map(|f| f.map(LookupIp::from));
+            return query;
             // If we skipped retrying the  query, this will return the
             // successful lookup, otherwise, if the retry failed, this will
             // return the last  query result --- either an empty lookup or the
@@ -226,6 +380,29 @@ where
     }
 }
 
+/// Returns the strategy to use, only querying for A records when `aaaa_requires_ipv6_route` is set
+///  and there is no IPv6 route
+pub(crate) fn routed_strategy(
+    strategy: LookupIpStrategy,
+    aaaa_requires_ipv6_route: bool,
+    has_ipv6_route: impl FnOnce() -> bool,
+) -> LookupIpStrategy {
+    match strategy {
+        // explicitly requested families are always queried
+        LookupIpStrategy::Ipv4Only | LookupIpStrategy::Ipv6Only => strategy,
+        LookupIpStrategy::Ipv4AndIpv6
+        | LookupIpStrategy::Ipv6thenIpv4
+        | LookupIpStrategy::Ipv4thenIpv6 => {
+            if aaaa_requires_ipv6_route && !has_ipv6_route() {
+                debug!("no IPv6 route, suppressing AAAA queries");
+                LookupIpStrategy::Ipv4Only
+            } else {
+                strategy
+            }
+        }
+    }
+}
+
 /// returns a new future for lookup
 async fn strategic_lookup<C>(
     name: Name,
@@ -233,17 +410,21 @@ async fn strategic_lookup<C>(
     client: CachingClient<C>,
     options: DnsRequestOptions,
     hosts: Option<Arc<Hosts>>,
-) -> Result<Lookup, ResolveError>
+) -> Result<LookupIp, ResolveError>
 where
     C: DnsHandle + 'static,
 {
-    match strategy {
+    // only the addresses of the hosts file have a scope id
+    let scope_ids = hosts.as_ref().and_then(|hosts| hosts.scope_ids(&name));
+    let lookup = match strategy {
         LookupIpStrategy::Ipv4Only => ipv4_only(name, client, options, hosts).await,
         LookupIpStrategy::Ipv6Only => ipv6_only(name, client, options, hosts).await,
         LookupIpStrategy::Ipv4AndIpv6 => ipv4_and_ipv6(name, client, options, hosts).await,
         LookupIpStrategy::Ipv6thenIpv4 => ipv6_then_ipv4(name, client, options, hosts).await,
         LookupIpStrategy::Ipv4thenIpv6 => ipv4_then_ipv6(name, client, options, hosts).await,
-    }
+    };
+
+    lookup.map(|lookup| LookupIp::from(lookup).with_scope_ids(scope_ids))
 }
 
 /// first lookups in hosts, then performs the query
@@ -332,7 +513,12 @@ where
                 "one of ipv4 or ipv6 lookup failed in ipv4_and_ipv6 strategy: {}",
                 e
             );
-            Ok(ips)
+            // a family without address is a complete answer, unlike a failed query
+            if e.is_no_records_found() {
+                Ok(ips)
+            } else {
+                Ok(ips.into_partial())
+            }
         }
         (Err(e1), Err(e2)) => {
             debug!(
@@ -445,7 +631,7 @@ pub mod tests {
     use crate::proto::op::Message;
     use crate::proto::rr::{Name, RData, Record};
     use crate::proto::xfer::{DnsHandle, DnsRequest, DnsResponse};
-    use hickory_proto::error::ProtoError;
+    use hickory_proto::error::{ProtoError, ProtoErrorKind};
 
     use futures_util::stream::{once, Stream};
 
@@ -466,6 +652,19 @@ pub mod tests {
         }
     }
 
+    /// Answers all the queries with [`v4_message`], recording their requests
+    #[derive(Clone, Default)]
+    pub struct RecordingHandle(pub Arc<Mutex<Vec<DnsRequest>>>);
+
+    impl DnsHandle for RecordingHandle {
+        type Response = Pin<Box<dyn Stream<Item = Result<DnsResponse, ProtoError>> + Send + Unpin>>;
+
+        fn send<R: Into<DnsRequest>>(&self, request: R) -> Self::Response {
+            self.0.lock().unwrap().push(request.into());
+            Box::pin(once(future::ready(v4_message())))
+        }
+    }
+
     pub fn v4_message() -> Result<DnsResponse, ProtoError> {
         let mut message = Message::new();
         message.add_query(Query::query(Name::root(), RecordType::A));
@@ -624,6 +823,45 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn test_ipv4_and_ipv6_partial_on_timeout() {
+        // ipv6 times out, ipv4 is returned flagged as partial
+        let lookup = block_on(ipv4_and_ipv6(
+            Name::root(),
+            CachingClient::new(
+                0,
+                mock(vec![
+                    Err(ProtoError::from(ProtoErrorKind::Timeout)),
+                    v4_message(),
+                ]),
+                false,
+            ),
+            DnsRequestOptions::default(),
+            None,
+        ))
+        .expect("ipv4 results should be returned");
+
+        assert!(lookup.is_partial());
+        assert_eq!(
+            lookup
+                .iter()
+                .map(|r| r.ip_addr().unwrap())
+                .collect::<Vec<IpAddr>>(),
+            vec![IpAddr::V4(Ipv4Addr::LOCALHOST)]
+        );
+
+        // no ipv6 record is a complete answer
+        let lookup = block_on(ipv4_and_ipv6(
+            Name::root(),
+            CachingClient::new(0, mock(vec![empty(), v4_message()]), false),
+            DnsRequestOptions::default(),
+            None,
+        ))
+        .unwrap();
+
+        assert!(!lookup.is_partial());
+    }
+
     #[test]
     fn test_ipv6_then_ipv4_strategy() {
         // ipv6 first
@@ -719,4 +957,261 @@ pub mod tests {
             vec![Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1)]
         );
     }
+
+    #[test]
+    fn test_filter_family() {
+        let mut client = CachingClient::new(2, mock(vec![v6_message(), v4_message()]), false);
+        let lookup = block_on(strategic_lookup(
+            Name::root(),
+            LookupIpStrategy::Ipv4AndIpv6,
+            client.clone(),
+            DnsRequestOptions::default(),
+            None,
+        ))
+        .unwrap();
+        assert_eq!(lookup.iter().count(), 2);
+
+        assert_eq!(
+            lookup
+                .filter_family(AddressFamilyFilter::Ipv4Only)
+                .iter()
+                .collect::<Vec<_>>(),
+            vec![IpAddr::V4(Ipv4Addr::LOCALHOST)]
+        );
+        assert_eq!(
+            lookup
+                .filter_family(AddressFamilyFilter::Ipv6Only)
+                .iter()
+                .collect::<Vec<_>>(),
+            vec![IpAddr::V6(Ipv6Addr::LOCALHOST)]
+        );
+
+        // the flags of the lookup are kept
+        let partial = LookupIp::from(lookup.as_lookup().clone().into_partial());
+        assert!(partial
+            .filter_family(AddressFamilyFilter::Ipv4Only)
+            .is_partial());
+
+        // the filtered out family is still cached, no further query is answered by the mock
+        let cached = block_on(client.lookup(
+            Query::query(Name::root(), RecordType::AAAA),
+            DnsRequestOptions::default(),
+        ))
+        .expect("AAAA should be cached");
+        assert_eq!(
+            cached
+                .iter()
+                .map(|r| r.ip_addr().unwrap())
+                .collect::<Vec<_>>(),
+            vec![IpAddr::V6(Ipv6Addr::LOCALHOST)]
+        );
+    }
+
+    #[test]
+    fn test_prefer_family() {
+        let v4 = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let v6 = IpAddr::V6(Ipv6Addr::LOCALHOST);
+        let mut client = CachingClient::new(2, mock(vec![v6_message(), v4_message()]), false);
+        let lookup = block_on(strategic_lookup(
+            Name::root(),
+            LookupIpStrategy::Ipv4AndIpv6,
+            client.clone(),
+            DnsRequestOptions::default(),
+            None,
+        ))
+        .unwrap();
+        let addresses = |preference| lookup.prefer_family(preference).iter().collect::<Vec<_>>();
+
+        assert_eq!(addresses(IpFamilyPreference::System), vec![v4, v6]);
+        assert_eq!(addresses(IpFamilyPreference::V4First), vec![v4, v6]);
+        assert_eq!(addresses(IpFamilyPreference::V6First), vec![v6, v4]);
+
+        // the flags of the lookup are kept
+        let partial = LookupIp::from(lookup.as_lookup().clone().into_partial());
+        assert!(partial
+            .prefer_family(IpFamilyPreference::V6First)
+            .is_partial());
+
+        // both families are still cached, no further query is answered by the mock
+        for record_type in [RecordType::A, RecordType::AAAA] {
+            assert!(block_on(client.lookup(
+                Query::query(Name::root(), record_type),
+                DnsRequestOptions::default(),
+            ))
+            .is_ok());
+        }
+    }
+
+    #[test]
+    fn test_weighted_order() {
+        let first = IpAddr::from(Ipv4Addr::new(127, 0, 0, 1));
+        let second = IpAddr::from(Ipv4Addr::new(127, 0, 0, 2));
+        let unused = IpAddr::from(Ipv6Addr::LOCALHOST);
+
+        let records = [first, second, unused]
+            .into_iter()
+            .map(|ip| Record::from_rdata(Name::root(), 86400, RData::from(ip)))
+            .collect::<Vec<_>>();
+        let lookup = LookupIp::from(Lookup::new_with_max_ttl(
+            Query::query(Name::root(), RecordType::A),
+            Arc::from(records),
+        ));
+
+        let weight = |ip: &IpAddr| match ip {
+            ip if *ip == first => 1,
+            ip if *ip == second => 3,
+            _ => 0,
+        };
+
+        const CALLS: usize = 10_000;
+        let mut second_first = 0;
+        for _ in 0..CALLS {
+            let ordered = lookup.weighted_order(weight).iter().collect::<Vec<_>>();
+            assert_eq!(ordered.len(), 3);
+            assert_eq!(ordered[2], unused);
+            if ordered[0] == second {
+                second_first += 1;
+            }
+        }
+
+        // the second address should lead 3 times out of 4
+        let ratio = second_first as f64 / CALLS as f64;
+        assert!((0.72..0.78).contains(&ratio), "ratio: {ratio}");
+
+        // the original order is unchanged
+        assert_eq!(
+            lookup.iter().collect::<Vec<_>>(),
+            vec![first, second, unused]
+        );
+    }
+
+    #[test]
+    fn test_aaaa_suppressed_without_ipv6_route() {
+        let strategy = routed_strategy(LookupIpStrategy::Ipv4AndIpv6, true, || false);
+        assert_eq!(strategy, LookupIpStrategy::Ipv4Only);
+
+        let lookup = block_on(strategic_lookup(
+            Name::root(),
+            strategy,
+            CachingClient::new(0, mock(vec![v6_message(), v4_message()]), false),
+            DnsRequestOptions::default(),
+            None,
+        ))
+        .unwrap();
+        assert_eq!(lookup.iter().collect::<Vec<_>>(), vec![Ipv4Addr::LOCALHOST]);
+
+        // AAAA queries are still sent with a route, or when not requested
+        assert_eq!(
+            routed_strategy(LookupIpStrategy::Ipv4AndIpv6, true, || true),
+            LookupIpStrategy::Ipv4AndIpv6
+        );
+        assert_eq!(
+            routed_strategy(LookupIpStrategy::Ipv6Only, false, || false),
+            LookupIpStrategy::Ipv6Only
+        );
+        assert_eq!(
+            routed_strategy(LookupIpStrategy::Ipv6thenIpv4, true, || false),
+            LookupIpStrategy::Ipv4Only
+        );
+
+        // an explicit IPv6 lookup is never overridden
+        assert_eq!(
+            routed_strategy(LookupIpStrategy::Ipv6Only, true, || false),
+            LookupIpStrategy::Ipv6Only
+        );
+    }
+
+    #[test]
+    fn test_scope_id_from_hosts() {
+        use std::str::FromStr;
+
+        let mut hosts = Hosts::default();
+        hosts
+            .read_hosts_conf("fe80::1%3 printer.local\n::1 localhost\n".as_bytes())
+            .unwrap();
+        let hosts = Some(Arc::new(hosts));
+
+        let lookup = block_on(LookupIpFuture::lookup(
+            vec![Name::from_str("printer.local").unwrap()],
+            LookupIpStrategy::Ipv6Only,
+            CachingClient::new(0, mock(vec![]), false),
+            DnsRequestOptions::default(),
+            hosts.clone(),
+            None,
+        ))
+        .unwrap();
+
+        let link_local = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+        assert_eq!(lookup.scope_id(&link_local), Some(3));
+        assert_eq!(lookup.scope_id(&Ipv6Addr::LOCALHOST), None);
+        assert_eq!(
+            lookup.socket_addrs(631).collect::<Vec<_>>(),
+            vec![SocketAddr::V6(SocketAddrV6::new(link_local, 631, 0, 3))]
+        );
+        // the same address resolved through DNS doesn't inherit the zone of the hosts file
+        let mut message = Message::new();
+        message.add_query(Query::query(Name::root(), RecordType::AAAA));
+        message.insert_answers(vec![Record::from_rdata(
+            Name::root(),
+            86400,
+            RData::AAAA(link_local.into()),
+        )]);
+        let response = DnsResponse::from_message(message).unwrap();
+
+        let lookup = block_on(LookupIpFuture::lookup(
+            vec![Name::root()],
+            LookupIpStrategy::Ipv6Only,
+            CachingClient::new(0, mock(vec![Ok(response)]), false),
+            DnsRequestOptions::default(),
+            hosts,
+            None,
+        ))
+        .unwrap();
+
+        assert_eq!(lookup.scope_id(&link_local), None);
+        assert_eq!(
+            lookup.socket_addrs(631).collect::<Vec<_>>(),
+            vec![SocketAddr::V6(SocketAddrV6::new(link_local, 631, 0, 0))]
+        );
+    }
+
+    #[test]
+    fn test_addresses_with_ttl() {
+        use std::str::FromStr;
+
+        let name = Name::from_str("www.example.com.").unwrap();
+        let a = |ip: [u8; 4], ttl| {
+            Record::from_rdata(name.clone(), ttl, RData::A(Ipv4Addr::from(ip).into()))
+        };
+        let records = vec![
+            a([127, 0, 0, 1], 300),
+            a([127, 0, 0, 2], 100),
+            Record::from_rdata(name.clone(), 600, RData::AAAA(Ipv6Addr::LOCALHOST.into())),
+        ];
+
+        let now = Instant::now();
+        let lookup = LookupIp::from(Lookup::new_with_deadline(
+            Query::query(name.clone(), RecordType::A),
+            Arc::from(records),
+            now + Duration::from_secs(100),
+        ));
+
+        // the A records are clamped to the min of their RRset
+        assert_eq!(
+            lookup.addresses_with_ttl(now + Duration::from_secs(30)),
+            vec![
+                (IpAddr::from([127, 0, 0, 1]), Duration::from_secs(70)),
+                (IpAddr::from([127, 0, 0, 2]), Duration::from_secs(70)),
+                (IpAddr::from(Ipv6Addr::LOCALHOST), Duration::from_secs(570)),
+            ]
+        );
+        assert_eq!(
+            lookup.addresses_with_ttl(now + Duration::from_secs(200)),
+            vec![
+                (IpAddr::from([127, 0, 0, 1]), Duration::ZERO),
+                (IpAddr::from([127, 0, 0, 2]), Duration::ZERO),
+                (IpAddr::from(Ipv6Addr::LOCALHOST), Duration::from_secs(400)),
+            ]
+        );
+    }
 }
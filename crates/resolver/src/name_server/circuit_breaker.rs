@@ -0,0 +1,164 @@
+//! Stops querying failing name servers, see `ResolverOpts::circuit_breaker`
+
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use parking_lot::Mutex;
+
+use crate::config::CircuitBreakerConfig;
+use crate::proto::xfer::Protocol;
+
+/// State of the circuit breaker of a name server
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Queries are sent to the name server
+    Closed,
+    /// The name server failed repeatedly, it is skipped until the cooldown elapses
+    Open,
+    /// The cooldown elapsed, a single probe query decides whether the circuit closes or opens again
+    HalfOpen,
+}
+
+/// Stops sending queries to a name server which is consistently failing
+///
+/// After `failure_threshold` consecutive failures the circuit opens for the `cooldown`. Once it
+///  elapses, a probe query is allowed through; its success closes the circuit, its failure opens
+///  it for another cooldown.
+pub(crate) struct CircuitBreaker {
+    config: Option<CircuitBreakerConfig>,
+    inner: Mutex<CircuitBreakerInner>,
+}
+
+#[derive(Default)]
+struct CircuitBreakerInner {
+    consecutive_failures: u32,
+    /// Set while the circuit is open, no query is allowed through before this time
+    open_until: Option<Instant>,
+    /// A probe query was allowed through and its result is pending
+    probing: bool,
+}
+
+impl CircuitBreaker {
+    /// A circuit breaker which never opens if `config` is `None`
+    pub(crate) fn new(config: Option<CircuitBreakerConfig>) -> Self {
+        Self {
+            config,
+            inner: Mutex::default(),
+        }
+    }
+
+    /// True if the circuit is open and its cooldown has not elapsed, the name server should be skipped
+    pub(crate) fn is_open(&self, now: Instant) -> bool {
+        matches!(self.inner.lock().open_until, Some(open_until) if now < open_until)
+    }
+
+    /// Returns true if a query may be sent, claiming the probe if the cooldown just elapsed
+    pub(crate) fn try_acquire(&self, now: Instant) -> bool {
+        let Some(config) = self.config else {
+            return true;
+        };
+
+        let mut inner = self.inner.lock();
+        match inner.open_until {
+            None => true,
+            Some(open_until) if now < open_until => false,
+            Some(_) => {
+                // other queries are held back until the probe completes, or another cooldown elapses
+                inner.open_until = now.checked_add(config.cooldown);
+                inner.probing = true;
+                true
+            }
+        }
+    }
+
+    pub(crate) fn record_success(&self) {
+        if self.config.is_some() {
+            *self.inner.lock() = CircuitBreakerInner::default();
+        }
+    }
+
+    pub(crate) fn record_failure(&self, now: Instant) {
+        let Some(config) = self.config else {
+            return;
+        };
+
+        let mut inner = self.inner.lock();
+        inner.consecutive_failures = inner.consecutive_failures.saturating_add(1);
+        if inner.probing || inner.consecutive_failures >= config.failure_threshold {
+            inner.open_until = now.checked_add(config.cooldown);
+            inner.probing = false;
+        }
+    }
+
+    pub(crate) fn state(&self, now: Instant) -> CircuitState {
+        let inner = self.inner.lock();
+        match inner.open_until {
+            None => CircuitState::Closed,
+            Some(open_until) if now < open_until && !inner.probing => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    pub(crate) fn consecutive_failures(&self) -> u32 {
+        self.inner.lock().consecutive_failures
+    }
+}
+
+/// Snapshot of the circuit breaker of a name server
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CircuitBreakerSnapshot {
+    /// Address of the name server
+    pub socket_addr: SocketAddr,
+    /// Protocol used with the name server
+    pub protocol: Protocol,
+    /// State of the circuit breaker
+    pub state: CircuitState,
+    /// Number of consecutive failures of the name server
+    pub consecutive_failures: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{CircuitBreaker, CircuitState};
+    use crate::config::CircuitBreakerConfig;
+
+    #[test]
+    fn test_circuit_breaker_probe() {
+        let now = Instant::now();
+        let cooldown = Duration::from_secs(60);
+        let breaker = CircuitBreaker::new(Some(CircuitBreakerConfig {
+            failure_threshold: 2,
+            cooldown,
+        }));
+
+        // the second consecutive failure opens the circuit for the cooldown
+        breaker.record_failure(now);
+        assert_eq!(breaker.state(now), CircuitState::Closed);
+        assert!(breaker.try_acquire(now));
+        breaker.record_failure(now);
+        assert_eq!(breaker.state(now), CircuitState::Open);
+        assert!(!breaker.try_acquire(now + cooldown / 2));
+
+        // once the cooldown elapsed, a single probe is let through
+        let probe = now + cooldown;
+        assert_eq!(breaker.state(probe), CircuitState::HalfOpen);
+        assert!(breaker.try_acquire(probe));
+        assert_eq!(breaker.state(probe), CircuitState::HalfOpen);
+        assert!(!breaker.try_acquire(probe));
+
+        // a failed probe opens the circuit for another cooldown
+        breaker.record_failure(probe);
+        assert_eq!(breaker.state(probe), CircuitState::Open);
+        assert!(!breaker.try_acquire(probe + cooldown / 2));
+
+        // a successful probe closes it
+        let probe = probe + cooldown;
+        assert!(breaker.try_acquire(probe));
+        breaker.record_success();
+        assert_eq!(breaker.state(probe), CircuitState::Closed);
+        assert_eq!(breaker.consecutive_failures(), 0);
+        assert!(breaker.try_acquire(probe));
+    }
+}
@@ -13,26 +13,14 @@ use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
-#[cfg(feature = "dns-over-tls")]
-use crate::proto::runtime::iocompat::AsyncIoStdAsTokio;
 use crate::proto::runtime::Spawn;
 #[cfg(feature = "tokio-runtime")]
 use crate::proto::runtime::TokioRuntimeProvider;
 use futures_util::future::FutureExt;
 use futures_util::ready;
 use futures_util::stream::{Stream, StreamExt};
-#[cfg(all(feature = "dns-over-native-tls", not(feature = "dns-over-rustls")))]
-use tokio_native_tls::TlsStream as TokioTlsStream;
-#[cfg(all(
-    feature = "dns-over-openssl",
-    not(feature = "dns-over-rustls"),
-    not(feature = "dns-over-native-tls")
-))]
-use tokio_openssl::SslStream as TokioTlsStream;
-#[cfg(feature = "dns-over-rustls")]
-use tokio_rustls::client::TlsStream as TokioTlsStream;
 
-use crate::config::{NameServerConfig, ResolverOpts};
+use crate::config::{NameServerConfig, OversizedUdpPolicy, ResolverOpts};
 #[cfg(any(feature = "dns-over-h3", feature = "dns-over-https-rustls"))]
 use crate::proto;
 #[cfg(feature = "dns-over-https-rustls")]
@@ -41,8 +29,6 @@ use crate::proto::h2::{HttpsClientConnect, HttpsClientStream};
 use crate::proto::h3::{H3ClientConnect, H3ClientStream};
 #[cfg(feature = "dns-over-quic")]
 use crate::proto::quic::{QuicClientConnect, QuicClientStream};
-#[cfg(feature = "dns-over-tls")]
-use crate::proto::runtime::iocompat::AsyncIoTokioAsStd;
 #[cfg(feature = "tokio-runtime")]
 #[allow(unused_imports)] // Complicated cfg for which protocols are enabled
 use crate::proto::runtime::TokioTime;
@@ -56,6 +42,8 @@ use crate::proto::{
         DnsMultiplexerConnect, DnsRequest, DnsResponse, Protocol,
     },
 };
+#[cfg(feature = "dns-over-tls")]
+use crate::tls::TlsBackendStream;
 
 /// Create `DnsHandle` with the help of `RuntimeProvider`.
 /// This trait is designed for customization.
@@ -73,11 +61,12 @@ pub trait ConnectionProvider: 'static + Clone + Send + Sync + Unpin {
         config: &NameServerConfig,
         options: &ResolverOpts,
     ) -> Result<Self::FutureConn, io::Error>;
-}
 
-#[cfg(feature = "dns-over-tls")]
-/// Predefined type for TLS client stream
-type TlsClientStream<S> = TcpClientStream<AsyncIoTokioAsStd<TokioTlsStream<AsyncIoStdAsTokio<S>>>>;
+    /// Spawn a task in the background, e.g. the refresh of a stale cache entry
+    ///
+    /// The task must run to completion, usually on the runtime of the `RuntimeProvider`.
+    fn spawn_bg(&self, task: Pin<Box<dyn Future<Output = ()> + Send>>);
+}
 
 /// The variants of all supported connections for the Resolver
 #[allow(clippy::large_enum_variant, clippy::type_complexity)]
@@ -101,16 +90,16 @@ pub(crate) enum ConnectionConnect<R: RuntimeProvider> {
                     Box<
                         dyn Future<
                                 Output = Result<
-                                    TlsClientStream<<R as RuntimeProvider>::Tcp>,
+                                    TlsBackendStream<<R as RuntimeProvider>::Tcp>,
                                     ProtoError,
                                 >,
                             > + Send
                             + 'static,
                     >,
                 >,
-                TlsClientStream<<R as RuntimeProvider>::Tcp>,
+                TlsBackendStream<<R as RuntimeProvider>::Tcp>,
             >,
-            DnsMultiplexer<TlsClientStream<<R as RuntimeProvider>::Tcp>>,
+            DnsMultiplexer<TlsBackendStream<<R as RuntimeProvider>::Tcp>>,
             TokioTime,
         >,
     ),
@@ -152,7 +141,8 @@ impl<R: RuntimeProvider> Future for ConnectionFuture<R> {
             }
             #[cfg(feature = "dns-over-https-rustls")]
             ConnectionConnect::Https(conn) => {
-                let (conn, bg) = ready!(conn.poll_unpin(cx))?;
+                let (conn, bg) =
+                    ready!(conn.poll_unpin(cx)).map_err(crate::tls::pin_mismatch_error)?;
                 self.spawner.spawn_bg(bg);
                 GenericConnection(conn)
             }
@@ -219,12 +209,17 @@ impl<P: RuntimeProvider> ConnectionProvider for GenericConnector<P> {
         config: &NameServerConfig,
         options: &ResolverOpts,
     ) -> Result<Self::FutureConn, io::Error> {
+        // the timeout of the name server takes precedence over the one of the resolver
+        let timeout = config.timeout.unwrap_or(options.timeout);
         let dns_connect = match (config.protocol, self.runtime_provider.quic_binder()) {
             (Protocol::Udp, _) => {
                 let provider_handle = self.runtime_provider.clone();
                 let stream = UdpClientStream::builder(config.socket_addr, provider_handle)
-                    .with_timeout(Some(options.timeout))
+                    .with_timeout(Some(timeout))
                     .avoid_local_ports(options.avoid_local_udp_ports.clone())
+                    .reject_oversized_responses(
+                        options.oversized_udp_policy == OversizedUdpPolicy::Retry,
+                    )
                     .build();
                 let exchange = DnsExchange::connect(stream);
                 ConnectionConnect::Udp(exchange)
@@ -233,43 +228,27 @@ impl<P: RuntimeProvider> ConnectionProvider for GenericConnector<P> {
                 let (future, handle) = TcpClientStream::new(
                     config.socket_addr,
                     None,
-                    Some(options.timeout),
+                    Some(timeout),
                     self.runtime_provider.clone(),
                 );
 
                 // TODO: need config for Signer...
-                let dns_conn = DnsMultiplexer::with_timeout(future, handle, options.timeout, None);
+                let dns_conn = DnsMultiplexer::with_timeout(future, handle, timeout, None);
                 let exchange = DnsExchange::connect(dns_conn);
                 ConnectionConnect::Tcp(exchange)
             }
             #[cfg(feature = "dns-over-tls")]
             (Protocol::Tls, _) => {
-                let socket_addr = config.socket_addr;
-                let timeout = options.timeout;
-                let tls_dns_name = config.tls_dns_name.clone().unwrap_or_default();
-                let tcp_future = self.runtime_provider.connect_tcp(socket_addr, None, None);
+                let tcp_future = self
+                    .runtime_provider
+                    .connect_tcp(config.socket_addr, None, None);
 
-                #[cfg(feature = "dns-over-rustls")]
-                let client_config = config.tls_config.clone();
-
-                #[cfg(feature = "dns-over-rustls")]
-                let (stream, handle) = {
-                    crate::tls::new_tls_stream_with_future(
-                        tcp_future,
-                        socket_addr,
-                        tls_dns_name,
-                        client_config,
-                    )
-                };
-                #[cfg(not(feature = "dns-over-rustls"))]
-                let (stream, handle) = {
-                    crate::tls::new_tls_stream_with_future(
-                        tcp_future,
-                        socket_addr,
-                        tls_dns_name,
-                        self.runtime_provider.clone(),
-                    )
-                };
+                let (stream, handle) = crate::tls::new_tls_stream_with_backend(
+                    options.tls_backend,
+                    tcp_future,
+                    config,
+                    self.runtime_provider.clone(),
+                );
 
                 let dns_conn = DnsMultiplexer::with_timeout(stream, handle, timeout, None);
                 let exchange = DnsExchange::connect(dns_conn);
@@ -353,6 +332,10 @@ impl<P: RuntimeProvider> ConnectionProvider for GenericConnector<P> {
             spawner: self.runtime_provider.create_handle(),
         })
     }
+
+    fn spawn_bg(&self, task: Pin<Box<dyn Future<Output = ()> + Send>>) {
+        self.runtime_provider.create_handle().spawn_bg(task.map(Ok));
+    }
 }
 
 /// A stream of response to a DNS request.
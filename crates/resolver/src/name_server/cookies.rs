@@ -0,0 +1,155 @@
+//! DNS cookies exchanged with the name servers, see `ResolverOpts::use_dns_cookies`
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::config::ResolverOpts;
+use crate::proto::op::{Edns, Message};
+use crate::proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+use crate::proto::xfer::DnsRequest;
+
+/// Length of a client cookie, see RFC 7873 section 4.1
+const CLIENT_COOKIE_LEN: usize = 8;
+
+/// Valid lengths of a server cookie, see RFC 7873 section 4.2
+const SERVER_COOKIE_LENS: RangeInclusive<usize> = 8..=32;
+
+/// The DNS cookies exchanged with the name servers, see RFC 7873
+///
+/// Each name server is sent its own random client cookie, along with the last server cookie it
+///  returned, so that off-path attackers can't spoof its responses without seeing the queries.
+#[derive(Default)]
+pub(crate) struct CookieJar {
+    servers: Mutex<HashMap<SocketAddr, Cookies>>,
+}
+
+struct Cookies {
+    client: [u8; CLIENT_COOKIE_LEN],
+    server: Option<Vec<u8>>,
+}
+
+impl CookieJar {
+    /// The cookie jar shared by the name servers, if DNS cookies are enabled by the options
+    pub(crate) fn for_options(options: &ResolverOpts) -> Option<Arc<Self>> {
+        options.use_dns_cookies.then(|| Arc::new(Self::default()))
+    }
+
+    /// Adds the COOKIE option for the name server to the request, replacing any previous one
+    pub(crate) fn add_cookie(&self, server: SocketAddr, request: &mut DnsRequest) {
+        let mut servers = self.servers.lock();
+        let cookies = servers.entry(server).or_insert_with(|| Cookies {
+            client: rand::random(),
+            server: None,
+        });
+
+        let mut data = cookies.client.to_vec();
+        if let Some(server_cookie) = &cookies.server {
+            data.extend_from_slice(server_cookie);
+        }
+
+        let options = request
+            .extensions_mut()
+            .get_or_insert_with(Edns::new)
+            .options_mut();
+        options.remove(EdnsCode::Cookie);
+        options.insert(EdnsOption::Unknown(EdnsCode::Cookie.into(), data));
+    }
+
+    /// Stores the server cookie of the response of the name server
+    ///
+    /// The server cookie of a response with another client cookie than the one sent to the name
+    ///  server is ignored, such responses are already discarded by the UDP stream. Responses without
+    ///  a COOKIE option come from name servers which don't support cookies.
+    pub(crate) fn learn(&self, server: SocketAddr, response: &Message) {
+        let Some(EdnsOption::Unknown(_, data)) = response
+            .extensions()
+            .as_ref()
+            .and_then(|edns| edns.option(EdnsCode::Cookie))
+        else {
+            return;
+        };
+
+        let mut servers = self.servers.lock();
+        let Some(cookies) = servers.get_mut(&server) else {
+            return;
+        };
+        if data.len() < CLIENT_COOKIE_LEN || data[..CLIENT_COOKIE_LEN] != cookies.client {
+            return;
+        }
+
+        let server_cookie = &data[CLIENT_COOKIE_LEN..];
+        if SERVER_COOKIE_LENS.contains(&server_cookie.len()) {
+            cookies.server = Some(server_cookie.to_vec());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::*;
+    use crate::proto::op::Query;
+    use crate::proto::rr::{Name, RecordType};
+
+    fn cookie(request: &DnsRequest) -> Vec<u8> {
+        match request
+            .extensions()
+            .as_ref()
+            .and_then(|edns| edns.option(EdnsCode::Cookie))
+        {
+            Some(EdnsOption::Unknown(10, data)) => data.clone(),
+            option => panic!("unexpected cookie option: {option:?}"),
+        }
+    }
+
+    fn response(cookie: Vec<u8>) -> Message {
+        let mut response = Message::new();
+        response
+            .extensions_mut()
+            .get_or_insert_with(Edns::new)
+            .options_mut()
+            .insert(EdnsOption::Unknown(10, cookie));
+        response
+    }
+
+    #[test]
+    fn test_cookie_jar() {
+        let jar = CookieJar::default();
+        let server = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 53);
+        let other = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 5353);
+        let mut request = DnsRequest::from(Message::new());
+        request.add_query(Query::query(Name::root(), RecordType::A));
+
+        // only the client cookie is sent at first, a different one to each name server
+        jar.add_cookie(server, &mut request);
+        let client = cookie(&request);
+        assert_eq!(client.len(), CLIENT_COOKIE_LEN);
+        let mut other_request = request.clone();
+        jar.add_cookie(other, &mut other_request);
+        assert_ne!(cookie(&other_request), client);
+
+        // the server cookie of a response with another client cookie is ignored
+        let mut spoofed = vec![0; CLIENT_COOKIE_LEN];
+        spoofed.extend_from_slice(&[1; 8]);
+        jar.learn(server, &response(spoofed));
+        jar.add_cookie(server, &mut request);
+        assert_eq!(cookie(&request), client);
+
+        // the server cookie is echoed back once learned
+        let mut server_cookie = client.clone();
+        server_cookie.extend_from_slice(&[2; 16]);
+        jar.learn(server, &response(server_cookie.clone()));
+        jar.add_cookie(server, &mut request);
+        assert_eq!(cookie(&request), server_cookie);
+
+        // responses without cookies don't forget the server cookie
+        jar.learn(server, &Message::new());
+        jar.add_cookie(server, &mut request);
+        assert_eq!(cookie(&request), server_cookie);
+    }
+}
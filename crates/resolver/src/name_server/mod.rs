@@ -7,18 +7,29 @@
 
 //! A module with associated items for working with nameservers
 
+mod circuit_breaker;
 mod connection_provider;
+mod cookies;
 #[allow(clippy::module_inception)]
 mod name_server;
 mod name_server_pool;
 mod name_server_state;
 mod name_server_stats;
+mod token_bucket;
 
+use self::circuit_breaker::CircuitBreaker;
+pub use self::circuit_breaker::{CircuitBreakerSnapshot, CircuitState};
 pub use self::connection_provider::{ConnectionProvider, GenericConnection, GenericConnector};
+use self::cookies::CookieJar;
 pub use self::name_server::{GenericNameServer, NameServer};
-pub use self::name_server_pool::{GenericNameServerPool, NameServerPool};
+pub(crate) use self::name_server_pool::PoolConnectivity;
+pub use self::name_server_pool::{
+    AnswerDiscrepancy, DiscrepancyReporter, GenericNameServerPool, LocalConnectivity,
+    NameServerPool,
+};
 use self::name_server_state::NameServerState;
 use self::name_server_stats::NameServerStats;
+use self::token_bucket::TokenBucket;
 
 #[cfg(feature = "tokio-runtime")]
 pub use self::connection_provider::TokioConnectionProvider;
@@ -7,6 +7,7 @@
 
 use std::cmp::Ordering;
 use std::fmt::{self, Debug, Formatter};
+use std::net::IpAddr;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::time::Instant;
@@ -15,14 +16,28 @@ use futures_util::lock::Mutex;
 use futures_util::stream::{once, Stream};
 
 use crate::proto::{
-    error::ProtoError,
-    xfer::{DnsHandle, DnsRequest, DnsResponse, FirstAnswer},
+    error::{ProtoError, ProtoErrorKind},
+    op::{Message, Query, ResponseCode},
+    rr::{
+        rdata::opt::{EdnsCode, EdnsOption},
+        Record, RecordType,
+    },
+    serialize::binary::{BinDecodable, BinDecoder, DEFAULT_MAX_POINTER_DEPTH},
+    xfer::{DnsHandle, DnsRequest, DnsResponse, FirstAnswer, Protocol, RequestTimer},
 };
-use tracing::debug;
+use tracing::{debug, warn};
 
-use crate::config::{NameServerConfig, ResolverOpts};
+use crate::config::{
+    AnswerTypeMismatchPolicy, EdnsAbsentPolicy, NameServerConfig, RecursionUnavailablePolicy,
+    ResolverOpts,
+};
 use crate::name_server::connection_provider::{ConnectionProvider, GenericConnector};
-use crate::name_server::{NameServerState, NameServerStats};
+use crate::name_server::{
+    CircuitBreaker, CircuitBreakerSnapshot, CircuitState, CookieJar, NameServerState,
+    NameServerStats, TokenBucket,
+};
+#[cfg(feature = "dns-over-rustls")]
+use crate::tls::SessionCache;
 
 /// This struct is used to create `DnsHandle` with the help of `P`.
 #[derive(Clone)]
@@ -32,6 +47,12 @@ pub struct NameServer<P: ConnectionProvider> {
     client: Arc<Mutex<Option<P::Conn>>>,
     state: Arc<NameServerState>,
     stats: Arc<NameServerStats>,
+    breaker: Arc<CircuitBreaker>,
+    cookies: Option<Arc<CookieJar>>,
+    /// Limits the rate of queries, shared by the name servers of a pool, see [`ResolverOpts::global_rate`]
+    global_rate: Option<Arc<TokenBucket>>,
+    #[cfg(feature = "dns-over-rustls")]
+    session_cache: Option<Arc<SessionCache>>,
     connection_provider: P,
 }
 
@@ -53,12 +74,20 @@ where
 {
     /// Construct a new Nameserver with the configuration and options. The connection provider will create UDP and TCP sockets
     pub fn new(config: NameServerConfig, options: ResolverOpts, connection_provider: P) -> Self {
+        #[cfg(feature = "dns-over-rustls")]
+        let (config, session_cache) = with_session_cache(config, &options);
+
         Self {
             config,
-            options,
             client: Arc::new(Mutex::new(None)),
             state: Arc::new(NameServerState::init(None)),
             stats: Arc::new(NameServerStats::default()),
+            breaker: Arc::new(CircuitBreaker::new(options.circuit_breaker)),
+            cookies: CookieJar::for_options(&options),
+            global_rate: TokenBucket::for_options(&options),
+            #[cfg(feature = "dns-over-rustls")]
+            session_cache,
+            options,
             connection_provider,
         }
     }
@@ -70,16 +99,49 @@ where
         client: P::Conn,
         connection_provider: P,
     ) -> Self {
+        #[cfg(feature = "dns-over-rustls")]
+        let (config, session_cache) = with_session_cache(config, &options);
+
         Self {
             config,
-            options,
             client: Arc::new(Mutex::new(Some(client))),
             state: Arc::new(NameServerState::init(None)),
             stats: Arc::new(NameServerStats::default()),
+            breaker: Arc::new(CircuitBreaker::new(options.circuit_breaker)),
+            cookies: CookieJar::for_options(&options),
+            global_rate: TokenBucket::for_options(&options),
+            #[cfg(feature = "dns-over-rustls")]
+            session_cache,
+            options,
             connection_provider,
         }
     }
 
+    /// Shares the DNS cookies with the other name servers of a pool, see [`CookieJar`]
+    pub(crate) fn with_cookie_jar(mut self, cookies: Option<Arc<CookieJar>>) -> Self {
+        self.cookies = cookies;
+        self
+    }
+
+    /// Shares the limit of the query rate with the other name servers of a pool, see [`TokenBucket`]
+    pub(crate) fn with_global_rate(mut self, global_rate: Option<Arc<TokenBucket>>) -> Self {
+        self.global_rate = global_rate;
+        self
+    }
+
+    /// True if no query may currently be sent under the global rate, see [`ResolverOpts::global_rate`]
+    pub(crate) fn is_rate_exceeded(&self, now: Instant) -> bool {
+        self.global_rate
+            .as_ref()
+            .is_some_and(|bucket| bucket.tokens(now) == 0)
+    }
+
+    /// Drops the connection, the next query reconnects to the name server
+    #[doc(hidden)]
+    pub async fn disconnect(&self) {
+        *self.client.lock().await = None;
+    }
+
     #[cfg(test)]
     #[allow(dead_code)]
     pub(crate) fn is_connected(&self) -> bool {
@@ -94,8 +156,12 @@ where
 
     /// This will return a mutable client to allows for sending messages.
     ///
-    /// If the connection is in a failed state, then this will establish a new connection
-    async fn connected_mut_client(&mut self) -> Result<P::Conn, ProtoError> {
+    /// If the connection is in a failed state, then this will establish a new connection, recording
+    ///  the time spent with the `timer`
+    async fn connected_mut_client(
+        &mut self,
+        timer: Option<&dyn RequestTimer>,
+    ) -> Result<P::Conn, ProtoError> {
         let mut client = self.client.lock().await;
 
         // if this is in a failure state
@@ -105,11 +171,16 @@ where
             // TODO: we need the local EDNS options
             self.state.reinit(None);
 
+            let start = Instant::now();
             let new_client = Box::pin(
                 self.connection_provider
                     .new_connection(&self.config, &self.options)?,
             )
-            .await?;
+            .await;
+            if let Some(timer) = timer {
+                timer.record_connect(start.elapsed());
+            }
+            let new_client = new_client?;
 
             // establish a new connection
             *client = Some(new_client);
@@ -126,16 +197,164 @@ where
         mut self,
         request: R,
     ) -> Result<DnsResponse, ProtoError> {
-        let client = self.connected_mut_client().await?;
+        // each query sent on the wire takes a token, including the parallel and retried queries
+        if let Some(bucket) = &self.global_rate {
+            if !bucket.try_acquire(Instant::now()) {
+                debug!(
+                    "global query rate exceeded, not sending to {}",
+                    self.config.socket_addr
+                );
+                return Err(ProtoErrorKind::Busy.into());
+            }
+        }
+
+        // another query may have claimed the probe of the circuit breaker since it was checked
+        if !self.breaker.try_acquire(Instant::now()) {
+            return Err(ProtoErrorKind::NoConnections.into());
+        }
+
+        let mut request: DnsRequest = request.into();
+        let edns_unsupported = self.state.is_edns_unsupported(Instant::now());
+        if edns_unsupported && request.extensions().is_some() {
+            debug!(
+                "name server {} doesn't support EDNS, sending without it",
+                self.config.socket_addr
+            );
+            *request.extensions_mut() = None;
+        }
+
+        // cookies are only sent over UDP, the other transports aren't exposed to off-path spoofing
+        let addr = self.config.socket_addr;
+        let cookies = self
+            .cookies
+            .clone()
+            .filter(|_| self.config.protocol == Protocol::Udp && !edns_unsupported);
+        if let Some(cookies) = &cookies {
+            cookies.add_cookie(addr, &mut request);
+        }
+        let sent_edns = request.extensions().is_some();
+
+        let check_answer_types =
+            self.options.answer_type_mismatch_policy == AnswerTypeMismatchPolicy::Reject;
+        let expected_query = if self.options.strict_response_query || check_answer_types {
+            request.queries().first().cloned()
+        } else {
+            None
+        };
+        let recursion_desired = request.recursion_desired();
+
+        let timer = request.timer().cloned();
+        let client = self.connected_mut_client(timer.as_deref()).await?;
         let now = Instant::now();
-        let response = client.send(request).first_answer().await;
+        let response = match &cookies {
+            // the UDP stream already discarded the responses with another client cookie, and kept
+            //  waiting for the genuine one, see RFC 7873 section 5.3
+            Some(cookies) => match client.send(request.clone()).first_answer().await {
+                // retried with the server cookie of the response
+                Ok(response) if response.response_code() == ResponseCode::BADCOOKIE => {
+                    cookies.learn(addr, &response);
+                    debug!("bad cookie from {addr}, retrying with the server cookie");
+                    cookies.add_cookie(addr, &mut request);
+                    let response = client.send(request).first_answer().await;
+                    if let Ok(response) = &response {
+                        cookies.learn(addr, response);
+                    }
+                    response
+                }
+                Ok(response) => {
+                    cookies.learn(addr, &response);
+                    Ok(response)
+                }
+                error => error,
+            },
+            None => client.send(request).first_answer().await,
+        };
         let rtt = now.elapsed();
+        if let Some(timer) = timer {
+            timer.record_response(rtt);
+        }
 
         match response {
             Ok(response) => {
                 // Record the measured latency.
                 self.stats.record_rtt(rtt);
 
+                // Reject malformed responses before anything is derived from them
+                if let Some(query) = &expected_query {
+                    if self.options.strict_response_query {
+                        verify_response_query(query, &response)?;
+                    }
+                    if check_answer_types {
+                        verify_answer_types(query, &response)?;
+                    }
+                }
+
+                // the response was decoded with the default limit, a stricter one requires decoding it
+                //  again, while a looser one can't be applied
+                if self.options.max_pointer_depth < DEFAULT_MAX_POINTER_DEPTH {
+                    verify_pointer_depth(self.options.max_pointer_depth, &response)?;
+                }
+
+                // a name server which isn't recursing may only return a referral or partial answers
+                if recursion_desired && !response.recursion_available() {
+                    match self.options.recursion_unavailable_policy {
+                        RecursionUnavailablePolicy::Accept => {}
+                        RecursionUnavailablePolicy::Warn => warn!(
+                            "recursion not available from name server {}",
+                            self.config.socket_addr
+                        ),
+                        RecursionUnavailablePolicy::Failover => {
+                            debug!(
+                                "recursion not available from name server {}, rejecting response",
+                                self.config.socket_addr
+                            );
+                            return Err(ProtoErrorKind::RecursionUnavailable.into());
+                        }
+                    }
+                }
+
+                // a name server which doesn't support EDNS omits the OPT record, see RFC 6891 section 7
+                if sent_edns && response.extensions().is_none() {
+                    match self.options.edns_absent_policy {
+                        EdnsAbsentPolicy::Accept => {}
+                        // DNSSEC requires EDNS, the queries keep setting the DO bit
+                        EdnsAbsentPolicy::Downgrade { .. } if self.options.validate => {}
+                        EdnsAbsentPolicy::Downgrade { reprobe_after } => {
+                            debug!(
+                                "EDNS not supported by name server {}, downgrading for {:?}",
+                                self.config.socket_addr, reprobe_after
+                            );
+                            self.state
+                                .set_edns_unsupported(Instant::now() + reprobe_after);
+                        }
+                        EdnsAbsentPolicy::Fail => {
+                            debug!(
+                                "EDNS not supported by name server {}, rejecting response",
+                                self.config.socket_addr
+                            );
+                            return Err(ProtoErrorKind::EdnsUnsupported.into());
+                        }
+                    }
+                }
+
+                // the scope of a client subnet answer isn't acted on, it is cached for the prefix of the query
+                if let Some(EdnsOption::Subnet(subnet)) = response
+                    .extensions()
+                    .as_ref()
+                    .and_then(|edns| edns.option(EdnsCode::Subnet))
+                {
+                    debug!(
+                        "name server {} answered for client subnet {}/{} with scope prefix {}",
+                        self.config.socket_addr,
+                        subnet.addr(),
+                        subnet.source_prefix(),
+                        subnet.scope_prefix()
+                    );
+                }
+
+                // the name server is healthy once its response is accepted
+                self.breaker.record_success();
+
                 // First evaluate if the message succeeded.
                 let response =
                     ProtoError::from_response(response, self.config.trust_negative_responses)?;
@@ -156,6 +375,7 @@ where
 
                 // record the failure
                 self.stats.record_connection_failure();
+                self.breaker.record_failure(Instant::now());
 
                 // These are connection failures, not lookup failures, that is handled in the resolver layer
                 Err(error)
@@ -163,10 +383,84 @@ where
         }
     }
 
+    /// Returns the state of the circuit breaker of this NameServer, see [`ResolverOpts::circuit_breaker`]
+    pub fn circuit_state(&self) -> CircuitState {
+        self.breaker.state(Instant::now())
+    }
+
+    /// Returns a snapshot of the circuit breaker of this NameServer
+    pub fn circuit_breaker_snapshot(&self) -> CircuitBreakerSnapshot {
+        CircuitBreakerSnapshot {
+            socket_addr: self.config.socket_addr,
+            protocol: self.config.protocol,
+            state: self.circuit_state(),
+            consecutive_failures: self.breaker.consecutive_failures(),
+        }
+    }
+
+    /// True if this NameServer responded without EDNS to a query with EDNS, see
+    ///  [`EdnsAbsentPolicy::Downgrade`]
+    ///
+    /// Queries are then sent to this NameServer without EDNS, until the downgrade expires.
+    pub fn is_edns_unsupported(&self) -> bool {
+        self.state.is_edns_unsupported(Instant::now())
+    }
+
+    /// True if this NameServer should be skipped, as its circuit breaker is open
+    pub(crate) fn is_circuit_open(&self, now: Instant) -> bool {
+        self.breaker.is_open(now)
+    }
+
+    /// The IP address of this NameServer
+    pub(crate) fn ip(&self) -> IpAddr {
+        self.config.socket_addr.ip()
+    }
+
+    /// The weight of this NameServer, see `NameServerConfig::weight`
+    pub(crate) fn weight(&self) -> u32 {
+        self.config.weight
+    }
+
     /// Specifies that this NameServer will treat negative responses as permanent failures and will not retry
     pub fn trust_nx_responses(&self) -> bool {
         self.config.trust_negative_responses
     }
+
+    /// Number of DNS over TLS connections to this NameServer which resumed a cached TLS session
+    ///
+    /// Always 0 unless `ResolverOpts::tls_session_cache_size` is set. The handshakes of the DNS over
+    ///  HTTPS and QUIC connections resume the sessions too, but aren't counted.
+    #[cfg(feature = "dns-over-rustls")]
+    pub fn tls_resumptions(&self) -> u64 {
+        self.session_cache
+            .as_ref()
+            .map_or(0, |cache| cache.resumptions())
+    }
+}
+
+/// Gives an encrypted upstream its own TLS session cache, see `ResolverOpts::tls_session_cache_size`
+#[cfg(feature = "dns-over-rustls")]
+fn with_session_cache(
+    mut config: NameServerConfig,
+    options: &ResolverOpts,
+) -> (NameServerConfig, Option<Arc<SessionCache>>) {
+    let size = match options.tls_session_cache_size {
+        Some(size) if config.protocol.is_encrypted() => size,
+        _ => return (config, None),
+    };
+
+    let cache = Arc::new(SessionCache::new(size));
+    match cache.client_config(config.tls_config.as_ref()) {
+        Ok(client_config) => {
+            config.tls_config = Some(client_config);
+            (config, Some(cache))
+        }
+        Err(error) => {
+            // the connection will fail with the same error
+            debug!("no TLS session cache for {}: {error}", config.socket_addr);
+            (config, None)
+        }
+    }
 }
 
 impl<P> DnsHandle for NameServer<P>
@@ -187,6 +481,55 @@ where
     }
 }
 
+/// Verifies that the response contains exactly one query, which matches the query of the request
+fn verify_response_query(expected: &Query, response: &DnsResponse) -> Result<(), ProtoError> {
+    match response.queries() {
+        [query] if query == expected => Ok(()),
+        [query] => Err(ProtoErrorKind::QueryMismatch {
+            expected: Box::new(expected.clone()),
+            received: Box::new(query.clone()),
+        }
+        .into()),
+        queries => Err(ProtoErrorKind::BadQueryCount(queries.len()).into()),
+    }
+}
+
+/// Verifies that the answer records of the response are of the queried type, or lead to it
+fn verify_answer_types(expected: &Query, response: &DnsResponse) -> Result<(), ProtoError> {
+    if expected.query_type().is_any() {
+        return Ok(());
+    }
+
+    let mismatched = response
+        .answers()
+        .iter()
+        .map(Record::record_type)
+        .find(|record_type| {
+            *record_type != expected.query_type()
+                && !matches!(
+                    record_type,
+                    // DNAME (39) is not yet modeled by hickory-proto, rfc6672
+                    RecordType::CNAME | RecordType::RRSIG | RecordType::Unknown(39)
+                )
+        });
+    match mismatched {
+        Some(record_type) => Err(ProtoErrorKind::AnswerTypeMismatch {
+            query: Box::new(expected.clone()),
+            record_type,
+        }
+        .into()),
+        None => Ok(()),
+    }
+}
+
+/// Verifies that no name of the response is reached through more than `max` compression pointers
+fn verify_pointer_depth(max: usize, response: &DnsResponse) -> Result<(), ProtoError> {
+    let mut decoder = BinDecoder::new(response.as_buffer());
+    decoder.set_max_pointer_depth(max);
+    Message::read(&mut decoder)?;
+    Ok(())
+}
+
 impl<P> Ord for NameServer<P>
 where
     P: ConnectionProvider + Send,
@@ -240,6 +583,33 @@ mod tests {
     use super::*;
     use crate::name_server::connection_provider::TokioConnectionProvider;
 
+    #[tokio::test(start_paused = true)]
+    async fn test_least_latency_order() {
+        use crate::config::ServerSelectionStrategy;
+        use crate::name_server::name_server_pool::order_name_servers;
+
+        let options = ResolverOpts {
+            server_selection_strategy: ServerSelectionStrategy::LeastLatency,
+            ..ResolverOpts::default()
+        };
+        let name_server = |ip: Ipv4Addr| {
+            let config = NameServerConfig::new(SocketAddr::new(ip.into(), 53), Protocol::Udp);
+            NameServer::new(config, options.clone(), TokioConnectionProvider::default())
+        };
+        let idle = name_server(Ipv4Addr::new(192, 0, 2, 1));
+        let busy = name_server(Ipv4Addr::new(192, 0, 2, 2));
+
+        // the SRTT of the name server queried a minute ago decayed below the one just queried
+        idle.stats.record_rtt(Duration::from_millis(100));
+        tokio::time::advance(Duration::from_secs(60)).await;
+        busy.stats.record_rtt(Duration::from_millis(80));
+
+        let mut conns = vec![busy.clone(), idle.clone()];
+        order_name_servers(&mut conns, &options);
+        assert_eq!(conns[0].ip(), idle.ip());
+        assert_eq!(conns[1].ip(), busy.ip());
+    }
+
     #[test]
     fn test_name_server() {
         subscribe();
@@ -253,6 +623,8 @@ mod tests {
             #[cfg(feature = "dns-over-rustls")]
             tls_config: None,
             bind_addr: None,
+            timeout: None,
+            weight: 0,
         };
         let io_loop = Runtime::new().unwrap();
         let name_server = future::lazy(|_| {
@@ -292,6 +664,8 @@ mod tests {
             #[cfg(feature = "dns-over-rustls")]
             tls_config: None,
             bind_addr: None,
+            timeout: None,
+            weight: 0,
         };
         let io_loop = Runtime::new().unwrap();
         let name_server = future::lazy(|_| {
@@ -310,4 +684,89 @@ mod tests {
             }))
             .is_err());
     }
+
+    #[test]
+    fn test_name_server_timeout() {
+        // the name server never responds, its timeout overrides the much longer one of the resolver
+        let silent = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let options = ResolverOpts {
+            timeout: Duration::from_secs(3600),
+            ..ResolverOpts::default()
+        };
+        let mut config = NameServerConfig::new(silent.local_addr().unwrap(), Protocol::Udp);
+        config.timeout = Some(Duration::from_millis(100));
+
+        let io_loop = Runtime::new().unwrap();
+        let name_server = future::lazy(|_| {
+            GenericNameServer::new(config, options, TokioConnectionProvider::default())
+        });
+
+        let name = Name::parse("www.example.com.", None).unwrap();
+        let error = io_loop
+            .block_on(name_server.then(|name_server| {
+                name_server
+                    .lookup(
+                        Query::query(name, RecordType::A),
+                        DnsRequestOptions::default(),
+                    )
+                    .first_answer()
+            }))
+            .expect_err("the query should time out");
+        assert!(matches!(error.kind(), ProtoErrorKind::Timeout));
+    }
+
+    #[test]
+    fn test_recursion_unavailable_keeps_circuit_failures() {
+        use crate::config::{CircuitBreakerConfig, RecursionUnavailablePolicy};
+        use crate::proto::op::{Message, MessageType};
+
+        // the name server answers without the Recursion Available flag
+        let socket = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let config = NameServerConfig::new(socket.local_addr().unwrap(), Protocol::Udp);
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            while let Ok((len, src)) = socket.recv_from(&mut buf) {
+                let request = Message::from_vec(&buf[..len]).unwrap();
+                let mut response = Message::new();
+                response
+                    .set_id(request.id())
+                    .set_message_type(MessageType::Response)
+                    .set_recursion_desired(true)
+                    .add_queries(request.queries().to_vec());
+                socket.send_to(&response.to_vec().unwrap(), src).unwrap();
+            }
+        });
+
+        let options = ResolverOpts {
+            recursion_unavailable_policy: RecursionUnavailablePolicy::Failover,
+            circuit_breaker: Some(CircuitBreakerConfig {
+                failure_threshold: 2,
+                cooldown: Duration::from_secs(60),
+            }),
+            ..ResolverOpts::default()
+        };
+        let io_loop = Runtime::new().unwrap();
+        let name = Name::parse("www.example.com.", None).unwrap();
+        let (name_server, error) = io_loop.block_on(async {
+            let name_server =
+                GenericNameServer::new(config, options, TokioConnectionProvider::default());
+            name_server.breaker.record_failure(Instant::now());
+            let error = name_server
+                .lookup(
+                    Query::query(name, RecordType::A),
+                    DnsRequestOptions::default(),
+                )
+                .first_answer()
+                .await
+                .expect_err("the response should be rejected");
+            (name_server, error)
+        });
+
+        // the rejected response doesn't reset the failures of the circuit breaker
+        assert!(matches!(error.kind(), ProtoErrorKind::RecursionUnavailable));
+        assert_eq!(
+            name_server.circuit_breaker_snapshot().consecutive_failures,
+            1
+        );
+    }
 }
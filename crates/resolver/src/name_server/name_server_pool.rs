@@ -5,18 +5,23 @@
 // https://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
-use std::cmp::Ordering;
+use std::cmp::{Ordering, Reverse};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, UdpSocket};
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use futures_util::future::FutureExt;
 use futures_util::stream::{once, FuturesUnordered, Stream, StreamExt};
 use hickory_proto::error::ProtoErrorKind;
+use parking_lot::Mutex;
 use smallvec::SmallVec;
 
 use crate::proto::error::ProtoError;
+use crate::proto::op::{Edns, Query};
+use crate::proto::rr::rdata::opt::EdnsOption;
+use crate::proto::rr::Record;
 #[cfg(test)]
 #[cfg(feature = "tokio-runtime")]
 use crate::proto::runtime::TokioRuntimeProvider;
@@ -27,9 +32,14 @@ use tracing::debug;
 use rand::thread_rng as rng;
 use rand::Rng;
 
-use crate::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts, ServerOrderingStrategy};
+use crate::config::{
+    AnswerDiscrepancyPolicy, NameServerConfigGroup, ProtocolPreference, ResolverConfig,
+    ResolverOpts, ServerOrderingStrategy, ServerSelectionStrategy, TruncatedTcpPolicy,
+};
+use crate::lookup::same_records;
 use crate::name_server::connection_provider::{ConnectionProvider, GenericConnector};
 use crate::name_server::name_server::NameServer;
+use crate::name_server::{CircuitBreakerSnapshot, CookieJar, TokenBucket};
 
 /// Abstract interface for mocking purpose
 #[derive(Clone)]
@@ -38,8 +48,32 @@ pub struct NameServerPool<P: ConnectionProvider + Send + 'static> {
     datagram_conns: Arc<[NameServer<P>]>, /* All NameServers must be the same type */
     stream_conns: Arc<[NameServer<P>]>,   /* All NameServers must be the same type */
     options: ResolverOpts,
+    /// The local connectivity, shared by the clones of this pool
+    connectivity: Arc<PoolConnectivity>,
+    /// Limits the rate of queries to all name servers, see [`ResolverOpts::global_rate`]
+    global_rate: Option<Arc<TokenBucket>>,
+    /// Reports differing answers, see [`AnswerDiscrepancyPolicy::Flag`]
+    discrepancy_reporter: Arc<Mutex<Option<DiscrepancyReporter>>>,
 }
 
+/// Differing answers of two name servers to the same query, see [`AnswerDiscrepancyPolicy::Flag`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AnswerDiscrepancy {
+    /// The queries of the request
+    pub queries: Vec<Query>,
+    /// The name server of the response which is used
+    pub first_server: IpAddr,
+    /// The answers of the response which is used
+    pub first_answers: Vec<Record>,
+    /// The name server whose answers differ
+    pub other_server: IpAddr,
+    /// The differing answers
+    pub other_answers: Vec<Record>,
+}
+
+/// Callback reporting differing answers, see [`NameServerPool::set_discrepancy_reporter`]
+pub type DiscrepancyReporter = Arc<dyn Fn(&AnswerDiscrepancy) + Send + Sync>;
+
 /// A pool of NameServers
 ///
 /// This is not expected to be used directly, see [crate::AsyncResolver].
@@ -66,6 +100,8 @@ where
         options: ResolverOpts,
         conn_provider: P,
     ) -> Self {
+        let cookies = CookieJar::for_options(&options);
+        let global_rate = TokenBucket::for_options(&options);
         let datagram_conns: Vec<NameServer<P>> = config
             .name_servers()
             .iter()
@@ -81,6 +117,8 @@ where
                 let ns_config = { ns_config.clone() };
 
                 NameServer::new(ns_config, options.clone(), conn_provider.clone())
+                    .with_cookie_jar(cookies.clone())
+                    .with_global_rate(global_rate.clone())
             })
             .collect();
 
@@ -99,12 +137,17 @@ where
                 let ns_config = { ns_config.clone() };
 
                 NameServer::new(ns_config, options.clone(), conn_provider.clone())
+                    .with_cookie_jar(cookies.clone())
+                    .with_global_rate(global_rate.clone())
             })
             .collect();
 
         Self {
             datagram_conns: Arc::from(datagram_conns),
             stream_conns: Arc::from(stream_conns),
+            connectivity: Arc::new(PoolConnectivity::for_options(&options)),
+            global_rate,
+            discrepancy_reporter: Arc::default(),
             options,
         }
     }
@@ -115,8 +158,13 @@ where
         options: ResolverOpts,
         conn_provider: P,
     ) -> Self {
-        let map_config_to_ns =
-            |ns_config| NameServer::new(ns_config, options.clone(), conn_provider.clone());
+        let cookies = CookieJar::for_options(&options);
+        let global_rate = TokenBucket::for_options(&options);
+        let map_config_to_ns = |ns_config| {
+            NameServer::new(ns_config, options.clone(), conn_provider.clone())
+                .with_cookie_jar(cookies.clone())
+                .with_global_rate(global_rate.clone())
+        };
 
         let (datagram, stream): (Vec<_>, Vec<_>) = name_servers
             .into_inner()
@@ -129,6 +177,9 @@ where
         Self {
             datagram_conns: Arc::from(datagram_conns),
             stream_conns: Arc::from(stream_conns),
+            connectivity: Arc::new(PoolConnectivity::for_options(&options)),
+            global_rate,
+            discrepancy_reporter: Arc::default(),
             options,
         }
     }
@@ -139,9 +190,20 @@ where
         datagram_conns: Vec<NameServer<P>>,
         stream_conns: Vec<NameServer<P>>,
     ) -> Self {
+        let global_rate = TokenBucket::for_options(&options);
+        let share_rate = |conns: Vec<NameServer<P>>| {
+            conns
+                .into_iter()
+                .map(|conn| conn.with_global_rate(global_rate.clone()))
+                .collect::<Arc<[_]>>()
+        };
+
         Self {
-            datagram_conns: Arc::from(datagram_conns),
-            stream_conns: Arc::from(stream_conns),
+            datagram_conns: share_rate(datagram_conns),
+            stream_conns: share_rate(stream_conns),
+            connectivity: Arc::new(PoolConnectivity::for_options(&options)),
+            global_rate,
+            discrepancy_reporter: Arc::default(),
             options,
         }
     }
@@ -153,30 +215,137 @@ where
         datagram_conns: Arc<[NameServer<P>]>,
         stream_conns: Arc<[NameServer<P>]>,
     ) -> Self {
+        let global_rate = TokenBucket::for_options(&options);
+        let share_rate = |conns: Arc<[NameServer<P>]>| {
+            conns
+                .iter()
+                .map(|conn| conn.clone().with_global_rate(global_rate.clone()))
+                .collect::<Arc<[_]>>()
+        };
+
         Self {
-            datagram_conns,
-            stream_conns,
+            datagram_conns: share_rate(datagram_conns),
+            stream_conns: share_rate(stream_conns),
+            connectivity: Arc::new(PoolConnectivity::for_options(&options)),
+            global_rate,
+            discrepancy_reporter: Arc::default(),
             options,
         }
     }
 
+    /// Returns a snapshot of the circuit breakers of all name servers of this pool
+    ///
+    /// See [`ResolverOpts::circuit_breaker`].
+    pub fn circuit_breakers(&self) -> Vec<CircuitBreakerSnapshot> {
+        self.datagram_conns
+            .iter()
+            .chain(self.stream_conns.iter())
+            .map(NameServer::circuit_breaker_snapshot)
+            .collect()
+    }
+
+    /// Returns the number of queries which may currently be sent under the global rate
+    ///
+    /// Returns `None` if the rate isn't limited, see [`ResolverOpts::global_rate`].
+    pub fn global_tokens(&self) -> Option<u32> {
+        self.global_rate
+            .as_ref()
+            .map(|bucket| bucket.tokens(Instant::now()))
+    }
+
+    /// Overrides the detected local connectivity of this pool
+    ///
+    /// The connectivity is only used if [`ResolverOpts::skip_unreachable_address_families`] is set.
+    pub fn with_local_connectivity(mut self, connectivity: LocalConnectivity) -> Self {
+        self.connectivity = Arc::new(PoolConnectivity {
+            fixed: Some(connectivity),
+            ..PoolConnectivity::for_options(&self.options)
+        });
+        self
+    }
+
+    /// Sets the callback reporting differing answers of the name servers queried in parallel
+    ///
+    /// This is only called with [`AnswerDiscrepancyPolicy::Flag`], for each response whose answers
+    ///  differ from the first response. The reporter is shared by the clones of this pool.
+    pub fn set_discrepancy_reporter(&self, reporter: Option<DiscrepancyReporter>) {
+        *self.discrepancy_reporter.lock() = reporter;
+    }
+
+    /// Returns the slot of the discrepancy reporter, to set it after this pool is wrapped
+    pub(crate) fn discrepancy_reporter(&self) -> Arc<Mutex<Option<DiscrepancyReporter>>> {
+        Arc::clone(&self.discrepancy_reporter)
+    }
+
     async fn try_send(
         opts: ResolverOpts,
         conns: Arc<[NameServer<P>]>,
+        connectivity: Arc<PoolConnectivity>,
         request: DnsRequest,
+        stream: bool,
+        reporter: Option<DiscrepancyReporter>,
     ) -> Result<DnsResponse, ProtoError> {
         let mut conns: Vec<NameServer<P>> = conns.to_vec();
 
-        match opts.server_ordering_strategy {
-            // select the highest priority connection
-            //   reorder the connections based on current view...
-            //   this reorders the inner set
-            ServerOrderingStrategy::QueryStatistics => conns.sort_unstable(),
-            ServerOrderingStrategy::UserProvidedOrder => {}
+        // skip name servers which this host can't reach, unless none are left
+        let mixed_families = conns.iter().any(|conn| conn.ip().is_ipv4())
+            && conns.iter().any(|conn| conn.ip().is_ipv6());
+        let skip_unreachable = opts.skip_unreachable_address_families && mixed_families;
+        if let Some(local) = connectivity.get().filter(|_| skip_unreachable) {
+            if conns.iter().any(|conn| local.can_reach(conn.ip())) {
+                conns.retain(|conn| {
+                    let reachable = local.can_reach(conn.ip());
+                    if !reachable {
+                        debug!("skipping unreachable name server: {}", conn.ip());
+                    }
+                    reachable
+                });
+            }
         }
+
+        order_name_servers(&mut conns, &opts);
+        // skip name servers whose circuit breaker is open, failing if none is left
+        let now = Instant::now();
+        conns.retain(|conn| !conn.is_circuit_open(now));
+
         let request_loop = request.clone();
 
-        parallel_conn_loop(conns, request_loop, opts).await
+        let result = parallel_conn_loop(conns, request_loop, opts, stream, reporter).await;
+        if let Err(error) = &result {
+            if error.is_io()
+                || error.is_no_connections()
+                || matches!(error.kind(), ProtoErrorKind::Timeout)
+            {
+                connectivity.refresh();
+            }
+        }
+        result
+    }
+
+    /// Returns the local connectivity of this pool, shared with the resolver
+    pub(crate) fn local_connectivity(&self) -> Arc<PoolConnectivity> {
+        Arc::clone(&self.connectivity)
+    }
+}
+
+/// Orders the name servers per the server selection and ordering strategies of the options
+pub(super) fn order_name_servers<P: ConnectionProvider>(
+    conns: &mut [NameServer<P>],
+    opts: &ResolverOpts,
+) {
+    match (
+        opts.server_selection_strategy,
+        opts.server_ordering_strategy,
+    ) {
+        // the sorts are stable, name servers of equal weight or latency keep the user order
+        (ServerSelectionStrategy::Weighted, _) => conns.sort_by_key(|conn| Reverse(conn.weight())),
+        // name servers are ordered by their decayed SRTT, see `NameServerStats`
+        (ServerSelectionStrategy::LeastLatency, _) => conns.sort(),
+        // select the highest priority connection
+        //   reorder the connections based on current view...
+        //   this reorders the inner set
+        (_, ServerOrderingStrategy::QueryStatistics) => conns.sort_unstable(),
+        (_, ServerOrderingStrategy::UserProvidedOrder) => {}
     }
 }
 
@@ -188,9 +357,14 @@ where
 
     fn send<R: Into<DnsRequest>>(&self, request: R) -> Self::Response {
         let opts = self.options.clone();
-        let request = request.into();
+        let connectivity = Arc::clone(&self.connectivity);
+        let mut request = request.into();
+        add_edns_options(&mut request, &opts.edns_options);
+
         let datagram_conns = Arc::clone(&self.datagram_conns);
         let stream_conns = Arc::clone(&self.stream_conns);
+        let reporter = self.discrepancy_reporter.lock().clone();
+        let deadline = request.options().deadline;
         // TODO: remove this clone, return the Message in the error?
         let tcp_message = request.clone();
 
@@ -206,34 +380,74 @@ where
 
         // it wasn't a local query, continue with standard lookup path
         let request = mdns.take_request();
-        Box::pin(once(async move {
+        let send = async move {
             debug!("sending request: {:?}", request.queries());
 
-            // First try the UDP connections
+            // First try the UDP connections, unless only TCP is used
             let udp_res: Result<DnsResponse, ProtoError> =
-                match Self::try_send(opts.clone(), datagram_conns, request).await {
-                    Ok(response) if response.truncated() => {
-                        debug!("truncated response received, retrying over TCP");
-                        Ok(response)
-                    }
-                    Err(e) if (opts.try_tcp_on_error && e.is_io()) || e.is_no_connections() => {
-                        debug!("error from UDP, retrying over TCP: {}", e);
-                        Err(e)
+                if opts.protocol_preference == ProtocolPreference::TcpOnly {
+                    debug!("TCP only, skipping UDP");
+                    Err(ProtoError::from(ProtoErrorKind::NoConnections))
+                } else {
+                    match Self::try_send(
+                        opts.clone(),
+                        datagram_conns,
+                        connectivity.clone(),
+                        request,
+                        false,
+                        reporter.clone(),
+                    )
+                    .await
+                    {
+                        Ok(response) if response.truncated() => {
+                            debug!("truncated response received, retrying over TCP");
+                            Ok(response)
+                        }
+                        Err(e) if (opts.try_tcp_on_error && e.is_io()) || e.is_no_connections() => {
+                            debug!("error from UDP, retrying over TCP: {}", e);
+                            Err(e)
+                        }
+                        Err(e) if opts.retry_servfail_other_transport && e.is_serv_fail() => {
+                            debug!("SERVFAIL from UDP, retrying over TCP");
+                            Err(e)
+                        }
+                        Err(e) if e.is_oversized_udp_response() => {
+                            debug!("oversized response received, retrying over TCP");
+                            Err(e)
+                        }
+                        result => return result,
                     }
-                    result => return result.map_err(ProtoError::from),
                 };
 
             if stream_conns.is_empty() {
                 debug!("no TCP connections available");
-                return udp_res.map_err(ProtoError::from);
+                return udp_res;
             }
 
             // Try query over TCP, as response to query over UDP was either truncated or was an
             // error.
-            let tcp_res = Self::try_send(opts, stream_conns, tcp_message).await;
+            let tcp_res = Self::try_send(
+                opts,
+                stream_conns,
+                connectivity,
+                tcp_message,
+                true,
+                reporter,
+            )
+            .await;
 
             let tcp_err = match tcp_res {
-                res @ Ok(..) => return res.map_err(ProtoError::from),
+                res @ Ok(..) => return res,
+                // never fallback to the partial UDP data in this case
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        ProtoErrorKind::TruncatedStreamResponse
+                            | ProtoErrorKind::OversizedStreamResponse { .. }
+                    ) =>
+                {
+                    return Err(e)
+                }
                 Err(e) => e,
             };
 
@@ -247,16 +461,151 @@ where
                 Ordering::Greater => Err(udp_err),
                 _ => Err(tcp_err),
             }
+        };
+
+        let Some(deadline) = deadline else {
+            return Box::pin(once(send));
+        };
+
+        // the whole exchange, including retries over the other name servers and TCP, is bounded
+        Box::pin(once(async move {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            <<P as ConnectionProvider>::RuntimeProvider as RuntimeProvider>::Timer::timeout(
+                remaining, send,
+            )
+            .await
+            .unwrap_or_else(|_| {
+                debug!("deadline exceeded, request timed out");
+                Err(ProtoError::from(ProtoErrorKind::Timeout))
+            })
+        }))
+    }
+}
+
+/// The address families over which this host has connectivity
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LocalConnectivity {
+    /// This host has a route to IPv4 addresses
+    pub ipv4: bool,
+    /// This host has a route to IPv6 addresses
+    pub ipv6: bool,
+}
+
+impl LocalConnectivity {
+    /// Detects the local connectivity, by checking for a route to an address of each family
+    ///
+    /// No packets are sent, connecting a UDP socket only performs the route lookup.
+    pub fn detect() -> Self {
+        fn has_route(probe: IpAddr) -> bool {
+            let unspecified = match probe {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            };
+
+            UdpSocket::bind((unspecified, 0))
+                .and_then(|socket| socket.connect((probe, 53)))
+                .is_ok()
+        }
+
+        // documentation addresses, RFC 5737 and RFC 3849, only the route to them matters
+        Self {
+            ipv4: has_route(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))),
+            ipv6: has_route(IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))),
+        }
+    }
+
+    /// Returns true if this host is expected to be able to reach the address
+    ///
+    /// Loopback addresses are always considered reachable.
+    pub fn can_reach(&self, ip: IpAddr) -> bool {
+        if ip.is_loopback() {
+            return true;
+        }
+
+        match ip {
+            IpAddr::V4(_) => self.ipv4,
+            IpAddr::V6(_) => self.ipv6,
+        }
+    }
+}
+
+impl Default for LocalConnectivity {
+    /// Assumes connectivity over both address families
+    fn default() -> Self {
+        Self {
+            ipv4: true,
+            ipv6: true,
+        }
+    }
+}
+
+/// The local connectivity of a pool, see [`ResolverOpts::skip_unreachable_address_families`] and
+///  [`ResolverOpts::aaaa_requires_ipv6_route`]
+///
+/// The connectivity is only detected once needed, and detected again after queries failed, e.g.
+///  as the network changed.
+#[derive(Debug)]
+pub(crate) struct PoolConnectivity {
+    /// The connectivity is never detected over TCP only, which must not create any UDP socket
+    enabled: bool,
+    /// Overrides the detection, see [`NameServerPool::with_local_connectivity`]
+    fixed: Option<LocalConnectivity>,
+    /// The connectivity detected when first needed, until it is refreshed
+    detected: Mutex<Option<LocalConnectivity>>,
+}
+
+impl PoolConnectivity {
+    fn for_options(options: &ResolverOpts) -> Self {
+        Self {
+            enabled: (options.skip_unreachable_address_families
+                || options.aaaa_requires_ipv6_route)
+                && options.protocol_preference != ProtocolPreference::TcpOnly,
+            fixed: None,
+            detected: Mutex::new(None),
+        }
+    }
+
+    /// Returns the local connectivity, detecting it if needed, or `None` if it isn't used
+    pub(crate) fn get(&self) -> Option<LocalConnectivity> {
+        if !self.enabled {
+            return None;
+        }
+        if let Some(fixed) = self.fixed {
+            return Some(fixed);
+        }
+
+        Some(*self.detected.lock().get_or_insert_with(|| {
+            let detected = LocalConnectivity::detect();
+            debug!("detected local connectivity: {:?}", detected);
+            detected
         }))
     }
+
+    /// Returns false if this host is known to have no route to IPv6 addresses
+    pub(crate) fn has_ipv6_route(&self) -> bool {
+        match self.get() {
+            Some(local) => local.ipv6,
+            None => true,
+        }
+    }
+
+    /// Forgets the detected connectivity, it is detected again when next needed
+    fn refresh(&self) {
+        self.detected.lock().take();
+    }
 }
 
 // TODO: we should be able to have a self-referential future here with Pin and not require cloned conns
 /// An async function that will loop over all the conns with a max parallel request count of ops.num_concurrent_req
+///
+/// `stream` should be true for stream connections, on which truncated responses, and responses
+///  larger than the `max_response_size` of the options, are rejected.
 async fn parallel_conn_loop<P>(
     mut conns: Vec<NameServer<P>>,
     request: DnsRequest,
     opts: ResolverOpts,
+    stream: bool,
+    reporter: Option<DiscrepancyReporter>,
 ) -> Result<DnsResponse, ProtoError>
 where
     P: ConnectionProvider + 'static,
@@ -281,10 +630,19 @@ where
 
         // construct the parallel requests, 2 is the default
         let mut par_conns = SmallVec::<[NameServer<P>; 2]>::new();
-        let count = conns.len().min(opts.num_concurrent_reqs.max(1));
+        let concurrency = match opts.server_selection_strategy {
+            ServerSelectionStrategy::Parallel => opts.num_concurrent_reqs.max(1),
+            ServerSelectionStrategy::Sequential
+            | ServerSelectionStrategy::Weighted
+            | ServerSelectionStrategy::LeastLatency => 1,
+        };
+        let count = conns.len().min(concurrency);
 
-        // Shuffe DNS NameServers to avoid overloads to the first configured ones
-        if opts.shuffle_dns_servers {
+        // Shuffe DNS NameServers to avoid overloads to the first configured ones, the other
+        //  strategies have already sorted them
+        let shuffle = opts.shuffle_dns_servers
+            && opts.server_selection_strategy == ServerSelectionStrategy::Parallel;
+        if shuffle {
             for _ in 0..count {
                 let idx = rng().gen_range(0..conns.len());
 
@@ -300,6 +658,14 @@ where
         }
 
         if par_conns.is_empty() {
+            // waiting for the busy name servers is pointless once the global rate is exceeded
+            if busy
+                .iter()
+                .any(|conn| conn.is_rate_exceeded(Instant::now()))
+            {
+                debug!("global query rate exceeded, rejecting request");
+                return Err(ProtoErrorKind::Busy.into());
+            }
             if !busy.is_empty() && backoff < Duration::from_millis(300) {
                 <<P as ConnectionProvider>::RuntimeProvider as RuntimeProvider>::Timer::delay_for(
                     backoff,
@@ -312,27 +678,88 @@ where
             return Err(err);
         }
 
+        let max_size = opts.max_response_size;
         let mut requests = par_conns
             .into_iter()
             .map(move |conn| {
+                let ip = conn.ip();
                 conn.send(request_cont.clone())
                     .first_answer()
-                    .map(|result| result.map_err(|e| (conn, e)))
+                    .map(move |result| {
+                        let result = match result {
+                            Ok(response) if stream => check_stream_response(response, max_size),
+                            result => result,
+                        };
+                        // the failed name server is boxed to keep the error variant small
+                        result
+                            .map(|response| (ip, response))
+                            .map_err(|e| Box::new((conn, e)))
+                    })
             })
             .collect::<FuturesUnordered<_>>();
 
+        // the response used, while the other responses of this round are compared to it. Except
+        //  with `First`, every response of this round is awaited, and the responses of the next
+        //  rounds, after failures, are not compared
+        let mut answer = None::<(IpAddr, DnsResponse)>;
         while let Some(result) = requests.next().await {
             let (conn, e) = match result {
-                Ok(sent) => return Ok(sent),
-                Err((conn, e)) => (conn, e),
+                Ok((ip, response)) => {
+                    let Some((first_ip, first)) = &answer else {
+                        if opts.answer_discrepancy_policy == AnswerDiscrepancyPolicy::First {
+                            return Ok(response);
+                        }
+                        answer = Some((ip, response));
+                        continue;
+                    };
+
+                    if same_records(first.answers(), response.answers()) {
+                        continue;
+                    }
+
+                    debug!("answers of {} differ from the answers of {}", ip, first_ip);
+                    match opts.answer_discrepancy_policy {
+                        AnswerDiscrepancyPolicy::PreferDnssecValidated
+                            if !first.authentic_data() && response.authentic_data() =>
+                        {
+                            answer = Some((ip, response));
+                        }
+                        AnswerDiscrepancyPolicy::Flag => {
+                            if let Some(reporter) = &reporter {
+                                reporter(&AnswerDiscrepancy {
+                                    queries: first.queries().to_vec(),
+                                    first_server: *first_ip,
+                                    first_answers: first.answers().to_vec(),
+                                    other_server: ip,
+                                    other_answers: response.answers().to_vec(),
+                                });
+                            }
+                        }
+                        _ => {}
+                    }
+                    continue;
+                }
+                Err(failed) => *failed,
             };
 
+            // the errors of the other name servers don't matter once a response is received
+            if answer.is_some() {
+                continue;
+            }
+
             match e.kind() {
                 ProtoErrorKind::NoRecordsFound {
                     trusted, soa, ns, ..
                 } if *trusted || soa.is_some() || ns.is_some() => {
                     return Err(e);
                 }
+                ProtoErrorKind::TruncatedStreamResponse
+                | ProtoErrorKind::OversizedStreamResponse { .. }
+                    if opts.truncated_tcp_policy == TruncatedTcpPolicy::Fail =>
+                {
+                    debug!("truncated or oversized response over a stream connection, failing");
+                    return Err(e);
+                }
                 _ if e.is_busy() => {
                     busy.push(conn);
                 }
@@ -348,6 +775,28 @@ where
                 _ => {}
             }
         }
+
+        if let Some((_, response)) = answer {
+            return Ok(response);
+        }
+    }
+}
+
+/// Rejects a response received over a stream connection which only contains partial data
+fn check_stream_response(
+    response: DnsResponse,
+    max_size: Option<usize>,
+) -> Result<DnsResponse, ProtoError> {
+    if response.truncated() {
+        return Err(ProtoErrorKind::TruncatedStreamResponse.into());
+    }
+
+    let received = response.as_buffer().len();
+    match max_size {
+        Some(max) if received > max => {
+            Err(ProtoErrorKind::OversizedStreamResponse { max, received }.into())
+        }
+        _ => Ok(response),
     }
 }
 
@@ -400,6 +849,19 @@ impl Stream for Local {
     }
 }
 
+/// Adds the custom EDNS options, see [`ResolverOpts::edns_options`]
+fn add_edns_options(request: &mut DnsRequest, options: &[(u16, Vec<u8>)]) {
+    if options.is_empty() {
+        return;
+    }
+
+    let edns = request.extensions_mut().get_or_insert_with(Edns::new);
+    for (code, data) in options {
+        edns.options_mut()
+            .insert(EdnsOption::Unknown(*code, data.clone()));
+    }
+}
+
 #[cfg(test)]
 #[cfg(feature = "tokio-runtime")]
 mod tests {
@@ -431,6 +893,8 @@ mod tests {
             #[cfg(feature = "dns-over-rustls")]
             tls_config: None,
             bind_addr: None,
+            timeout: None,
+            weight: 0,
         };
 
         let config2 = NameServerConfig {
@@ -442,6 +906,8 @@ mod tests {
             #[cfg(feature = "dns-over-rustls")]
             tls_config: None,
             bind_addr: None,
+            timeout: None,
+            weight: 0,
         };
 
         let mut resolver_config = ResolverConfig::new();
@@ -505,6 +971,8 @@ mod tests {
             #[cfg(feature = "dns-over-rustls")]
             tls_config: None,
             bind_addr: None,
+            timeout: None,
+            weight: 0,
         };
 
         let opts = ResolverOpts {
@@ -571,4 +1039,146 @@ mod tests {
             "if this is failing then the NameServers aren't being properly shared."
         );
     }
+
+    #[test]
+    fn test_add_edns_options() {
+        use crate::proto::op::Message;
+        use crate::proto::rr::rdata::opt::EdnsCode;
+
+        let mut request = DnsRequest::new(Message::new(), DnsRequestOptions::default());
+        add_edns_options(&mut request, &[]);
+        assert!(request.extensions().is_none());
+
+        add_edns_options(&mut request, &[(65001, vec![1, 2, 3])]);
+        let edns = request.extensions().as_ref().expect("no OPT record");
+        assert_eq!(
+            edns.option(EdnsCode::Unknown(65001)),
+            Some(&EdnsOption::Unknown(65001, vec![1, 2, 3]))
+        );
+
+        // the option is emitted in the OPT record of the message
+        let message = Message::from_vec(&request.to_vec().unwrap()).unwrap();
+        let edns = message.extensions().as_ref().expect("no OPT record");
+        assert_eq!(
+            edns.option(EdnsCode::Unknown(65001)),
+            Some(&EdnsOption::Unknown(65001, vec![1, 2, 3]))
+        );
+    }
+
+    #[test]
+    fn test_oversized_udp_response_retried_over_tcp() {
+        use std::io::{Read, Write};
+
+        use crate::config::OversizedUdpPolicy;
+        use crate::proto::op::{Message, MessageType};
+        use crate::proto::rr::{rdata::A, RData, Record};
+
+        // answers the request with as many A records as requested
+        let respond = |request: &[u8], count: u8| {
+            let request = Message::from_vec(request).unwrap();
+            let name = request.queries()[0].name().clone();
+            let mut response = Message::new();
+            response
+                .set_id(request.id())
+                .set_message_type(MessageType::Response)
+                .set_recursion_desired(true)
+                .set_recursion_available(true)
+                .add_queries(request.queries().to_vec());
+            for i in 0..count {
+                let rdata = RData::A(A::new(192, 0, 2, i));
+                response.add_answer(Record::from_rdata(name.clone(), 300, rdata));
+            }
+            response.to_vec().unwrap()
+        };
+
+        // the UDP response exceeds the 512 bytes advertised without EDNS
+        let udp = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let udp_addr = udp.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            while let Ok((len, src)) = udp.recv_from(&mut buf) {
+                udp.send_to(&respond(&buf[..len], 64), src).unwrap();
+            }
+        });
+
+        let tcp = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let tcp_addr = tcp.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for mut stream in tcp.incoming().map_while(Result::ok) {
+                let mut len = [0u8; 2];
+                while stream.read_exact(&mut len).is_ok() {
+                    let mut request = vec![0u8; usize::from(u16::from_be_bytes(len))];
+                    stream.read_exact(&mut request).unwrap();
+                    let response = respond(&request, 1);
+                    stream
+                        .write_all(&(response.len() as u16).to_be_bytes())
+                        .unwrap();
+                    stream.write_all(&response).unwrap();
+                }
+            }
+        });
+
+        let mut config = ResolverConfig::new();
+        config.add_name_server(NameServerConfig::new(udp_addr, Protocol::Udp));
+        config.add_name_server(NameServerConfig::new(tcp_addr, Protocol::Tcp));
+
+        let io_loop = Runtime::new().unwrap();
+        let name = Name::from_str("www.example.com.").unwrap();
+        let answers = |oversized_udp_policy| {
+            let options = ResolverOpts {
+                oversized_udp_policy,
+                ..ResolverOpts::default()
+            };
+            let pool = GenericNameServerPool::tokio_from_config(
+                &config,
+                options,
+                TokioRuntimeProvider::new(),
+            );
+            let response = io_loop
+                .block_on(
+                    pool.lookup(
+                        Query::query(name.clone(), RecordType::A),
+                        DnsRequestOptions::default(),
+                    )
+                    .first_answer(),
+                )
+                .expect("lookup failed");
+            response.answers().len()
+        };
+
+        // the oversized response is accepted by default, and retried over TCP otherwise
+        assert_eq!(answers(OversizedUdpPolicy::Accept), 64);
+        assert_eq!(answers(OversizedUdpPolicy::Retry), 1);
+    }
+
+    #[test]
+    fn test_pool_connectivity() {
+        // the connectivity isn't used by default
+        let mut options = ResolverOpts::default();
+        assert_eq!(PoolConnectivity::for_options(&options).get(), None);
+
+        // nor over TCP only, where no UDP socket may be created for the detection
+        options.skip_unreachable_address_families = true;
+        options.protocol_preference = ProtocolPreference::TcpOnly;
+        assert_eq!(PoolConnectivity::for_options(&options).get(), None);
+
+        options.protocol_preference = ProtocolPreference::default();
+        let fixed = LocalConnectivity {
+            ipv4: true,
+            ipv6: false,
+        };
+        let connectivity = PoolConnectivity {
+            fixed: Some(fixed),
+            ..PoolConnectivity::for_options(&options)
+        };
+        assert_eq!(connectivity.get(), Some(fixed));
+
+        // the detected connectivity is kept until refreshed
+        let connectivity = PoolConnectivity::for_options(&options);
+        *connectivity.detected.lock() = Some(fixed);
+        assert_eq!(connectivity.get(), Some(fixed));
+        assert!(!connectivity.has_ipv6_route());
+        connectivity.refresh();
+        assert!(connectivity.detected.lock().is_none());
+    }
 }
@@ -16,6 +16,9 @@ use futures_util::lock::Mutex;
 pub(crate) struct NameServerState {
     conn_state: AtomicU8,
     remote_edns: Mutex<Arc<Option<Edns>>>,
+    /// The remote responded without EDNS to a query with EDNS, it is considered not to support
+    ///  EDNS until this instant, this survives reconnections
+    edns_unsupported_until: parking_lot::Mutex<Option<Instant>>,
 }
 
 /// State of a connection with a remote NameServer.
@@ -70,6 +73,7 @@ impl NameServerState {
         Self {
             conn_state: AtomicU8::new(NameServerStateInner::Init.into()),
             remote_edns: Mutex::new(Arc::new(None)),
+            edns_unsupported_until: parking_lot::Mutex::new(None),
         }
     }
 
@@ -98,6 +102,26 @@ impl NameServerState {
         self.store(NameServerStateInner::Established);
     }
 
+    /// Remembers that the remote doesn't support EDNS, until the instant
+    pub(crate) fn set_edns_unsupported(&self, until: Instant) {
+        *self.edns_unsupported_until.lock() = Some(until);
+    }
+
+    /// True if the remote was found not to support EDNS, see [`Self::set_edns_unsupported`]
+    ///
+    /// Once the downgrade expired, the remote is probed with EDNS again.
+    pub(crate) fn is_edns_unsupported(&self, now: Instant) -> bool {
+        let mut until = self.edns_unsupported_until.lock();
+        match *until {
+            Some(until) if now < until => true,
+            Some(_) => {
+                *until = None;
+                false
+            }
+            None => false,
+        }
+    }
+
     /// transition to the Failed state
     ///
     /// when is the time of the failure
@@ -149,6 +173,8 @@ impl Eq for NameServerState {}
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use super::*;
     use crate::name_server::NameServerState;
 
@@ -169,4 +195,20 @@ mod tests {
         assert_eq!(established.cmp(&failed), Ordering::Greater);
         assert_eq!(failed.cmp(&failed), Ordering::Equal);
     }
+
+    #[test]
+    fn test_edns_unsupported() {
+        let now = Instant::now();
+        let state = NameServerState::init(None);
+        assert!(!state.is_edns_unsupported(now));
+
+        let reprobe_after = Duration::from_secs(60);
+        state.set_edns_unsupported(now + reprobe_after);
+        assert!(state.is_edns_unsupported(now));
+        assert!(state.is_edns_unsupported(now + Duration::from_secs(59)));
+
+        // EDNS is sent again once the downgrade expired
+        assert!(!state.is_edns_unsupported(now + reprobe_after));
+        assert!(!state.is_edns_unsupported(now));
+    }
 }
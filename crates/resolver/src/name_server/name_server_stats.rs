@@ -119,7 +119,7 @@ impl NameServerStats {
     /// Returns the raw SRTT value.
     ///
     /// Prefer to use `decayed_srtt` when ordering name servers.
-    fn srtt(&self) -> Duration {
+    pub(crate) fn srtt(&self) -> Duration {
         Duration::from_micros(u64::from(
             self.srtt_microseconds.load(atomic::Ordering::Acquire),
         ))
@@ -0,0 +1,73 @@
+//! Limit of the rate of queries sent to the name servers, see [`TokenBucket`]
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use parking_lot::Mutex;
+
+use crate::config::{Rate, ResolverOpts};
+
+/// Limits the rate of queries, shared by all the name servers of a pool
+///
+/// The bucket holds up to `limit` tokens and starts full. Each query takes a token, tokens are
+///  refilled continuously so that `limit` tokens are added over each `period`.
+pub(crate) struct TokenBucket {
+    rate: Rate,
+    inner: Mutex<TokenBucketInner>,
+}
+
+struct TokenBucketInner {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// The shared bucket of the global rate of the options, if any
+    pub(crate) fn for_options(options: &ResolverOpts) -> Option<Arc<Self>> {
+        options
+            .global_rate
+            .map(|rate| Arc::new(Self::new(rate, Instant::now())))
+    }
+
+    pub(crate) fn new(rate: Rate, now: Instant) -> Self {
+        Self {
+            rate,
+            inner: Mutex::new(TokenBucketInner {
+                tokens: f64::from(rate.limit),
+                last_refill: now,
+            }),
+        }
+    }
+
+    /// Returns true if a query may be sent, taking a token
+    pub(crate) fn try_acquire(&self, now: Instant) -> bool {
+        let mut inner = self.inner.lock();
+        self.refill(&mut inner, now);
+
+        if inner.tokens < 1.0 {
+            return false;
+        }
+
+        inner.tokens -= 1.0;
+        true
+    }
+
+    /// Returns the number of queries which may currently be sent
+    pub(crate) fn tokens(&self, now: Instant) -> u32 {
+        let mut inner = self.inner.lock();
+        self.refill(&mut inner, now);
+        inner.tokens as u32
+    }
+
+    fn refill(&self, inner: &mut TokenBucketInner, now: Instant) {
+        let elapsed = now.saturating_duration_since(inner.last_refill);
+        inner.last_refill = inner.last_refill.max(now);
+
+        let limit = f64::from(self.rate.limit);
+        let refilled = match self.rate.period.as_secs_f64() {
+            period if period > 0.0 => elapsed.as_secs_f64() / period * limit,
+            _ => limit,
+        };
+        inner.tokens = (inner.tokens + refilled).min(limit);
+    }
+}
@@ -6,26 +6,43 @@
 // copied, modified, or distributed except according to those terms.
 
 //! Structs for creating and using a AsyncResolver
+use std::collections::HashMap;
 use std::fmt;
+use std::future::Future;
 use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 
-use tracing::{debug, trace};
+use parking_lot::{Mutex, RwLock};
 
-use crate::caching_client::CachingClient;
-use crate::config::{ResolveHosts, ResolverConfig, ResolverOpts};
-use crate::dns_lru::{self, DnsLru};
-use crate::error::ResolveError;
+use tracing::instrument::Instrumented;
+use tracing::{debug, debug_span, trace, Instrument, Span};
+
+use crate::caching_client::{CachingClient, RedactionPolicy, ResponseLogger, ResponseRewriter};
+use crate::config::{
+    AddressFamilyFilter, IpFamilyPreference, LookupContext, LookupOptions, RecordFilter,
+    ResolveHosts, ResolverConfig, ResolverOpts, StaleMode,
+};
+use crate::dns_lru::{self, DnsCache, DnsLru};
+use crate::error::{NegativeResponse, ResolveError, ResolveErrorKind};
 use crate::hosts::Hosts;
-use crate::lookup::{self, Lookup, LookupEither, LookupFuture};
-use crate::lookup_ip::{LookupIp, LookupIpFuture};
+use crate::lookup::{self, Lookup, LookupEither, LookupFuture, TypedRecordData};
+use crate::lookup_ip::{routed_strategy, LookupIp, LookupIpFuture};
 #[cfg(feature = "tokio-runtime")]
 use crate::name_server::TokioConnectionProvider;
-use crate::name_server::{ConnectionProvider, NameServerPool};
-use crate::proto::op::Query;
+use crate::name_server::{
+    ConnectionProvider, DiscrepancyReporter, NameServerPool, PoolConnectivity,
+};
+use crate::proto::error::{ProtoError, ProtoErrorKind};
+use crate::proto::op::{Query, ResponseCode};
 use crate::proto::rr::domain::usage::ONION;
+use crate::proto::rr::rdata::SRV;
 use crate::proto::rr::{IntoName, Name, RData, Record, RecordType};
+#[cfg(feature = "dnssec")]
+use crate::proto::xfer::DnssecDnsHandle;
 use crate::proto::xfer::{DnsRequestOptions, RetryDnsHandle};
+use crate::timing::{LookupTimer, LookupTiming};
 
 /// An asynchronous resolver for DNS generic over async Runtimes.
 ///
@@ -46,8 +63,25 @@ pub struct Resolver<P: ConnectionProvider> {
     options: ResolverOpts,
     client_cache: CachingClient<LookupEither<P>>,
     hosts: Option<Arc<Hosts>>,
+    discrepancy_reporter: Arc<Mutex<Option<DiscrepancyReporter>>>,
+    /// The local connectivity detected by the name server pool
+    connectivity: Arc<PoolConnectivity>,
+    /// Validates the lookups requiring DNSSEC secure records, if this resolver doesn't validate
+    ///  others, see [`LookupOptions::require_secure`]
+    #[cfg(feature = "dnssec")]
+    secure_client: Option<LookupEither<P>>,
+    /// The predicates of the registered record filters, see [`Resolver::add_record_filter`]
+    record_filters: Arc<RwLock<HashMap<RecordFilter, RecordPredicate>>>,
+    /// Correlation data attached to every lookup, see [`Resolver::with_context`]
+    context: Option<LookupContext>,
 }
 
+/// A predicate on the data of the records returned by a lookup, see [`RecordFilter`]
+type RecordPredicate = Arc<dyn Fn(&RData) -> bool + Send + Sync>;
+
+/// The identifier of the next registered record filter, unique across resolvers
+static NEXT_RECORD_FILTER: AtomicU64 = AtomicU64::new(0);
+
 /// An AsyncResolver used with Tokio
 #[cfg(feature = "tokio-runtime")]
 pub type TokioResolver = Resolver<TokioConnectionProvider>;
@@ -121,6 +155,41 @@ impl<R: ConnectionProvider> Resolver<R> {
         Self::new_with_conn(config, options, provider)
     }
 
+    /// Constructs a new `Resolver`, failing if it has no way to resolve names
+    ///
+    /// If the config has no name servers, the fallback name servers of
+    ///  [`ResolverOpts::empty_name_servers`] are used. Without a fallback, an error of kind
+    ///  [`ResolveErrorKind::NoNameServers`](crate::error::ResolveErrorKind::NoNameServers) is
+    ///  returned unless the hosts file is used.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - configuration, name_servers, etc. for the Resolver
+    /// * `options` - basic lookup options for the resolver
+    /// * `provider` - connection provider, for DNS connections, I/O, and timers
+    pub fn try_new(
+        mut config: ResolverConfig,
+        options: ResolverOpts,
+        provider: R,
+    ) -> Result<Self, ResolveError> {
+        if config.name_servers().is_empty() {
+            match options.empty_name_servers.fallback() {
+                Some(name_servers) => {
+                    debug!("no name servers configured, using the fallback name servers");
+                    for name_server in name_servers.iter() {
+                        config.add_name_server(name_server.clone());
+                    }
+                }
+                None if options.use_hosts_file == ResolveHosts::Never => {
+                    return Err(ResolveErrorKind::NoNameServers.into());
+                }
+                None => {}
+            }
+        }
+
+        Ok(Self::new_with_conn(config, options, provider))
+    }
+
     /// Constructs a new Resolver with the system configuration.
     ///
     /// see [TokioAsyncResolver::tokio_from_system_conf(..)] instead.
@@ -157,15 +226,58 @@ impl<P: ConnectionProvider> Resolver<P> {
     /// * `options` - basic lookup options for the resolver
     /// * `conn_provider` - connection provider, for DNS connections, I/O, and timers
     pub fn new_with_conn(config: ResolverConfig, options: ResolverOpts, conn_provider: P) -> Self {
-        let pool =
-            NameServerPool::from_config_with_provider(&config, options.clone(), conn_provider);
+        let cache = DnsLru::new(options.cache_size, dns_lru::TtlConfig::from_opts(&options))
+            .with_negative_excluded_types(options.negative_cache_excluded_types.iter().copied())
+            .with_min_cached_records(options.min_cached_records.clone());
+        Self::new_with_cache(config, options, conn_provider, cache)
+    }
+
+    /// Constructs a new `Resolver` storing its lookups in a cache owned by the caller
+    ///
+    /// The cache may be shared by several resolvers, the lookups of each one are then served from
+    ///  the cache to the others. The cache keeps its own configuration, so the cache options of
+    ///  `options` are ignored: `cache_size`, the options read by
+    ///  [`TtlConfig::from_opts`](dns_lru::TtlConfig::from_opts), e.g. the TTL bounds, `rotate` and
+    ///  `serve_stale_ttl`, as well as `negative_cache_excluded_types` and `min_cached_records`. The
+    ///  `cache_size` still bounds the cache of the DS records used for DNSSEC validation.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - configuration, name_servers, etc. for the Resolver
+    /// * `options` - basic lookup options for the resolver
+    /// * `conn_provider` - connection provider, for DNS connections, I/O, and timers
+    /// * `cache` - the cache of the lookups of the resolver
+    pub fn new_with_cache(
+        config: ResolverConfig,
+        options: ResolverOpts,
+        conn_provider: P,
+        cache: DnsLru,
+    ) -> Self {
+        let pool = NameServerPool::from_config_with_provider(
+            &config,
+            options.clone(),
+            conn_provider.clone(),
+        );
+        let discrepancy_reporter = pool.discrepancy_reporter();
+        let connectivity = pool.local_connectivity();
         let either;
         let client = RetryDnsHandle::new(pool, options.attempts);
+        #[cfg(feature = "dnssec")]
+        let secure_client = (!options.validate).then(|| {
+            LookupEither::Secure(
+                DnssecDnsHandle::new(client.clone())
+                    .with_verification_offload(options.dnssec_verification_offload)
+                    .with_ds_cache(options.cache_size, cache.positive_max_ttl()),
+            )
+        });
         if options.validate {
             #[cfg(feature = "dnssec")]
             {
-                use crate::proto::xfer::DnssecDnsHandle;
-                either = LookupEither::Secure(DnssecDnsHandle::new(client));
+                either = LookupEither::Secure(
+                    DnssecDnsHandle::new(client)
+                        .with_verification_offload(options.dnssec_verification_offload)
+                        .with_ds_cache(options.cache_size, cache.positive_max_ttl()),
+                );
             }
 
             #[cfg(not(feature = "dnssec"))]
@@ -184,12 +296,30 @@ impl<P: ConnectionProvider> Resolver<P> {
         };
 
         trace!("handle passed back");
-        let lru = DnsLru::new(options.cache_size, dns_lru::TtlConfig::from_opts(&options));
+        let mut client_cache =
+            CachingClient::with_cache(cache, either, options.preserve_intermediates)
+                .with_min_query_interval(options.min_query_interval)
+                .with_bogus_ttl(options.bogus_cache_ttl)
+                .with_strict_glue(options.strict_glue)
+                .with_max_cname_follow_ups(options.max_cname_follow_ups)
+                .with_nodata_as_nxdomain(options.nodata_as_nxdomain)
+                .with_shared_client_subnet(options.edns_client_subnet)
+                .with_background_refresh(move |task| conn_provider.spawn_bg(task));
+        if let StaleMode::StaleWhileRevalidate { grace } = options.stale_mode {
+            client_cache = client_cache.with_stale_while_revalidate(grace);
+        }
+
         Self {
             config,
-            client_cache: CachingClient::with_cache(lru, either, options.preserve_intermediates),
+            client_cache,
             options,
             hosts,
+            discrepancy_reporter,
+            connectivity,
+            #[cfg(feature = "dnssec")]
+            secure_client,
+            record_filters: Arc::default(),
+            context: None,
         }
     }
 
@@ -200,7 +330,33 @@ impl<P: ConnectionProvider> Resolver<P> {
     #[cfg(feature = "system-config")]
     pub fn from_system_conf_with_provider(conn_provider: P) -> Result<Self, ResolveError> {
         let (config, options) = super::system_conf::read_system_conf()?;
-        Ok(Self::new_with_conn(config, options, conn_provider))
+        Self::try_new(config, options, conn_provider)
+    }
+
+    /// Returns a clone of this resolver whose lookups are attached to the context
+    ///
+    /// The clone shares the cache and the name servers of this resolver. Every lookup method of the
+    ///  clone runs in a `lookup` span recording the context, see [`LookupContext`], which
+    ///  encloses the events of the cache and of the name servers. [`LookupOptions::context`]
+    ///  takes precedence for a single lookup.
+    pub fn with_context(&self, context: LookupContext) -> Self {
+        Self {
+            context: Some(context),
+            ..self.clone()
+        }
+    }
+
+    /// Runs the lookup in the span of the context of this resolver, if any
+    fn in_context<F: Future>(&self, lookup: F) -> Instrumented<F> {
+        let span = match self.context {
+            Some(context) => debug_span!(
+                "lookup",
+                trace_id = %format_args!("{:032x}", context.trace_id),
+                span_id = %format_args!("{:016x}", context.span_id),
+            ),
+            None => Span::none(),
+        };
+        lookup.instrument(span)
     }
 
     /// Per request options based on the ResolverOpts
@@ -208,6 +364,7 @@ impl<P: ConnectionProvider> Resolver<P> {
         let mut request_opts = DnsRequestOptions::default();
         request_opts.recursion_desired = self.options.recursion_desired;
         request_opts.use_edns = self.options.edns0;
+        request_opts.client_subnet = self.options.edns_client_subnet;
 
         request_opts
     }
@@ -238,6 +395,155 @@ impl<P: ConnectionProvider> Resolver<P> {
             .await
     }
 
+    /// Generic lookup for any RecordType, returning negative responses as [`NegativeResponse`]
+    ///
+    /// Negative responses, i.e. `NXDOMAIN` or no record of the type, carry the SOA and the
+    ///  remaining negative TTL, from the cache when cached, rather than being opaque errors. This is
+    ///  useful to a forwarder reconstructing the negative response for its clients. Other failures
+    ///  are returned as errors, see [`Self::lookup`].
+    pub async fn lookup_or_negative<N: IntoName>(
+        &self,
+        name: N,
+        record_type: RecordType,
+    ) -> Result<Result<Lookup, NegativeResponse>, ResolveError> {
+        match self.lookup(name, record_type).await {
+            Ok(lookup) => Ok(Ok(lookup)),
+            Err(error) => match error.negative_response() {
+                Some(negative) => Ok(Err(negative)),
+                None => Err(error),
+            },
+        }
+    }
+
+    /// Generic lookup for any RecordType, returning the durations of its phases along the result
+    ///
+    /// See [`LookupTiming`] for the phases, which are only timed for lookups made with this method.
+    pub async fn lookup_with_timing<N: IntoName>(
+        &self,
+        name: N,
+        record_type: RecordType,
+    ) -> (Result<Lookup, ResolveError>, LookupTiming) {
+        let start = Instant::now();
+        let timer = Arc::new(LookupTimer::default());
+        let lookup = match name.into_name() {
+            Ok(name) => {
+                self.in_context(LookupFuture::lookup_with_hosts(
+                    self.build_names(name),
+                    record_type,
+                    self.request_options(),
+                    self.client_cache.clone().with_timer(timer.clone()),
+                    self.hosts.clone(),
+                ))
+                .await
+            }
+            Err(err) => Err(err.into()),
+        };
+
+        (lookup, timer.finish(start.elapsed()))
+    }
+
+    /// Generic lookup for any RecordType, with options specific to this call
+    ///
+    /// See [`Self::lookup`], the `options` only apply to this lookup and take precedence over the
+    ///  `ResolverOpts` of this resolver.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - name of the record to lookup, if name is not a valid domain name, an error will be returned
+    /// * `record_type` - type of record to lookup, all RecordData responses will be filtered to this type
+    /// * `options` - per-call options for this lookup
+    pub async fn lookup_with_options<N: IntoName>(
+        &self,
+        name: N,
+        record_type: RecordType,
+        options: LookupOptions,
+    ) -> Result<Lookup, ResolveError> {
+        let name = match name.into_name() {
+            Ok(name) => name,
+            Err(err) => return Err(err.into()),
+        };
+        let predicate = match options.record_filter {
+            Some(filter) => match self.record_filters.read().get(&filter) {
+                Some(predicate) => Some(predicate.clone()),
+                None => return Err(ResolveErrorKind::Message("unknown record filter").into()),
+            },
+            None => None,
+        };
+
+        let mut request_opts = self.request_options();
+        request_opts.checking_disabled = options.checking_disabled;
+        request_opts.deadline = options.deadline;
+        request_opts.cache_min_ttl = options.min_ttl;
+        request_opts.bypass_cache = options.bypass_cache;
+        request_opts.client_subnet = options.client_subnet.or(request_opts.client_subnet);
+
+        let in_context = options.context.map(|context| self.with_context(context));
+        let resolver = in_context.as_ref().unwrap_or(self);
+        let lookup = match options.require_secure {
+            true => {
+                resolver
+                    .secure_lookup(name, record_type, request_opts)
+                    .await?
+            }
+            false => {
+                resolver
+                    .inner_lookup(name, record_type, request_opts)
+                    .await?
+            }
+        };
+
+        let Some(filter) = predicate else {
+            return Ok(lookup);
+        };
+        let filtered = filter_lookup(&lookup, record_type, &*filter);
+        match filtered
+            .record_iter()
+            .any(|record| is_looked_up(record, record_type))
+        {
+            true => Ok(filtered),
+            false => Err(filtered_out(lookup.query())),
+        }
+    }
+
+    /// Registers a predicate on the data of the records returned by the lookups, see
+    ///  [`LookupOptions::record_filter`]
+    ///
+    /// Returns the filter to set in the options of the lookups, the clones of this resolver share
+    ///  the registered filters.
+    pub fn add_record_filter(
+        &self,
+        predicate: impl Fn(&RData) -> bool + Send + Sync + 'static,
+    ) -> RecordFilter {
+        let filter = RecordFilter(NEXT_RECORD_FILTER.fetch_add(1, Ordering::Relaxed));
+        self.record_filters
+            .write()
+            .insert(filter, Arc::new(predicate));
+        filter
+    }
+
+    /// Unregisters the record filter, the lookups with this filter fail from then on
+    pub fn remove_record_filter(&self, filter: RecordFilter) {
+        self.record_filters.write().remove(&filter);
+    }
+
+    /// Generic lookup returning the record data downcast to the type `T`
+    ///
+    /// Any records in the response which are not of type `T`, e.g. CNAMEs followed along the
+    ///  way, are filtered out.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - name of the record to lookup, if name is not a valid domain name, an error will be returned
+    ///
+    /// The record type looked up is the record type of `T`.
+    pub async fn lookup_typed<T: TypedRecordData>(
+        &self,
+        name: impl IntoName,
+    ) -> Result<Vec<T>, ResolveError> {
+        let lookup = self.lookup(name, T::RECORD_TYPE).await?;
+        Ok(lookup.typed_iter::<T>().cloned().collect())
+    }
+
     fn push_name(name: Name, names: &mut Vec<Name>) {
         if !names.contains(&name) {
             names.push(name);
@@ -316,16 +622,81 @@ impl<P: ConnectionProvider> Resolver<P> {
     where
         L: From<Lookup> + Send + Sync + 'static,
     {
+        self.lookup_with_client(self.client_cache.clone(), name, record_type, options)
+            .await
+            .map(L::from)
+    }
+
+    async fn lookup_with_client(
+        &self,
+        client: CachingClient<LookupEither<P>>,
+        name: Name,
+        record_type: RecordType,
+        options: DnsRequestOptions,
+    ) -> Result<Lookup, ResolveError> {
         let names = self.build_names(name);
-        LookupFuture::lookup_with_hosts(
+        self.in_context(LookupFuture::lookup_with_hosts(
             names,
             record_type,
             options,
-            self.client_cache.clone(),
+            client,
             self.hosts.clone(),
-        )
+        ))
         .await
-        .map(L::from)
+    }
+
+    /// Looks up the records, failing unless they are DNSSEC secure, see
+    ///  [`LookupOptions::require_secure`]
+    async fn secure_lookup(
+        &self,
+        name: Name,
+        record_type: RecordType,
+        mut options: DnsRequestOptions,
+    ) -> Result<Lookup, ResolveError> {
+        // the DO bit asks upstream for the RRSIGs, even if this resolver doesn't validate others
+        options.use_edns = true;
+        options.edns_set_dnssec_ok = true;
+
+        #[cfg(feature = "dnssec")]
+        let result = match &self.secure_client {
+            Some(secure_client) => {
+                let client = self.client_cache.clone().with_client(secure_client.clone());
+                let result = self
+                    .lookup_with_client(client.clone(), name.clone(), record_type, options)
+                    .await;
+
+                // the records cached by the other lookups of this resolver weren't validated
+                if options.bypass_cache || is_secure_result(&result, options) {
+                    result
+                } else {
+                    options.bypass_cache = true;
+                    self.lookup_with_client(client, name, record_type, options)
+                        .await
+                }
+            }
+            None => self.inner_lookup(name, record_type, options).await,
+        };
+        #[cfg(not(feature = "dnssec"))]
+        let result = self.inner_lookup(name, record_type, options).await;
+
+        if is_secure_result(&result, options) {
+            return result;
+        }
+
+        let query = match &result {
+            Ok(lookup) => lookup.query().clone(),
+            Err(error) => match error.proto().map(ProtoError::kind) {
+                Some(ProtoErrorKind::NoRecordsFound { query, .. }) => (**query).clone(),
+                #[cfg(feature = "dnssec")]
+                Some(ProtoErrorKind::Nsec { query, .. }) => query.clone(),
+                _ => return result,
+            },
+        };
+        debug!("{} {} is not secure", query.name(), record_type);
+        Err(ResolveErrorKind::NotSecure {
+            query: Box::new(query),
+        }
+        .into())
     }
 
     /// Performs a dual-stack DNS lookup for the IP for the given hostname.
@@ -373,15 +744,52 @@ impl<P: ConnectionProvider> Resolver<P> {
         let names = self.build_names(name);
         let hosts = self.hosts.as_ref().cloned();
 
-        LookupIpFuture::lookup(
-            names,
+        let strategy = routed_strategy(
             self.options.ip_strategy,
+            self.options.aaaa_requires_ipv6_route,
+            || self.connectivity.has_ipv6_route(),
+        );
+
+        self.in_context(LookupIpFuture::lookup(
+            names,
+            strategy,
             self.client_cache.clone(),
             self.request_options(),
             hosts,
             finally_ip_addr.map(Record::into_data),
-        )
+        ))
         .await
+        .and_then(|lookup| match self.options.address_family_filter {
+            AddressFamilyFilter::Any => Ok(lookup),
+            filter => {
+                let filtered = lookup.filter_family(filter);
+                match filtered.iter().next() {
+                    Some(_) => Ok(filtered),
+                    None => Err(filtered_out(lookup.query())),
+                }
+            }
+        })
+        .map(|lookup| match self.options.ip_family_preference {
+            IpFamilyPreference::System => lookup,
+            preference => lookup.prefer_family(preference),
+        })
+    }
+
+    /// Performs a [`Self::lookup_ip`] and orders the resulting addresses randomly by weight
+    ///
+    /// See [`LookupIp::weighted_order`] for how the weights are applied; the cached order is left untouched.
+    ///
+    /// # Arguments
+    /// * `host` - string hostname, if this is an invalid hostname, an error will be returned.
+    /// * `weight` - relative weight of each address, addresses with a weight of `0` are placed last.
+    pub async fn lookup_ip_weighted<F: Fn(&IpAddr) -> u32>(
+        &self,
+        host: impl IntoName,
+        weight: F,
+    ) -> Result<LookupIp, ResolveError> {
+        self.lookup_ip(host)
+            .await
+            .map(|lookup| lookup.weighted_order(weight))
     }
 
     /// Customizes the static hosts used in this resolver.
@@ -389,6 +797,41 @@ impl<P: ConnectionProvider> Resolver<P> {
         self.hosts = hosts.map(Arc::new);
     }
 
+    /// Sets a rewriter inspecting and optionally modifying each response before it's cached
+    ///
+    /// Rewriting affects both what gets cached and what is returned, e.g. to strip records, adjust
+    ///  TTLs or redact data. Responses which are already cached are not rewritten.
+    pub fn set_response_rewriter(&mut self, rewriter: Option<ResponseRewriter>) {
+        self.client_cache.set_response_rewriter(rewriter);
+    }
+
+    /// Sets a logger receiving an audit log entry for each response received from upstream
+    ///
+    /// The names and the data of the records matching the policy are redacted from the entries,
+    ///  see [`ResponseLogger`]. Responses are logged before they are rewritten, and responses
+    ///  served from the cache are not logged.
+    pub fn set_response_logger(&mut self, logger: Option<ResponseLogger>, policy: RedactionPolicy) {
+        self.client_cache.set_response_logger(logger, policy);
+    }
+
+    /// Sets a secondary cache, e.g. shared over the network, backing the cache of this resolver
+    ///
+    /// The misses of the cache are read from the secondary cache, and the lookups received from
+    ///  upstream are stored in both caches, see [`ReadThrough`](crate::dns_lru::ReadThrough). The
+    ///  lookups with a client subnet or a minimum TTL are not shared with the secondary cache, and
+    ///  [`Resolver::clear_cache`] leaves it unchanged.
+    pub fn set_secondary_cache(&mut self, cache: Option<Arc<dyn DnsCache>>) {
+        self.client_cache.set_secondary_cache(cache);
+    }
+
+    /// Sets a callback reporting differing answers of the name servers queried in parallel
+    ///
+    /// This is only called with [`AnswerDiscrepancyPolicy::Flag`](crate::config::AnswerDiscrepancyPolicy::Flag),
+    ///  see [`NameServerPool::set_discrepancy_reporter`].
+    pub fn set_discrepancy_reporter(&mut self, reporter: Option<DiscrepancyReporter>) {
+        *self.discrepancy_reporter.lock() = reporter;
+    }
+
     lookup_fn!(
         reverse_lookup,
         lookup::ReverseLookup,
@@ -404,6 +847,49 @@ impl<P: ConnectionProvider> Resolver<P> {
     lookup_fn!(tlsa_lookup, lookup::TlsaLookup, RecordType::TLSA);
     lookup_fn!(txt_lookup, lookup::TxtLookup, RecordType::TXT);
     lookup_fn!(cert_lookup, lookup::CertLookup, RecordType::CERT);
+
+    /// Performs an SRV lookup and resolves the addresses of each target
+    ///
+    /// The records are ordered by ascending priority, and randomly by weight for records of equal
+    ///  priority, as described in [RFC 2782](https://tools.ietf.org/html/rfc2782). Addresses
+    ///  included in the additional section of the SRV response are used when present, otherwise the
+    ///  targets are resolved concurrently with [`Self::lookup_ip`], which uses the cache. Targets
+    ///  that fail to resolve are returned without any addresses.
+    ///
+    /// # Arguments
+    /// * `name` - name of the service, e.g. `_xmpp-client._tcp.example.com.`
+    pub async fn lookup_srv_resolved<N: IntoName>(
+        &self,
+        name: N,
+    ) -> Result<Vec<(SRV, Vec<IpAddr>)>, ResolveError> {
+        let lookup = self.srv_lookup(name).await?;
+        Ok(lookup
+            .resolve_targets(|target| self.lookup_ip(target))
+            .await)
+    }
+
+    /// Performs an MX lookup and resolves the addresses of each exchange
+    ///
+    /// The preferences and exchanges are ordered by ascending preference, i.e. most preferred
+    ///  first, as described in [RFC 5321](https://tools.ietf.org/html/rfc5321#section-5.1).
+    ///  Addresses included in the additional section of the MX response are used when they cover
+    ///  the address families of the `ip_strategy` option, otherwise the exchanges are resolved
+    ///  concurrently with [`Self::lookup_ip`], which uses the cache. Exchanges that fail to resolve
+    ///  are returned with the addresses of the additional section only, if any.
+    ///
+    /// # Arguments
+    /// * `name` - name of the mail domain, e.g. `example.com.`
+    pub async fn lookup_mx_resolved<N: IntoName>(
+        &self,
+        name: N,
+    ) -> Result<Vec<(u16, Name, Vec<IpAddr>)>, ResolveError> {
+        let lookup = self.mx_lookup(name).await?;
+        Ok(lookup
+            .resolve_exchanges(self.options.ip_strategy, |exchange| {
+                self.lookup_ip(exchange)
+            })
+            .await)
+    }
 }
 
 impl<P: ConnectionProvider> fmt::Debug for Resolver<P> {
@@ -412,6 +898,60 @@ impl<P: ConnectionProvider> fmt::Debug for Resolver<P> {
     }
 }
 
+/// Filters the records of the looked up type, see [`LookupOptions::record_filter`]
+fn filter_lookup(
+    lookup: &Lookup,
+    record_type: RecordType,
+    filter: &(dyn Fn(&RData) -> bool + Send + Sync),
+) -> Lookup {
+    lookup.filter_records(|record| !is_looked_up(record, record_type) || filter(record.data()))
+}
+
+/// True if the record is of the looked up type, rather than e.g. a CNAME followed to it
+fn is_looked_up(record: &Record, record_type: RecordType) -> bool {
+    record_type.is_any() || record.record_type() == record_type
+}
+
+/// Returns the error of a lookup whose records were all removed by a filter, as if none were found
+fn filtered_out(query: &Query) -> ResolveError {
+    ProtoError::nx_error(
+        Box::new(query.clone()),
+        None,
+        None,
+        None,
+        ResponseCode::NoError,
+        false,
+        None,
+    )
+    .into()
+}
+
+/// True if all the records of the lookup were validated by DNSSEC, or if the nonexistence of the
+///  records was, see [`LookupOptions::require_secure`]
+///
+/// Errors other than a negative response are never secure, but they are not answers either.
+#[cfg(feature = "dnssec")]
+fn is_secure_result(result: &Result<Lookup, ResolveError>, options: DnsRequestOptions) -> bool {
+    match result {
+        Ok(lookup) => {
+            let mut records = lookup.record_iter().peekable();
+            records.peek().is_some() && records.all(|record| record.proof().is_secure())
+        }
+        // the NSEC records are only checked when checking isn't disabled
+        Err(error) => match error.proto().map(ProtoError::kind) {
+            Some(ProtoErrorKind::NoRecordsFound { soa: Some(soa), .. }) => {
+                !options.checking_disabled && soa.proof().is_secure()
+            }
+            _ => false,
+        },
+    }
+}
+
+#[cfg(not(feature = "dnssec"))]
+fn is_secure_result(_result: &Result<Lookup, ResolveError>, _options: DnsRequestOptions) -> bool {
+    false
+}
+
 /// Unit tests compatible with different runtime.
 #[cfg(any(test, feature = "testing"))]
 #[allow(dead_code, unreachable_pub)]
@@ -986,11 +1526,92 @@ mod tests {
     use test_support::subscribe;
     use tokio::runtime::Runtime;
 
-    use crate::config::{ResolverConfig, ResolverOpts};
+    use std::future::{self, Future};
+    use std::io;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+    use std::pin::Pin;
+
+    use futures_util::stream::{once, Stream};
+
+    use crate::config::{
+        LookupContext, LookupIpStrategy, NameServerConfig, NameServerConfigGroup, ResolverConfig,
+        ResolverOpts,
+    };
     use crate::name_server::GenericConnection;
+    use crate::proto::op::{Message, MessageType};
+    use crate::proto::runtime::TokioRuntimeProvider;
+    use crate::proto::xfer::{DnsHandle, DnsResponse};
 
     use super::*;
 
+    /// Connects to all the name servers through the handle, e.g. a mock
+    #[derive(Clone)]
+    struct HandleConnProvider<H>(H);
+
+    impl<H: DnsHandle + Clone + Send + Sync + Unpin + 'static> ConnectionProvider
+        for HandleConnProvider<H>
+    {
+        type Conn = H;
+        type FutureConn = future::Ready<Result<H, ProtoError>>;
+        type RuntimeProvider = TokioRuntimeProvider;
+
+        fn new_connection(
+            &self,
+            _: &NameServerConfig,
+            _: &ResolverOpts,
+        ) -> Result<Self::FutureConn, io::Error> {
+            Ok(future::ready(Ok(self.0.clone())))
+        }
+
+        fn spawn_bg(&self, task: Pin<Box<dyn Future<Output = ()> + Send>>) {
+            tokio::spawn(task);
+        }
+    }
+
+    /// Returns a resolver sending all its queries through the handle
+    fn handle_resolver<H: DnsHandle + Clone + Send + Sync + Unpin + 'static>(
+        handle: H,
+        options: ResolverOpts,
+    ) -> Resolver<HandleConnProvider<H>> {
+        let name_servers =
+            NameServerConfigGroup::from_ips_clear(&[Ipv4Addr::LOCALHOST.into()], 53, true);
+        Resolver::new(
+            ResolverConfig::from_parts(None, vec![], name_servers),
+            options,
+            HandleConnProvider(handle),
+        )
+    }
+
+    /// Answers each query with the data of its type, under the queried name
+    #[derive(Clone)]
+    struct AnswerHandle(Arc<[RData]>);
+
+    impl DnsHandle for AnswerHandle {
+        type Response = Pin<Box<dyn Stream<Item = Result<DnsResponse, ProtoError>> + Send + Unpin>>;
+
+        fn send<R: Into<DnsRequest>>(&self, request: R) -> Self::Response {
+            let request = request.into();
+            let query = request.queries()[0].clone();
+            let answers = self
+                .0
+                .iter()
+                .filter(|rdata| rdata.record_type() == query.query_type())
+                .map(|rdata| Record::from_rdata(query.name().clone(), 300, rdata.clone()))
+                .collect();
+
+            let mut message = Message::new();
+            message
+                .set_id(request.id())
+                .set_message_type(MessageType::Response)
+                .set_recursion_available(true);
+            message.add_query(query);
+            message.insert_answers(answers);
+            Box::pin(once(future::ready(Ok(
+                DnsResponse::from_message(message).unwrap()
+            ))))
+        }
+    }
+
     fn is_send_t<T: Send>() -> bool {
         true
     }
@@ -1014,6 +1635,203 @@ mod tests {
         assert!(is_send_t::<LookupFuture<GenericConnection>>());
     }
 
+    #[test]
+    fn test_record_filter() {
+        use std::time::Instant;
+
+        use crate::proto::rr::rdata::A;
+
+        let a = |ip: [u8; 4]| RData::A(A::from(Ipv4Addr::from(ip)));
+        let handle = AnswerHandle(Arc::from([
+            a([10, 0, 1, 1]),
+            a([10, 0, 2, 1]),
+            a([10, 0, 1, 2]),
+        ]));
+        let cache = DnsLru::new(1, dns_lru::TtlConfig::default());
+        let name_servers =
+            NameServerConfigGroup::from_ips_clear(&[Ipv4Addr::LOCALHOST.into()], 53, true);
+        let resolver = Resolver::new_with_cache(
+            ResolverConfig::from_parts(None, vec![], name_servers),
+            ResolverOpts::default(),
+            HandleConnProvider(handle),
+            cache.clone(),
+        );
+        let io_loop = Runtime::new().expect("failed to create tokio runtime");
+        let lookup = |filter| {
+            let options = LookupOptions {
+                record_filter: Some(filter),
+                ..LookupOptions::default()
+            };
+            io_loop.block_on(resolver.lookup_with_options(
+                "www.example.com.",
+                RecordType::A,
+                options,
+            ))
+        };
+
+        // only the addresses in 10.0.1.0/24 are returned
+        let subnet = resolver.add_record_filter(
+            |rdata| matches!(rdata, RData::A(a) if a.0.octets()[..3] == [10, 0, 1]),
+        );
+        let filtered = lookup(subnet).expect("lookup failed");
+        assert_eq!(
+            filtered.iter().collect::<Vec<_>>(),
+            vec![&a([10, 0, 1, 1]), &a([10, 0, 1, 2])]
+        );
+
+        // the cache still holds all the addresses
+        let query = Query::query(Name::from_ascii("www.example.com.").unwrap(), RecordType::A);
+        let cached = cache.get(&query, Instant::now()).unwrap().unwrap();
+        assert_eq!(cached.records().len(), 3);
+
+        // the lookup fails when no address matches
+        let none = resolver.add_record_filter(|_| false);
+        assert!(lookup(none).unwrap_err().is_no_records_found());
+
+        // or when the filter is not registered anymore
+        resolver.remove_record_filter(subnet);
+        assert!(!lookup(subnet).unwrap_err().is_no_records_found());
+    }
+
+    #[test]
+    fn test_address_family_filter() {
+        use std::time::Instant;
+
+        let v4 = Ipv4Addr::new(192, 0, 2, 1);
+        let v6 = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+        let handle = AnswerHandle(Arc::from([RData::A(v4.into()), RData::AAAA(v6.into())]));
+        let io_loop = Runtime::new().expect("failed to create tokio runtime");
+        let cache = DnsLru::new(4, dns_lru::TtlConfig::default());
+        let resolver = |ip_strategy, address_family_filter| {
+            let options = ResolverOpts {
+                ip_strategy,
+                address_family_filter,
+                ..ResolverOpts::default()
+            };
+            let name_servers =
+                NameServerConfigGroup::from_ips_clear(&[Ipv4Addr::LOCALHOST.into()], 53, true);
+            Resolver::new_with_cache(
+                ResolverConfig::from_parts(None, vec![], name_servers),
+                options,
+                HandleConnProvider(handle.clone()),
+                cache.clone(),
+            )
+        };
+
+        // only the addresses of the family are returned
+        let resolver_v4 = resolver(LookupIpStrategy::Ipv4AndIpv6, AddressFamilyFilter::Ipv4Only);
+        let lookup = io_loop
+            .block_on(resolver_v4.lookup_ip("www.example.com."))
+            .expect("lookup failed");
+        assert_eq!(lookup.iter().collect::<Vec<_>>(), vec![IpAddr::from(v4)]);
+
+        // both families are still cached
+        let name = Name::from_ascii("www.example.com.").unwrap();
+        for record_type in [RecordType::A, RecordType::AAAA] {
+            let query = Query::query(name.clone(), record_type);
+            assert!(cache.get(&query, Instant::now()).is_some());
+        }
+
+        // the lookup fails when no address of the family is left
+        let resolver_v6 = resolver(LookupIpStrategy::Ipv4Only, AddressFamilyFilter::Ipv6Only);
+        let error = io_loop
+            .block_on(resolver_v6.lookup_ip("www.example.com."))
+            .expect_err("no address should be left");
+        assert!(error.is_no_records_found());
+    }
+
+    #[test]
+    fn test_lookup_timing() {
+        use std::pin::Pin;
+        use std::time::Duration;
+
+        use futures_util::stream::{once, Stream};
+
+        use crate::lookup::tests::v4_message;
+        use crate::proto::error::ProtoError;
+        use crate::proto::xfer::{DnsHandle, DnsResponse};
+
+        const DELAY: Duration = Duration::from_millis(50);
+
+        /// Responds to each query after the delay
+        #[derive(Clone)]
+        struct DelayedConn;
+
+        impl DnsHandle for DelayedConn {
+            type Response = Pin<Box<dyn Stream<Item = Result<DnsResponse, ProtoError>> + Send>>;
+
+            fn send<R: Into<DnsRequest> + Unpin + Send + 'static>(&self, _: R) -> Self::Response {
+                Box::pin(once(async {
+                    tokio::time::sleep(DELAY).await;
+                    v4_message()
+                }))
+            }
+        }
+
+        let io_loop = Runtime::new().expect("failed to create tokio runtime");
+        let resolver = handle_resolver(DelayedConn, ResolverOpts::default());
+
+        let (lookup, timing) = io_loop.block_on(resolver.lookup_with_timing(".", RecordType::A));
+        lookup.expect("failed to run lookup");
+
+        let phases = timing.cache + timing.connect + timing.response + timing.validation;
+        assert!(timing.response >= DELAY, "{timing:?}");
+        assert!(phases <= timing.total, "{timing:?}");
+        // the rest of the lookup, e.g. processing the response, is short compared to the network
+        assert!(phases * 2 >= timing.total, "{timing:?}");
+
+        // the second lookup is answered from the cache
+        let (lookup, timing) = io_loop.block_on(resolver.lookup_with_timing(".", RecordType::A));
+        lookup.expect("failed to run lookup");
+        assert_eq!(timing.response, Duration::ZERO);
+        assert!(timing.cache <= timing.total);
+    }
+
+    #[test]
+    fn test_try_new_without_name_servers() {
+        use crate::config::EmptyNameServers;
+
+        let options = ResolverOpts {
+            use_hosts_file: ResolveHosts::Never,
+            ..ResolverOpts::default()
+        };
+        let error = Resolver::try_new(
+            ResolverConfig::new(),
+            options.clone(),
+            TokioConnectionProvider::default(),
+        )
+        .expect_err("resolver without name servers was constructed");
+        assert!(matches!(error.kind(), ResolveErrorKind::NoNameServers));
+
+        // the hosts file can still resolve names
+        let hosts_options = ResolverOpts {
+            use_hosts_file: ResolveHosts::Always,
+            ..options.clone()
+        };
+        let resolver = Resolver::try_new(
+            ResolverConfig::new(),
+            hosts_options,
+            TokioConnectionProvider::default(),
+        )
+        .expect("resolver using the hosts file failed");
+        assert!(resolver.config().name_servers().is_empty());
+
+        let fallback_options = ResolverOpts {
+            empty_name_servers: EmptyNameServers::Cloudflare,
+            ..options
+        };
+        let resolver = Resolver::try_new(
+            ResolverConfig::new(),
+            fallback_options,
+            TokioConnectionProvider::default(),
+        )
+        .expect("resolver with fallback failed");
+        assert_eq!(
+            resolver.config().name_servers(),
+            &*NameServerConfigGroup::cloudflare()
+        );
+    }
+
     #[test]
     fn test_lookup_google() {
         use super::testing::lookup_test;
@@ -1210,4 +2028,209 @@ mod tests {
             assert_eq!(resolver.build_names(name.clone()).len(), 2);
         }
     }
+
+    #[test]
+    fn test_lookup_context() {
+        #[derive(Clone, Default)]
+        struct Capture(Arc<parking_lot::Mutex<Vec<u8>>>);
+
+        impl std::io::Write for Capture {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        // the events of the lookups, emitted by the cache and the name servers
+        let capture = Capture::default();
+        let events = || {
+            let output = String::from_utf8(std::mem::take(&mut *capture.0.lock())).unwrap();
+            output.lines().map(str::to_owned).collect::<Vec<_>>()
+        };
+        let writer = capture.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+
+        let resolver = handle_resolver(
+            AnswerHandle(Arc::from([RData::A(crate::proto::rr::rdata::A::new(
+                192, 0, 2, 1,
+            ))])),
+            ResolverOpts::default(),
+        );
+        let context = LookupContext {
+            trace_id: 0x0af7651916cd43dd8448eb211c80319c,
+            span_id: 0xb7ad6b7169203331,
+        };
+
+        // the queries sent upstream, in the span of their lookup
+        let sent_queries = || {
+            events()
+                .into_iter()
+                .filter(|event| event.contains("name_server_pool: sending request"))
+                .collect::<Vec<_>>()
+        };
+        let in_span = |event: &str, trace_id: &str| {
+            event.contains(&format!(
+                "lookup{{trace_id={trace_id} span_id=b7ad6b7169203331}}:"
+            ))
+        };
+
+        let io_loop = Runtime::new().unwrap();
+        tracing::subscriber::with_default(subscriber, || {
+            // the context of the resolver applies to all its lookup methods
+            let in_context = resolver.with_context(context);
+            io_loop
+                .block_on(in_context.lookup_ip("www.example.com."))
+                .unwrap();
+            io_loop
+                .block_on(in_context.txt_lookup("mail.example.com."))
+                .unwrap_err();
+            let sent = sent_queries();
+            assert_eq!(sent.len(), 2);
+            assert!(sent
+                .iter()
+                .all(|event| in_span(event, "0af7651916cd43dd8448eb211c80319c")));
+
+            // the context of the options takes precedence, without nesting the lookup spans
+            let options = LookupOptions {
+                context: Some(LookupContext {
+                    trace_id: 0x4bf92f3577b34da6a3ce929d0e0e4736,
+                    ..context
+                }),
+                ..LookupOptions::default()
+            };
+            io_loop
+                .block_on(in_context.lookup_with_options(
+                    "ftp.example.com.",
+                    RecordType::A,
+                    options,
+                ))
+                .unwrap();
+            let sent = sent_queries();
+            assert_eq!(sent.len(), 1);
+            assert!(in_span(&sent[0], "4bf92f3577b34da6a3ce929d0e0e4736"));
+            assert_eq!(sent[0].matches("lookup{").count(), 1);
+
+            // the lookups of the resolver without context are in no span
+            io_loop
+                .block_on(resolver.lookup("www.example.org.", RecordType::A))
+                .unwrap();
+            let sent = sent_queries();
+            assert_eq!(sent.len(), 1);
+            assert!(!sent[0].contains("lookup{"));
+        });
+    }
+
+    #[test]
+    fn test_edns_client_subnet() {
+        use std::net::Ipv4Addr;
+
+        use crate::lookup_ip::tests::RecordingHandle;
+        use crate::proto::rr::rdata::opt::ClientSubnet;
+        use crate::proto::xfer::DnsHandle;
+
+        // the encoded OPT record of a query of the resolver with the subnet
+        let encoded_query = |subnet: ClientSubnet| {
+            let options = ResolverOpts {
+                edns_client_subnet: Some(subnet),
+                ..ResolverOpts::default()
+            };
+            let resolver = Resolver::tokio(ResolverConfig::default(), options);
+            let handle = RecordingHandle::default();
+            drop(handle.lookup(
+                Query::query(Name::root(), RecordType::A),
+                resolver.request_options(),
+            ));
+
+            let request = handle.0.lock().unwrap().pop().expect("no request sent");
+            request.to_vec().unwrap()
+        };
+        let contains = |bytes: &[u8], option: &[u8]| {
+            bytes.windows(option.len()).any(|window| window == option)
+        };
+
+        // OPTION-CODE 8, OPTION-LENGTH 7, FAMILY 1, SOURCE PREFIX-LENGTH 24, SCOPE PREFIX-LENGTH 0,
+        //  then the 3 octets of the prefix
+        let subnet = ClientSubnet::new(Ipv4Addr::new(192, 0, 2, 0).into(), 24, 0);
+        let bytes = encoded_query(subnet);
+        assert!(contains(&bytes, &[0, 8, 0, 7, 0, 1, 24, 0, 192, 0, 2]));
+
+        // only the family is sent in the privacy mode
+        let subnet = ClientSubnet::new(Ipv4Addr::UNSPECIFIED.into(), 0, 0);
+        let bytes = encoded_query(subnet);
+        assert!(contains(&bytes, &[0, 8, 0, 4, 0, 1, 0, 0]));
+    }
+
+    /// Answers all the queries with an unsigned NXDOMAIN, recording their requests
+    #[derive(Clone, Default)]
+    struct NxDomainHandle(Arc<Mutex<Vec<DnsRequest>>>);
+
+    impl DnsHandle for NxDomainHandle {
+        type Response = Pin<Box<dyn Stream<Item = Result<DnsResponse, ProtoError>> + Send + Unpin>>;
+
+        fn send<R: Into<DnsRequest>>(&self, request: R) -> Self::Response {
+            use crate::proto::rr::rdata::SOA;
+
+            let request = request.into();
+            let zone = Name::from_ascii("example.com.").unwrap();
+            let soa = SOA::new(zone.clone(), zone.clone(), 1, 3600, 600, 86400, 300);
+
+            let mut message = Message::new();
+            message
+                .set_id(request.id())
+                .set_message_type(MessageType::Response)
+                .set_response_code(ResponseCode::NXDomain)
+                .set_recursion_available(true);
+            message.add_query(request.queries()[0].clone());
+            message.add_name_server(Record::from_rdata(zone, 300, RData::SOA(soa)));
+            self.0.lock().push(request);
+            Box::pin(once(future::ready(Ok(
+                DnsResponse::from_message(message).unwrap()
+            ))))
+        }
+    }
+
+    #[test]
+    fn test_require_secure_negative_response() {
+        use crate::config::LookupOptions;
+
+        subscribe();
+        let handle = NxDomainHandle::default();
+        let resolver = handle_resolver(handle.clone(), ResolverOpts::default());
+        let options = LookupOptions {
+            require_secure: true,
+            ..LookupOptions::default()
+        };
+
+        // the unsigned NXDOMAIN isn't secure, even though the resolver doesn't validate
+        let runtime = Runtime::new().unwrap();
+        let error = runtime
+            .block_on(resolver.lookup_with_options("www.example.com.", RecordType::A, options))
+            .expect_err("an unsigned negative response should fail");
+        assert!(
+            matches!(error.kind(), ResolveErrorKind::NotSecure { .. }),
+            "{error:?}"
+        );
+
+        // the queries asked upstream for the RRSIGs
+        let requests = handle.0.lock().clone();
+        assert!(!requests.is_empty());
+        assert!(requests.iter().all(|request| request
+            .extensions()
+            .as_ref()
+            .is_some_and(|edns| edns.dnssec_ok())));
+
+        // the other lookups are unaffected
+        let error = runtime
+            .block_on(resolver.lookup("www.example.com.", RecordType::A))
+            .expect_err("the name should not exist");
+        assert!(error.is_nx_domain(), "{error:?}");
+    }
 }
@@ -75,6 +75,8 @@ fn into_resolver_config(
             #[cfg(feature = "dns-over-rustls")]
             tls_config: None,
             bind_addr: None,
+            timeout: None,
+            weight: 0,
         });
         nameservers.push(NameServerConfig {
             socket_addr: SocketAddr::new(ip.into(), DEFAULT_PORT),
@@ -85,6 +87,8 @@ fn into_resolver_config(
             #[cfg(feature = "dns-over-rustls")]
             tls_config: None,
             bind_addr: None,
+            timeout: None,
+            weight: 0,
         });
     }
     if nameservers.is_empty() {
@@ -146,6 +150,8 @@ mod tests {
                 #[cfg(feature = "dns-over-rustls")]
                 tls_config: None,
                 bind_addr: None,
+                timeout: None,
+                weight: 0,
             },
             NameServerConfig {
                 socket_addr: addr,
@@ -156,6 +162,8 @@ mod tests {
                 #[cfg(feature = "dns-over-rustls")]
                 tls_config: None,
                 bind_addr: None,
+                timeout: None,
+                weight: 0,
             },
         ]
     }
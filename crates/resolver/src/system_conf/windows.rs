@@ -38,6 +38,8 @@ fn get_name_servers() -> ResolveResult<Vec<NameServerConfig>> {
             #[cfg(feature = "dns-over-rustls")]
             tls_config: None,
             bind_addr: None,
+            timeout: None,
+            weight: 0,
         });
         name_servers.push(NameServerConfig {
             socket_addr,
@@ -48,6 +50,8 @@ fn get_name_servers() -> ResolveResult<Vec<NameServerConfig>> {
             #[cfg(feature = "dns-over-rustls")]
             tls_config: None,
             bind_addr: None,
+            timeout: None,
+            weight: 0,
         });
     }
     Ok(name_servers)
@@ -0,0 +1,124 @@
+//! Timing breakdown of lookups, see [`Resolver::lookup_with_timing`](crate::Resolver::lookup_with_timing)
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use crate::proto::xfer::RequestTimer;
+
+/// Durations of the phases of a lookup
+///
+/// Each phase sums the time spent in it across all the queries of the lookup, e.g. for each name
+///  of the search list or each retry. The remainder of the `total` is spent elsewhere, e.g.
+///  looking up the hosts file or processing the responses.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LookupTiming {
+    /// Duration of the whole lookup
+    pub total: Duration,
+    /// Time spent checking the cache
+    pub cache: Duration,
+    /// Time spent establishing connections to name servers
+    pub connect: Duration,
+    /// Time spent awaiting responses of name servers
+    pub response: Duration,
+    /// Time spent validating responses with DNSSEC, including the lookups of the keys
+    ///
+    /// This is the time the validation added to the upstream queries, the connections and responses
+    ///  of the lookups of the keys are included in `connect` and `response`.
+    pub validation: Duration,
+}
+
+/// A phase of a lookup, see [`LookupTiming`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Phase {
+    Cache,
+    Connect,
+    Response,
+}
+
+/// Records the timing of a lookup, carried by the requests it sends
+#[derive(Debug, Default)]
+pub(crate) struct LookupTimer(Mutex<LookupTiming>);
+
+impl LookupTimer {
+    fn record(&self, phase: Phase, elapsed: Duration) {
+        let mut timing = self.0.lock();
+        match phase {
+            Phase::Cache => timing.cache += elapsed,
+            Phase::Connect => timing.connect += elapsed,
+            Phase::Response => timing.response += elapsed,
+        }
+    }
+
+    /// Returns the time spent connecting and awaiting responses so far
+    fn upstream(&self) -> Duration {
+        let timing = self.0.lock();
+        timing.connect + timing.response
+    }
+
+    /// Returns the timing recorded so far, with the `total` duration of the lookup
+    pub(crate) fn finish(&self, total: Duration) -> LookupTiming {
+        LookupTiming {
+            total,
+            ..*self.0.lock()
+        }
+    }
+}
+
+impl RequestTimer for LookupTimer {
+    fn record_connect(&self, elapsed: Duration) {
+        self.record(Phase::Connect, elapsed);
+    }
+
+    fn record_response(&self, elapsed: Duration) {
+        self.record(Phase::Response, elapsed);
+    }
+}
+
+/// Measures a phase, when the lookup is timed
+///
+/// Without a timer, no time is taken so there is no overhead.
+pub(crate) struct PhaseTimer {
+    phase: Phase,
+    start: Option<(Arc<LookupTimer>, Instant)>,
+}
+
+impl PhaseTimer {
+    pub(crate) fn start(timer: Option<&Arc<LookupTimer>>, phase: Phase) -> Self {
+        let start = timer.map(|timer| (Arc::clone(timer), Instant::now()));
+        Self { phase, start }
+    }
+
+    /// Adds the time elapsed since the start to the phase
+    pub(crate) fn stop(self) {
+        if let Some((timer, start)) = self.start {
+            timer.record(self.phase, start.elapsed());
+        }
+    }
+}
+
+/// Measures the time DNSSEC validation adds to an upstream query
+///
+/// This is the time elapsed, less the time spent connecting and awaiting responses meanwhile.
+pub(crate) struct ValidationTimer {
+    start: Option<(Arc<LookupTimer>, Instant, Duration)>,
+}
+
+impl ValidationTimer {
+    pub(crate) fn start(timer: Option<&Arc<LookupTimer>>) -> Self {
+        let start = timer.map(|timer| (Arc::clone(timer), Instant::now(), timer.upstream()));
+        Self { start }
+    }
+
+    /// Adds the time elapsed since the start, less the upstream time meanwhile, to the validation
+    pub(crate) fn stop(self) {
+        let Some((timer, start, before)) = self.start else {
+            return;
+        };
+
+        let elapsed = start.elapsed();
+        let upstream = timer.upstream().saturating_sub(before);
+        timer.0.lock().validation += elapsed.saturating_sub(upstream);
+    }
+}
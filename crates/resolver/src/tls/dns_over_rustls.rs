@@ -13,14 +13,29 @@ use std::future::Future;
 use std::io;
 use std::net::SocketAddr;
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
 
+use futures_util::TryFutureExt;
 use once_cell::sync::Lazy;
-use rustls::{ClientConfig, RootCertStore};
+use parking_lot::Mutex;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::{
+    ClientSessionMemoryCache, ClientSessionStore, Resumption, Tls12ClientSessionValue,
+    Tls13ClientSessionValue, WebPkiServerVerifier,
+};
+use rustls::crypto::hash::HashAlgorithm;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{
+    CertificateError, ClientConfig, DigitallySignedStruct, HandshakeKind, NamedGroup, OtherError,
+    RootCertStore, SignatureScheme, SupportedCipherSuite,
+};
+use webpki::EndEntityCert;
 
-use crate::proto::error::ProtoError;
+use crate::proto::error::{ProtoError, ProtoErrorKind};
 use crate::proto::rustls::tls_client_stream::tls_client_connect_with_future;
-use crate::proto::rustls::TlsClientStream;
+use crate::proto::rustls::{crypto_provider, TlsClientStream};
 use crate::proto::tcp::DnsTcpStream;
 use crate::proto::BufDnsStreamHandle;
 
@@ -56,25 +71,298 @@ pub(crate) static CLIENT_CONFIG: Lazy<Result<Arc<ClientConfig>, ProtoError>> = L
     // If by the time we reach this point the root store remains empty then
     // our feature config hasn't resulted in a populated root store. Return an
     // early error rather than trying to validate a peer certificate without any
-    // trust anchors.
+    // trust anchors. Name servers with a `tls_config`, e.g. built from a custom
+    // store with `TlsClientConfig::new_with_roots`, never use this config.
     if root_store.is_empty() {
         return Err(ProtoError::from(
-         "no root certificates configured: you must enable the webpki-roots or native-certs feature".to_owned(),
+         "no root certificates configured: you must enable the webpki-roots or native-certs feature, or set a tls_config".to_owned(),
         ));
     }
 
-    let mut client_config =
-        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
-            .with_safe_default_protocol_versions()
-            .unwrap()
-            .with_root_certificates(root_store)
-            .with_no_client_auth();
+    Ok(Arc::new(roots_config(root_store)?))
+});
+
+/// Builds a client config trusting the roots, like the default one
+///
+/// The config uses the crypto provider of the default config, with SNI disabled likewise.
+pub(crate) fn roots_config(roots: RootCertStore) -> Result<ClientConfig, ProtoError> {
+    let mut client_config = ClientConfig::builder_with_provider(crypto_provider())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| ProtoError::from(format!("unsupported crypto provider: {e}")))?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
 
     // The port (853) of DOT is for dns dedicated, SNI is unnecessary. (ISP block by the SNI name)
+    //  Name servers requiring it are configured with `TlsClientConfig::with_sni`, which is used as is.
     client_config.enable_sni = false;
 
-    Ok(Arc::new(client_config))
-});
+    Ok(client_config)
+}
+
+/// Builds a client config authenticating with the certificate chain and its private key
+///
+/// The config uses the crypto provider of the default config, with SNI disabled likewise.
+pub(crate) fn client_auth_config(
+    roots: RootCertStore,
+    cert_chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+) -> Result<ClientConfig, ProtoError> {
+    let mut client_config = ClientConfig::builder_with_provider(crypto_provider())
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_client_auth_cert(cert_chain, key)?;
+    client_config.enable_sni = false;
+
+    Ok(client_config)
+}
+
+/// Replaces the verifier of the client config, to only accept the name servers whose certificate
+///  key is pinned
+///
+/// The certificates are validated with the `roots` first, an empty pin set disables pinning. The
+///  verifier uses the crypto provider of the config.
+pub(crate) fn pin_spki(
+    client_config: &mut ClientConfig,
+    roots: RootCertStore,
+    pinned_spki: Vec<[u8; 32]>,
+) -> Result<(), ProtoError> {
+    let provider = client_config.crypto_provider().clone();
+    let inner = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+        .build()
+        .map_err(|e| ProtoError::from(format!("invalid root certificates: {e}")))?;
+
+    let verifier: Arc<dyn ServerCertVerifier> = if pinned_spki.is_empty() {
+        inner
+    } else {
+        Arc::new(PinnedSpkiVerifier {
+            inner,
+            pinned_spki,
+            provider,
+        })
+    };
+    client_config.dangerous().set_certificate_verifier(verifier);
+
+    Ok(())
+}
+
+/// Validates the certificate chain, then checks the SHA-256 hash of the SubjectPublicKeyInfo of the
+///  leaf certificate against the pinned ones
+#[derive(Debug)]
+struct PinnedSpkiVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pinned_spki: Vec<[u8; 32]>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedSpkiVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+
+        let hash = spki_sha256(&self.provider, end_entity)?;
+        if self.pinned_spki.contains(&hash) {
+            Ok(verified)
+        } else {
+            let error = ProtoError::from(ProtoErrorKind::SpkiPinMismatch(
+                server_name.to_str().into_owned(),
+            ));
+            Err(CertificateError::Other(OtherError(Arc::new(error))).into())
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// Returns the SHA-256 hash of the SubjectPublicKeyInfo of the certificate, as pinned with
+///  [`pin_spki`]
+///
+/// The hash doesn't depend on the provider, so unlike the connections this doesn't choose the
+///  crypto provider of the default client config.
+pub(crate) fn spki_pin(cert: &CertificateDer<'_>) -> Option<[u8; 32]> {
+    let provider = match CryptoProvider::get_default() {
+        Some(provider) => provider.clone(),
+        None => Arc::new(rustls::crypto::ring::default_provider()),
+    };
+
+    spki_sha256(&provider, cert).ok()
+}
+
+/// Hashes the data with the SHA-256 implementation of the provider's cipher suites
+///
+/// The suites of both TLS versions are searched, as a provider may only support one of them.
+fn sha256(provider: &CryptoProvider, data: &[u8]) -> Option<[u8; 32]> {
+    let hash = provider
+        .cipher_suites
+        .iter()
+        .map(|suite| match suite {
+            SupportedCipherSuite::Tls12(suite) => suite.common.hash_provider,
+            SupportedCipherSuite::Tls13(suite) => suite.common.hash_provider,
+        })
+        .find(|hash| hash.algorithm() == HashAlgorithm::SHA256)?;
+
+    hash.hash(data).as_ref().try_into().ok()
+}
+
+/// Returns the SHA-256 hash of the DER encoded SubjectPublicKeyInfo of the certificate
+fn spki_sha256(
+    provider: &CryptoProvider,
+    cert: &CertificateDer<'_>,
+) -> Result<[u8; 32], rustls::Error> {
+    let cert = EndEntityCert::try_from(cert).map_err(|_| CertificateError::BadEncoding)?;
+    sha256(provider, &cert.subject_public_key_info()).ok_or_else(|| {
+        rustls::Error::General("no SHA-256 implementation in the crypto provider".to_owned())
+    })
+}
+
+/// Returns the error of [`PinnedSpkiVerifier`] rather than the I/O error of the handshake wrapping it
+pub(crate) fn pin_mismatch_error(error: ProtoError) -> ProtoError {
+    let ProtoErrorKind::Io(io) = error.kind() else {
+        return error;
+    };
+    let Some(rustls::Error::InvalidCertificate(CertificateError::Other(OtherError(other)))) =
+        io.get_ref().and_then(|e| e.downcast_ref::<rustls::Error>())
+    else {
+        return error;
+    };
+
+    match other.downcast_ref::<ProtoError>() {
+        Some(pin_mismatch) => pin_mismatch.clone(),
+        None => error,
+    }
+}
+
+/// The session caches of the client configs returned by [`SessionCache::client_config`]
+///
+/// The resumption store of a client config can't be read back, the connections find the cache
+///  counting their resumed handshakes here.
+static SESSION_CACHES: Lazy<Mutex<Vec<SessionCacheEntry>>> = Lazy::new(Mutex::default);
+
+type SessionCacheEntry = (Weak<ClientConfig>, Weak<SessionCache>);
+
+/// The TLS sessions of a single upstream, see `ResolverOpts::tls_session_cache_size`
+///
+/// Counts the handshakes which resumed one of its sessions, i.e. the full handshakes avoided.
+#[derive(Debug)]
+pub(crate) struct SessionCache {
+    sessions: ClientSessionMemoryCache,
+    resumptions: AtomicU64,
+}
+
+impl SessionCache {
+    pub(crate) fn new(size: usize) -> Self {
+        Self {
+            sessions: ClientSessionMemoryCache::new(size),
+            resumptions: AtomicU64::new(0),
+        }
+    }
+
+    /// Number of connections which resumed a cached session
+    pub(crate) fn resumptions(&self) -> u64 {
+        self.resumptions.load(Ordering::Relaxed)
+    }
+
+    /// Returns a copy of the client config, or of the default one, resuming sessions from this cache
+    pub(crate) fn client_config(
+        self: &Arc<Self>,
+        client_config: Option<&TlsClientConfig>,
+    ) -> Result<TlsClientConfig, ProtoError> {
+        let client_config = match client_config {
+            Some(TlsClientConfig(client_config)) => client_config.clone(),
+            None => CLIENT_CONFIG.clone()?,
+        };
+
+        let mut client_config = (*client_config).clone();
+        client_config.resumption = Resumption::store(self.clone());
+        let client_config = Arc::new(client_config);
+
+        let mut caches = SESSION_CACHES.lock();
+        caches.retain(|(config, cache)| config.strong_count() > 0 && cache.strong_count() > 0);
+        caches.push((Arc::downgrade(&client_config), Arc::downgrade(self)));
+        Ok(TlsClientConfig(client_config))
+    }
+
+    /// Counts a resumed handshake of a connection with the client config, if it has a session cache
+    fn resumed(client_config: &Arc<ClientConfig>) {
+        let cache = SESSION_CACHES
+            .lock()
+            .iter()
+            .find(|(config, _)| config.as_ptr() == Arc::as_ptr(client_config))
+            .and_then(|(_, cache)| cache.upgrade());
+
+        if let Some(cache) = cache {
+            cache.resumptions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl ClientSessionStore for SessionCache {
+    fn set_kx_hint(&self, server_name: ServerName<'static>, group: NamedGroup) {
+        self.sessions.set_kx_hint(server_name, group)
+    }
+
+    fn kx_hint(&self, server_name: &ServerName<'_>) -> Option<NamedGroup> {
+        self.sessions.kx_hint(server_name)
+    }
+
+    fn set_tls12_session(&self, server_name: ServerName<'static>, value: Tls12ClientSessionValue) {
+        self.sessions.set_tls12_session(server_name, value)
+    }
+
+    fn tls12_session(&self, server_name: &ServerName<'_>) -> Option<Tls12ClientSessionValue> {
+        self.sessions.tls12_session(server_name)
+    }
+
+    fn remove_tls12_session(&self, server_name: &ServerName<'static>) {
+        self.sessions.remove_tls12_session(server_name)
+    }
+
+    fn insert_tls13_ticket(
+        &self,
+        server_name: ServerName<'static>,
+        value: Tls13ClientSessionValue,
+    ) {
+        self.sessions.insert_tls13_ticket(server_name, value)
+    }
+
+    fn take_tls13_ticket(
+        &self,
+        server_name: &ServerName<'static>,
+    ) -> Option<Tls13ClientSessionValue> {
+        self.sessions.take_tls13_ticket(server_name)
+    }
+}
 
 #[allow(clippy::type_complexity)]
 pub(crate) fn new_tls_stream_with_future<S, F>(
@@ -103,7 +391,114 @@ where
             }
         }
     };
+
     let (stream, handle) =
-        tls_client_connect_with_future(future, socket_addr, dns_name, client_config);
+        tls_client_connect_with_future(future, socket_addr, dns_name, client_config.clone());
+    let stream = stream
+        .map_err(pin_mismatch_error)
+        .inspect_ok(move |stream| {
+            tracing::debug!(
+                "TLS connection to {socket_addr}: {:?}, {:?}, {:?}",
+                stream.protocol_version(),
+                stream.negotiated_cipher_suite(),
+                stream.handshake_kind(),
+            );
+            if stream.handshake_kind() == Some(HandshakeKind::Resumed) {
+                SessionCache::resumed(&client_config);
+            }
+        });
     (Box::pin(stream), handle)
 }
+
+#[cfg(test)]
+mod tests {
+    use rustls::crypto::ring::{cipher_suite, default_provider};
+
+    use super::*;
+
+    #[test]
+    fn test_sha256() {
+        // the SHA-256 hash of the empty input
+        let expected = [
+            0xe3, 0xb0, 0xc4, 0x42, 0x98, 0xfc, 0x1c, 0x14, 0x9a, 0xfb, 0xf4, 0xc8, 0x99, 0x6f,
+            0xb9, 0x24, 0x27, 0xae, 0x41, 0xe4, 0x64, 0x9b, 0x93, 0x4c, 0xa4, 0x95, 0x99, 0x1b,
+            0x78, 0x52, 0xb8, 0x55,
+        ];
+        assert_eq!(sha256(&default_provider(), &[]), Some(expected));
+
+        // a provider without any TLS 1.3 cipher suite still hashes with its TLS 1.2 ones
+        let tls12_provider = CryptoProvider {
+            cipher_suites: vec![cipher_suite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256],
+            ..default_provider()
+        };
+        assert_eq!(sha256(&tls12_provider, &[]), Some(expected));
+
+        let sha384_provider = CryptoProvider {
+            cipher_suites: vec![cipher_suite::TLS13_AES_256_GCM_SHA384],
+            ..default_provider()
+        };
+        assert_eq!(sha256(&sha384_provider, &[]), None);
+    }
+
+    #[test]
+    fn test_pinned_spki_client_auth() {
+        use std::path::Path;
+
+        use crate::config::TlsClientConfig;
+        use crate::proto::rustls::tls_server::{read_cert, read_key};
+
+        let test_data = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../tests/test-data");
+        let mut roots = RootCertStore::empty();
+        roots.add_parsable_certificates(read_cert(&test_data.join("ca.pem")).unwrap());
+        let cert_chain = read_cert(&test_data.join("cert.pem")).unwrap();
+        let key = read_key(&test_data.join("cert.key")).unwrap();
+        let pin = spki_pin(&cert_chain[0]).expect("failed to parse the certificate");
+
+        // the pin is checked once the chain is validated
+        let provider = Arc::new(default_provider());
+        let verifier = |pinned_spki| PinnedSpkiVerifier {
+            inner: WebPkiServerVerifier::builder_with_provider(
+                Arc::new(roots.clone()),
+                provider.clone(),
+            )
+            .build()
+            .unwrap(),
+            pinned_spki,
+            provider: provider.clone(),
+        };
+        let name = ServerName::try_from("ns.example.com").unwrap();
+        // within the validity of the test certificate, see `scripts/gen_certs.sh`
+        let now = UnixTime::since_unix_epoch(std::time::Duration::from_secs(1_735_689_600));
+        let verify = |verifier: PinnedSpkiVerifier| {
+            verifier.verify_server_cert(&cert_chain[0], &[], &name, &[], now)
+        };
+        verify(verifier(vec![[0; 32], pin])).unwrap();
+        let Err(rustls::Error::InvalidCertificate(CertificateError::Other(OtherError(error)))) =
+            verify(verifier(vec![[0; 32]]))
+        else {
+            panic!("another pinned key should be rejected");
+        };
+        assert!(matches!(
+            error.downcast_ref::<ProtoError>().map(ProtoError::kind),
+            Some(ProtoErrorKind::SpkiPinMismatch(name)) if name == "ns.example.com"
+        ));
+
+        // pinning keeps the client certificate of the config
+        let config = TlsClientConfig::new_with_client_auth(roots.clone(), cert_chain, key)
+            .and_then(|config| config.with_pinned_spki(roots, vec![pin]))
+            .expect("failed to build the client config");
+        assert!(config.0.client_auth_cert_resolver.has_certs());
+        assert!(!config.0.enable_sni);
+    }
+
+    #[test]
+    fn test_spki_sha256_bad_encoding() {
+        let cert = CertificateDer::from(&[0x30, 0x00][..]);
+        assert_eq!(
+            spki_sha256(&default_provider(), &cert),
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::BadEncoding
+            ))
+        );
+    }
+}
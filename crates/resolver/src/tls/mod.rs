@@ -5,7 +5,23 @@
 // https://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
+use std::fmt::{self, Display};
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
 use cfg_if::cfg_if;
+use futures_util::stream::{Stream, StreamExt};
+use futures_util::TryFutureExt;
+
+use crate::config::{NameServerConfig, TlsBackend};
+use crate::proto::error::ProtoError;
+use crate::proto::runtime::{RuntimeProvider, TokioTime};
+use crate::proto::tcp::DnsTcpStream;
+use crate::proto::xfer::{DnsClientStream, SerialMessage};
+use crate::proto::BufDnsStreamHandle;
 
 mod dns_over_native_tls;
 mod dns_over_openssl;
@@ -13,18 +29,133 @@ mod dns_over_rustls;
 
 cfg_if! {
     if #[cfg(feature = "dns-over-rustls")] {
-        pub(crate) use self::dns_over_rustls::new_tls_stream_with_future;
-        #[cfg(any(feature = "dns-over-https-rustls", feature = "dns-over-quic", feature = "dns-over-h3"))]
-        pub(crate) use self::dns_over_rustls::CLIENT_CONFIG;
+        pub(crate) use self::dns_over_rustls::{
+            client_auth_config, pin_spki, roots_config, spki_pin, SessionCache, CLIENT_CONFIG,
+        };
+        #[cfg(feature = "dns-over-https-rustls")]
+        pub(crate) use self::dns_over_rustls::pin_mismatch_error;
+        pub use crate::proto::rustls::set_crypto_provider;
+        pub(crate) const DEFAULT_BACKEND: TlsBackend = TlsBackend::Rustls;
     } else if #[cfg(feature = "dns-over-native-tls")] {
-        pub(crate) use self::dns_over_native_tls::new_tls_stream_with_future;
+        pub(crate) const DEFAULT_BACKEND: TlsBackend = TlsBackend::NativeTls;
     } else if #[cfg(feature = "dns-over-openssl")] {
-        pub(crate) use self::dns_over_openssl::new_tls_stream_with_future;
+        pub(crate) const DEFAULT_BACKEND: TlsBackend = TlsBackend::OpenSsl;
     } else {
         compile_error!("One of the dns-over-rustls, dns-over-native-tls, or dns-over-openssl must be enabled for dns-over-tls features");
     }
 }
 
+/// A DNS over TLS stream of any of the enabled backends, see [`TlsBackend`]
+pub(crate) enum TlsBackendStream<S: DnsTcpStream> {
+    #[cfg(feature = "dns-over-rustls")]
+    Rustls(crate::proto::rustls::TlsClientStream<S>),
+    #[cfg(feature = "dns-over-native-tls")]
+    NativeTls(crate::proto::native_tls::TlsClientStream<S>),
+    #[cfg(feature = "dns-over-openssl")]
+    OpenSsl(crate::proto::openssl::TlsClientStream<S>),
+}
+
+impl<S: DnsTcpStream> DnsClientStream for TlsBackendStream<S> {
+    type Time = TokioTime;
+
+    fn name_server_addr(&self) -> SocketAddr {
+        match self {
+            #[cfg(feature = "dns-over-rustls")]
+            Self::Rustls(stream) => stream.name_server_addr(),
+            #[cfg(feature = "dns-over-native-tls")]
+            Self::NativeTls(stream) => stream.name_server_addr(),
+            #[cfg(feature = "dns-over-openssl")]
+            Self::OpenSsl(stream) => stream.name_server_addr(),
+        }
+    }
+}
+
+impl<S: DnsTcpStream> Display for TlsBackendStream<S> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "dns-over-rustls")]
+            Self::Rustls(stream) => Display::fmt(stream, formatter),
+            #[cfg(feature = "dns-over-native-tls")]
+            Self::NativeTls(stream) => Display::fmt(stream, formatter),
+            #[cfg(feature = "dns-over-openssl")]
+            Self::OpenSsl(stream) => Display::fmt(stream, formatter),
+        }
+    }
+}
+
+impl<S: DnsTcpStream> Stream for TlsBackendStream<S> {
+    type Item = Result<SerialMessage, ProtoError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.get_mut() {
+            #[cfg(feature = "dns-over-rustls")]
+            Self::Rustls(stream) => stream.poll_next_unpin(cx),
+            #[cfg(feature = "dns-over-native-tls")]
+            Self::NativeTls(stream) => stream.poll_next_unpin(cx),
+            #[cfg(feature = "dns-over-openssl")]
+            Self::OpenSsl(stream) => stream.poll_next_unpin(cx),
+        }
+    }
+}
+
+/// Connects to the name server over TLS with the `backend`
+///
+/// The rustls backend uses the `tls_config` of the name server, the other ones their default
+///  configuration.
+#[allow(clippy::type_complexity)]
+#[cfg_attr(
+    not(any(feature = "dns-over-native-tls", feature = "dns-over-openssl")),
+    allow(unused_variables)
+)]
+pub(crate) fn new_tls_stream_with_backend<P: RuntimeProvider, F>(
+    backend: TlsBackend,
+    future: F,
+    config: &NameServerConfig,
+    provider: P,
+) -> (
+    Pin<Box<dyn Future<Output = Result<TlsBackendStream<P::Tcp>, ProtoError>> + Send>>,
+    BufDnsStreamHandle,
+)
+where
+    F: Future<Output = io::Result<P::Tcp>> + Send + Unpin + 'static,
+{
+    let socket_addr = config.socket_addr;
+    let dns_name = config.tls_dns_name.clone().unwrap_or_default();
+
+    match backend {
+        #[cfg(feature = "dns-over-rustls")]
+        TlsBackend::Rustls => {
+            let (stream, handle) = dns_over_rustls::new_tls_stream_with_future(
+                future,
+                socket_addr,
+                dns_name,
+                config.tls_config.clone(),
+            );
+            (Box::pin(stream.map_ok(TlsBackendStream::Rustls)), handle)
+        }
+        #[cfg(feature = "dns-over-native-tls")]
+        TlsBackend::NativeTls => {
+            let (stream, handle) = dns_over_native_tls::new_tls_stream_with_future(
+                future,
+                socket_addr,
+                dns_name,
+                provider,
+            );
+            (Box::pin(stream.map_ok(TlsBackendStream::NativeTls)), handle)
+        }
+        #[cfg(feature = "dns-over-openssl")]
+        TlsBackend::OpenSsl => {
+            let (stream, handle) = dns_over_openssl::new_tls_stream_with_future(
+                future,
+                socket_addr,
+                dns_name,
+                provider,
+            );
+            (Box::pin(stream.map_ok(TlsBackendStream::OpenSsl)), handle)
+        }
+    }
+}
+
 #[cfg(any(feature = "dns-over-native-tls", feature = "dns-over-rustls"))]
 #[cfg(any(feature = "webpki-roots", feature = "native-certs"))]
 #[cfg(test)]
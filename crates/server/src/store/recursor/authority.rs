@@ -65,6 +65,8 @@ impl RecursiveAuthority {
                 #[cfg(feature = "dns-over-rustls")]
                 tls_config: None,
                 bind_addr: None, // TODO: need to support bind addresses
+                timeout: None,
+                weight: 0,
             });
 
             roots.push(NameServerConfig {
@@ -76,6 +78,8 @@ impl RecursiveAuthority {
                 #[cfg(feature = "dns-over-rustls")]
                 tls_config: None,
                 bind_addr: None,
+                timeout: None,
+                weight: 0,
             });
         }
 
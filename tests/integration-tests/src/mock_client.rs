@@ -140,6 +140,10 @@ impl<O: OnSend + Unpin> ConnectionProvider for MockConnProvider<O> {
             self.on_send.clone(),
         ))))
     }
+
+    fn spawn_bg(&self, task: Pin<Box<dyn Future<Output = ()> + Send>>) {
+        tokio::spawn(task);
+    }
 }
 
 #[derive(Clone)]
@@ -1,22 +1,30 @@
 #![cfg(feature = "dnssec")]
 
+use std::pin::Pin;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex as StdMutex};
 
 use futures::executor::block_on;
+use futures::{Stream, TryStreamExt};
 use tokio::runtime::Runtime;
 
 use hickory_client::client::{Client, ClientHandle, MemoizeClientHandle};
-use hickory_proto::op::ResponseCode;
-use hickory_proto::rr::dnssec::{Proof, TrustAnchor};
-use hickory_proto::rr::rdata::A;
+use hickory_proto::error::ProtoError;
+use hickory_proto::op::{Query, ResponseCode};
+use hickory_proto::rr::dnssec::rdata::{DNSSECRData, DS};
+use hickory_proto::rr::dnssec::{Algorithm, DigestType, KeyPair, Proof, SigSigner, TrustAnchor};
+use hickory_proto::rr::rdata::{A, SOA};
 use hickory_proto::rr::Name;
-use hickory_proto::rr::{DNSClass, RData, RecordType};
+use hickory_proto::rr::{DNSClass, RData, Record, RecordType};
 use hickory_proto::runtime::TokioRuntimeProvider;
 use hickory_proto::tcp::TcpClientStream;
 use hickory_proto::udp::UdpClientStream;
+use hickory_proto::xfer::{DnsHandle, DnsRequest, DnsRequestOptions, DnsResponse, FirstAnswer};
 use hickory_proto::DnssecDnsHandle;
-use hickory_server::authority::{Authority, Catalog};
+use hickory_server::authority::{Authority, AuthorityObject, Catalog, ZoneType};
+use hickory_server::dnssec::NxProofKind;
+use hickory_server::store::in_memory::InMemoryAuthority;
 
 use hickory_integration::example_authority::create_secure_example;
 use hickory_integration::{TestClientStream, GOOGLE_V4};
@@ -96,6 +104,243 @@ where
     assert_eq!(response.response_code(), ResponseCode::NXDomain);
 }
 
+#[test]
+fn test_checking_disabled_bogus_nonet() {
+    with_nonet_upstream(BogusUpstream, test_checking_disabled_bogus);
+}
+
+fn test_checking_disabled_bogus<H>(client: DnssecDnsHandle<H>, io_loop: Runtime)
+where
+    H: ClientHandle + Sync + 'static,
+{
+    let query = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+
+    // the validating upstream refuses to return the bogus records
+    let response = io_loop.block_on(
+        client
+            .lookup(query.clone(), DnsRequestOptions::default())
+            .first_answer(),
+    );
+    assert!(response.is_err());
+
+    let mut options = DnsRequestOptions::default();
+    options.checking_disabled = true;
+    let response = io_loop
+        .block_on(client.lookup(query, options).first_answer())
+        .expect("query failed");
+
+    let records = response
+        .answers()
+        .iter()
+        .filter(|r| r.record_type() == RecordType::A)
+        .collect::<Vec<_>>();
+    assert!(!records.is_empty());
+    for record in records {
+        assert_eq!(record.data(), &RData::A(A::new(10, 0, 0, 66)));
+        assert_eq!(record.proof(), Proof::Bogus);
+    }
+}
+
+/// Simulates a validating upstream for a bogus zone: A records are altered after signing, and
+///  SERVFAIL is returned for them unless the CD bit was set on the request.
+#[derive(Clone)]
+struct BogusUpstream<H>(H);
+
+impl<H: DnsHandle> DnsHandle for BogusUpstream<H> {
+    type Response = Pin<Box<dyn Stream<Item = Result<DnsResponse, ProtoError>> + Send>>;
+
+    fn send<R: Into<DnsRequest> + Unpin + Send + 'static>(&self, request: R) -> Self::Response {
+        let request = request.into();
+        let checking_disabled = request.checking_disabled();
+
+        Box::pin(self.0.send(request).map_ok(move |mut response| {
+            let mut bogus = false;
+            for record in response.answers_mut() {
+                if record.record_type() == RecordType::A {
+                    record.set_data(RData::A(A::new(10, 0, 0, 66)));
+                    bogus = true;
+                }
+            }
+
+            if bogus && !checking_disabled {
+                response.take_answers();
+                response.set_response_code(ResponseCode::ServFail);
+            }
+
+            response
+        }))
+    }
+}
+
+#[test]
+fn test_ds_chain_nonet() {
+    let child_origin = Name::from_str("sub.example.com.").unwrap();
+    let name = Name::from_str("www.sub.example.com.").unwrap();
+
+    // the child zone is signed with its own key
+    let mut child = InMemoryAuthority::empty(
+        child_origin.clone(),
+        ZoneType::Primary,
+        false,
+        Some(NxProofKind::Nsec),
+    );
+    child.upsert_mut(
+        Record::from_rdata(
+            child_origin.clone(),
+            3600,
+            RData::SOA(SOA::new(
+                Name::from_str("ns.sub.example.com.").unwrap(),
+                Name::from_str("admin.sub.example.com.").unwrap(),
+                1,
+                7200,
+                3600,
+                1209600,
+                3600,
+            )),
+        ),
+        0,
+    );
+    child.upsert_mut(
+        Record::from_rdata(name.clone(), 3600, RData::A(A::new(10, 0, 0, 1))),
+        0,
+    );
+
+    let key = KeyPair::from_rsa(openssl::rsa::Rsa::generate(2_048).unwrap()).unwrap();
+    let child_dnskey = key.to_dnskey(Algorithm::RSASHA256).unwrap();
+    let signer = SigSigner::dnssec(
+        child_dnskey.clone(),
+        key,
+        child_origin.clone(),
+        time::Duration::weeks(1).try_into().unwrap(),
+    );
+    child.add_zone_signing_key_mut(signer).unwrap();
+    child.secure_zone_mut().unwrap();
+
+    // the parent zone, which is the trust anchor, holds the DS record for the child zone
+    let mut parent = create_secure_example();
+    let ds = DS::new(
+        child_dnskey.calculate_key_tag().unwrap(),
+        Algorithm::RSASHA256,
+        DigestType::SHA256,
+        child_dnskey
+            .to_digest(&child_origin, DigestType::SHA256)
+            .unwrap()
+            .as_ref()
+            .to_vec(),
+    );
+    parent.upsert_mut(
+        Record::from_rdata(
+            child_origin.clone(),
+            3600,
+            RData::DNSSEC(DNSSECRData::DS(ds.clone())),
+        ),
+        0,
+    );
+    parent.secure_zone_mut().unwrap();
+
+    let trust_anchor = {
+        let signers = block_on(parent.secure_keys());
+        let public_key = signers
+            .first()
+            .expect("expected a key in the authority")
+            .key()
+            .to_public_key()
+            .expect("could not convert keypair to public_key");
+
+        let mut trust_anchor = TrustAnchor::new();
+        trust_anchor.insert_trust_anchor(&public_key);
+
+        Arc::new(trust_anchor)
+    };
+
+    let parent_origin = parent.origin().clone();
+    let parent: Arc<dyn AuthorityObject> = Arc::new(parent);
+
+    // the DS record is only served by the parent zone, as with a real delegation
+    let mut all_zones = Catalog::new();
+    all_zones.upsert(parent_origin.clone(), vec![parent.clone()]);
+    all_zones.upsert(child_origin.clone().into(), vec![Arc::new(child)]);
+    let mut parent_zone = Catalog::new();
+    parent_zone.upsert(parent_origin, vec![parent]);
+
+    let io_loop = Runtime::new().unwrap();
+    let upstream = DelegatingUpstream {
+        all_zones: nonet_client(all_zones, &io_loop),
+        parent_zone: nonet_client(parent_zone, &io_loop),
+        ds_queries: Arc::new(AtomicUsize::new(0)),
+    };
+    let ds_queries = upstream.ds_queries.clone();
+    let client = DnssecDnsHandle::with_trust_anchor(upstream, trust_anchor);
+
+    let query = Query::query(name.clone(), RecordType::A);
+    let lookup = || {
+        let response = io_loop
+            .block_on(
+                client
+                    .lookup(query.clone(), DnsRequestOptions::default())
+                    .first_answer(),
+            )
+            .expect("query failed");
+
+        let record = &response.answers()[0];
+        assert_eq!(record.data(), &RData::A(A::new(10, 0, 0, 1)));
+        assert_eq!(record.proof(), Proof::Secure);
+    };
+
+    // the DS record at the zone cut was fetched to build the chain of trust
+    lookup();
+    let fetched = ds_queries.load(Ordering::Relaxed);
+    assert!(fetched > 0);
+
+    let chain = client.ds_chain(&name);
+    assert_eq!(chain.len(), 1);
+    assert_eq!(chain[0].name(), &child_origin);
+    assert_eq!(chain[0].data(), &ds);
+
+    // and is served from the cache afterwards
+    lookup();
+    assert_eq!(ds_queries.load(Ordering::Relaxed), fetched);
+}
+
+fn nonet_client(catalog: Catalog, io_loop: &Runtime) -> MemoizeClientHandle<Client> {
+    let (stream, sender) = TestClientStream::new(Arc::new(StdMutex::new(catalog)));
+    let client = Client::new(stream, sender, None);
+
+    let (client, bg) = io_loop
+        .block_on(client)
+        .expect("failed to create new client");
+
+    hickory_proto::runtime::spawn_bg(io_loop, bg);
+    MemoizeClientHandle::new(client)
+}
+
+/// Sends DS queries to the name server of the parent zone, and all other queries to a name server
+///  for all zones.
+#[derive(Clone)]
+struct DelegatingUpstream<H> {
+    all_zones: H,
+    parent_zone: H,
+    ds_queries: Arc<AtomicUsize>,
+}
+
+impl<H: DnsHandle> DnsHandle for DelegatingUpstream<H> {
+    type Response = H::Response;
+
+    fn send<R: Into<DnsRequest> + Unpin + Send + 'static>(&self, request: R) -> Self::Response {
+        let request = request.into();
+        if request
+            .queries()
+            .iter()
+            .any(|query| query.query_type() == RecordType::DS)
+        {
+            self.ds_queries.fetch_add(1, Ordering::Relaxed);
+            self.parent_zone.send(request)
+        } else {
+            self.all_zones.send(request)
+        }
+    }
+}
+
 // TODO: NSEC response code wrong in Hickory DNS? Issue #53
 // #[test]
 // fn test_nsec_query_type_nonet() {
@@ -192,6 +437,15 @@ where
 fn with_nonet<F>(test: F)
 where
     F: Fn(DnssecDnsHandle<MemoizeClientHandle<Client>>, Runtime),
+{
+    with_nonet_upstream(|client| client, test)
+}
+
+fn with_nonet_upstream<H, U, F>(upstream: U, test: F)
+where
+    H: ClientHandle + Sync,
+    U: Fn(MemoizeClientHandle<Client>) -> H,
+    F: Fn(DnssecDnsHandle<H>, Runtime),
 {
     let succeeded = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
     let succeeded_clone = succeeded.clone();
@@ -240,7 +494,7 @@ where
         .expect("failed to create new client");
 
     hickory_proto::runtime::spawn_bg(&io_loop, bg);
-    let client = MemoizeClientHandle::new(client);
+    let client = upstream(MemoizeClientHandle::new(client));
     let secure_client = DnssecDnsHandle::with_trust_anchor(client, trust_anchor);
 
     test(secure_client, io_loop);
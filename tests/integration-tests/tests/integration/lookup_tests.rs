@@ -1,5 +1,7 @@
 use std::{
+    future::{ready, Future},
     net::*,
+    pin::Pin,
     str::FromStr,
     sync::{Arc, Mutex as StdMutex},
 };
@@ -7,17 +9,22 @@ use std::{
 use tokio::runtime::Runtime;
 
 use hickory_proto::{
-    op::Query,
+    error::ProtoError,
+    op::{Message, Query},
     rr::{rdata::A, DNSClass, Name, RData, Record, RecordType},
     runtime::TokioTime,
     xfer::{DnsExchange, DnsMultiplexer, DnsResponse},
 };
 use hickory_resolver::{
     caching_client::CachingClient,
-    config::LookupIpStrategy,
+    config::{
+        AnswerTypeMismatchPolicy, LookupIpStrategy, NameServerConfigGroup, ResolveHosts,
+        ResolverConfig, ResolverOpts,
+    },
+    dns_lru::{DnsLru, TtlConfig},
     lookup::{Lookup, LookupFuture},
     lookup_ip::LookupIpFuture,
-    Hosts,
+    Hosts, Resolver,
 };
 use hickory_server::{
     authority::{Authority, Catalog},
@@ -542,3 +549,195 @@ fn test_forward_ns() {
         },
     }
 }
+
+/// Answers every query with the message, or fails them without a message
+#[derive(Clone)]
+struct AnswerOnSend(Option<Message>);
+
+impl OnSend for AnswerOnSend {
+    fn on_send<E>(
+        &self,
+        _response: Result<DnsResponse, E>,
+    ) -> Pin<Box<dyn Future<Output = Result<DnsResponse, E>> + Send>>
+    where
+        E: From<ProtoError> + Send + 'static,
+    {
+        let response = match &self.0 {
+            Some(message) => Ok(DnsResponse::from_message(message.clone()).unwrap()),
+            None => Err(E::from(ProtoError::from("no answer"))),
+        };
+        Box::pin(ready(response))
+    }
+}
+
+#[test]
+fn test_shared_cache() {
+    let query = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+    let record = v4_record(query.name().clone(), Ipv4Addr::new(127, 0, 0, 2));
+    let answer = message(query.clone(), vec![record], vec![], vec![]);
+
+    let config = ResolverConfig::from_parts(
+        None,
+        vec![],
+        NameServerConfigGroup::from_ips_clear(&[IpAddr::V4(Ipv4Addr::LOCALHOST)], 53, true),
+    );
+    let mut options = ResolverOpts::default();
+    options.use_hosts_file = ResolveHosts::Never;
+
+    let cache = DnsLru::new(8, TtlConfig::default());
+    let resolver = |on_send: AnswerOnSend| {
+        Resolver::new_with_cache(
+            config.clone(),
+            options.clone(),
+            MockConnProvider { on_send },
+            cache.clone(),
+        )
+    };
+    let answering = resolver(AnswerOnSend(Some(answer)));
+    let failing = resolver(AnswerOnSend(None));
+
+    let io_loop = Runtime::new().unwrap();
+    let lookup = io_loop
+        .block_on(answering.lookup(query.name().clone(), RecordType::A))
+        .expect("lookup should succeed");
+    assert_eq!(
+        *lookup.iter().next().unwrap(),
+        RData::A(A::new(127, 0, 0, 2))
+    );
+
+    // the other resolver can't reach its name servers, the lookup is served from the shared cache
+    let lookup = io_loop
+        .block_on(failing.lookup(query.name().clone(), RecordType::A))
+        .expect("lookup should be served from the cache");
+    assert_eq!(
+        *lookup.iter().next().unwrap(),
+        RData::A(A::new(127, 0, 0, 2))
+    );
+}
+
+#[test]
+fn test_answer_type_mismatch_rejected() {
+    use hickory_proto::error::ProtoErrorKind;
+    use std::time::Instant;
+
+    // A broken middlebox answers the AAAA query with A records only.
+    let query = Query::query(
+        Name::from_str("www.example.com.").unwrap(),
+        RecordType::AAAA,
+    );
+    let record = v4_record(query.name().clone(), Ipv4Addr::new(127, 0, 0, 2));
+    let answer = message(query.clone(), vec![record], vec![], vec![]);
+
+    let config = ResolverConfig::from_parts(
+        None,
+        vec![],
+        NameServerConfigGroup::from_ips_clear(&[IpAddr::V4(Ipv4Addr::LOCALHOST)], 53, true),
+    );
+    let mut options = ResolverOpts::default();
+    options.use_hosts_file = ResolveHosts::Never;
+    options.answer_type_mismatch_policy = AnswerTypeMismatchPolicy::Reject;
+
+    let cache = DnsLru::new(8, TtlConfig::default());
+    let resolver = Resolver::new_with_cache(
+        config,
+        options,
+        MockConnProvider {
+            on_send: AnswerOnSend(Some(answer)),
+        },
+        cache.clone(),
+    );
+
+    let io_loop = Runtime::new().unwrap();
+    let error = io_loop
+        .block_on(resolver.lookup(query.name().clone(), RecordType::AAAA))
+        .expect_err("the mismatched response should be rejected");
+    assert!(matches!(
+        error.proto().map(ProtoError::kind),
+        Some(ProtoErrorKind::AnswerTypeMismatch {
+            record_type: RecordType::A,
+            ..
+        })
+    ));
+
+    // nothing was cached, neither for the query nor for the type of the answer
+    let now = Instant::now();
+    assert!(cache.get(&query, now).is_none());
+    let a_query = Query::query(query.name().clone(), RecordType::A);
+    assert!(cache.get(&a_query, now).is_none());
+}
+
+#[cfg(feature = "dnssec")]
+#[test]
+fn test_require_secure() {
+    use hickory_proto::rr::dnssec::Proof;
+    use hickory_resolver::{config::LookupOptions, error::ResolveErrorKind};
+    use std::time::Instant;
+
+    let config = ResolverConfig::from_parts(
+        None,
+        vec![],
+        NameServerConfigGroup::from_ips_clear(&[IpAddr::V4(Ipv4Addr::LOCALHOST)], 53, true),
+    );
+    let mut options = ResolverOpts::default();
+    options.use_hosts_file = ResolveHosts::Never;
+
+    // the records are served from the cache, as validated by another resolver
+    let cache = DnsLru::new(8, TtlConfig::default());
+    let insert = |name: &str, proof: Proof| {
+        let query = Query::query(Name::from_str(name).unwrap(), RecordType::A);
+        let mut record = v4_record(query.name().clone(), Ipv4Addr::new(127, 0, 0, 2));
+        record.set_proof(proof);
+        cache.insert_records(query, [record].into_iter(), Instant::now());
+    };
+    insert("signed.example.com.", Proof::Secure);
+    insert("unsigned.example.com.", Proof::Insecure);
+
+    // the insecure records are queried again, upstream still serves them unsigned
+    let query = Query::query(
+        Name::from_str("unsigned.example.com.").unwrap(),
+        RecordType::A,
+    );
+    let record = v4_record(query.name().clone(), Ipv4Addr::new(127, 0, 0, 2));
+    let unsigned = message(query, vec![record], vec![], vec![]);
+
+    let resolver = Resolver::new_with_cache(
+        config,
+        options,
+        MockConnProvider {
+            on_send: AnswerOnSend(Some(unsigned)),
+        },
+        cache,
+    );
+    let mut require_secure = LookupOptions::default();
+    require_secure.require_secure = true;
+
+    let io_loop = Runtime::new().unwrap();
+    let lookup = io_loop
+        .block_on(resolver.lookup_with_options(
+            "signed.example.com.",
+            RecordType::A,
+            require_secure.clone(),
+        ))
+        .expect("secure lookup should succeed");
+    assert_eq!(
+        *lookup.iter().next().unwrap(),
+        RData::A(A::new(127, 0, 0, 2))
+    );
+
+    let error = io_loop
+        .block_on(resolver.lookup_with_options(
+            "unsigned.example.com.",
+            RecordType::A,
+            require_secure,
+        ))
+        .expect_err("insecure lookup should fail");
+    assert!(
+        matches!(error.kind(), ResolveErrorKind::NotSecure { query } if query.name().to_ascii() == "unsigned.example.com."),
+        "{error:?}"
+    );
+
+    // other lookups of the same resolver are not affected
+    io_loop
+        .block_on(resolver.lookup("unsigned.example.com.", RecordType::A))
+        .expect("lookup without require_secure should succeed");
+}
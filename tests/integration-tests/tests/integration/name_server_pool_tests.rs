@@ -4,19 +4,26 @@ use std::pin::Pin;
 use std::str::FromStr;
 use std::sync::{
     atomic::{AtomicIsize, Ordering},
-    Arc,
+    Arc, Mutex,
 };
 use std::task::Poll;
+use std::time::Duration;
 
 use futures::executor::block_on;
 
 use hickory_integration::mock_client::*;
 use hickory_proto::error::{ProtoError, ProtoErrorKind};
-use hickory_proto::op::{Query, ResponseCode};
+use hickory_proto::op::{Edns, Message, Query, ResponseCode};
 use hickory_proto::rr::{Name, RecordType};
 use hickory_proto::xfer::{DnsHandle, DnsResponse, FirstAnswer, Protocol};
-use hickory_resolver::config::{NameServerConfig, ResolverOpts, ServerOrderingStrategy};
-use hickory_resolver::name_server::{NameServer, NameServerPool};
+use hickory_resolver::config::{
+    AnswerDiscrepancyPolicy, CircuitBreakerConfig, EdnsAbsentPolicy, NameServerConfig,
+    ProtocolPreference, Rate, RecursionUnavailablePolicy, ResolverOpts, ServerOrderingStrategy,
+    ServerSelectionStrategy, TruncatedTcpPolicy,
+};
+use hickory_resolver::name_server::{
+    AnswerDiscrepancy, CircuitState, LocalConnectivity, NameServer, NameServerPool,
+};
 
 const DEFAULT_SERVER_ADDR: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
 
@@ -28,7 +35,14 @@ fn mock_nameserver(
     messages: Vec<Result<DnsResponse, ProtoError>>,
     options: ResolverOpts,
 ) -> MockedNameServer<DefaultOnSend> {
-    mock_nameserver_on_send_nx(messages, options, DefaultOnSend, DEFAULT_SERVER_ADDR, false)
+    mock_nameserver_on_send_nx(
+        messages,
+        options,
+        DefaultOnSend,
+        DEFAULT_SERVER_ADDR,
+        false,
+        0,
+    )
 }
 
 #[cfg(test)]
@@ -37,7 +51,7 @@ fn mock_nameserver_with_addr(
     addr: IpAddr,
     options: ResolverOpts,
 ) -> MockedNameServer<DefaultOnSend> {
-    mock_nameserver_on_send_nx(messages, options, DefaultOnSend, addr, false)
+    mock_nameserver_on_send_nx(messages, options, DefaultOnSend, addr, false, 0)
 }
 
 #[cfg(test)]
@@ -52,6 +66,7 @@ fn mock_nameserver_trust_nx(
         DefaultOnSend,
         DEFAULT_SERVER_ADDR,
         trust_negative_responses,
+        0,
     )
 }
 
@@ -61,7 +76,7 @@ fn mock_nameserver_on_send<O: OnSend + Unpin>(
     options: ResolverOpts,
     on_send: O,
 ) -> MockedNameServer<O> {
-    mock_nameserver_on_send_nx(messages, options, on_send, DEFAULT_SERVER_ADDR, false)
+    mock_nameserver_on_send_nx(messages, options, on_send, DEFAULT_SERVER_ADDR, false, 0)
 }
 
 #[cfg(test)]
@@ -71,6 +86,7 @@ fn mock_nameserver_on_send_nx<O: OnSend + Unpin>(
     on_send: O,
     addr: IpAddr,
     trust_negative_responses: bool,
+    weight: u32,
 ) -> MockedNameServer<O> {
     let conn_provider = MockConnProvider {
         on_send: on_send.clone(),
@@ -87,6 +103,8 @@ fn mock_nameserver_on_send_nx<O: OnSend + Unpin>(
             #[cfg(any(feature = "dns-over-rustls", feature = "dns-over-https-rustls"))]
             tls_config: None,
             bind_addr: None,
+            timeout: None,
+            weight,
         },
         options,
         client,
@@ -231,6 +249,488 @@ fn test_datagram_stream_upgrade_on_truncation_despite_udp() {
     assert_eq!(response.answers(), &[tcp_record1, tcp_record2]);
 }
 
+#[test]
+fn test_truncated_stream_response() {
+    // Both UDP and TCP return truncated responses, the partial data must never be returned.
+
+    let query = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+
+    let partial_record = v4_record(query.name().clone(), Ipv4Addr::LOCALHOST);
+    let tcp_record = v4_record(query.name().clone(), Ipv4Addr::new(127, 0, 0, 2));
+
+    let mut truncated_message = message(query.clone(), vec![partial_record], vec![], vec![]);
+    truncated_message.set_truncated(true);
+    let tcp_message = message(query.clone(), vec![tcp_record.clone()], vec![], vec![]);
+
+    let pool = |policy: TruncatedTcpPolicy| {
+        let mut options = ResolverOpts::default();
+        options.num_concurrent_reqs = 1;
+        options.server_ordering_strategy = ServerOrderingStrategy::UserProvidedOrder;
+        options.truncated_tcp_policy = policy;
+
+        let udp_nameserver = mock_nameserver(
+            vec![Ok(
+                DnsResponse::from_message(truncated_message.clone()).unwrap()
+            )],
+            Default::default(),
+        );
+        let truncated_nameserver = mock_nameserver_with_addr(
+            vec![Ok(
+                DnsResponse::from_message(truncated_message.clone()).unwrap()
+            )],
+            Ipv4Addr::new(128, 0, 0, 1).into(),
+            Default::default(),
+        );
+        let tcp_nameserver = mock_nameserver_with_addr(
+            vec![Ok(DnsResponse::from_message(tcp_message.clone()).unwrap())],
+            Ipv4Addr::new(129, 0, 0, 1).into(),
+            Default::default(),
+        );
+
+        mock_nameserver_pool(
+            vec![udp_nameserver],
+            vec![truncated_nameserver, tcp_nameserver],
+            None,
+            options,
+        )
+    };
+
+    // the next TCP name server returns the complete response
+    let request = message(query.clone(), vec![], vec![], vec![]);
+    let response = block_on(pool(TruncatedTcpPolicy::Retry).send(request).first_answer())
+        .expect("lookup should be retried on the next TCP name server");
+    assert_eq!(response.answers(), &[tcp_record]);
+
+    let request = message(query, vec![], vec![], vec![]);
+    let error = block_on(pool(TruncatedTcpPolicy::Fail).send(request).first_answer())
+        .expect_err("lookup should fail on the truncated TCP response");
+    assert!(matches!(
+        error.kind(),
+        ProtoErrorKind::TruncatedStreamResponse
+    ));
+}
+
+#[test]
+fn test_oversized_stream_response() {
+    // A TCP response larger than the maximum response size is rejected like a truncated one.
+
+    let query = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+
+    let records = (0..16)
+        .map(|i| v4_record(query.name().clone(), Ipv4Addr::new(127, 0, 0, i)))
+        .collect::<Vec<_>>();
+    let oversized_message = message(query.clone(), records, vec![], vec![]);
+    let tcp_record = v4_record(query.name().clone(), Ipv4Addr::new(127, 0, 1, 1));
+    let tcp_message = message(query.clone(), vec![tcp_record.clone()], vec![], vec![]);
+
+    let pool = |policy: TruncatedTcpPolicy| {
+        let mut options = ResolverOpts::default();
+        options.num_concurrent_reqs = 1;
+        options.server_ordering_strategy = ServerOrderingStrategy::UserProvidedOrder;
+        options.truncated_tcp_policy = policy;
+        options.max_response_size = Some(128);
+
+        let oversized_nameserver = mock_nameserver_with_addr(
+            vec![Ok(
+                DnsResponse::from_message(oversized_message.clone()).unwrap()
+            )],
+            Ipv4Addr::new(128, 0, 0, 1).into(),
+            Default::default(),
+        );
+        let tcp_nameserver = mock_nameserver_with_addr(
+            vec![Ok(DnsResponse::from_message(tcp_message.clone()).unwrap())],
+            Ipv4Addr::new(129, 0, 0, 1).into(),
+            Default::default(),
+        );
+
+        mock_nameserver_pool(
+            vec![],
+            vec![oversized_nameserver, tcp_nameserver],
+            None,
+            options,
+        )
+    };
+
+    // the next TCP name server returns a response within the maximum size
+    let request = message(query.clone(), vec![], vec![], vec![]);
+    let response = block_on(pool(TruncatedTcpPolicy::Retry).send(request).first_answer())
+        .expect("lookup should be retried on the next TCP name server");
+    assert_eq!(response.answers(), &[tcp_record]);
+
+    let request = message(query, vec![], vec![], vec![]);
+    let error = block_on(pool(TruncatedTcpPolicy::Fail).send(request).first_answer())
+        .expect_err("lookup should fail on the oversized TCP response");
+    assert!(matches!(
+        error.kind(),
+        ProtoErrorKind::OversizedStreamResponse { max: 128, .. }
+    ));
+}
+
+#[test]
+fn test_recursion_unavailable() {
+    // A name server without the Recursion Available flag isn't recursing for recursive queries.
+
+    let query = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+
+    let forwarder_record = v4_record(query.name().clone(), Ipv4Addr::LOCALHOST);
+    let recursive_record = v4_record(query.name().clone(), Ipv4Addr::new(127, 0, 0, 2));
+
+    let forwarder_message = message(
+        query.clone(),
+        vec![forwarder_record.clone()],
+        vec![],
+        vec![],
+    );
+    let mut recursive_message = message(
+        query.clone(),
+        vec![recursive_record.clone()],
+        vec![],
+        vec![],
+    );
+    recursive_message.set_recursion_available(true);
+
+    let send = |policy: RecursionUnavailablePolicy, recursive: bool| {
+        let mut options = ResolverOpts::default();
+        options.num_concurrent_reqs = 1;
+        options.server_ordering_strategy = ServerOrderingStrategy::UserProvidedOrder;
+        options.recursion_unavailable_policy = policy;
+
+        let forwarder = mock_nameserver(
+            vec![Ok(
+                DnsResponse::from_message(forwarder_message.clone()).unwrap()
+            )],
+            options.clone(),
+        );
+        let recursive = recursive.then(|| {
+            mock_nameserver_with_addr(
+                vec![Ok(
+                    DnsResponse::from_message(recursive_message.clone()).unwrap()
+                )],
+                Ipv4Addr::new(128, 0, 0, 1).into(),
+                options.clone(),
+            )
+        });
+        let pool = mock_nameserver_pool(
+            Some(forwarder).into_iter().chain(recursive).collect(),
+            vec![],
+            None,
+            options,
+        );
+
+        let mut request = message(query.clone(), vec![], vec![], vec![]);
+        request.set_recursion_desired(true);
+        block_on(pool.send(request).first_answer())
+    };
+
+    // the response of the first name server is used as is
+    for policy in [
+        RecursionUnavailablePolicy::Accept,
+        RecursionUnavailablePolicy::Warn,
+    ] {
+        let response = send(policy, true).expect("response should be accepted");
+        assert_eq!(response.answers(), &[forwarder_record.clone()]);
+    }
+
+    // the query fails over to the name server offering recursion
+    let response = send(RecursionUnavailablePolicy::Failover, true)
+        .expect("lookup should fail over to the next name server");
+    assert_eq!(response.answers(), &[recursive_record]);
+
+    let error = send(RecursionUnavailablePolicy::Failover, false)
+        .expect_err("lookup should fail without a recursive name server");
+    assert!(matches!(error.kind(), ProtoErrorKind::RecursionUnavailable));
+}
+
+#[test]
+fn test_edns_absent() {
+    // A name server responding without an OPT record to a query with EDNS doesn't support EDNS.
+
+    let query = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+    let record = v4_record(query.name().clone(), Ipv4Addr::LOCALHOST);
+    let response = message(query.clone(), vec![record.clone()], vec![], vec![]);
+
+    let mut request = message(query.clone(), vec![], vec![], vec![]);
+    request.set_edns(Edns::new());
+
+    let name_server_validating = |policy: EdnsAbsentPolicy, validate: bool| {
+        let mut options = ResolverOpts::default();
+        options.edns_absent_policy = policy;
+        options.validate = validate;
+        mock_nameserver(
+            vec![Ok(DnsResponse::from_message(response.clone()).unwrap())],
+            options,
+        )
+    };
+    let name_server = |policy: EdnsAbsentPolicy| name_server_validating(policy, false);
+
+    // the response is used, and EDNS keeps being sent
+    let accept = name_server(EdnsAbsentPolicy::Accept);
+    let answer =
+        block_on(accept.send(request.clone()).first_answer()).expect("response should be accepted");
+    assert_eq!(answer.answers(), &[record.clone()]);
+    assert!(!accept.is_edns_unsupported());
+
+    // the response is used, and the name server is remembered not to support EDNS for a while
+    let reprobe_after = Duration::from_secs(3600);
+    let downgrade = name_server(EdnsAbsentPolicy::Downgrade { reprobe_after });
+    let answer = block_on(downgrade.send(request.clone()).first_answer())
+        .expect("response should be accepted");
+    assert_eq!(answer.answers(), &[record.clone()]);
+    assert!(downgrade.is_edns_unsupported());
+    assert!(downgrade.clone().is_edns_unsupported());
+
+    // DNSSEC requires EDNS, the name server is never downgraded when validating
+    let validating = name_server_validating(EdnsAbsentPolicy::Downgrade { reprobe_after }, true);
+    let answer = block_on(validating.send(request.clone()).first_answer())
+        .expect("response should be accepted");
+    assert_eq!(answer.answers(), &[record]);
+    assert!(!validating.is_edns_unsupported());
+
+    let fail = name_server(EdnsAbsentPolicy::Fail);
+    let error = block_on(fail.send(request).first_answer())
+        .expect_err("response without EDNS should be rejected");
+    assert!(matches!(error.kind(), ProtoErrorKind::EdnsUnsupported));
+    assert!(!fail.is_edns_unsupported());
+}
+
+#[test]
+fn test_strict_response_query() {
+    // Responses which don't contain exactly the query of the request are rejected when strict.
+
+    let query = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+    let other = Query::query(Name::from_str("www.example.net.").unwrap(), RecordType::A);
+    let record = v4_record(query.name().clone(), Ipv4Addr::LOCALHOST);
+
+    let mut two_queries = message(query.clone(), vec![record.clone()], vec![], vec![]);
+    two_queries.add_query(other.clone());
+    let mismatched = message(other, vec![record], vec![], vec![]);
+
+    let send = |response: &Message, strict: bool| {
+        let mut options = ResolverOpts::default();
+        options.strict_response_query = strict;
+
+        let nameserver = mock_nameserver(
+            vec![Ok(DnsResponse::from_message(response.clone()).unwrap())],
+            options.clone(),
+        );
+        let pool = mock_nameserver_pool(vec![nameserver], vec![], None, options);
+
+        let request = message(query.clone(), vec![], vec![], vec![]);
+        block_on(pool.send(request).first_answer())
+    };
+
+    let error = send(&two_queries, true).expect_err("two queries should be rejected");
+    assert!(matches!(error.kind(), ProtoErrorKind::BadQueryCount(2)));
+
+    let error = send(&mismatched, true).expect_err("a different query should be rejected");
+    assert!(matches!(error.kind(), ProtoErrorKind::QueryMismatch { .. }));
+
+    // the responses are accepted when not strict
+    assert!(send(&two_queries, false).is_ok());
+    assert!(send(&mismatched, false).is_ok());
+}
+
+#[test]
+fn test_unreachable_address_family_skipped() {
+    // On an IPv4-only host, the IPv6 name server is skipped, when requested, even though it's first
+    //  in order.
+
+    let query = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+
+    let v6_addr = IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 53));
+    let v4_addr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 53));
+    let v6_answer = v4_record(query.name().clone(), Ipv4Addr::new(127, 0, 0, 6));
+    let v4_answer = v4_record(query.name().clone(), Ipv4Addr::new(127, 0, 0, 4));
+
+    let send = |skip_unreachable: bool| {
+        let mut options = ResolverOpts::default();
+        options.server_ordering_strategy = ServerOrderingStrategy::UserProvidedOrder;
+        options.num_concurrent_reqs = 1;
+        options.skip_unreachable_address_families = skip_unreachable;
+
+        let v6_message = message(query.clone(), vec![v6_answer.clone()], vec![], vec![]);
+        let v4_message = message(query.clone(), vec![v4_answer.clone()], vec![], vec![]);
+        let v6_nameserver = mock_nameserver_with_addr(
+            vec![Ok(DnsResponse::from_message(v6_message).unwrap())],
+            v6_addr,
+            options.clone(),
+        );
+        let v4_nameserver = mock_nameserver_with_addr(
+            vec![Ok(DnsResponse::from_message(v4_message).unwrap())],
+            v4_addr,
+            options.clone(),
+        );
+
+        let pool = mock_nameserver_pool(vec![v6_nameserver, v4_nameserver], vec![], None, options)
+            .with_local_connectivity(LocalConnectivity {
+                ipv4: true,
+                ipv6: false,
+            });
+
+        let request = message(query.clone(), vec![], vec![], vec![]);
+        block_on(pool.send(request).first_answer()).expect("lookup failed")
+    };
+
+    assert_eq!(send(true).answers(), &[v4_answer.clone()]);
+
+    // the IPv6 name server is attempted by default
+    assert_eq!(send(false).answers(), &[v6_answer.clone()]);
+}
+
+#[test]
+fn test_max_pointer_depth() {
+    // The answer names are reached through a chain of 1, 2 and 3 compression pointers.
+    let mut buffer = vec![0, 0, 0x81, 0x80, 0, 1, 0, 3, 0, 0, 0, 0];
+    buffer.extend([0x00, 0x00, 0x01, 0x00, 0x01]);
+    let mut previous = 12_u16;
+    for _ in 0..3 {
+        let start = buffer.len() as u16;
+        buffer.extend((0xC000 | previous).to_be_bytes());
+        buffer.extend([0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x0E, 0x10, 0x00, 0x04]);
+        buffer.extend([127, 0, 0, 1]);
+        previous = start;
+    }
+    let message = Message::from_vec(&buffer).expect("failed to parse message");
+
+    let send = |max_pointer_depth: usize| {
+        let mut options = ResolverOpts::default();
+        options.max_pointer_depth = max_pointer_depth;
+
+        let response = DnsResponse::new(message.clone(), buffer.clone());
+        let nameserver = mock_nameserver(vec![Ok(response)], options.clone());
+        let pool = mock_nameserver_pool(vec![nameserver], vec![], None, options);
+
+        let request = message.clone();
+        block_on(pool.send(request).first_answer())
+    };
+
+    let error = send(2).expect_err("pointer chain should be rejected");
+    assert!(matches!(
+        error.kind(),
+        ProtoErrorKind::PointerChainTooLong(2)
+    ));
+
+    let response = send(ResolverOpts::default().max_pointer_depth).expect("lookup failed");
+    assert_eq!(response.answers().len(), 3);
+}
+
+#[test]
+fn test_circuit_breaker() {
+    // The first name server fails twice, opening its circuit for the cooldown, which doesn't
+    //  elapse during the test, see the tests of the circuit breaker for the probe.
+
+    let query = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+    let failing_record = v4_record(query.name().clone(), Ipv4Addr::new(127, 0, 0, 1));
+    let healthy_record = v4_record(query.name().clone(), Ipv4Addr::new(127, 0, 0, 2));
+    let response = |record: &hickory_proto::rr::Record| {
+        let message = message(query.clone(), vec![record.clone()], vec![], vec![]);
+        Ok(DnsResponse::from_message(message).unwrap())
+    };
+    let io_error = || {
+        let error = std::io::Error::new(std::io::ErrorKind::Other, "Some I/O Error");
+        Err(ProtoError::from(error))
+    };
+
+    let mut options = ResolverOpts::default();
+    options.server_ordering_strategy = ServerOrderingStrategy::UserProvidedOrder;
+    options.num_concurrent_reqs = 1;
+    options.circuit_breaker = Some(CircuitBreakerConfig {
+        failure_threshold: 2,
+        cooldown: Duration::from_secs(3600),
+    });
+
+    let failing_nameserver = mock_nameserver(
+        vec![response(&failing_record), io_error(), io_error()],
+        options.clone(),
+    );
+    let healthy_nameserver = mock_nameserver(
+        vec![
+            response(&healthy_record),
+            response(&healthy_record),
+            response(&healthy_record),
+        ],
+        options.clone(),
+    );
+    let pool = mock_nameserver_pool(
+        vec![failing_nameserver, healthy_nameserver],
+        vec![],
+        None,
+        options,
+    );
+
+    let lookup = || {
+        let request = message(query.clone(), vec![], vec![], vec![]);
+        block_on(pool.send(request).first_answer())
+            .expect("lookup failed")
+            .answers()
+            .to_vec()
+    };
+    let failing_state = || pool.circuit_breakers()[0].state;
+
+    assert_eq!(lookup(), vec![healthy_record.clone()]);
+    assert_eq!(failing_state(), CircuitState::Closed);
+    assert_eq!(lookup(), vec![healthy_record.clone()]);
+    assert_eq!(failing_state(), CircuitState::Open);
+
+    // the failing name server is skipped, even though it would now respond
+    assert_eq!(lookup(), vec![healthy_record]);
+    assert_eq!(failing_state(), CircuitState::Open);
+    assert_eq!(pool.circuit_breakers()[0].consecutive_failures, 2);
+}
+
+#[test]
+fn test_global_rate() {
+    // each query sent to a name server takes from the same bucket, including the parallel
+    //  queries, no token is refilled during the test
+    let query = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+    let record = v4_record(query.name().clone(), Ipv4Addr::new(127, 0, 0, 1));
+    let response = || {
+        let message = message(query.clone(), vec![record.clone()], vec![], vec![]);
+        Ok(DnsResponse::from_message(message).unwrap())
+    };
+    let servfail = || {
+        let mut message = message(query.clone(), vec![], vec![], vec![]);
+        message.set_response_code(ResponseCode::ServFail);
+        Ok(DnsResponse::from_message(message).unwrap())
+    };
+
+    let mut options = ResolverOpts::default();
+    options.global_rate = Some(Rate {
+        limit: 3,
+        period: Duration::from_secs(3600),
+    });
+
+    // the messages are popped off the back, both name servers fail the first query
+    let first = mock_nameserver_with_addr(
+        vec![response(), servfail()],
+        IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+        options.clone(),
+    );
+    let second = mock_nameserver_with_addr(
+        vec![response(), servfail()],
+        IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)),
+        options.clone(),
+    );
+    let pool = mock_nameserver_pool(vec![first, second], vec![], None, options);
+    assert_eq!(pool.global_tokens(), Some(3));
+
+    let send = || {
+        let request = message(query.clone(), vec![], vec![], vec![]);
+        block_on(pool.send(request).first_answer())
+    };
+
+    // the query is sent to both name servers in parallel
+    assert!(send().is_err_and(|error| !error.is_busy()));
+    assert_eq!(pool.global_tokens(), Some(1));
+
+    // only one of the name servers is queried
+    assert!(send().is_ok());
+    assert_eq!(pool.global_tokens(), Some(0));
+
+    assert!(send().is_err_and(|error| error.is_busy()));
+    assert_eq!(pool.global_tokens(), Some(0));
+}
+
 #[test]
 fn test_datagram_fails_to_stream() {
     // Lookup to UDP should fail, and then the query should be retried on TCP because
@@ -305,6 +805,40 @@ fn test_tcp_fallback_only_on_truncated() {
     }
 }
 
+#[test]
+fn test_servfail_retried_over_stream() {
+    // Lookup to UDP should fail with SERVFAIL, and then the query should be retried on TCP because
+    // `retry_servfail_other_transport` is set to true.
+
+    let query = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+
+    let mut udp_message = message(query.clone(), vec![], vec![], vec![]);
+    udp_message.set_response_code(ResponseCode::ServFail);
+    let tcp_record = v4_record(query.name().clone(), Ipv4Addr::new(127, 0, 0, 2));
+    let tcp_message = message(query.clone(), vec![tcp_record.clone()], vec![], vec![]);
+
+    let udp_nameserver = mock_nameserver(
+        vec![ProtoError::from_response(
+            DnsResponse::from_message(udp_message).unwrap(),
+            false,
+        )],
+        Default::default(),
+    );
+    let tcp_nameserver = mock_nameserver(
+        vec![Ok(DnsResponse::from_message(tcp_message).unwrap())],
+        Default::default(),
+    );
+
+    let mut options = ResolverOpts::default();
+    options.retry_servfail_other_transport = true;
+    let pool = mock_nameserver_pool(vec![udp_nameserver], vec![tcp_nameserver], None, options);
+
+    let request = message(query, vec![], vec![], vec![]);
+    let future = pool.send(request).first_answer();
+    let response = block_on(future).expect("lookup should be retried over TCP");
+    assert_eq!(response.answers(), &[tcp_record]);
+}
+
 #[test]
 fn test_no_tcp_fallback_on_non_io_error() {
     // Lookup to UDP should fail with a non I/O error, and the resolver should not retry
@@ -433,6 +967,231 @@ fn test_tcp_fallback_on_no_connections() {
     }
 }
 
+#[derive(Clone, Default)]
+struct OnSendCounter {
+    sent: Arc<AtomicIsize>,
+}
+
+impl OnSend for OnSendCounter {
+    fn on_send<E>(
+        &self,
+        response: Result<DnsResponse, E>,
+    ) -> Pin<Box<dyn Future<Output = Result<DnsResponse, E>> + Send>>
+    where
+        E: From<ProtoError> + Send + 'static,
+    {
+        self.sent.fetch_add(1, Ordering::Relaxed);
+        Box::pin(std::future::ready(response))
+    }
+}
+
+#[test]
+fn test_tcp_only() {
+    // With `ProtocolPreference::TcpOnly`, the UDP name server should never be queried, even
+    // though it would answer.
+
+    let query = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+
+    let udp_record = v4_record(query.name().clone(), Ipv4Addr::LOCALHOST);
+    let tcp_record = v4_record(query.name().clone(), Ipv4Addr::new(127, 0, 0, 2));
+
+    let udp_message = message(query.clone(), vec![udp_record], vec![], vec![]);
+    let tcp_message = message(query.clone(), vec![tcp_record.clone()], vec![], vec![]);
+
+    let options = ResolverOpts {
+        protocol_preference: ProtocolPreference::TcpOnly,
+        ..ResolverOpts::default()
+    };
+
+    let udp_sent = OnSendCounter::default();
+    let udp_nameserver = mock_nameserver_on_send(
+        vec![Ok(DnsResponse::from_message(udp_message).unwrap())],
+        options.clone(),
+        udp_sent.clone(),
+    );
+    let tcp_sent = OnSendCounter::default();
+    let tcp_nameserver = mock_nameserver_on_send(
+        vec![Ok(DnsResponse::from_message(tcp_message).unwrap())],
+        options.clone(),
+        tcp_sent.clone(),
+    );
+
+    let pool =
+        mock_nameserver_pool_on_send(vec![udp_nameserver], vec![tcp_nameserver], None, options);
+
+    let request = message(query, vec![], vec![], vec![]);
+    let response = block_on(pool.send(request).first_answer()).expect("lookup failed");
+    assert_eq!(response.answers(), &[tcp_record]);
+    assert_eq!(udp_sent.sent.load(Ordering::Relaxed), 0);
+    assert_eq!(tcp_sent.sent.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn test_answer_discrepancy() {
+    // Two name servers queried in parallel answer with different A records, only the second one
+    // being DNSSEC validated.
+
+    let query = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+
+    let first_addr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    let second_addr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+    let first_record = v4_record(query.name().clone(), Ipv4Addr::new(127, 0, 0, 1));
+    let second_record = v4_record(query.name().clone(), Ipv4Addr::new(127, 0, 0, 2));
+
+    let send = |policy: AnswerDiscrepancyPolicy| {
+        let options = ResolverOpts {
+            num_concurrent_reqs: 2,
+            answer_discrepancy_policy: policy,
+            ..ResolverOpts::default()
+        };
+
+        let first_message = message(query.clone(), vec![first_record.clone()], vec![], vec![]);
+        let mut second_message =
+            message(query.clone(), vec![second_record.clone()], vec![], vec![]);
+        second_message.set_authentic_data(true);
+
+        let first_nameserver = mock_nameserver_with_addr(
+            vec![Ok(DnsResponse::from_message(first_message).unwrap())],
+            first_addr,
+            options.clone(),
+        );
+        let second_nameserver = mock_nameserver_with_addr(
+            vec![Ok(DnsResponse::from_message(second_message).unwrap())],
+            second_addr,
+            options.clone(),
+        );
+
+        let pool = mock_nameserver_pool(
+            vec![first_nameserver, second_nameserver],
+            vec![],
+            None,
+            options,
+        );
+        let reports = Arc::new(Mutex::new(Vec::<AnswerDiscrepancy>::new()));
+        let reported = Arc::clone(&reports);
+        pool.set_discrepancy_reporter(Some(Arc::new(move |discrepancy| {
+            reported.lock().unwrap().push(discrepancy.clone())
+        })));
+
+        let request = message(query.clone(), vec![], vec![], vec![]);
+        let response = block_on(pool.send(request).first_answer()).expect("lookup failed");
+        let reports = reports.lock().unwrap().clone();
+        (response, reports)
+    };
+
+    // the first answer is used without waiting for the other name server
+    let (response, reports) = send(AnswerDiscrepancyPolicy::First);
+    assert_eq!(response.answers().len(), 1);
+    assert!(reports.is_empty());
+
+    // the DNSSEC validated answer is preferred, without any report
+    let (response, reports) = send(AnswerDiscrepancyPolicy::PreferDnssecValidated);
+    assert_eq!(response.answers(), &[second_record.clone()]);
+    assert!(response.authentic_data());
+    assert!(reports.is_empty());
+
+    // the discrepancy is reported with both answers, and the first answer is used
+    let (response, reports) = send(AnswerDiscrepancyPolicy::Flag);
+    assert_eq!(reports.len(), 1);
+    let report = &reports[0];
+    assert_eq!(report.queries, vec![query.clone()]);
+    assert_eq!(response.answers(), report.first_answers.as_slice());
+    let mut servers = [report.first_server, report.other_server];
+    servers.sort();
+    assert_eq!(servers, [first_addr, second_addr]);
+    let mut answers = [report.first_answers.clone(), report.other_answers.clone()];
+    answers.sort_by_key(|answers| answers[0].data().to_string());
+    assert_eq!(answers, [vec![first_record], vec![second_record]]);
+}
+
+/// Delays the responses to the DS and DNSKEY queries of DNSSEC validation
+#[cfg(feature = "dnssec")]
+#[derive(Clone)]
+struct SlowDnssecOnSend {
+    delay: Duration,
+}
+
+#[cfg(feature = "dnssec")]
+impl OnSend for SlowDnssecOnSend {
+    fn on_send<E>(
+        &self,
+        response: Result<DnsResponse, E>,
+    ) -> Pin<Box<dyn Future<Output = Result<DnsResponse, E>> + Send>>
+    where
+        E: From<ProtoError> + Send + 'static,
+    {
+        let slow = response.as_ref().is_ok_and(|response| {
+            response
+                .queries()
+                .iter()
+                .any(|query| matches!(query.query_type(), RecordType::DS | RecordType::DNSKEY))
+        });
+        let delay = self.delay;
+
+        Box::pin(async move {
+            if slow {
+                tokio::time::sleep(delay).await;
+            }
+            response
+        })
+    }
+}
+
+#[cfg(feature = "dnssec")]
+#[tokio::test]
+async fn test_deadline_bounds_dnssec_validation() {
+    // The answer is received immediately, but the DS query issued to validate it is slow to be
+    // answered. The deadline of the request should bound the validation.
+    use std::time::Instant;
+
+    use hickory_proto::xfer::DnsRequestOptions;
+    use hickory_proto::DnssecDnsHandle;
+
+    let query = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+    let ds_query = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::DS);
+    let record = v4_record(query.name().clone(), Ipv4Addr::LOCALHOST);
+    let delay = Duration::from_millis(500);
+
+    let lookup = |deadline: Option<Instant>| {
+        let options = ResolverOpts {
+            num_concurrent_reqs: 1,
+            ..ResolverOpts::default()
+        };
+
+        let answer = message(query.clone(), vec![record.clone()], vec![], vec![]);
+        let ds_answer = message(ds_query.clone(), vec![], vec![], vec![]);
+        let nameserver = mock_nameserver_on_send(
+            vec![
+                Ok(DnsResponse::from_message(ds_answer).unwrap()),
+                Ok(DnsResponse::from_message(answer).unwrap()),
+            ],
+            options.clone(),
+            SlowDnssecOnSend { delay },
+        );
+        let pool = mock_nameserver_pool_on_send(vec![nameserver], vec![], None, options);
+
+        let mut request_options = DnsRequestOptions::default();
+        request_options.deadline = deadline;
+        DnssecDnsHandle::new(pool)
+            .lookup(query.clone(), request_options)
+            .first_answer()
+    };
+
+    // without a deadline, the validation waits for the slow DS response
+    let start = Instant::now();
+    let response = lookup(None).await.expect("lookup failed");
+    assert_eq!(response.answers().len(), 1);
+    assert!(start.elapsed() >= delay);
+
+    // the validation is cut at the deadline, and the lookup times out
+    let start = Instant::now();
+    let error = lookup(Some(start + Duration::from_millis(50)))
+        .await
+        .expect_err("lookup should time out");
+    assert!(matches!(error.kind(), ProtoErrorKind::Timeout), "{error:?}");
+    assert!(start.elapsed() < delay);
+}
+
 #[test]
 fn test_trust_nx_responses_fails() {
     let query = Query::query(Name::from_str("www.example.").unwrap(), RecordType::A);
@@ -670,6 +1429,55 @@ fn test_user_provided_server_order() {
         });
 }
 
+#[test]
+fn test_weighted_server_selection() {
+    use hickory_proto::rr::Record;
+
+    let mut options = ResolverOpts::default();
+    options.server_selection_strategy = ServerSelectionStrategy::Weighted;
+    // the name servers are not shuffled with this strategy
+    options.shuffle_dns_servers = true;
+
+    let query = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+    let light_record = v4_record(query.name().clone(), Ipv4Addr::new(127, 0, 0, 2));
+    let heavy_record = v4_record(query.name().clone(), Ipv4Addr::LOCALHOST);
+    let to_response = |record: &Record| {
+        let message = message(query.clone(), vec![record.clone()], vec![], vec![]);
+        vec![Ok(DnsResponse::from_message(message).unwrap())]
+    };
+
+    // the heavier name server is configured last, it has a single response
+    let light_nameserver = mock_nameserver_on_send_nx(
+        to_response(&light_record),
+        Default::default(),
+        DefaultOnSend,
+        Ipv4Addr::new(128, 0, 0, 1).into(),
+        false,
+        1,
+    );
+    let heavy_nameserver = mock_nameserver_on_send_nx(
+        to_response(&heavy_record),
+        Default::default(),
+        DefaultOnSend,
+        Ipv4Addr::new(129, 0, 0, 1).into(),
+        false,
+        10,
+    );
+    let pool = mock_nameserver_pool(
+        vec![light_nameserver, heavy_nameserver],
+        vec![],
+        None,
+        options,
+    );
+
+    // the lighter name server is only queried once the heavier one fails
+    for expected_record in [heavy_record, light_record] {
+        let request = message(query.clone(), vec![], vec![], vec![]);
+        let response = block_on(pool.send(request).first_answer()).unwrap();
+        assert_eq!(response.answers()[0], expected_record);
+    }
+}
+
 #[test]
 fn test_return_error_from_highest_priority_nameserver() {
     let query = Query::query(Name::from_str("www.example.").unwrap(), RecordType::A);
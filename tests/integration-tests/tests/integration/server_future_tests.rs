@@ -236,7 +236,12 @@ async fn test_server_www_tls() {
     let server_continue = Arc::new(AtomicBool::new(true));
     let server_continue2 = server_continue.clone();
 
-    let server = tokio::spawn(server_thread_tls(tcp_listener, server_continue2, cert_key));
+    let server = tokio::spawn(server_thread_tls(
+        tcp_listener,
+        server_continue2,
+        cert_key,
+        Duration::from_secs(30),
+    ));
     let client = tokio::spawn(client_thread_www(lazy_tls_client(
         ipaddr,
         dns_name.to_string(),
@@ -250,6 +255,608 @@ async fn test_server_www_tls() {
     server.await.unwrap();
 }
 
+#[cfg(feature = "dns-over-rustls")]
+#[tokio::test]
+async fn test_tls_session_resumption() {
+    use hickory_resolver::config::{ResolverOpts, TlsClientConfig};
+
+    let (ipaddr, server, server_continue, client_config) = tls_session_server().await;
+    let options = ResolverOpts {
+        tls_session_cache_size: Some(8),
+        ..ResolverOpts::default()
+    };
+    let name_server =
+        tls_session_name_server(ipaddr, TlsClientConfig(Arc::new(client_config)), options);
+
+    lookup_www(&name_server).await;
+    assert_eq!(name_server.tls_resumptions(), 0);
+
+    // the reconnection resumes the session of the first connection
+    name_server.disconnect().await;
+    lookup_www(&name_server).await;
+    assert_eq!(name_server.tls_resumptions(), 1);
+
+    server_continue.store(false, Ordering::Relaxed);
+    server.await.unwrap();
+}
+
+#[cfg(feature = "dns-over-rustls")]
+#[tokio::test]
+async fn test_tls_session_store() {
+    use hickory_resolver::config::{ResolverOpts, TlsClientConfig};
+    use rustls::client::{
+        ClientSessionMemoryCache, ClientSessionStore, Tls12ClientSessionValue,
+        Tls13ClientSessionValue,
+    };
+    use rustls::pki_types::ServerName;
+    use rustls::NamedGroup;
+    use std::sync::atomic::AtomicUsize;
+
+    /// Counts the sessions taken from the store, i.e. the resumptions offered to the server
+    #[derive(Debug)]
+    struct CountingStore {
+        sessions: ClientSessionMemoryCache,
+        taken: AtomicUsize,
+    }
+
+    impl ClientSessionStore for CountingStore {
+        fn set_kx_hint(&self, server_name: ServerName<'static>, group: NamedGroup) {
+            self.sessions.set_kx_hint(server_name, group)
+        }
+
+        fn kx_hint(&self, server_name: &ServerName<'_>) -> Option<NamedGroup> {
+            self.sessions.kx_hint(server_name)
+        }
+
+        fn set_tls12_session(
+            &self,
+            server_name: ServerName<'static>,
+            value: Tls12ClientSessionValue,
+        ) {
+            self.sessions.set_tls12_session(server_name, value)
+        }
+
+        fn tls12_session(&self, server_name: &ServerName<'_>) -> Option<Tls12ClientSessionValue> {
+            let session = self.sessions.tls12_session(server_name);
+            if session.is_some() {
+                self.taken.fetch_add(1, Ordering::Relaxed);
+            }
+            session
+        }
+
+        fn remove_tls12_session(&self, server_name: &ServerName<'static>) {
+            self.sessions.remove_tls12_session(server_name)
+        }
+
+        fn insert_tls13_ticket(
+            &self,
+            server_name: ServerName<'static>,
+            value: Tls13ClientSessionValue,
+        ) {
+            self.sessions.insert_tls13_ticket(server_name, value)
+        }
+
+        fn take_tls13_ticket(
+            &self,
+            server_name: &ServerName<'static>,
+        ) -> Option<Tls13ClientSessionValue> {
+            let session = self.sessions.take_tls13_ticket(server_name);
+            if session.is_some() {
+                self.taken.fetch_add(1, Ordering::Relaxed);
+            }
+            session
+        }
+    }
+
+    let (ipaddr, server, server_continue, client_config) = tls_session_server().await;
+    let store = Arc::new(CountingStore {
+        sessions: ClientSessionMemoryCache::new(8),
+        taken: AtomicUsize::new(0),
+    });
+    let tls_config = TlsClientConfig(Arc::new(client_config)).with_session_store(store.clone());
+
+    // a new name server for each connection, sharing the store through the config, the first
+    //  connection makes a full handshake and the second one resumes its session
+    let name_server =
+        || tls_session_name_server(ipaddr, tls_config.clone(), ResolverOpts::default());
+    lookup_www(&name_server()).await;
+    assert_eq!(store.taken.load(Ordering::Relaxed), 0);
+
+    lookup_www(&name_server()).await;
+    assert_eq!(store.taken.load(Ordering::Relaxed), 1);
+
+    server_continue.store(false, Ordering::Relaxed);
+    server.await.unwrap();
+}
+
+/// Compares the time of the queries on a new connection, with a full or a resumed TLS handshake
+///
+/// Resuming a session skips the transfer and the verification of the server's certificate chain,
+///  and the signature of the handshake. The savings depend on the machine and on the round trip
+///  time to the server, so they are printed rather than asserted, run with:
+///
+/// ```text
+/// cargo test -p hickory-integration --all-features bench_tls_session_resumption -- --ignored --nocapture
+/// ```
+#[cfg(feature = "dns-over-rustls")]
+#[tokio::test]
+#[ignore]
+async fn bench_tls_session_resumption() {
+    use hickory_resolver::config::{ResolverOpts, TlsClientConfig};
+    use rustls::client::{ClientSessionMemoryCache, Resumption};
+    use std::time::Instant;
+
+    const CONNECTIONS: u32 = 50;
+
+    let (ipaddr, server, server_continue, mut client_config) = tls_session_server().await;
+    let resumed_config = TlsClientConfig(Arc::new(client_config.clone()))
+        .with_session_store(Arc::new(ClientSessionMemoryCache::new(8)));
+    client_config.resumption = Resumption::disabled();
+    let full_config = TlsClientConfig(Arc::new(client_config));
+
+    // a new name server for each query, i.e. a new connection and TLS handshake
+    let mean_query_time = |tls_config: TlsClientConfig| async move {
+        let start = Instant::now();
+        for _ in 0..CONNECTIONS {
+            let name_server =
+                tls_session_name_server(ipaddr, tls_config.clone(), ResolverOpts::default());
+            lookup_www(&name_server).await;
+        }
+        start.elapsed() / CONNECTIONS
+    };
+
+    // the first connection of the resumed config makes a full handshake, storing the session
+    lookup_www(&tls_session_name_server(
+        ipaddr,
+        resumed_config.clone(),
+        ResolverOpts::default(),
+    ))
+    .await;
+
+    let full = mean_query_time(full_config).await;
+    let resumed = mean_query_time(resumed_config).await;
+    println!(
+        "mean query time with a full handshake: {full:?}, with a resumed session: {resumed:?}"
+    );
+
+    server_continue.store(false, Ordering::Relaxed);
+    server.await.unwrap();
+}
+
+/// Starts a TLS server for the example zone, and returns a client config trusting its certificate
+#[cfg(feature = "dns-over-rustls")]
+async fn tls_session_server() -> (
+    SocketAddr,
+    tokio::task::JoinHandle<()>,
+    Arc<AtomicBool>,
+    ClientConfig,
+) {
+    use hickory_proto::rustls::tls_server;
+    use std::env;
+    use std::path::Path;
+
+    let server_path = env::var("TDNS_WORKSPACE_ROOT").unwrap_or_else(|_| "../..".to_owned());
+    let ca =
+        tls_server::read_cert(Path::new(&format!("{server_path}/tests/test-data/ca.pem"))).unwrap();
+    let cert = tls_server::read_cert(Path::new(&format!(
+        "{server_path}/tests/test-data/cert.pem"
+    )))
+    .unwrap();
+    let key = tls_server::read_key(Path::new(&format!(
+        "{server_path}/tests/test-data/cert.key"
+    )))
+    .unwrap();
+
+    let addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+    let tcp_listener = TcpListener::bind(&addr).await.unwrap();
+    let ipaddr = tcp_listener.local_addr().unwrap();
+    let server_continue = Arc::new(AtomicBool::new(true));
+
+    let server = tokio::spawn(server_thread_tls(
+        tcp_listener,
+        server_continue.clone(),
+        (cert, key),
+        Duration::from_secs(30),
+    ));
+
+    let mut root_store = RootCertStore::empty();
+    root_store.add_parsable_certificates(ca);
+    let client_config =
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(root_store)
+            .with_no_client_auth();
+
+    (ipaddr, server, server_continue, client_config)
+}
+
+/// Returns a name server connecting to the server of [`tls_session_server`] over TLS
+#[cfg(feature = "dns-over-rustls")]
+fn tls_session_name_server(
+    ipaddr: SocketAddr,
+    tls_config: hickory_resolver::config::TlsClientConfig,
+    options: hickory_resolver::config::ResolverOpts,
+) -> hickory_resolver::name_server::GenericNameServer<TokioRuntimeProvider> {
+    use hickory_proto::xfer::Protocol;
+    use hickory_resolver::config::NameServerConfig;
+    use hickory_resolver::name_server::{GenericNameServer, TokioConnectionProvider};
+
+    let config = NameServerConfig {
+        socket_addr: ipaddr,
+        protocol: Protocol::Tls,
+        tls_dns_name: Some("ns.example.com".to_string()),
+        http_endpoint: None,
+        trust_negative_responses: false,
+        tls_config: Some(tls_config),
+        bind_addr: None,
+        timeout: None,
+        weight: 0,
+    };
+    GenericNameServer::new(config, options, TokioConnectionProvider::default())
+}
+
+/// Looks up the A records of `www.example.com.` from the name server, which must succeed
+#[cfg(feature = "dns-over-rustls")]
+async fn lookup_www(
+    name_server: &hickory_resolver::name_server::GenericNameServer<TokioRuntimeProvider>,
+) {
+    use hickory_proto::xfer::{DnsRequestOptions, FirstAnswer};
+
+    let query = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+    let response = name_server
+        .lookup(query, DnsRequestOptions::default())
+        .first_answer()
+        .await
+        .expect("query failed");
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+}
+
+#[cfg(feature = "dns-over-rustls")]
+#[tokio::test]
+async fn test_tls_client_auth() {
+    use hickory_proto::rustls::tls_server;
+    use hickory_proto::xfer::{DnsRequestOptions, FirstAnswer, Protocol};
+    use hickory_resolver::config::{NameServerConfig, ResolverOpts, TlsClientConfig};
+    use hickory_resolver::name_server::{GenericNameServer, TokioConnectionProvider};
+    use rustls::server::WebPkiClientVerifier;
+    use rustls::ServerConfig;
+    use std::env;
+    use std::path::Path;
+
+    let server_path = env::var("TDNS_WORKSPACE_ROOT").unwrap_or_else(|_| "../..".to_owned());
+    let ca =
+        tls_server::read_cert(Path::new(&format!("{server_path}/tests/test-data/ca.pem"))).unwrap();
+    let cert = tls_server::read_cert(Path::new(&format!(
+        "{server_path}/tests/test-data/cert.pem"
+    )))
+    .unwrap();
+    let key = || {
+        tls_server::read_key(Path::new(&format!(
+            "{server_path}/tests/test-data/cert.key"
+        )))
+        .unwrap()
+    };
+
+    let mut root_store = RootCertStore::empty();
+    root_store.add_parsable_certificates(ca);
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+    // the server requires a client certificate issued by the test CA
+    let verifier =
+        WebPkiClientVerifier::builder_with_provider(Arc::new(root_store.clone()), provider.clone())
+            .build()
+            .unwrap();
+    let server_config = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(cert.clone(), key())
+        .unwrap();
+
+    let addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+    let tcp_listener = TcpListener::bind(&addr).await.unwrap();
+    let ipaddr = tcp_listener.local_addr().unwrap();
+    let server_continue = Arc::new(AtomicBool::new(true));
+
+    let catalog = new_catalog();
+    let mut server = ServerFuture::new(catalog);
+    server
+        .register_tls_listener_with_tls_config(
+            tcp_listener,
+            Duration::from_secs(30),
+            Arc::new(server_config),
+        )
+        .expect("failed to register TLS");
+    let server = tokio::spawn({
+        let server_continue = server_continue.clone();
+        async move {
+            while server_continue.load(Ordering::Relaxed) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            server.shutdown_gracefully().await.unwrap();
+        }
+    });
+
+    let query = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+    let lookup = |tls_config: TlsClientConfig| {
+        let config = NameServerConfig {
+            socket_addr: ipaddr,
+            protocol: Protocol::Tls,
+            tls_dns_name: Some("ns.example.com".to_string()),
+            http_endpoint: None,
+            trust_negative_responses: false,
+            tls_config: Some(tls_config),
+            bind_addr: None,
+            timeout: None,
+            weight: 0,
+        };
+        let name_server = GenericNameServer::new(
+            config,
+            ResolverOpts::default(),
+            TokioConnectionProvider::default(),
+        );
+        let query = query.clone();
+        async move {
+            name_server
+                .lookup(query, DnsRequestOptions::default())
+                .first_answer()
+                .await
+        }
+    };
+
+    // the client certificate is accepted
+    let client_auth =
+        TlsClientConfig::new_with_client_auth(root_store.clone(), cert.clone(), key())
+            .expect("valid client certificate");
+    let response = lookup(client_auth)
+        .await
+        .expect("query with a client certificate failed");
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+
+    // the connection is refused without a client certificate
+    let no_client_auth = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(root_store.clone())
+        .with_no_client_auth();
+    lookup(TlsClientConfig(Arc::new(no_client_auth)))
+        .await
+        .expect_err("query without a client certificate should fail");
+
+    // an invalid private key is reported rather than failing on connection
+    TlsClientConfig::new_with_client_auth(
+        root_store,
+        cert,
+        PrivateKeyDer::Pkcs8(vec![0; 16].into()),
+    )
+    .expect_err("invalid private key should be rejected");
+
+    server_continue.store(false, Ordering::Relaxed);
+    server.await.unwrap();
+}
+
+#[cfg(feature = "dns-over-rustls")]
+#[tokio::test]
+async fn test_tls_pinned_spki() {
+    use hickory_proto::error::ProtoErrorKind;
+    use hickory_proto::rustls::tls_server;
+    use hickory_proto::xfer::{DnsRequestOptions, FirstAnswer, Protocol};
+    use hickory_resolver::config::{NameServerConfig, ResolverOpts, TlsClientConfig};
+    use hickory_resolver::name_server::{GenericNameServer, TokioConnectionProvider};
+    use rustls::ServerConfig;
+    use std::env;
+    use std::path::Path;
+
+    let server_path = env::var("TDNS_WORKSPACE_ROOT").unwrap_or_else(|_| "../..".to_owned());
+    let ca =
+        tls_server::read_cert(Path::new(&format!("{server_path}/tests/test-data/ca.pem"))).unwrap();
+    let cert = tls_server::read_cert(Path::new(&format!(
+        "{server_path}/tests/test-data/cert.pem"
+    )))
+    .unwrap();
+    let key = tls_server::read_key(Path::new(&format!(
+        "{server_path}/tests/test-data/cert.key"
+    )))
+    .unwrap();
+
+    let mut root_store = RootCertStore::empty();
+    root_store.add_parsable_certificates(ca);
+    let pin = TlsClientConfig::spki_pin(&cert[0]).expect("failed to parse the certificate");
+
+    let server_config =
+        ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(cert, key)
+            .unwrap();
+
+    let addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+    let tcp_listener = TcpListener::bind(&addr).await.unwrap();
+    let ipaddr = tcp_listener.local_addr().unwrap();
+    let server_continue = Arc::new(AtomicBool::new(true));
+
+    let catalog = new_catalog();
+    let mut server = ServerFuture::new(catalog);
+    server
+        .register_tls_listener_with_tls_config(
+            tcp_listener,
+            Duration::from_secs(30),
+            Arc::new(server_config),
+        )
+        .expect("failed to register TLS");
+    let server = tokio::spawn({
+        let server_continue = server_continue.clone();
+        async move {
+            while server_continue.load(Ordering::Relaxed) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            server.shutdown_gracefully().await.unwrap();
+        }
+    });
+
+    let query = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+    let lookup = |pinned_spki: Vec<[u8; 32]>| {
+        let tls_config = TlsClientConfig::new_with_roots(root_store.clone())
+            .and_then(|config| config.with_pinned_spki(root_store.clone(), pinned_spki))
+            .expect("failed to build the client config");
+        let config = NameServerConfig {
+            socket_addr: ipaddr,
+            protocol: Protocol::Tls,
+            tls_dns_name: Some("ns.example.com".to_string()),
+            http_endpoint: None,
+            trust_negative_responses: false,
+            tls_config: Some(tls_config),
+            bind_addr: None,
+            timeout: None,
+            weight: 0,
+        };
+        let name_server = GenericNameServer::new(
+            config,
+            ResolverOpts::default(),
+            TokioConnectionProvider::default(),
+        );
+        let query = query.clone();
+        async move {
+            name_server
+                .lookup(query, DnsRequestOptions::default())
+                .first_answer()
+                .await
+        }
+    };
+
+    // the pinned key is accepted, among others
+    let response = lookup(vec![[0; 32], pin])
+        .await
+        .expect("query with the pinned key failed");
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+
+    // no pinning without pins
+    let response = lookup(vec![]).await.expect("query without pinning failed");
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+
+    // any other key is rejected
+    let error = lookup(vec![[0; 32]])
+        .await
+        .expect_err("query with another pinned key should fail");
+    assert!(
+        matches!(error.kind(), ProtoErrorKind::SpkiPinMismatch(name) if name == "ns.example.com"),
+        "{error:?}"
+    );
+
+    server_continue.store(false, Ordering::Relaxed);
+    server.await.unwrap();
+}
+
+#[cfg(feature = "dns-over-rustls")]
+#[tokio::test]
+async fn test_tls_alpn() {
+    use hickory_proto::rustls::tls_server;
+    use hickory_proto::xfer::{DnsRequestOptions, FirstAnswer, Protocol};
+    use hickory_resolver::config::{NameServerConfig, ResolverOpts, TlsClientConfig};
+    use hickory_resolver::name_server::{GenericNameServer, TokioConnectionProvider};
+    use rustls::ServerConfig;
+    use std::env;
+    use std::path::Path;
+
+    let server_path = env::var("TDNS_WORKSPACE_ROOT").unwrap_or_else(|_| "../..".to_owned());
+    let ca =
+        tls_server::read_cert(Path::new(&format!("{server_path}/tests/test-data/ca.pem"))).unwrap();
+    let cert = tls_server::read_cert(Path::new(&format!(
+        "{server_path}/tests/test-data/cert.pem"
+    )))
+    .unwrap();
+    let key = tls_server::read_key(Path::new(&format!(
+        "{server_path}/tests/test-data/cert.key"
+    )))
+    .unwrap();
+
+    let mut root_store = RootCertStore::empty();
+    root_store.add_parsable_certificates(ca);
+    let client_config = TlsClientConfig::new_with_roots(root_store).unwrap();
+
+    // the server only accepts connections negotiating DoT, or no protocol at all
+    let mut server_config =
+        ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(cert, key)
+            .unwrap();
+    server_config.alpn_protocols = vec![b"dot".to_vec()];
+
+    let addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+    let tcp_listener = TcpListener::bind(&addr).await.unwrap();
+    let ipaddr = tcp_listener.local_addr().unwrap();
+    let server_continue = Arc::new(AtomicBool::new(true));
+
+    let catalog = new_catalog();
+    let mut server = ServerFuture::new(catalog);
+    server
+        .register_tls_listener_with_tls_config(
+            tcp_listener,
+            Duration::from_secs(30),
+            Arc::new(server_config),
+        )
+        .expect("failed to register TLS");
+    let server = tokio::spawn({
+        let server_continue = server_continue.clone();
+        async move {
+            while server_continue.load(Ordering::Relaxed) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            server.shutdown_gracefully().await.unwrap();
+        }
+    });
+
+    let query = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+    let lookup = |tls_config: TlsClientConfig| {
+        let config = NameServerConfig {
+            socket_addr: ipaddr,
+            protocol: Protocol::Tls,
+            tls_dns_name: Some("ns.example.com".to_string()),
+            http_endpoint: None,
+            trust_negative_responses: false,
+            tls_config: Some(tls_config),
+            bind_addr: None,
+            timeout: None,
+            weight: 0,
+        };
+        let name_server = GenericNameServer::new(
+            config,
+            ResolverOpts::default(),
+            TokioConnectionProvider::default(),
+        );
+        let query = query.clone();
+        async move {
+            name_server
+                .lookup(query, DnsRequestOptions::default())
+                .first_answer()
+                .await
+        }
+    };
+
+    // no protocol is offered by default
+    let response = lookup(client_config.clone())
+        .await
+        .expect("query offering no protocol failed");
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+
+    // the configured protocols are offered
+    let response = lookup(client_config.with_alpn_protocols(vec![b"h2".to_vec(), b"dot".to_vec()]))
+        .await
+        .expect("query offering h2 and DoT failed");
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+
+    lookup(client_config.with_alpn_protocols(vec![b"h2".to_vec()]))
+        .await
+        .expect_err("query offering h2 only should fail");
+
+    server_continue.store(false, Ordering::Relaxed);
+    server.await.unwrap();
+}
+
 async fn lazy_udp_client(addr: SocketAddr) -> Client {
     let conn = UdpClientStream::builder(addr, TokioRuntimeProvider::default()).build();
     let (client, driver) = Client::connect(conn).await.expect("failed to connect");
@@ -371,6 +978,7 @@ async fn server_thread_tls(
     tls_listener: TcpListener,
     server_continue: Arc<AtomicBool>,
     cert_chain: (Vec<CertificateDer<'static>>, PrivateKeyDer<'static>),
+    timeout: Duration,
 ) {
     use std::path::Path;
 
@@ -384,7 +992,7 @@ async fn server_thread_tls(
     // let pkcs12 = ((pkcs12.cert, pkcs12.chain), pkcs12.pkey);
 
     server
-        .register_tls_listener(tls_listener, Duration::from_secs(30), cert_chain)
+        .register_tls_listener(tls_listener, timeout, cert_chain)
         .expect("failed to register TLS");
 
     while server_continue.load(Ordering::Relaxed) {
@@ -131,6 +131,8 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
             #[cfg(feature = "dns-over-rustls")]
             tls_config: None,
             bind_addr: opts.bind.map(|ip| SocketAddr::new(ip, 0)),
+            timeout: None,
+            weight: 0,
         });
 
         roots.push(NameServerConfig {
@@ -142,6 +144,8 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
             #[cfg(feature = "dns-over-rustls")]
             tls_config: None,
             bind_addr: opts.bind.map(|ip| SocketAddr::new(ip, 0)),
+            timeout: None,
+            weight: 0,
         });
     }
 
@@ -282,6 +282,8 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
             #[cfg(feature = "dns-over-rustls")]
             tls_config: None,
             bind_addr: opts.bind.map(|ip| SocketAddr::new(ip, 0)),
+            timeout: None,
+            weight: 0,
         });
 
         name_servers.push(NameServerConfig {
@@ -293,6 +295,8 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
             #[cfg(feature = "dns-over-rustls")]
             tls_config: None,
             bind_addr: opts.bind.map(|ip| SocketAddr::new(ip, 0)),
+            timeout: None,
+            weight: 0,
         });
     }
 